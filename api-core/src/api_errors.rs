@@ -7,11 +7,13 @@ use actix_web::{HttpResponse, ResponseError};
 use serde::{Deserialize, Serialize};
 
 #[derive(Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct ErrorResponse {
     pub error: ApiError,
 }
 
 #[derive(Default, Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct ApiError {
     #[serde(skip)]
     pub http_code: StatusCode,
@@ -66,6 +68,17 @@ pub fn bad_requests(message: &str) -> ApiError {
     }
 }
 
+pub fn too_many_requests(retry_after_secs: u64) -> ApiError {
+    let code = ApiErrorCode::TooManyRequests;
+    ApiError {
+        http_code: StatusCode::TOO_MANY_REQUESTS,
+        code: code as u16,
+        status: code.to_string(),
+        message: format!("rate limit exceeded, retry after {retry_after_secs}s"),
+        details: HashMap::from([("retry_after".into(), retry_after_secs.to_string())]),
+    }
+}
+
 pub fn internal_server_error() -> ApiError {
     let code = ApiErrorCode::InternalError;
     ApiError {
@@ -92,10 +105,14 @@ pub enum ApiErrorCode {
     // The server cannot handle the request due to stale indexer,
     // or unreachable BitcoinRPC or DB, or something else.
     ServiceUnavailable = 503,
+    // db query took longer than the configured timeout and was cancelled
+    Timeout = 504,
     // invalid api-key
     AccessDenied = 401,
     // api-key blocked or suspended
     Forbidden = 403,
+    // rate limit for api-key or client ip is exceeded
+    TooManyRequests = 429,
     // entity for passed params is not found
     NotFound = 1000,
     // user provided bad input
@@ -106,6 +123,8 @@ pub enum ApiErrorCode {
     NotEnoughBalance = 1003,
     // collect utxo: increase "max_utxos" parameter
     NeedMoreUtxos = 1004,
+    // history at or below the pruned height was deleted by `db prune`
+    HistoryPruned = 1005,
 }
 
 impl Display for ApiErrorCode {
@@ -113,13 +132,16 @@ impl Display for ApiErrorCode {
         let val = match self {
             Self::InternalError => "internal_error",
             Self::ServiceUnavailable => "service_unavailable",
+            Self::Timeout => "timeout",
             Self::AccessDenied => "access_denied",
             Self::Forbidden => "access_forbidden",
+            Self::TooManyRequests => "too_many_requests",
             Self::NotFound => "not_found",
             Self::BadInput => "bad_input",
             Self::InvalidAddress => "invalid_address",
             Self::NotEnoughBalance => "not_enough_balance",
             Self::NeedMoreUtxos => "not_enough_utxos",
+            Self::HistoryPruned => "history_pruned",
         };
         write!(f, "{val}")
     }
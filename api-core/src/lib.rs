@@ -1,5 +1,6 @@
 pub mod api_errors;
 pub mod pages;
+pub mod request_id;
 pub mod serde_utils;
 pub mod server;
 
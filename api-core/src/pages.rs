@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Default, Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub enum OrderBy {
     #[serde(rename = "asc", alias = "ASC")]
     Asc,
@@ -17,6 +18,10 @@ impl OrderBy {
             Self::Desc => Self::Asc,
         }
     }
+
+    pub fn sql(self) -> SqlOrder {
+        SqlOrder(self)
+    }
 }
 
 impl std::str::FromStr for OrderBy {
@@ -41,53 +46,229 @@ impl std::fmt::Display for OrderBy {
     }
 }
 
-#[derive(Default, Clone, Serialize, Deserialize, Debug)]
+/// Direction of an sql `ORDER BY` term. It renders only `ASC` or `DESC`,
+/// so it's safe to put into the query text.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SqlOrder(OrderBy);
+
+impl SqlOrder {
+    pub const ASC: SqlOrder = SqlOrder(OrderBy::Asc);
+    pub const DESC: SqlOrder = SqlOrder(OrderBy::Desc);
+
+    pub fn reverse(self) -> Self {
+        SqlOrder(self.0.reverse())
+    }
+
+    /// Comparison operator of the keyset condition, it selects rows that go after the cursor.
+    pub fn after_op(self) -> &'static str {
+        match self.0 {
+            OrderBy::Asc => ">",
+            OrderBy::Desc => "<",
+        }
+    }
+}
+
+impl From<OrderBy> for SqlOrder {
+    fn from(order: OrderBy) -> Self {
+        SqlOrder(order)
+    }
+}
+
+impl std::fmt::Display for SqlOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            OrderBy::Asc => write!(f, "ASC"),
+            OrderBy::Desc => write!(f, "DESC"),
+        }
+    }
+}
+
+/// Order of the primary sort column and of the tie-breakers that make pages stable.
+/// E.g. amount sorting keeps the oldest outputs first among the equal amounts in both directions.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SortOrder {
+    pub primary: SqlOrder,
+    pub secondary: SqlOrder,
+}
+
+impl SortOrder {
+    /// Tie-breakers follow the primary order.
+    pub fn uniform(order: OrderBy) -> Self {
+        Self {
+            primary: order.sql(),
+            secondary: order.sql(),
+        }
+    }
+
+    pub fn new(primary: impl Into<SqlOrder>, secondary: impl Into<SqlOrder>) -> Self {
+        Self {
+            primary: primary.into(),
+            secondary: secondary.into(),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct ListResponseMeta {
     pub page: u32,
     pub limit: u32,
     pub offset: u32,
     pub has_more: bool,
     pub total_records: u64,
+    /// `false` if the records couldn't be counted, then `total_records` is the number
+    /// of records up to the end of this page and `has_more` is guessed by the page size.
+    #[serde(default = "total_is_exact_default")]
+    pub total_is_exact: bool,
+    /// Cursor of the next page, set only for keyset pagination.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+// responses without the field come from the versions that always counted the total
+fn total_is_exact_default() -> bool {
+    true
+}
+
+impl Default for ListResponseMeta {
+    fn default() -> Self {
+        Self {
+            page: 0,
+            limit: 0,
+            offset: 0,
+            has_more: false,
+            total_records: 0,
+            total_is_exact: true,
+            next_cursor: None,
+        }
+    }
 }
 
 impl ListResponseMeta {
-    pub fn new(limit: u32, offset: u32, total: u64) -> Self {
+    /// Offset pagination with the counted `total`, the page is derived from the `offset`.
+    pub fn from_counts(limit: u32, offset: u32, total: u64) -> Self {
         Self {
             page: (offset / limit.max(1)),
             limit,
             offset,
-            has_more: u64::from(offset + limit) < total,
+            has_more: u64::from(offset) + u64::from(limit) < total,
+            total_records: total,
+            total_is_exact: true,
+            next_cursor: None,
+        }
+    }
+
+    /// Offset pagination when the count has failed, `page_len` is the number of returned records.
+    /// A full page is assumed to have more records after it.
+    pub fn unknown_total(limit: u32, offset: u32, page_len: usize) -> Self {
+        Self {
+            has_more: limit > 0 && page_len >= limit as usize,
+            total_records: u64::from(offset) + page_len as u64,
+            total_is_exact: false,
+            ..Self::from_counts(limit, offset, 0)
+        }
+    }
+
+    /// [`Self::from_counts`] if the `total` is counted, [`Self::unknown_total`] otherwise.
+    pub fn from_optional_total(
+        limit: u32,
+        offset: u32,
+        total: Option<u64>,
+        page_len: usize,
+    ) -> Self {
+        match total {
+            Some(total) => Self::from_counts(limit, offset, total),
+            None => Self::unknown_total(limit, offset, page_len),
+        }
+    }
+
+    pub fn with_cursor(limit: u32, total: u64, next_cursor: Option<String>) -> Self {
+        Self {
+            page: 0,
+            limit,
+            offset: 0,
+            has_more: next_cursor.is_some(),
             total_records: total,
+            total_is_exact: true,
+            next_cursor,
         }
     }
 }
 
 #[test]
 fn test_page_is_correct() {
-    let meta = ListResponseMeta::new(10, 0, 320);
+    let meta = ListResponseMeta::from_counts(10, 0, 320);
     assert_eq!(meta.limit, 10);
     assert_eq!(meta.offset, 0);
     assert_eq!(meta.page, 0);
     assert_eq!(meta.total_records, 320);
     assert!(meta.has_more);
+    assert!(meta.total_is_exact);
 
-    let meta = ListResponseMeta::new(10, 10, 320);
+    let meta = ListResponseMeta::from_counts(10, 10, 320);
     assert_eq!(meta.page, 1);
 
-    let meta = ListResponseMeta::new(10, 14, 320);
+    let meta = ListResponseMeta::from_counts(10, 14, 320);
     assert_eq!(meta.page, 1);
 
-    let meta = ListResponseMeta::new(10, 20, 320);
+    let meta = ListResponseMeta::from_counts(10, 20, 320);
     assert_eq!(meta.page, 2);
 
-    let meta = ListResponseMeta::new(10, 220, 320);
+    let meta = ListResponseMeta::from_counts(10, 220, 320);
     assert_eq!(meta.page, 22);
 
-    let meta = ListResponseMeta::new(0, 0, 320);
+    let meta = ListResponseMeta::from_counts(0, 0, 320);
     assert_eq!(meta.page, 0);
 }
 
+#[test]
+fn test_has_more() {
+    // limit, offset, total
+    let counted = [
+        ("empty result", 10, 0, 0, false),
+        ("exactly one page", 10, 0, 10, false),
+        ("one more record", 10, 0, 11, true),
+        ("last partial page", 10, 20, 25, false),
+        ("offset past the end", 10, 30, 25, false),
+        ("full page before the last", 10, 10, 25, true),
+    ];
+    for (case, limit, offset, total, has_more) in counted {
+        let meta = ListResponseMeta::from_counts(limit, offset, total);
+        assert_eq!(meta.has_more, has_more, "{case}");
+        assert_eq!(meta.total_records, total, "{case}");
+    }
+
+    // limit, offset, page_len
+    let uncounted = [
+        ("empty result", 10, 0, 0, false, 0),
+        ("exactly one page", 10, 0, 10, true, 10),
+        ("last partial page", 10, 20, 5, false, 25),
+        ("zero limit", 0, 0, 0, false, 0),
+    ];
+    for (case, limit, offset, page_len, has_more, total) in uncounted {
+        let meta = ListResponseMeta::unknown_total(limit, offset, page_len);
+        assert_eq!(meta.has_more, has_more, "{case}");
+        assert_eq!(meta.total_records, total, "{case}");
+        assert!(!meta.total_is_exact, "{case}");
+    }
+
+    let meta = ListResponseMeta::from_optional_total(10, 0, None, 3);
+    assert!(!meta.total_is_exact);
+    let meta = ListResponseMeta::from_optional_total(10, 0, Some(3), 3);
+    assert!(meta.total_is_exact);
+}
+
+#[test]
+fn test_total_is_exact_by_default() {
+    let meta: ListResponseMeta = serde_json::from_str(
+        r#"{"page":0,"limit":10,"offset":0,"has_more":false,"total_records":3}"#,
+    )
+    .unwrap();
+    assert!(meta.total_is_exact);
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct ListResult<T: Serialize> {
     pub meta: Option<ListResponseMeta>,
     pub records: Vec<T>,
@@ -102,37 +283,46 @@ impl<T: Serialize> From<Vec<T>> for ListResult<T> {
     }
 }
 
+/// utoipa doesn't expand `#[serde(flatten)]` in `IntoParams`, so queries which flatten it
+/// skip the field with `param(ignore)` and handlers list `PageParams` next to them.
 #[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
 pub struct PageParams {
     #[serde(default)]
     pub order: OrderBy,
+    /// Page size, 50 by default and 1000 at most.
     #[serde(default, deserialize_with = "number_or_string")]
     pub limit: Option<u32>,
+    /// Has priority over the `page`.
     #[serde(default, deserialize_with = "number_or_string")]
     pub offset: Option<u32>,
     #[serde(default, deserialize_with = "number_or_string")]
     pub page: Option<u32>,
 }
 
-fn number_or_string<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+/// Numbers of the query string come as strings when the params are flattened into another struct.
+pub fn number_or_string<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
 where
     D: serde::Deserializer<'de>,
+    T: Deserialize<'de> + std::str::FromStr,
+    T::Err: std::fmt::Display,
 {
     #[derive(Deserialize, Debug)]
     #[serde(untagged)]
-    pub enum Value {
-        Numeric(u32),
+    pub enum Value<T> {
+        Numeric(T),
         Textual(String),
     }
 
-    let val = Option::<Value>::deserialize(deserializer)?;
+    let val = Option::<Value<T>>::deserialize(deserializer)?;
     let Some(v) = val else {
         return Ok(None);
     };
 
     match v {
         Value::Numeric(n) => Ok(Some(n)),
-        Value::Textual(s) => Ok(Some(s.parse::<u32>().map_err(serde::de::Error::custom)?)),
+        Value::Textual(s) => Ok(Some(s.parse::<T>().map_err(serde::de::Error::custom)?)),
     }
 }
 
@@ -175,3 +365,22 @@ impl PageParams {
         Ok((limit, page * limit))
     }
 }
+
+#[test]
+fn test_sql_order() {
+    assert_eq!(OrderBy::Asc.sql().to_string(), "ASC");
+    assert_eq!(OrderBy::Desc.sql().to_string(), "DESC");
+    assert_eq!(OrderBy::Desc.sql().reverse(), SqlOrder::ASC);
+    assert_eq!(SqlOrder::ASC.after_op(), ">");
+    assert_eq!(SqlOrder::DESC.after_op(), "<");
+
+    let order = SortOrder::new(OrderBy::Desc, SqlOrder::ASC);
+    assert_eq!(
+        (order.primary, order.secondary),
+        (SqlOrder::DESC, SqlOrder::ASC)
+    );
+    assert_eq!(
+        SortOrder::uniform(OrderBy::Asc),
+        SortOrder::new(SqlOrder::ASC, SqlOrder::ASC)
+    );
+}
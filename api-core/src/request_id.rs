@@ -0,0 +1,108 @@
+use std::sync::Arc;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpMessage};
+use sentry::SentryFutureExt;
+use tracing::Instrument;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Max length of a request id accepted from a client.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Id of the current request, handlers can take it with `ReqData<RequestId>`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RequestId(pub String);
+
+/// Takes the request id from the `x-request-id` header or generates a new one
+/// and returns it in the response header.
+///
+/// The request is handled within the `request` span with `request_id`, `path`
+/// and `api_key` fields, so every log line of the request carries them.
+/// `api_key` is recorded by the auth middleware. The request id is set as a sentry tag.
+pub async fn request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let request_id = req
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| is_valid(v))
+        .map(String::from)
+        .unwrap_or_else(|| ulid::Ulid::new().to_string());
+
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        path = %req.path(),
+        api_key = tracing::field::Empty,
+    );
+    let hub = Arc::new(sentry::Hub::new_from_top(sentry::Hub::current()));
+    hub.configure_scope(|scope| scope.set_tag("request_id", &request_id));
+
+    req.extensions_mut().insert(RequestId(request_id.clone()));
+    let mut res = next.call(req).instrument(span).bind_hub(hub).await?;
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        res.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    Ok(res)
+}
+
+/// Client ids are logged as is, so only short printable ones are accepted.
+fn is_valid(request_id: &str) -> bool {
+    !request_id.is_empty()
+        && request_id.len() <= MAX_REQUEST_ID_LEN
+        && request_id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::middleware::from_fn;
+    use actix_web::web::ReqData;
+    use actix_web::{web, App, HttpResponse};
+
+    use super::*;
+
+    #[test]
+    fn test_is_valid() {
+        assert!(is_valid("01J9Z3K5N8Q2W4E6R8T0Y2U4I6"));
+        assert!(is_valid("req-1"));
+        assert!(!is_valid(""));
+        assert!(!is_valid("req 1"));
+        assert!(!is_valid("req\n1"));
+        assert!(!is_valid(&"a".repeat(MAX_REQUEST_ID_LEN + 1)));
+    }
+
+    #[actix_web::test]
+    async fn test_request_id() {
+        use actix_web::test;
+
+        let app = test::init_service(App::new().wrap(from_fn(request_id)).route(
+            "/",
+            web::get().to(|id: ReqData<RequestId>| async move {
+                HttpResponse::Ok().body(id.into_inner().0)
+            }),
+        ))
+        .await;
+
+        let req = test::TestRequest::get()
+            .insert_header((REQUEST_ID_HEADER, "req-1"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.headers().get(REQUEST_ID_HEADER).unwrap(), "req-1");
+        assert_eq!(test::read_body(res).await, "req-1");
+
+        let req = test::TestRequest::get()
+            .insert_header((REQUEST_ID_HEADER, "req 1"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        let id = res.headers().get(REQUEST_ID_HEADER).unwrap().clone();
+        assert_ne!(id, "req 1");
+        assert_eq!(test::read_body(res).await, id.as_bytes());
+    }
+}
@@ -100,3 +100,51 @@ pub mod bigdecimal_plain_str {
         deserializer.deserialize_str(BigDecimalVisitor)
     }
 }
+
+/// Optional [`bigdecimal_plain_str`], missing values need `#[serde(default)]`.
+pub mod bigdecimal_plain_str_opt {
+    use bigdecimal::BigDecimal;
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S>(value: &Option<BigDecimal>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match value {
+            Some(value) => super::bigdecimal_plain_str::serialize(value, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<BigDecimal>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        super::bigdecimal_plain_str::deserialize(deserializer).map(Some)
+    }
+}
+
+/// List of strings passed as one comma separated value, e.g. in a query string.
+pub mod comma_separated {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(values: &[String], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&values.join(","))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+        Ok(value
+            .split(',')
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(String::from)
+            .collect())
+    }
+}
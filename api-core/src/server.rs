@@ -13,8 +13,9 @@ use tokio::select;
 use tokio_util::sync::CancellationToken;
 
 use crate::api_errors::ApiError;
+use crate::request_id::request_id;
 
-#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Config {
     #[serde(default = "defaults::listen_address")]
     pub listen_address: String,
@@ -26,6 +27,41 @@ pub struct Config {
     pub cors_domain: String,
     #[serde(default)]
     pub allowed_headers: Vec<String>,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    /// Compress responses with gzip/brotli/zstd, depending on `Accept-Encoding`.
+    #[serde(default = "defaults::enable_compression")]
+    pub enable_compression: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen_address: defaults::listen_address(),
+            port: defaults::api_port(),
+            enable_cors: false,
+            cors_domain: String::new(),
+            allowed_headers: Vec::new(),
+            rate_limit: RateLimitConfig::default(),
+            enable_compression: defaults::enable_compression(),
+        }
+    }
+}
+
+/// Token bucket limits applied per api key (or per client ip, if there is no key).
+#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+pub struct RateLimitConfig {
+    #[serde(default)]
+    pub enable: bool,
+    /// Refill rate of the bucket.
+    #[serde(default = "defaults::rate_limit_per_minute")]
+    pub requests_per_minute: u32,
+    /// Capacity of the bucket, max number of requests that can be done at once.
+    #[serde(default = "defaults::rate_limit_burst")]
+    pub burst: u32,
+    /// `X-Forwarded-For` is taken into account only for requests from these addresses.
+    #[serde(default)]
+    pub trusted_proxies: Vec<std::net::IpAddr>,
 }
 
 #[derive(Default, Serialize, Deserialize, Clone, Debug)]
@@ -49,6 +85,18 @@ mod defaults {
         3000
     }
 
+    pub fn enable_compression() -> bool {
+        true
+    }
+
+    pub fn rate_limit_per_minute() -> u32 {
+        600
+    }
+
+    pub fn rate_limit_burst() -> u32 {
+        60
+    }
+
     pub fn metrics_port() -> u16 {
         9140
     }
@@ -98,6 +146,8 @@ where
             )
             .wrap(middleware::Logger::default())
             .wrap(Condition::new(enable_metrics, metrics_middleware.clone()))
+            // wraps the metrics middleware, so the response sizes are recorded before compression
+            .wrap(compression(&config))
             .wrap(Condition::new(
                 config.enable_cors,
                 cors(&config.cors_domain, &config.allowed_headers),
@@ -115,6 +165,8 @@ where
                     }
                 }
             })
+            // the outermost, so all the middlewares log within the request span
+            .wrap(middleware::from_fn(request_id))
             .service(api_service.service())
             .default_service(web::to(not_found))
     })
@@ -137,6 +189,12 @@ async fn not_found() -> HttpResponse {
     crate::api_errors::not_found().into()
 }
 
+/// Responses that already have `Content-Encoding` (e.g. `identity` for SSE streams)
+/// are left as is.
+pub fn compression(config: &Config) -> Condition<middleware::Compress> {
+    Condition::new(config.enable_compression, middleware::Compress::default())
+}
+
 pub fn cors(cors_domain: &str, allowed_headers: &[String]) -> Cors {
     let mut headers = vec![
         http::header::AUTHORIZATION,
@@ -211,3 +269,88 @@ pub async fn run_metrics_server(
 async fn default_service() -> HttpResponse {
     HttpResponse::Forbidden().finish()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use actix_web::http::header::{ACCEPT_ENCODING, CONTENT_ENCODING};
+    use actix_web::test;
+
+    use super::*;
+
+    fn items() -> Vec<String> {
+        (0..1000).map(|i| format!("item-{i}")).collect()
+    }
+
+    #[actix_web::test]
+    async fn test_compression() {
+        let app = test::init_service(
+            App::new()
+                .wrap(compression(&Config::default()))
+                .route(
+                    "/list",
+                    web::get().to(|| async { HttpResponse::Ok().json(items()) }),
+                )
+                .route(
+                    "/stream",
+                    web::get().to(|| async {
+                        HttpResponse::Ok()
+                            .insert_header((CONTENT_ENCODING, "identity"))
+                            .body(items().join("\n"))
+                    }),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/list").to_request();
+        let res = test::call_service(&app, req).await;
+        assert!(res.headers().get(CONTENT_ENCODING).is_none());
+        let plain = test::read_body(res).await;
+
+        let req = test::TestRequest::get()
+            .uri("/list")
+            .insert_header((ACCEPT_ENCODING, "gzip"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.headers().get(CONTENT_ENCODING).unwrap(), "gzip");
+        let compressed = test::read_body(res).await;
+        assert!(compressed.len() < plain.len());
+
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(compressed.as_ref())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        let decoded: serde_json::Value = serde_json::from_slice(&decoded).unwrap();
+        let plain: serde_json::Value = serde_json::from_slice(&plain).unwrap();
+        assert_eq!(decoded, plain);
+
+        let req = test::TestRequest::get()
+            .uri("/stream")
+            .insert_header((ACCEPT_ENCODING, "gzip"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.headers().get(CONTENT_ENCODING).unwrap(), "identity");
+        assert_eq!(test::read_body(res).await, items().join("\n"));
+    }
+
+    #[actix_web::test]
+    async fn test_compression_disabled() {
+        let config = Config {
+            enable_compression: false,
+            ..Default::default()
+        };
+        let app = test::init_service(App::new().wrap(compression(&config)).route(
+            "/list",
+            web::get().to(|| async { HttpResponse::Ok().json(items()) }),
+        ))
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/list")
+            .insert_header((ACCEPT_ENCODING, "gzip"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert!(res.headers().get(CONTENT_ENCODING).is_none());
+    }
+}
@@ -4,7 +4,7 @@ use std::str::FromStr;
 use actix_web::http::StatusCode;
 use actix_web::{HttpResponse, ResponseError};
 use api_core::api_errors::*;
-use api_core::pages::PageParams;
+use api_core::pages::{number_or_string, PageParams};
 use api_core::serde_utils::bytevec_as_hex;
 use bitcoin::script::Builder;
 use bitcoin::{Amount, OutPoint, ScriptBuf, Sequence, TxIn, TxOut, Txid, Witness};
@@ -13,17 +13,128 @@ use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
 
 use super::types::Hash;
-use super::UtxoSortMode;
+use super::{AmountRange, UtxoSortMode};
 
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[cfg_attr(feature = "sqlx", derive(FromRow))]
 pub struct Balance {
     pub address: String,
     pub balance: i64,
     pub utxo_count: i64,
+    /// `false` if the address has never received an output, `true` if it did,
+    /// even if everything is spent. Set only for single address requests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "sqlx", sqlx(default))]
+    pub exists: Option<bool>,
+    /// Sum of the mempool outputs paying to the address.
+    /// Present only when requested with `include_mempool=true`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "sqlx", sqlx(default))]
+    pub pending_in: Option<i64>,
+    /// Sum of the address outputs spent by the mempool txs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "sqlx", sqlx(default))]
+    pub pending_out: Option<i64>,
+    /// `balance + pending_in - pending_out`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "sqlx", sqlx(default))]
+    pub pending_balance: Option<i64>,
+    /// Part of the `balance` that can be collected for a plain btc transfer.
+    /// Present only when requested with `detailed=true`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "sqlx", sqlx(default))]
+    pub spendable_balance: Option<i64>,
+    /// The rest of the `balance`, `spendable_balance` + the breakdown is the `balance`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "sqlx", sqlx(skip))]
+    pub unspendable_breakdown: Option<UnspendableBalance>,
+    /// Labels of the address, present only when requested with `include_labels=true`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "sqlx", sqlx(default))]
+    pub labels: Option<Vec<String>>,
+}
+
+/// Amounts of the utxos that can't be spent, each utxo is counted once
+/// in the first matching field.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UnspendableBalance {
+    /// Coinbase outputs with less than 100 confirmations.
+    pub immature: i64,
+    pub with_runes: i64,
+    pub with_inscriptions: i64,
+    /// Spendable otherwise, but locked by the collect requests.
+    /// Always 0 if the cache is disabled.
+    pub locked: i64,
 }
 
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct BalanceQuery {
+    /// Add pending amounts from the mempool txs.
+    #[serde(default)]
+    pub include_mempool: bool,
+    /// Add `spendable_balance` and `unspendable_breakdown`.
+    #[serde(default)]
+    pub detailed: bool,
+    /// Add `labels` of the address, requires the `labels` scope.
+    #[serde(default)]
+    pub include_labels: bool,
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct AddressSummaryQuery {
+    /// Add `labels` of the address, requires the `labels` scope.
+    #[serde(default)]
+    pub include_labels: bool,
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct BalancesRequest {
+    pub addresses: Vec<String>,
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AddressError {
+    pub address: String,
+    pub error: String,
+}
+
+/// Btc and runes holdings of the address with its recent txs.
+/// Parts that failed to load are null and described in `errors`.
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AddressSummary {
+    pub address: String,
+    pub balance: Option<Balance>,
+    pub runes: Option<Vec<crate::RuneBalance>>,
+    /// Up to 5 most recent confirmed txs.
+    pub recent_txs: Option<Vec<TxInfo>>,
+    /// Last block indexed by the bitcoin indexer.
+    pub indexer_height: Option<u64>,
+    /// Present only when requested with `include_labels=true`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub labels: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct BalancesResponse {
+    pub balances: Vec<Balance>,
+    /// Addresses that were rejected, they are not included into the `balances`.
+    pub errors: Vec<AddressError>,
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[cfg_attr(feature = "sqlx", derive(FromRow))]
 pub struct BtcBalanceHistoryPoint {
     pub block: i64,
@@ -47,6 +158,7 @@ pub struct Input {
 }
 
 #[derive(Default, Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[cfg_attr(feature = "sqlx", derive(FromRow))]
 pub struct InputFull {
     pub id: i64,
@@ -60,6 +172,7 @@ pub struct InputFull {
     pub parent_tx_id: i32,
     pub address: String,
     #[serde(with = "bytevec_as_hex")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub pk_script: Vec<u8>,
     pub amount: i64,
     pub coinbase: bool,
@@ -76,6 +189,7 @@ pub struct InputsSum {
 }
 
 #[derive(Default, Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[cfg_attr(feature = "sqlx", derive(FromRow))]
 pub struct BtcOutput {
     pub id: i64,
@@ -85,12 +199,53 @@ pub struct BtcOutput {
     pub vout: i32,
     pub address: String,
     #[serde(with = "bytevec_as_hex")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub pk_script: Vec<u8>,
     pub amount: i64,
     pub coinbase: bool,
     pub spend: bool,
 }
 
+/// Output of the address with its spending state.
+#[derive(Default, Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+pub struct BtcOutputHistory {
+    pub id: i64,
+    pub block: i64,
+    pub tx_id: i32,
+    pub tx_hash: Hash,
+    pub vout: i32,
+    pub address: String,
+    #[serde(with = "bytevec_as_hex")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub pk_script: Vec<u8>,
+    pub amount: i64,
+    pub coinbase: bool,
+    pub spent: bool,
+    /// Tx that spends the output.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spent_in_tx: Option<Hash>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spent_block: Option<i64>,
+}
+
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct OutputsQuery {
+    #[serde(flatten)]
+    #[cfg_attr(feature = "openapi", param(ignore))]
+    pub page: PageParams,
+    /// Lists spent outputs as well, only unspent ones by default.
+    #[serde(default)]
+    pub include_spent: bool,
+    /// Lists outputs of this and the following blocks. With `include_spent` it must be above
+    /// the pruned height, otherwise 410 is returned.
+    #[serde(default)]
+    pub min_height: Option<u64>,
+}
+
 #[derive(Default, Clone, Debug, Deserialize, Serialize)]
 #[cfg_attr(feature = "sqlx", derive(FromRow))]
 pub struct BtcOutputsSum {
@@ -102,6 +257,7 @@ pub struct BtcOutputsSum {
 }
 
 #[derive(Default, Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[cfg_attr(feature = "sqlx", derive(FromRow))]
 pub struct BtcUtxo {
     pub id: i64,
@@ -111,13 +267,43 @@ pub struct BtcUtxo {
     pub vout: i32,
     pub address: String,
     #[serde(with = "bytevec_as_hex")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub pk_script: Vec<u8>,
     pub amount: i64,
-    /// DEPRECATED
+    /// DEPRECATED: utxos are never spent, it's always false and isn't serialized anymore.
+    /// Use `GET /outputs/{address}?include_spent=true` to get spent outputs.
+    #[serde(default, skip_serializing)]
     pub spend: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "sqlx", sqlx(default))]
+    pub coinbase: Option<bool>,
+    /// Set by the api from the cached chain tip, the utxo's block is the first confirmation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "sqlx", sqlx(default))]
+    pub confirmations: Option<i64>,
+}
+
+#[derive(Default, Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+pub struct UtxoStats {
+    pub address: String,
+    pub count: i64,
+    pub total_amount: i64,
+    pub min_amount: i64,
+    pub max_amount: i64,
+    pub avg_amount: i64,
+    /// Coinbase outputs with less than 100 confirmations.
+    pub immature_count: i64,
+    pub runes_count: i64,
+    pub inscriptions_count: i64,
 }
 
 impl BtcUtxo {
+    pub fn set_confirmations(&mut self, tip: u64) {
+        self.confirmations = Some(crate::confirmations(self.block, tip));
+    }
+
     pub fn out_point(&self) -> OutPoint {
         OutPoint {
             txid: (&self.tx_hash).into(),
@@ -146,42 +332,151 @@ impl BtcUtxo {
 }
 
 #[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
 pub struct UtxoQuery {
     #[serde(flatten)]
+    #[cfg_attr(feature = "openapi", param(ignore))]
     pub page: PageParams,
     #[serde(default)]
     pub sorting: UtxoSortMode,
+    /// Only utxos with amount greater than it, use `min_amount` instead.
+    #[serde(default, deserialize_with = "number_or_string")]
+    #[deprecated = "use `min_amount` instead"]
     pub amount_threshold: Option<u64>,
+    /// Min amount of the utxo, inclusive.
+    #[serde(default, deserialize_with = "number_or_string")]
+    pub min_amount: Option<u64>,
+    /// Max amount of the utxo, inclusive.
+    #[serde(default, deserialize_with = "number_or_string")]
+    pub max_amount: Option<u64>,
     #[serde(default)]
     pub skip_premature: bool,
     #[serde(default)]
     pub no_runes: bool,
+    /// Value of the `meta.next_cursor` from the previous page.
+    /// Can't be used together with `offset` or `page`.
+    pub cursor: Option<String>,
+}
+
+impl UtxoQuery {
+    #[allow(deprecated)]
+    pub fn amount_range(&self) -> anyhow::Result<AmountRange> {
+        AmountRange::from_query(self.amount_threshold, self.min_amount, self.max_amount)
+    }
 }
 
 #[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct CollectUtxo {
     pub amount: u64,
     pub request_id: String,
 }
 
 #[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UtxoRef {
+    pub tx_hash: Hash,
+    pub vout: i32,
+}
+
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UnlockUtxoRequest {
+    pub request_id: String,
+    pub outpoints: Vec<UtxoRef>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum UnlockStatus {
+    Released,
+    NotFound,
+    OwnedByOther,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UnlockResult {
+    pub tx_hash: Hash,
+    pub vout: i32,
+    pub status: UnlockStatus,
+}
+
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct ConsolidationQuery {
+    /// Max number of utxos the address should have after the consolidation.
+    pub target_count: u32,
+    /// Fee rate of the consolidation tx in sat/vB.
+    pub fee_rate: f64,
+}
+
+/// Advisory plan of a tx that spends the smallest utxos of an address into one output.
+/// Utxos with runes or inscriptions are never spent.
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ConsolidationPlan {
+    /// Utxos of the address, including the ones that can't be consolidated.
+    pub utxo_count: u64,
+    /// Utxos of the address after the consolidation.
+    pub resulting_count: u64,
+    /// Utxos to spend, empty if the address already has `target_count` utxos or less.
+    pub inputs: Vec<UtxoRef>,
+    pub input_amount: u64,
+    /// Estimated virtual size of the consolidation tx.
+    pub vsize: u64,
+    pub fee_rate: f64,
+    pub fee: u64,
+    /// Value of the single output, `input_amount - fee`.
+    pub output_value: u64,
+}
+
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UtxoLock {
+    pub tx_hash: Hash,
+    pub vout: i32,
+    pub amount: i64,
+    /// Id of the request that holds the lock.
+    pub request_id: String,
+    /// Remaining lock lifetime in seconds.
+    pub ttl: i64,
+}
+
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct TxInOuts {
-    // TODO: add this fields
-    // pub heigh: u64,
-    // pub block_hash: String,
-    // pub blocktime: u64,
+    // TODO: add this field
     // pub raw_tx: Option<String>,
-    // pub network_fee: u64,
+    #[serde(default)]
+    pub height: Option<u64>,
+    #[serde(default)]
+    pub block_hash: Option<String>,
+    #[serde(default)]
+    pub blocktime: Option<u64>,
+    #[serde(default)]
+    pub confirmations: Option<u64>,
+    /// Not set for coinbase txs.
+    #[serde(default)]
+    pub network_fee: Option<u64>,
     pub inputs: Vec<InputFull>,
     pub outputs: Vec<BtcOutput>,
 }
 
 #[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
 pub struct ListTxQuery {
     #[serde(flatten)]
+    #[cfg_attr(feature = "openapi", param(ignore))]
     pub page: PageParams,
     #[serde(default)]
     pub min_height: Option<u64>,
+    /// Includes pending txs, they go before the confirmed ones in `desc` order
+    /// and after them in `asc`.
     #[serde(default)]
     pub mempool: bool,
     #[serde(default)]
@@ -189,15 +484,18 @@ pub struct ListTxQuery {
 }
 
 #[derive(Default, Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[cfg_attr(feature = "sqlx", derive(FromRow))]
 pub struct TxInfo {
     pub tx_hash: Hash,
+    /// -1 for mempool txs.
     pub block: i64,
     pub income: bool,
     pub spend: bool,
 }
 
 #[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct RawTxInfo {
     pub in_active_chain: Option<bool>,
     pub confirmations: Option<u32>,
@@ -209,13 +507,30 @@ pub struct RawTxInfo {
     pub blockheight: Option<usize>,
     /// a position of a transaction in a block
     pub txnumber: Option<usize>,
+    /// Empty when the tx is reconstructed from the index.
     pub raw_tx: String,
+    #[serde(default)]
+    pub source: TxSource,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum TxSource {
+    /// Full tx returned by the btc node.
+    #[default]
+    Node,
+    /// The node doesn't have the tx (e.g. it's pruned),
+    /// only block position is known from the indexed outputs and inputs.
+    Index,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct RpcError {
     pub code: i32,
     pub message: String,
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<Object>))]
     pub data: Option<Box<serde_json::value::RawValue>>,
 }
 
@@ -235,24 +550,96 @@ impl PartialEq for RpcError {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PsbtOutput {
+    pub address: String,
+    pub amount: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct BuildPsbtRequest {
+    /// Inputs are collected from this address, the change goes back to it.
+    pub from_address: String,
+    pub outputs: Vec<PsbtOutput>,
+    /// Fee rate in sat/vB.
+    pub fee_rate: f64,
+    /// Selected utxos are locked with this id.
+    pub request_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct BuildPsbtResponse {
+    /// Base64 encoded unsigned psbt.
+    pub psbt: String,
+    pub inputs: Vec<BtcUtxo>,
+    /// Estimated virtual size of the signed tx.
+    pub vsize: u64,
+    pub fee: u64,
+    /// Value of the change output, 0 if the tx has no change.
+    pub change: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct SendTxRequest {
     pub tx: String,
 }
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct SendTxQuery {
+    /// Mark inputs of the tx as spent in the mempool cache before the response,
+    /// so they are excluded from the utxo listings right away.
+    #[serde(default)]
+    pub sync_cache: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct TxHash {
     pub tx_hash: String,
 }
 
 pub type SendTxResponse = Response<TxHash, RpcError>;
 
+/// Result of the mempool acceptance check of a single transaction.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct TxAcceptance {
+    pub tx_hash: String,
+    pub allowed: bool,
+    pub reject_reason: Option<String>,
+    /// Present only if the tx is allowed.
+    pub vsize: Option<u64>,
+    /// Fee in sats, present only if the tx is allowed.
+    pub fee: Option<u64>,
+}
+
+pub type TestTxResponse = Response<Vec<TxAcceptance>, RpcError>;
+
 /// This allows us to return error as a value, not as "api error".
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct Response<T, E> {
     pub result: Option<T>,
     pub error: Option<E>,
 }
 
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AddressMempoolTx {
+    pub tx_hash: Hash,
+    /// The tx pays to the address.
+    pub incoming: bool,
+    /// The tx spends outputs of the address.
+    pub outgoing: bool,
+    /// Unix timestamp when the tx was noticed by the indexer.
+    pub first_seen: i64,
+}
+
 pub type GetMempoolTxsResponse = Response<Vec<Txid>, RpcError>;
 
 pub type GetTxResponse = Response<RawTxInfo, RpcError>;
@@ -268,8 +655,16 @@ pub enum FBtcApiError {
     #[error("bad input: {0}")]
     BadInput(String),
 
-    #[error("not enough balance: required={required}, available={available}")]
-    NotEnoughBalance { required: u128, available: u128 },
+    /// `available` is the confirmed balance of the address,
+    /// `collectable` is the part of it that can be spent by the request.
+    #[error(
+        "not enough balance: required={required}, available={available}, collectable={collectable}"
+    )]
+    NotEnoughBalance {
+        required: u128,
+        available: u128,
+        collectable: u128,
+    },
 
     // TODO(Bohdan): we probably want to pass these args to caller so that this can be shown to end user.
     #[error("Top {max} biggest UTXOs are not enough to collect {target} amount (collected={collected}). Total UTXOs={total_utxos}")]
@@ -286,11 +681,21 @@ pub enum FBtcApiError {
     #[error("not found")]
     NotFound,
 
+    #[error("request took too long; narrow it down or retry later")]
+    Timeout,
+
     #[error("unauthorized")]
     Unauthorized,
 
     #[error("forbidden; api-key is blocked")]
     Forbidden,
+
+    #[error("too many requests; retry after {0}s")]
+    TooManyRequests(u64),
+
+    /// Outputs and inputs at or below `pruned_height` may be deleted by `db prune`.
+    #[error("history at or below block {pruned_height} is pruned; query heights above it")]
+    HistoryPruned { pruned_height: i64 },
 }
 
 impl TryFrom<&ApiError> for FBtcApiError {
@@ -303,9 +708,24 @@ impl TryFrom<&ApiError> for FBtcApiError {
             ApiErrorCode::AccessDenied => Unauthorized,
             ApiErrorCode::Forbidden => Forbidden,
             ApiErrorCode::ServiceUnavailable => ServiceUnavailable,
+            ApiErrorCode::Timeout => Timeout,
+            ApiErrorCode::TooManyRequests => TooManyRequests(
+                error
+                    .details
+                    .get("retry_after")
+                    .and_then(|v| u64::from_str(v).ok())
+                    .unwrap_or_default(),
+            ),
             ApiErrorCode::InvalidAddress => InvalidAddress(error.message.clone()),
             ApiErrorCode::BadInput => BadInput(error.message.clone()),
             ApiErrorCode::NotFound => NotFound,
+            ApiErrorCode::HistoryPruned => HistoryPruned {
+                pruned_height: error
+                    .details
+                    .get("pruned_height")
+                    .and_then(|v| i64::from_str(v).ok())
+                    .unwrap_or_default(),
+            },
             ApiErrorCode::NeedMoreUtxos => NeedMoreUtxos {
                 max: 0,
                 total_utxos: 0,
@@ -323,10 +743,16 @@ impl TryFrom<&ApiError> for FBtcApiError {
                     .get("available")
                     .and_then(|v| u128::from_str(v).ok())
                     .unwrap_or_default();
+                let collectable = error
+                    .details
+                    .get("collectable")
+                    .and_then(|v| u128::from_str(v).ok())
+                    .unwrap_or_default();
 
                 NotEnoughBalance {
                     required,
                     available,
+                    collectable,
                 }
             }
         })
@@ -342,15 +768,26 @@ impl From<&FBtcApiError> for ApiError {
             Forbidden => ApiErrorCode::Forbidden,
             InternalError => ApiErrorCode::InternalError,
             ServiceUnavailable => ApiErrorCode::ServiceUnavailable,
+            Timeout => ApiErrorCode::Timeout,
             InvalidAddress(_) => ApiErrorCode::InvalidAddress,
             BadInput(_) => ApiErrorCode::BadInput,
             NotFound => ApiErrorCode::NotFound,
+            TooManyRequests(retry_after) => {
+                details.insert("retry_after".into(), retry_after.to_string());
+                ApiErrorCode::TooManyRequests
+            }
+            HistoryPruned { pruned_height } => {
+                details.insert("pruned_height".into(), pruned_height.to_string());
+                ApiErrorCode::HistoryPruned
+            }
             NotEnoughBalance {
                 required,
                 available,
+                collectable,
             } => {
                 details.insert("required".into(), required.to_string());
                 details.insert("available".into(), available.to_string());
+                details.insert("collectable".into(), collectable.to_string());
                 ApiErrorCode::NotEnoughBalance
             }
             NeedMoreUtxos {
@@ -384,9 +821,12 @@ impl ResponseError for FBtcApiError {
             Forbidden => StatusCode::FORBIDDEN,
             InternalError => StatusCode::INTERNAL_SERVER_ERROR,
             ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            Timeout => StatusCode::GATEWAY_TIMEOUT,
             InvalidAddress(_) => StatusCode::BAD_REQUEST,
             BadInput(_) => StatusCode::BAD_REQUEST,
             NotFound => StatusCode::NOT_FOUND,
+            TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            HistoryPruned { .. } => StatusCode::GONE,
             NeedMoreUtxos { .. } | NotEnoughBalance { .. } => StatusCode::BAD_REQUEST,
         }
     }
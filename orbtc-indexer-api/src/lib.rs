@@ -1,3 +1,5 @@
+use api_core::pages::PageParams;
+use bigdecimal::BigDecimal;
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "sqlx")]
 use sqlx::prelude::FromRow;
@@ -12,6 +14,7 @@ pub use runes::*;
 pub use types::{Amount, Hash};
 
 #[derive(Default, Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub enum UtxoSortMode {
     #[serde(rename = "age", alias = "AGE")]
     Age,
@@ -19,6 +22,10 @@ pub enum UtxoSortMode {
     #[default]
     #[serde(rename = "amount", alias = "AMOUNT")]
     Amount,
+
+    /// Smallest outputs first, regardless of the requested order.
+    #[serde(rename = "dust_first", alias = "DUST_FIRST")]
+    DustFirst,
 }
 
 impl std::str::FromStr for UtxoSortMode {
@@ -27,8 +34,9 @@ impl std::str::FromStr for UtxoSortMode {
         match s.to_ascii_lowercase().as_str() {
             "amount" => Ok(Self::Amount),
             "age" => Ok(Self::Age),
+            "dust_first" => Ok(Self::DustFirst),
             _ => Err(anyhow::anyhow!(
-                "invalid utxo_sort_mode: possible values are `age` or `amount`"
+                "invalid utxo_sort_mode: possible values are `age`, `amount` or `dust_first`"
             )),
         }
     }
@@ -39,20 +47,270 @@ impl std::fmt::Display for UtxoSortMode {
         match self {
             Self::Age => write!(f, "age"),
             Self::Amount => write!(f, "amount"),
+            Self::DustFirst => write!(f, "dust_first"),
         }
     }
 }
 
+/// Inclusive bounds of the utxo amount, an unset bound isn't checked.
+/// Btc amounts are sats, rune amounts are big integers of the rune units.
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AmountRange<T = u64> {
+    pub min: Option<T>,
+    pub max: Option<T>,
+}
+
+/// Amount of the [`AmountRange`].
+pub trait RangeAmount: Sized + PartialOrd + std::fmt::Display {
+    /// The smallest amount greater than `self`.
+    fn next_up(&self) -> Self;
+
+    /// Checks the amount passed by the user as `name`.
+    fn validate(&self, _name: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+impl RangeAmount for u64 {
+    fn next_up(&self) -> Self {
+        self.saturating_add(1)
+    }
+}
+
+impl RangeAmount for BigDecimal {
+    fn next_up(&self) -> Self {
+        self + BigDecimal::from(1)
+    }
+
+    /// Rune amounts are passed in the rune units, so they can't be fractional.
+    fn validate(&self, name: &str) -> anyhow::Result<()> {
+        if !self.is_integer() || *self < BigDecimal::from(0) {
+            anyhow::bail!("{name}({self}) must be a non-negative integer");
+        }
+        Ok(())
+    }
+}
+
+impl<T: RangeAmount> AmountRange<T> {
+    /// Only amounts greater than the `threshold`.
+    pub fn above(threshold: Option<T>) -> Self {
+        Self {
+            min: threshold.map(|t| t.next_up()),
+            max: None,
+        }
+    }
+
+    /// Bounds of the utxo query. The deprecated exclusive `amount_threshold`
+    /// can't be combined with the inclusive `min_amount` and `max_amount`.
+    pub fn from_query(
+        amount_threshold: Option<T>,
+        min_amount: Option<T>,
+        max_amount: Option<T>,
+    ) -> anyhow::Result<Self> {
+        if amount_threshold.is_some() && (min_amount.is_some() || max_amount.is_some()) {
+            anyhow::bail!("amount_threshold can't be used together with min_amount or max_amount");
+        }
+        let named = [
+            ("amount_threshold", &amount_threshold),
+            ("min_amount", &min_amount),
+            ("max_amount", &max_amount),
+        ];
+        for (name, value) in named {
+            if let Some(value) = value {
+                value.validate(name)?;
+            }
+        }
+        if let (Some(min), Some(max)) = (&min_amount, &max_amount) {
+            if min > max {
+                anyhow::bail!("min_amount({min}) must not be greater than max_amount({max})");
+            }
+        }
+        if amount_threshold.is_some() {
+            return Ok(Self::above(amount_threshold));
+        }
+        Ok(Self {
+            min: min_amount,
+            max: max_amount,
+        })
+    }
+
+    pub fn map<U>(self, f: impl Fn(T) -> U) -> AmountRange<U> {
+        AmountRange {
+            min: self.min.map(&f),
+            max: self.max.map(&f),
+        }
+    }
+}
+
+/// Bucket size of the balance history points.
+#[derive(Default, Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum HistoryGranularity {
+    #[default]
+    #[serde(rename = "block", alias = "BLOCK")]
+    Block,
+
+    /// Approximated by 144 blocks.
+    #[serde(rename = "daily", alias = "DAILY")]
+    Daily,
+
+    /// Approximated by 1008 blocks.
+    #[serde(rename = "weekly", alias = "WEEKLY")]
+    Weekly,
+}
+
+impl HistoryGranularity {
+    pub fn blocks(&self) -> i64 {
+        match self {
+            Self::Block => 1,
+            Self::Daily => 144,
+            Self::Weekly => 144 * 7,
+        }
+    }
+
+    /// First block of the bucket that contains the `block`.
+    pub fn bucket(&self, block: i64) -> i64 {
+        block - block % self.blocks()
+    }
+}
+
+/// History is always ordered by block ascending, pages are counted in buckets.
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct BalanceHistoryQuery {
+    pub from_block: Option<u64>,
+    pub to_block: Option<u64>,
+    #[serde(default)]
+    pub granularity: HistoryGranularity,
+    // not a flattened `PageParams`, numbers of the query string can't be deserialized through it
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+    pub page: Option<u32>,
+}
+
+impl BalanceHistoryQuery {
+    /// Inclusive block range of the history.
+    pub fn range(&self) -> anyhow::Result<(i64, i64)> {
+        let from = self.from_block.unwrap_or_default() as i64;
+        let to = self.to_block.map(|b| b as i64).unwrap_or(i64::MAX);
+        if from > to {
+            anyhow::bail!("from_block({from}) must not be greater than to_block({to})");
+        }
+        Ok((from, to))
+    }
+
+    pub fn limit_offset(&self) -> anyhow::Result<(u32, u32)> {
+        PageParams {
+            limit: self.limit,
+            offset: self.offset,
+            page: self.page,
+            ..Default::default()
+        }
+        .limit_offset()
+    }
+}
+
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
 #[cfg_attr(feature = "sqlx", derive(FromRow))]
 pub struct LastIndexedBlock {
     pub indexer: String,
     pub height: i64,
+    /// Unix timestamp of the last update of the height.
+    #[serde(default)]
+    pub updated_at: Option<i64>,
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+pub struct BlockInfo {
+    pub height: i64,
+    pub hash: Hash,
+    pub blocktime: i64,
+    pub indexer: String,
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct IndexedBlock {
+    pub hash: Hash,
+    pub blocktime: i64,
+}
+
+/// State of the block at the given height as it's seen by each indexer.
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct BlockDetails {
+    pub height: i64,
+    /// false if indexers have stored different hashes for this height.
+    pub consistent: bool,
+    pub indexers: std::collections::BTreeMap<String, IndexedBlock>,
+}
+
+/// Fees of the block, fee rates are in sat/vB.
+/// Txs spending outputs that aren't indexed have unknown fee and are skipped.
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+pub struct BlockStats {
+    pub height: i64,
+    /// Number of txs including the coinbase.
+    pub tx_count: i32,
+    /// Number of txs the fees are known for.
+    pub fee_tx_count: i32,
+    pub total_fees: i64,
+    pub fee_rate_min: f64,
+    pub fee_rate_p10: f64,
+    pub fee_rate_p25: f64,
+    pub fee_rate_p50: f64,
+    pub fee_rate_p75: f64,
+    pub fee_rate_p90: f64,
+    pub fee_rate_max: f64,
+}
+
+/// Max number of blocks in the range of [`BlockStatsQuery`].
+pub const MAX_BLOCK_STATS_RANGE: i64 = 1_000;
+
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct BlockStatsQuery {
+    pub from: u64,
+    pub to: u64,
+}
+
+impl BlockStatsQuery {
+    /// Inclusive block range of the stats.
+    pub fn range(&self) -> anyhow::Result<(i64, i64)> {
+        let (from, to) = (self.from as i64, self.to as i64);
+        if from > to {
+            anyhow::bail!("from({from}) must not be greater than to({to})");
+        }
+        if to - from >= MAX_BLOCK_STATS_RANGE {
+            anyhow::bail!("range must not be longer than {MAX_BLOCK_STATS_RANGE} blocks");
+        }
+        Ok((from, to))
+    }
+}
+
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct ListBlocksQuery {
+    #[serde(flatten)]
+    #[cfg_attr(feature = "openapi", param(ignore))]
+    pub page: PageParams,
+    pub indexer: Option<String>,
 }
 
 #[derive(Default, Clone, Copy, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct StatusResponse {
     pub healthy: bool,
+    /// Some indexer lags behind the node by more than half of the allowed lag.
+    #[serde(default)]
+    pub degraded: bool,
     pub db: bool,
     pub btc_node: bool,
     pub btc_height: u64,
@@ -60,6 +318,25 @@ pub struct StatusResponse {
     pub btc_indexer_height: u64,
     pub runes_indexer: bool,
     pub runes_indexer_height: u64,
+    /// Present only if the inscriptions indexer is expected to run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inscriptions_indexer: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inscriptions_indexer_height: Option<u64>,
+    /// Unix timestamp of the last block indexed by the btc indexer.
+    #[serde(default)]
+    pub btc_indexer_updated_at: Option<i64>,
+    /// Unix timestamp of the last block indexed by the runes indexer.
+    #[serde(default)]
+    pub runes_indexer_updated_at: Option<i64>,
+    /// Seconds since the least recently updated indexer has indexed a block.
+    #[serde(default)]
+    pub seconds_since_last_block: Option<u64>,
+}
+
+/// Number of confirmations of a block at the `tip` height, the block itself counts as one.
+pub fn confirmations(block: i64, tip: u64) -> i64 {
+    (tip as i64 - block + 1).max(0)
 }
 
 #[derive(Debug, Clone)]
@@ -96,6 +373,8 @@ impl Utxo {
                 pk_script: u.pk_script.clone(),
                 amount: u.btc_amount,
                 spend: false,
+                coinbase: u.coinbase,
+                confirmations: u.confirmations,
             },
         }
     }
@@ -1,5 +1,12 @@
-use api_core::pages::PageParams;
-use api_core::serde_utils::{bigdecimal_plain_str, bytevec_as_hex};
+use std::collections::{BTreeMap, HashMap};
+
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use api_core::api_errors::*;
+use api_core::pages::{number_or_string, PageParams};
+use api_core::serde_utils::{
+    bigdecimal_plain_str, bigdecimal_plain_str_opt, bytevec_as_hex, comma_separated,
+};
 use bigdecimal::BigDecimal;
 use bitcoin::script::Builder;
 use bitcoin::{Amount, OutPoint, ScriptBuf, Sequence, TxIn, TxOut, Witness};
@@ -7,10 +14,12 @@ use serde::{Deserialize, Serialize};
 #[cfg(feature = "sqlx")]
 use sqlx::prelude::FromRow;
 
+use super::btc::AddressError;
 use super::types::Hash;
-use super::UtxoSortMode;
+use super::{AmountRange, RangeAmount, UtxoSortMode};
 
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[cfg_attr(feature = "sqlx", derive(FromRow))]
 pub struct Rune {
     pub block: i64,
@@ -21,14 +30,19 @@ pub struct Rune {
     pub symbol: String,
     pub mints: i32,
     #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub max_supply: BigDecimal,
     #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub premine: BigDecimal,
     #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub burned: BigDecimal,
     #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub minted: BigDecimal,
     #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub in_circulation: BigDecimal,
     pub divisibility: i32,
     pub turbo: bool,
@@ -36,10 +50,34 @@ pub struct Rune {
     pub etching_tx: Hash,
     pub commitment_tx: Hash,
     #[serde(with = "bytevec_as_hex")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub raw_data: Vec<u8>,
     pub is_featured: bool,
 }
 
+/// Holders and supply of the rune, a record of `GET /runes/stats`.
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+pub struct RuneStats {
+    pub rune: String,
+    pub rune_id: String,
+    /// Addresses with non-zero balance.
+    pub holders: i64,
+    pub utxo_count: i64,
+    #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub in_circulation: BigDecimal,
+    #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub minted: BigDecimal,
+    #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub burned: BigDecimal,
+    /// Last block with an output of the rune, not set if the rune has never been minted or transferred.
+    pub last_activity_block: Option<i64>,
+}
+
 impl Rune {
     pub fn to_rune_id(&self) -> ordinals::RuneId {
         ordinals::RuneId {
@@ -49,7 +87,40 @@ impl Rune {
     }
 }
 
+/// Rune with mint terms decoded from the etching.
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RuneDetails {
+    #[serde(flatten)]
+    pub rune: Rune,
+    /// `null` for cenotaph runes and runes etched without terms.
+    pub terms: Option<RuneMintTerms>,
+    /// Whether a mint included in the next block would be valid.
+    pub mintable_now: bool,
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RuneMintTerms {
+    /// Amount of runes per one mint.
+    #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub amount: BigDecimal,
+    /// Max number of mints.
+    #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub cap: BigDecimal,
+    #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub remaining_mints: BigDecimal,
+    /// First block where mints are valid.
+    pub start_height: Option<u64>,
+    /// Mints are valid before this block.
+    pub end_height: Option<u64>,
+}
+
 #[derive(Default, Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[cfg_attr(feature = "sqlx", derive(FromRow))]
 pub struct RuneUtxo {
     pub id: i64,
@@ -61,13 +132,32 @@ pub struct RuneUtxo {
     pub rune_id: String,
     pub address: String,
     #[serde(with = "bytevec_as_hex")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub pk_script: Vec<u8>,
     #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub amount: BigDecimal,
     pub btc_amount: i64,
+    /// Output also carries inscriptions.
+    #[serde(default)]
+    #[cfg_attr(feature = "sqlx", sqlx(default))]
+    pub has_inscriptions: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "sqlx", sqlx(default))]
+    pub coinbase: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "sqlx", sqlx(default))]
+    pub confirmations: Option<i64>,
 }
 
 impl RuneUtxo {
+    /// Also sets `coinbase`: `runes_utxos` view has no such column,
+    /// but the coinbase tx is always the first one in the block.
+    pub fn set_confirmations(&mut self, tip: u64) {
+        self.confirmations = Some(crate::confirmations(self.block, tip));
+        self.coinbase.get_or_insert(self.tx_id == 0);
+    }
+
     pub fn out_point(&self) -> OutPoint {
         OutPoint {
             txid: (&self.tx_hash).into(),
@@ -95,6 +185,7 @@ impl RuneUtxo {
 }
 
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[cfg_attr(feature = "sqlx", derive(FromRow))]
 pub struct RuneBalance {
     pub address: String,
@@ -103,9 +194,14 @@ pub struct RuneBalance {
     pub divisibility: i32,
     pub symbol: String,
     #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub balance: BigDecimal,
     pub btc_balance: i64,
     pub utxo_count: i64,
+    /// Same as `Balance::exists`, tells whether the address is known to the index at all.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "sqlx", sqlx(default))]
+    pub exists: Option<bool>,
 }
 
 impl RuneBalance {
@@ -119,65 +215,503 @@ impl RuneBalance {
         ordinals::RuneId { block, tx }
     }
 }
+
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+pub struct RuneHolder {
+    #[serde(flatten)]
+    #[cfg_attr(feature = "sqlx", sqlx(flatten))]
+    pub balance: RuneBalance,
+    /// 1-based position by balance, the biggest holder goes first.
+    pub rank: i64,
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[cfg_attr(feature = "sqlx", derive(FromRow))]
 pub struct RuneBalanceHistory {
     pub block: i64,
     #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub rune_balance: BigDecimal,
     pub btc_balance: i64,
     #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub rune_income: BigDecimal,
     pub btc_income: i64,
     #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub rune_spent: BigDecimal,
     pub btc_spent: i64,
     pub in_count: i64,
     pub out_count: i64,
 }
 
+/// Transfers of the rune within one transaction.
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+pub struct RuneActivity {
+    pub block: i64,
+    pub tx_hash: Hash,
+    pub tx_id: i32,
+    /// Sum of the rune amount allocated to the transaction outputs.
+    #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub total_amount: BigDecimal,
+    pub outputs_count: i64,
+    pub addresses_involved: i64,
+}
+
+#[derive(Default, Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum RuneEventType {
+    #[default]
+    Etch,
+    Mint,
+    Burn,
+}
+
+impl std::str::FromStr for RuneEventType {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "etch" => Ok(Self::Etch),
+            "mint" => Ok(Self::Mint),
+            "burn" => Ok(Self::Burn),
+            _ => Err(anyhow::anyhow!(
+                "invalid event_type: possible values are `etch`, `mint` or `burn`"
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for RuneEventType {
+    type Error = anyhow::Error;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl std::fmt::Display for RuneEventType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Etch => write!(f, "etch"),
+            Self::Mint => write!(f, "mint"),
+            Self::Burn => write!(f, "burn"),
+        }
+    }
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+pub struct RuneEvent {
+    pub block: i64,
+    pub tx_id: i32,
+    pub tx_hash: Hash,
+    pub rune: String,
+    pub rune_id: String,
+    #[cfg_attr(feature = "sqlx", sqlx(try_from = "String"))]
+    pub event_type: RuneEventType,
+    /// Minted, premined or burned amount.
+    #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub amount: BigDecimal,
+}
+
 #[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct RuneEventsQuery {
+    #[serde(flatten)]
+    #[cfg_attr(feature = "openapi", param(ignore))]
+    pub page: PageParams,
+    pub event_type: Option<RuneEventType>,
+}
+
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
 pub struct ListRunesQuery {
     #[serde(flatten)]
+    #[cfg_attr(feature = "openapi", param(ignore))]
     pub page: PageParams,
     pub name: Option<String>,
     pub featured: Option<bool>,
+    /// `age` if not set.
+    #[serde(default)]
+    pub sort: Option<RuneSortMode>,
+    /// Comma separated rune ids, e.g. `840000:1,840000:2`.
+    #[serde(default, with = "comma_separated")]
+    #[cfg_attr(feature = "openapi", param(value_type = Option<String>))]
+    pub rune_ids: Vec<String>,
+    /// Min etching block, inclusive.
+    #[serde(default, deserialize_with = "number_or_string")]
+    pub from_block: Option<u64>,
+    /// Max etching block, inclusive.
+    #[serde(default, deserialize_with = "number_or_string")]
+    pub to_block: Option<u64>,
+}
+
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum RuneSortMode {
+    /// By etching block and position in the block.
+    #[default]
+    Age,
+    /// By number of addresses with non-zero balance.
+    Holders,
+    /// By number of mints.
+    Minted,
+    /// By amount in circulation.
+    Supply,
+    /// By share of `max_supply` that is already minted.
+    Progress,
+}
+
+impl std::str::FromStr for RuneSortMode {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "age" => Ok(Self::Age),
+            "holders" => Ok(Self::Holders),
+            "minted" => Ok(Self::Minted),
+            "supply" => Ok(Self::Supply),
+            "progress" => Ok(Self::Progress),
+            _ => Err(anyhow::anyhow!(
+                "invalid rune sort mode: possible values are `age`, `holders`, `minted`, `supply` or `progress`"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for RuneSortMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Age => write!(f, "age"),
+            Self::Holders => write!(f, "holders"),
+            Self::Minted => write!(f, "minted"),
+            Self::Supply => write!(f, "supply"),
+            Self::Progress => write!(f, "progress"),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
 pub struct SearchQuery {
     pub s: String,
 }
 
 #[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct RunesFilter {
     pub runes: Vec<String>,
 }
 
 #[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RunesBalancesRequest {
+    pub addresses: Vec<String>,
+    /// Optional filter, if set only balances of these runes are returned.
+    #[serde(default)]
+    pub runes: Option<Vec<String>>,
+}
+
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RunesBalancesResponse {
+    /// Balances grouped by address, every valid requested address is present,
+    /// the list is empty if the address has no runes.
+    pub balances: BTreeMap<String, Vec<RuneBalance>>,
+    /// Addresses that were rejected, they are not included into the `balances`.
+    pub errors: Vec<AddressError>,
+}
+
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct DecodeRunestoneRequest {
+    /// Hex encoded transaction.
+    pub tx: String,
+}
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ArtifactKind {
+    /// Transaction has no runestone.
+    #[default]
+    None,
+    Runestone,
+    /// Malformed runestone, all runes of the transaction inputs are burned.
+    Cenotaph,
+}
+
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct DecodedRunestone {
+    pub kind: ArtifactKind,
+    pub etching: Option<DecodedEtching>,
+    /// Id of the minted rune.
+    pub mint: Option<String>,
+    /// Name of the minted rune, `null` if the rune is not indexed.
+    pub mint_rune: Option<String>,
+    /// Output that receives unallocated runes.
+    pub pointer: Option<u32>,
+    pub edicts: Vec<DecodedEdict>,
+    /// Reason why the runestone is a cenotaph.
+    pub flaw: Option<String>,
+}
+
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ValidateEtchingRequest {
+    /// Hex encoded etching transaction, it doesn't have to be broadcasted.
+    pub tx: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum EtchingCheckKind {
+    /// The tx has a valid runestone with an etching.
+    Runestone,
+    /// The rune isn't reserved and isn't less than the minimum at the height.
+    MinimumName,
+    /// No rune with the name is etched yet.
+    NameAvailable,
+    /// An input commits to the rune in a taproot output of a mature tx.
+    Commitment,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct EtchingCheck {
+    pub check: EtchingCheckKind,
+    pub passed: bool,
+    /// Why the check failed or isn't required.
+    pub reason: Option<String>,
+}
+
+/// Whether the runes indexer accepts the etching if the tx is mined in the next block.
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct EtchingVerdict {
+    /// All checks are passed.
+    pub valid: bool,
+    /// Spaced rune name, `null` if the name is not set and will be reserved.
+    pub rune: Option<String>,
+    /// Height of the next block after the indexed one, the checks are made for it.
+    pub height: u64,
+    /// Checks that require the etching are not listed if the tx has no valid one.
+    pub checks: Vec<EtchingCheck>,
+}
+
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct TracedRuneAmount {
+    pub rune_id: String,
+    /// Spaced rune name, `null` if the rune is not indexed.
+    pub rune: Option<String>,
+    #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub amount: BigDecimal,
+}
+
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct TracedAllocation {
+    pub vout: u32,
+    #[serde(flatten)]
+    pub rune: TracedRuneAmount,
+}
+
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct TracedEdict {
+    /// Rune id of the edict, `0:0` refers to the rune etched by the tx.
+    pub rune_id: String,
+    #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub amount: BigDecimal,
+    pub output: u32,
+    /// Empty if the rune of the edict has nothing to allocate.
+    pub allocations: Vec<TracedAllocation>,
+}
+
+/// How the runes indexer allocated runes of the tx.
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RuneAllocationTrace {
+    pub txid: Hash,
+    pub kind: ArtifactKind,
+    pub mint: Option<TracedRuneAmount>,
+    pub premine: Option<TracedRuneAmount>,
+    /// Runes of the inputs, the mint and the premine before the edicts.
+    pub unallocated: Vec<TracedRuneAmount>,
+    pub edicts: Vec<TracedEdict>,
+    pub pointer: Option<u32>,
+    /// Output that got the runes left after the edicts, `null` if they were burned.
+    pub default_output: Option<u32>,
+    /// Runes left after the edicts.
+    pub remainder: Vec<TracedRuneAmount>,
+    /// Resulting balances of the outputs.
+    pub outputs: Vec<TracedAllocation>,
+    pub burned: Vec<TracedRuneAmount>,
+}
+
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct DecodedEtching {
+    /// Spaced rune name, `null` if the name is not set and will be reserved.
+    pub rune: Option<String>,
+    pub divisibility: Option<u8>,
+    pub symbol: Option<char>,
+    #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub premine: BigDecimal,
+    pub turbo: bool,
+    pub terms: Option<DecodedTerms>,
+}
+
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct DecodedTerms {
+    #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub amount: BigDecimal,
+    #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub cap: BigDecimal,
+    pub height_start: Option<u64>,
+    pub height_end: Option<u64>,
+    /// Relative to the etching block.
+    pub offset_start: Option<u64>,
+    pub offset_end: Option<u64>,
+}
+
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct DecodedEdict {
+    pub rune_id: String,
+    /// Spaced rune name, `null` if the rune is not indexed.
+    pub rune: Option<String>,
+    #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub amount: BigDecimal,
+    pub output: u32,
+}
+
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
 pub struct RunesUtxoQuery {
     #[serde(flatten)]
+    #[cfg_attr(feature = "openapi", param(ignore))]
     pub page: PageParams,
     #[serde(default)]
     pub sorting: UtxoSortMode,
-    pub amount_threshold: Option<u64>,
+    /// Only utxos with amount greater than it, use `min_amount` instead.
+    #[serde(default, with = "bigdecimal_plain_str_opt")]
+    #[cfg_attr(feature = "openapi", param(value_type = Option<String>))]
+    #[deprecated = "use `min_amount` instead"]
+    pub amount_threshold: Option<BigDecimal>,
+    /// Min rune amount of the utxo in the rune units, inclusive.
+    #[serde(default, with = "bigdecimal_plain_str_opt")]
+    #[cfg_attr(feature = "openapi", param(value_type = Option<String>))]
+    pub min_amount: Option<BigDecimal>,
+    /// Max rune amount of the utxo in the rune units, inclusive.
+    #[serde(default, with = "bigdecimal_plain_str_opt")]
+    #[cfg_attr(feature = "openapi", param(value_type = Option<String>))]
+    pub max_amount: Option<BigDecimal>,
+    /// Value of the `meta.next_cursor` from the previous page.
+    /// Can't be used together with `offset` or `page`.
+    pub cursor: Option<String>,
+    /// Return UTXOs that also carry inscriptions.
+    #[serde(default)]
+    pub include_inscribed: bool,
+}
+
+impl RunesUtxoQuery {
+    #[allow(deprecated)]
+    pub fn amount_range(&self) -> anyhow::Result<AmountRange<BigDecimal>> {
+        AmountRange::from_query(
+            self.amount_threshold.clone(),
+            self.min_amount.clone(),
+            self.max_amount.clone(),
+        )
+    }
 }
 
 #[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
 pub struct RunesHoldersQuery {
     #[serde(flatten)]
+    #[cfg_attr(feature = "openapi", param(ignore))]
     pub page: PageParams,
-    pub amount_threshold: Option<u64>,
+    /// Only holders with balance greater than it, in the rune units.
+    #[serde(default, with = "bigdecimal_plain_str_opt")]
+    #[cfg_attr(feature = "openapi", param(value_type = Option<String>))]
+    pub amount_threshold: Option<BigDecimal>,
+}
+
+impl RunesHoldersQuery {
+    pub fn amount_threshold(&self) -> anyhow::Result<Option<BigDecimal>> {
+        if let Some(threshold) = &self.amount_threshold {
+            threshold.validate("amount_threshold")?;
+        }
+        Ok(self.amount_threshold.clone())
+    }
 }
 
 #[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct CollectRunesUtxo {
     #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub amount: BigDecimal,
     pub request_id: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct BuildRunePsbtRequest {
+    /// Runes and fee inputs are collected from this address, the change goes back to it.
+    pub from_address: String,
+    /// Receives the runes in a 546 sats output.
+    pub to_address: String,
+    #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub amount: BigDecimal,
+    /// Fee rate in sat/vB.
+    pub fee_rate: f64,
+    /// Selected utxos are locked with this id.
+    pub request_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct BuildRunePsbtResponse {
+    /// Base64 encoded unsigned psbt.
+    pub psbt: String,
+    pub rune_inputs: Vec<RuneUtxo>,
+    /// Inputs without runes and inscriptions that pay the rest of the fee.
+    pub btc_inputs: Vec<crate::BtcUtxo>,
+    /// Estimated virtual size of the signed tx.
+    pub vsize: u64,
+    pub fee: u64,
+    /// Value of the change output, it also receives the rest of the runes.
+    pub change: u64,
+}
+
 #[derive(Default, Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[cfg_attr(feature = "sqlx", derive(FromRow))]
 pub struct RuneInputFull {
     pub id: i64,
@@ -193,9 +727,11 @@ pub struct RuneInputFull {
     pub rune_id: String,
     pub address: String,
     #[serde(with = "bytevec_as_hex")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub pk_script: Vec<u8>,
     pub btc_amount: i64,
     #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub amount: BigDecimal,
 }
 
@@ -212,6 +748,7 @@ pub struct RuneInputsSum {
 }
 
 #[derive(Default, Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[cfg_attr(feature = "sqlx", derive(FromRow))]
 pub struct RuneOutput {
     pub id: i64,
@@ -223,9 +760,11 @@ pub struct RuneOutput {
     pub rune_id: String,
     pub address: String,
     #[serde(with = "bytevec_as_hex")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub pk_script: Vec<u8>,
     pub btc_amount: i64,
     #[serde(with = "bigdecimal_plain_str")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub amount: BigDecimal,
 }
 
@@ -242,7 +781,309 @@ pub struct RuneOutputsSum {
 }
 
 #[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct RuneTxInOuts {
     pub inputs: Vec<RuneInputFull>,
     pub outputs: Vec<RuneOutput>,
 }
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum RuneApiError {
+    #[error("something went wrong")]
+    InternalError,
+    #[error("service is temporaly unavailable; check the /status response")]
+    ServiceUnavailable,
+    #[error("request took too long; narrow it down or retry later")]
+    Timeout,
+    #[error("rune with this name not exist: {0}")]
+    NotFound(String),
+    #[error("rune name is invalid: {0}")]
+    InvalidRuneName(String),
+    #[error("address is invalid: {0}")]
+    InvalidAddress(String),
+    #[error("bad input: {0}")]
+    BadInput(String),
+    #[error("unauthorized")]
+    Unauthorized,
+    /// `available` is the confirmed balance of the address,
+    /// `collectable` is the part of it that can be spent by the request.
+    #[error(
+        "not enough balance: required={required}, available={available}, collectable={collectable}"
+    )]
+    NotEnoughBalance {
+        required: u128,
+        available: u128,
+        collectable: u128,
+    },
+}
+
+/// Invalid rune names and addresses share the `BadInput` code with other bad inputs,
+/// they are told apart by the `field` detail.
+/// Errors of older servers have no details, the rune of `NotFound` is taken from the message.
+impl TryFrom<&ApiError> for RuneApiError {
+    type Error = anyhow::Error;
+    fn try_from(error: &ApiError) -> Result<Self, Self::Error> {
+        use RuneApiError::*;
+        let detail = |key: &str| error.details.get(key).cloned();
+        let message = |prefix: &str| {
+            let msg = error.message.as_str();
+            msg.strip_prefix(prefix).unwrap_or(msg).to_string()
+        };
+        let amount = |key: &str| {
+            error
+                .details
+                .get(key)
+                .and_then(|v| v.parse::<u128>().ok())
+                .unwrap_or_default()
+        };
+
+        let code = ApiErrorCode::try_from(error.code)?;
+        Ok(match code {
+            ApiErrorCode::InternalError => InternalError,
+            ApiErrorCode::ServiceUnavailable => ServiceUnavailable,
+            ApiErrorCode::Timeout => Timeout,
+            ApiErrorCode::AccessDenied => Unauthorized,
+            ApiErrorCode::NotFound => NotFound(
+                detail("rune").unwrap_or_else(|| message("rune with this name not exist: ")),
+            ),
+            ApiErrorCode::BadInput => match detail("field").as_deref() {
+                Some("rune") => InvalidRuneName(message("rune name is invalid: ")),
+                Some("address") => InvalidAddress(message("address is invalid: ")),
+                _ => BadInput(message("bad input: ")),
+            },
+            ApiErrorCode::NotEnoughBalance => NotEnoughBalance {
+                required: amount("required"),
+                available: amount("available"),
+                collectable: amount("collectable"),
+            },
+            code @ (ApiErrorCode::Forbidden
+            | ApiErrorCode::TooManyRequests
+            | ApiErrorCode::InvalidAddress
+            | ApiErrorCode::NeedMoreUtxos
+            | ApiErrorCode::HistoryPruned) => {
+                anyhow::bail!("{code} has no runes api error")
+            }
+        })
+    }
+}
+
+impl From<&RuneApiError> for ApiError {
+    fn from(error: &RuneApiError) -> ApiError {
+        use RuneApiError::*;
+        let mut details = HashMap::new();
+        let code = match error {
+            Unauthorized => ApiErrorCode::AccessDenied,
+            InternalError => ApiErrorCode::InternalError,
+            ServiceUnavailable => ApiErrorCode::ServiceUnavailable,
+            Timeout => ApiErrorCode::Timeout,
+            NotFound(rune) => {
+                details.insert("rune".into(), rune.clone());
+                ApiErrorCode::NotFound
+            }
+            InvalidRuneName(_) => {
+                details.insert("field".into(), "rune".into());
+                ApiErrorCode::BadInput
+            }
+            InvalidAddress(_) => {
+                details.insert("field".into(), "address".into());
+                ApiErrorCode::BadInput
+            }
+            BadInput(_) => ApiErrorCode::BadInput,
+            NotEnoughBalance {
+                required,
+                available,
+                collectable,
+            } => {
+                details.insert("required".into(), required.to_string());
+                details.insert("available".into(), available.to_string());
+                details.insert("collectable".into(), collectable.to_string());
+                ApiErrorCode::NotEnoughBalance
+            }
+        };
+        ApiError {
+            code: code as u16,
+            status: code.to_string(),
+            http_code: error.status_code(),
+            message: error.to_string(),
+            details,
+        }
+    }
+}
+
+impl ResponseError for RuneApiError {
+    fn status_code(&self) -> StatusCode {
+        use RuneApiError::*;
+        match self {
+            Unauthorized => StatusCode::UNAUTHORIZED,
+            InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+            ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            Timeout => StatusCode::GATEWAY_TIMEOUT,
+            NotFound(_) => StatusCode::NOT_FOUND,
+            InvalidRuneName(_) => StatusCode::BAD_REQUEST,
+            InvalidAddress(_) => StatusCode::BAD_REQUEST,
+            BadInput(_) => StatusCode::BAD_REQUEST,
+            NotEnoughBalance { .. } => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        ApiError::from(self).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_rune_api_error_round_trip() {
+        let errors = [
+            RuneApiError::InternalError,
+            RuneApiError::ServiceUnavailable,
+            RuneApiError::Timeout,
+            RuneApiError::NotFound("UNCOMMON•GOODS".into()),
+            RuneApiError::InvalidRuneName("invalid character ` `".into()),
+            RuneApiError::InvalidAddress("bech32 address encoding error".into()),
+            RuneApiError::BadInput("limit must be positive".into()),
+            RuneApiError::Unauthorized,
+            RuneApiError::NotEnoughBalance {
+                required: u128::MAX,
+                available: 1 << 70,
+                collectable: 1200,
+            },
+        ];
+        for err in errors {
+            let api_err = ApiError::from(&err);
+            assert_eq!(api_err.http_code, err.status_code());
+            let json = serde_json::to_string(&api_err).unwrap();
+            let decoded: ApiError = serde_json::from_str(&json).unwrap();
+            assert_eq!(RuneApiError::try_from(&decoded).unwrap(), err);
+        }
+
+        // errors of older servers have the values only in the message
+        let api_err = ApiError {
+            code: ApiErrorCode::NotFound as u16,
+            message: "rune with this name not exist: AAAA".into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            RuneApiError::try_from(&api_err).unwrap(),
+            RuneApiError::NotFound("AAAA".into())
+        );
+
+        let api_err = ApiError {
+            code: ApiErrorCode::TooManyRequests as u16,
+            ..Default::default()
+        };
+        assert!(RuneApiError::try_from(&api_err).is_err());
+    }
+
+    #[test]
+    fn test_rune_sort_mode_serde() {
+        let modes = [
+            RuneSortMode::Age,
+            RuneSortMode::Holders,
+            RuneSortMode::Minted,
+            RuneSortMode::Supply,
+            RuneSortMode::Progress,
+        ];
+        for mode in modes {
+            let json = serde_json::to_string(&mode).unwrap();
+            assert_eq!(json, format!("\"{mode}\""));
+            assert_eq!(serde_json::from_str::<RuneSortMode>(&json).unwrap(), mode);
+            assert_eq!(RuneSortMode::from_str(&mode.to_string()).unwrap(), mode);
+        }
+        assert!(serde_json::from_str::<RuneSortMode>("\"volume\"").is_err());
+        assert!(RuneSortMode::from_str("volume").is_err());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_utxo_amount_range() {
+        let amount = |s: &str| Some(BigDecimal::from_str(s).unwrap());
+        let query = |amount_threshold, min_amount, max_amount| RunesUtxoQuery {
+            amount_threshold,
+            min_amount,
+            max_amount,
+            ..Default::default()
+        };
+        assert_eq!(
+            query(None, None, None).amount_range().unwrap(),
+            AmountRange::default()
+        );
+        assert_eq!(
+            query(None, amount("546"), amount("546"))
+                .amount_range()
+                .unwrap(),
+            AmountRange {
+                min: amount("546"),
+                max: amount("546")
+            }
+        );
+        // the deprecated threshold stays exclusive
+        assert_eq!(
+            query(amount("546"), None, None).amount_range().unwrap(),
+            AmountRange {
+                min: amount("547"),
+                max: None
+            }
+        );
+        assert!(query(amount("546"), amount("546"), None)
+            .amount_range()
+            .is_err());
+        assert!(query(amount("546"), None, amount("1000"))
+            .amount_range()
+            .is_err());
+        assert!(query(None, amount("1000"), amount("546"))
+            .amount_range()
+            .is_err());
+        assert!(query(None, amount("-1"), None).amount_range().is_err());
+        assert!(query(None, None, amount("1.5")).amount_range().is_err());
+    }
+
+    #[test]
+    fn test_holders_amount_threshold() {
+        // more than u64::MAX
+        let query: RunesHoldersQuery = serde_json::from_str(
+            r#"{"amount_threshold": "340282366920938463463374607431768211455"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            query.amount_threshold().unwrap().unwrap().to_plain_string(),
+            u128::MAX.to_string()
+        );
+
+        let query: RunesHoldersQuery = serde_json::from_str("{}").unwrap();
+        assert_eq!(query.amount_threshold().unwrap(), None);
+
+        for value in ["-5", "0.5"] {
+            let query: RunesHoldersQuery =
+                serde_json::from_str(&format!(r#"{{"amount_threshold": "{value}"}}"#)).unwrap();
+            assert!(query.amount_threshold().is_err());
+        }
+    }
+
+    #[test]
+    fn test_rune_utxo_set_confirmations() {
+        let mut utxo = RuneUtxo {
+            block: 100,
+            tx_id: 0,
+            ..Default::default()
+        };
+        utxo.set_confirmations(100);
+        assert_eq!(utxo.confirmations, Some(1));
+        assert_eq!(utxo.coinbase, Some(true));
+
+        utxo.tx_id = 5;
+        utxo.coinbase = None;
+        utxo.set_confirmations(109);
+        assert_eq!(utxo.confirmations, Some(10));
+        assert_eq!(utxo.coinbase, Some(false));
+
+        // tip is not known yet
+        utxo.set_confirmations(0);
+        assert_eq!(utxo.confirmations, Some(0));
+    }
+}
@@ -165,6 +165,20 @@ impl serde::Serialize for Hash {
     }
 }
 
+/// Hashes are exposed as hex strings, see the `Serialize` impl.
+#[cfg(feature = "openapi")]
+impl utoipa::PartialSchema for Hash {
+    fn schema() -> utoipa::openapi::RefOr<utoipa::openapi::schema::Schema> {
+        utoipa::openapi::ObjectBuilder::new()
+            .schema_type(utoipa::openapi::schema::Type::String)
+            .description(Some("hex encoded hash"))
+            .into()
+    }
+}
+
+#[cfg(feature = "openapi")]
+impl utoipa::ToSchema for Hash {}
+
 impl<'de> serde::Deserialize<'de> for Hash {
     fn deserialize<D>(deserializer: D) -> Result<Hash, D::Error>
     where
@@ -1,10 +1,8 @@
 #![allow(dead_code)]
 
 use clap::Parser;
-use orbtc::cmd;
-use sentry_tracing::layer as sentry_layer;
-use tracing_subscriber::layer::SubscriberExt;
-use tracing_subscriber::util::SubscriberInitExt;
+use orbtc::cmd::{self, init_log};
+use orbtc::config::Config;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -27,18 +25,14 @@ struct Args {
 
     #[arg(long)]
     use_firehose: bool,
-}
 
-fn init_log(sentry_enabled: bool) {
-    if !sentry_enabled {
-        env_logger::init();
-        return;
-    }
+    /// Waits until another indexer instance releases the lock instead of exiting.
+    #[arg(long, default_value_t = false)]
+    wait_for_lock: bool,
 
-    let mut log_builder = env_logger::Builder::from_default_env();
-    let logger = sentry::integrations::log::SentryLogger::with_dest(log_builder.build());
-    log::set_boxed_logger(Box::new(logger)).unwrap();
-    log::set_max_level(log::LevelFilter::Debug);
+    /// Exits after the block at this height is indexed instead of waiting for new blocks.
+    #[arg(long)]
+    stop_at_height: Option<u64>,
 }
 
 #[actix_web::main]
@@ -46,7 +40,11 @@ async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
     let sentry_dsn = std::env::var("SENTRY_DSN");
 
-    init_log(sentry_dsn.is_ok());
+    // the config is read again by the command, which reports if it's invalid
+    let log_cfg = Config::read(&args.config)
+        .map(|cfg| cfg.log)
+        .unwrap_or_default();
+    init_log(&log_cfg, sentry_dsn.is_ok())?;
     if let Ok(dsn) = sentry_dsn {
         let environment = std::env::var("SENTRY_ENV").unwrap_or("unknown".to_string());
         let tracing_frequency: f32 = std::env::var("SENTRY_TRACES_RATES")
@@ -55,14 +53,6 @@ async fn main() -> anyhow::Result<()> {
             .unwrap_or(0.0)
             .clamp(0.0, 1.0);
 
-        // this will send traces to sentry
-        if let Err(err) = tracing_subscriber::registry()
-            .with(sentry_layer())
-            .try_init()
-        {
-            log::error!("Failed to init sentry tracing layer: {err}");
-        }
-
         let _guard = sentry::init((
             dsn,
             sentry::ClientOptions {
@@ -87,6 +77,8 @@ async fn main() -> anyhow::Result<()> {
             block: args.block,
             tx: None,
             use_firehose: args.use_firehose,
+            wait_for_lock: args.wait_for_lock,
+            stop_at_height: args.stop_at_height,
         };
         icmd.run(&args.config).await
     } else {
@@ -96,6 +88,8 @@ async fn main() -> anyhow::Result<()> {
             ignore_inputs: args.ignore_inputs,
             retry_on_fail: true,
             use_firehose: args.use_firehose,
+            wait_for_lock: args.wait_for_lock,
+            stop_at_height: args.stop_at_height,
         };
         icmd.run(&args.config).await
     };
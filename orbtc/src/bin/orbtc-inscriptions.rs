@@ -1,10 +1,8 @@
 #![allow(dead_code)]
 
 use clap::Parser;
-use orbtc::cmd;
-use sentry_tracing::layer as sentry_layer;
-use tracing_subscriber::layer::SubscriberExt;
-use tracing_subscriber::util::SubscriberInitExt;
+use orbtc::cmd::{self, init_log};
+use orbtc::config::Config;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -27,18 +25,10 @@ struct Args {
 
     #[arg(long)]
     pub dump_path: Option<String>,
-}
-
-fn init_log(sentry_enabled: bool) {
-    if !sentry_enabled {
-        env_logger::init();
-        return;
-    }
 
-    let mut log_builder = env_logger::Builder::from_default_env();
-    let logger = sentry::integrations::log::SentryLogger::with_dest(log_builder.build());
-    log::set_boxed_logger(Box::new(logger)).unwrap();
-    log::set_max_level(log::LevelFilter::Debug);
+    /// Waits until another indexer instance releases the lock instead of exiting.
+    #[arg(long, default_value_t = false)]
+    pub wait_for_lock: bool,
 }
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 12)]
@@ -46,7 +36,11 @@ async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
     let sentry_dsn = std::env::var("SENTRY_DSN");
 
-    init_log(sentry_dsn.is_ok());
+    // the config is read again by the command, which reports if it's invalid
+    let log_cfg = Config::read(&args.config)
+        .map(|cfg| cfg.log)
+        .unwrap_or_default();
+    init_log(&log_cfg, sentry_dsn.is_ok())?;
     if let Ok(dsn) = sentry_dsn {
         let environment = std::env::var("SENTRY_ENV").unwrap_or("unknown".to_string());
         let tracing_frequency: f32 = std::env::var("SENTRY_TRACES_RATES")
@@ -55,14 +49,6 @@ async fn main() -> anyhow::Result<()> {
             .unwrap_or(0.0)
             .clamp(0.0, 1.0);
 
-        // this will send traces to sentry
-        if let Err(err) = tracing_subscriber::registry()
-            .with(sentry_layer())
-            .try_init()
-        {
-            log::error!("Failed to init sentry tracing layer: {err}");
-        }
-
         let _guard = sentry::init((
             dsn,
             sentry::ClientOptions {
@@ -86,6 +72,7 @@ async fn main() -> anyhow::Result<()> {
         load_dump: args.load_dump,
         from: args.from,
         dump_path: args.dump_path.clone(),
+        wait_for_lock: args.wait_for_lock,
     };
     let res = icmd.run(&args.config).await;
 
@@ -1,13 +1,83 @@
+use std::collections::BTreeSet;
 use std::time::Duration;
 
 use anyhow::Context;
 use bb8::Pool;
-use bb8_redis::redis::AsyncCommands;
+use bb8_redis::redis::{self, AsyncCommands};
 use bb8_redis::RedisConnectionManager;
+use orbtc_indexer_api::UnlockStatus;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LockStatus {
+    Created,
+    /// The lock was already held by the same request.
+    Renewed,
+    OwnedByOther,
+}
 
 const FBTC_LOCKS_PREFIX: &str = "orbtc:utxo_locks";
+const RATE_LIMIT_PREFIX: &str = "orbtc:rate_limit";
 const NO_ID: &str = "p.j.fry";
 
+// compare-and-delete must be atomic, otherwise
+// we can drop the lock that was just taken by someone else.
+const UNLOCK_SCRIPT: &str = r#"
+local val = redis.call('GET', KEYS[1])
+if not val then
+    return 0
+end
+if val == ARGV[1] then
+    redis.call('DEL', KEYS[1])
+    return 1
+end
+return -1
+"#;
+
+// Takes the lock if it's free (1), a lock held by the same id is prolonged (2).
+// ARGV: id, ttl (seconds).
+const LOCK_SCRIPT: &str = r#"
+if redis.call('SET', KEYS[1], ARGV[1], 'NX', 'EX', ARGV[2]) then
+    return 1
+end
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    redis.call('EXPIRE', KEYS[1], ARGV[2])
+    return 2
+end
+return 0
+"#;
+
+// Prolongs the lock only if it's held by the same id.
+// ARGV: id, ttl (seconds).
+const RENEW_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    redis.call('EXPIRE', KEYS[1], ARGV[2])
+    return 1
+end
+return 0
+"#;
+
+// Token bucket: refill by elapsed time, then try to take one token.
+// Returns 0 if the token was taken, otherwise milliseconds to wait for the next one.
+// ARGV: capacity, refill rate (tokens per ms), current time (ms).
+const RATE_LIMIT_SCRIPT: &str = r#"
+local capacity = tonumber(ARGV[1])
+local rate = tonumber(ARGV[2])
+local now = tonumber(ARGV[3])
+local state = redis.call('HMGET', KEYS[1], 'tokens', 'ts')
+local tokens = tonumber(state[1]) or capacity
+local ts = tonumber(state[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - ts) * rate)
+local wait = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+else
+    wait = math.ceil((1 - tokens) / rate)
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'ts', tostring(now))
+redis.call('PEXPIRE', KEYS[1], math.ceil(capacity / rate) + 1000)
+return wait
+"#;
+
 #[derive(Clone)]
 pub struct Repo {
     pub pool: Pool<RedisConnectionManager>,
@@ -29,23 +99,52 @@ impl Repo {
         Ok(Self { pool, lock_ttl })
     }
 
+    /// Lock of the same `request_id` is prolonged.
     pub async fn lock_utxo(
         &self,
         tx_hash: &orbtc_indexer_api::types::Hash,
         vout: i32,
         request_id: &str,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<LockStatus> {
         let mut conn = self.pool.get().await?;
         let key = format!("{}:{}:{}", FBTC_LOCKS_PREFIX, tx_hash, vout);
-        let id = if request_id.is_empty() {
-            format!("{NO_ID}-{}", rand::random::<u32>())
-        } else {
-            request_id.to_owned()
-        };
+        let id = lock_owner_id(request_id);
 
-        conn.set_ex::<String, String, ()>(key, id, self.lock_ttl)
+        let res: i32 = redis::cmd("EVAL")
+            .arg(LOCK_SCRIPT)
+            .arg(1)
+            .arg(key)
+            .arg(id)
+            .arg(self.lock_ttl)
+            .query_async(&mut *conn)
             .await?;
-        Ok(())
+
+        Ok(match res {
+            1 => LockStatus::Created,
+            2 => LockStatus::Renewed,
+            _ => LockStatus::OwnedByOther,
+        })
+    }
+
+    /// Resets ttl of the lock held by `request_id`, returns `false` if it's not held anymore.
+    pub async fn renew_lock(
+        &self,
+        tx_hash: &orbtc_indexer_api::types::Hash,
+        vout: i32,
+        request_id: &str,
+    ) -> anyhow::Result<bool> {
+        let mut conn = self.pool.get().await?;
+        let key = format!("{}:{}:{}", FBTC_LOCKS_PREFIX, tx_hash, vout);
+
+        let res: i32 = redis::cmd("EVAL")
+            .arg(RENEW_SCRIPT)
+            .arg(1)
+            .arg(key)
+            .arg(request_id)
+            .arg(self.lock_ttl)
+            .query_async(&mut *conn)
+            .await?;
+        Ok(res == 1)
     }
 
     pub async fn check_is_locked(
@@ -58,17 +157,235 @@ impl Repo {
         let key = format!("{}:{}:{}", FBTC_LOCKS_PREFIX, tx_hash, vout);
         let data: Option<String> = conn.get(key).await?;
 
-        match data {
-            Some(val) => {
-                if val == NO_ID {
-                    return Ok(true);
-                }
-                // if request_id matches,
-                // this is the same session or repeated request,
-                // so we treat it like it is not locked
-                Ok(!Some(val).eq(request_id))
+        Ok(is_locked_by_other(data, request_id))
+    }
+
+    /// Checks locks of all outpoints with one `MGET`, returns the outpoints
+    /// locked by requests other than `request_id`.
+    pub async fn check_locked_batch(
+        &self,
+        outpoints: &[(&orbtc_indexer_api::types::Hash, i32)],
+        request_id: &Option<String>,
+    ) -> anyhow::Result<BTreeSet<(orbtc_indexer_api::types::Hash, i32)>> {
+        if outpoints.is_empty() {
+            return Ok(BTreeSet::new());
+        }
+
+        let mut conn = self.pool.get().await?;
+        let keys: Vec<String> = outpoints
+            .iter()
+            .map(|(tx_hash, vout)| format!("{}:{}:{}", FBTC_LOCKS_PREFIX, tx_hash, vout))
+            .collect();
+        // explicit MGET, `AsyncCommands::mget` sends GET for a single key
+        let data: Vec<Option<String>> = redis::cmd("MGET")
+            .arg(&keys)
+            .query_async(&mut *conn)
+            .await?;
+
+        let locked = outpoints
+            .iter()
+            .zip(data)
+            .filter(|(_, val)| is_locked_by_other(val.clone(), request_id))
+            .map(|((tx_hash, vout), _)| ((*tx_hash).clone(), *vout))
+            .collect();
+        Ok(locked)
+    }
+
+    /// Removes the lock only if it was taken with the same `request_id`.
+    pub async fn unlock_utxo(
+        &self,
+        tx_hash: &orbtc_indexer_api::types::Hash,
+        vout: i32,
+        request_id: &str,
+    ) -> anyhow::Result<UnlockStatus> {
+        let mut conn = self.pool.get().await?;
+        let key = format!("{}:{}:{}", FBTC_LOCKS_PREFIX, tx_hash, vout);
+
+        let res: i32 = redis::cmd("EVAL")
+            .arg(UNLOCK_SCRIPT)
+            .arg(1)
+            .arg(key)
+            .arg(request_id)
+            .query_async(&mut *conn)
+            .await?;
+
+        Ok(match res {
+            1 => UnlockStatus::Released,
+            0 => UnlockStatus::NotFound,
+            _ => UnlockStatus::OwnedByOther,
+        })
+    }
+
+    /// Returns holder's request id and remaining ttl of the lock for each outpoint.
+    pub async fn get_locks(
+        &self,
+        outpoints: &[(&orbtc_indexer_api::types::Hash, i32)],
+    ) -> anyhow::Result<Vec<Option<(String, i64)>>> {
+        if outpoints.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut conn = self.pool.get().await?;
+        let mut pipe = redis::pipe();
+        for (tx_hash, vout) in outpoints.iter() {
+            let key = format!("{}:{}:{}", FBTC_LOCKS_PREFIX, tx_hash, vout);
+            pipe.get(&key).ttl(&key);
+        }
+
+        let data: Vec<redis::Value> = pipe.query_async(&mut *conn).await?;
+        let mut locks = Vec::with_capacity(outpoints.len());
+        for pair in data.chunks(2) {
+            let [id, ttl] = pair else {
+                anyhow::bail!("unexpected redis response");
+            };
+            let id: Option<String> = redis::from_redis_value(id)?;
+            let ttl: i64 = redis::from_redis_value(ttl)?;
+            locks.push(id.map(|id| (id, ttl)));
+        }
+        Ok(locks)
+    }
+
+    /// Takes a token from the shared bucket of the `key`.
+    /// Returns `None` if the request is allowed, otherwise time to wait for the next token.
+    pub async fn take_rate_limit_token(
+        &self,
+        key: &str,
+        burst: u32,
+        per_minute: u32,
+    ) -> anyhow::Result<Option<Duration>> {
+        let mut conn = self.pool.get().await?;
+        let key = format!("{}:{}", RATE_LIMIT_PREFIX, key);
+        let rate = per_minute as f64 / 60_000.0;
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+
+        let wait_ms: u64 = redis::cmd("EVAL")
+            .arg(RATE_LIMIT_SCRIPT)
+            .arg(1)
+            .arg(key)
+            .arg(burst)
+            .arg(rate)
+            .arg(now)
+            .query_async(&mut *conn)
+            .await?;
+
+        Ok((wait_ms > 0).then(|| Duration::from_millis(wait_ms)))
+    }
+}
+
+/// Locks without request id get a random owner, so they can't be taken over by others.
+pub fn lock_owner_id(request_id: &str) -> String {
+    if request_id.is_empty() {
+        format!("{NO_ID}-{}", rand::random::<u32>())
+    } else {
+        request_id.to_owned()
+    }
+}
+
+fn is_locked_by_other(lock: Option<String>, request_id: &Option<String>) -> bool {
+    match lock {
+        Some(val) => {
+            if val == NO_ID {
+                return true;
             }
-            None => Ok(false),
+            // if request_id matches,
+            // this is the same session or repeated request,
+            // so we treat it like it is not locked
+            !Some(val).eq(request_id)
         }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use orbtc_indexer_api::types::Hash;
+
+    use super::*;
+
+    #[test]
+    fn test_is_locked_by_other() {
+        let rid = Some("req-1".to_string());
+        assert!(!is_locked_by_other(None, &rid));
+        assert!(!is_locked_by_other(Some("req-1".into()), &rid));
+        assert!(is_locked_by_other(Some("req-2".into()), &rid));
+        assert!(is_locked_by_other(Some("req-1".into()), &None));
+        assert!(is_locked_by_other(Some(NO_ID.into()), &Some(NO_ID.into())));
+    }
+
+    /// Counts commands with `INFO commandstats`, so it needs a dedicated redis.
+    #[tokio::test]
+    #[ignore = "requires redis, set REDIS_URL"]
+    async fn test_check_locked_batch_uses_one_command() {
+        let url = std::env::var("REDIS_URL").expect("REDIS_URL must be set");
+        let cache = Repo::new(&url, 60).await.unwrap();
+
+        let hashes: Vec<Hash> = (0..200).map(|i| Hash::sha2(i.to_string())).collect();
+        let outpoints: Vec<(&Hash, i32)> = hashes.iter().map(|h| (h, 0)).collect();
+        cache.lock_utxo(&hashes[0], 0, "req-1").await.unwrap();
+        cache.lock_utxo(&hashes[1], 0, "req-2").await.unwrap();
+
+        let calls = |cmd: &'static str| {
+            let cache = cache.clone();
+            async move {
+                let mut conn = cache.pool.get().await.unwrap();
+                let info: String = redis::cmd("INFO")
+                    .arg("commandstats")
+                    .query_async(&mut *conn)
+                    .await
+                    .unwrap();
+                info.lines()
+                    .find_map(|l| l.strip_prefix(&format!("cmdstat_{cmd}:calls=")))
+                    .and_then(|l| l.split(',').next())
+                    .map(|n| n.parse::<u64>().unwrap())
+                    .unwrap_or_default()
+            }
+        };
+
+        let rid = Some("req-1".to_string());
+        let (gets, mgets) = (calls("get").await, calls("mget").await);
+        let locked = cache.check_locked_batch(&outpoints, &rid).await.unwrap();
+        assert_eq!(calls("mget").await - mgets, 1);
+        assert_eq!(calls("get").await - gets, 0);
+        assert_eq!(locked, BTreeSet::from([(hashes[1].clone(), 0)]));
+
+        let gets = calls("get").await;
+        for (hash, vout) in outpoints.iter() {
+            let expected = locked.contains(&((*hash).clone(), *vout));
+            assert_eq!(
+                cache.check_is_locked(hash, *vout, &rid).await.unwrap(),
+                expected
+            );
+        }
+        assert_eq!(calls("get").await - gets, outpoints.len() as u64);
+
+        for hash in hashes.iter().take(2) {
+            cache.unlock_utxo(hash, 0, "req-1").await.unwrap();
+            cache.unlock_utxo(hash, 0, "req-2").await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires redis, set REDIS_URL"]
+    async fn test_lock_utxo_keeps_first_owner() {
+        let url = std::env::var("REDIS_URL").expect("REDIS_URL must be set");
+        let cache = Repo::new(&url, 60).await.unwrap();
+        let hash = Hash::sha2("lock-owner");
+
+        let lock = |id| cache.lock_utxo(&hash, 0, id);
+        assert_eq!(lock("req-1").await.unwrap(), LockStatus::Created);
+        assert_eq!(lock("req-2").await.unwrap(), LockStatus::OwnedByOther);
+        // repeated request prolongs its own lock
+        assert_eq!(lock("req-1").await.unwrap(), LockStatus::Renewed);
+
+        assert!(cache.renew_lock(&hash, 0, "req-1").await.unwrap());
+        assert!(!cache.renew_lock(&hash, 0, "req-2").await.unwrap());
+        let locks = cache.get_locks(&[(&hash, 0)]).await.unwrap();
+        assert_eq!(locks[0].as_ref().map(|(id, _)| id.as_str()), Some("req-1"));
+
+        cache.unlock_utxo(&hash, 0, "req-1").await.unwrap();
+        assert!(!cache.renew_lock(&hash, 0, "req-1").await.unwrap());
     }
 }
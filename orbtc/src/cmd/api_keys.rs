@@ -4,11 +4,11 @@ use crate::db;
 #[derive(Debug, clap::Parser)]
 pub enum ManageApiKeys {
     #[command(about = "Generates and save to the db new API Key")]
-    Add(Arg),
+    Add(AddArg),
     #[command(about = "Blocks API Key with passed name")]
     Block(Arg),
     #[command(about = "List API Keys")]
-    List,
+    List(ListArg),
 }
 
 #[derive(Debug, clap::Parser)]
@@ -17,6 +17,34 @@ pub struct Arg {
     name: String,
 }
 
+#[derive(Debug, clap::Parser)]
+pub struct AddArg {
+    #[arg(long)]
+    name: String,
+    /// Allows managing API keys with the `/v1/admin` endpoints.
+    #[arg(long, default_value_t = false)]
+    admin: bool,
+    /// Overrides the default requests per minute limit, 0 disables it.
+    #[arg(long)]
+    rate_limit: Option<i32>,
+    /// Comma separated list of: read, lock, broadcast, admin.
+    #[arg(long, value_delimiter = ',', default_values_t = db::scopes::DEFAULT.map(String::from))]
+    scopes: Vec<String>,
+    /// Comma separated list of networks where the key is accepted, any if empty.
+    #[arg(long, value_delimiter = ',')]
+    networks: Vec<String>,
+    /// The key is rejected after this number of days, never expires if not set.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    expires_in_days: Option<u32>,
+}
+
+#[derive(Debug, clap::Parser)]
+pub struct ListArg {
+    /// Lists expired keys too.
+    #[arg(long, default_value_t = false)]
+    show_expired: bool,
+}
+
 impl ManageApiKeys {
     pub async fn run(&self, cfg_path: &str) -> anyhow::Result<()> {
         let cfg = Config::read(cfg_path)?;
@@ -24,21 +52,52 @@ impl ManageApiKeys {
 
         match self {
             Self::Add(args) => {
-                let row = db::ApiKey::new(&args.name);
+                if let Some(scope) = args.scopes.iter().find(|s| !db::scopes::is_valid(s)) {
+                    anyhow::bail!(
+                        "invalid scope={scope}, allowed: {}",
+                        db::scopes::ALL.join(", ")
+                    );
+                }
+
+                let row = db::ApiKey {
+                    is_admin: args.admin,
+                    rate_limit_per_minute: args.rate_limit,
+                    scopes: args.scopes.clone(),
+                    networks: args.networks.clone(),
+                    expires_at: args.expires_in_days.map(db::ApiKey::expiry_in_days),
+                    ..db::ApiKey::new(&args.name)
+                };
                 println!("name: {}", args.name);
                 println!("key: {}", &row.key);
+                if let Some(expires_at) = row.expires_at {
+                    println!("expires_at: {expires_at}");
+                }
                 repo.insert_api_key(row).await?;
             }
             Self::Block(args) => {
                 repo.block_api_key(&args.name).await?;
             }
-            Self::List => {
-                let keys = repo.select_api_keys().await?;
-                println!(" NAME\t KEY\t BLOCKED\t CAN_LOCK_UTXO");
+            Self::List(args) => {
+                let keys = repo.select_api_keys(args.show_expired).await?;
+                println!(
+                    " NAME\t KEY\t BLOCKED\t CAN_LOCK_UTXO\t IS_ADMIN\t SCOPES\t NETWORKS\t EXPIRES_AT"
+                );
                 for key in keys {
+                    let expires_at = match key.expires_at {
+                        Some(_) if key.is_expired() => "expired".to_string(),
+                        Some(t) => t.to_string(),
+                        None => "never".to_string(),
+                    };
                     println!(
-                        "{}\t {}\t {}\t {}",
-                        key.name, key.key, key.blocked, key.can_lock_utxo,
+                        "{}\t {}\t {}\t {}\t {}\t {}\t {}\t {}",
+                        key.name,
+                        key.key,
+                        key.blocked,
+                        key.can_lock_utxo,
+                        key.is_admin,
+                        key.scopes.join(","),
+                        key.networks.join(","),
+                        expires_at,
                     )
                 }
             }
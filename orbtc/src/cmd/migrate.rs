@@ -1,7 +1,10 @@
+use bitcoincore_rpc::RpcApi;
 use clap::Parser;
+use tokio_util::sync::CancellationToken;
 
+use super::indexer::ReindexTarget;
 use crate::config::Config;
-use crate::db;
+use crate::{db, indexer};
 
 #[derive(Debug, Parser)]
 pub enum DbCmd {
@@ -15,8 +18,41 @@ pub enum DbCmd {
     RestoreIndexes,
     #[command(about = "Prints migrations metadata")]
     ListMigrations,
+    #[command(
+        about = "Delete outputs spent more than --keep-depth blocks ago together with their inputs"
+    )]
+    Prune(PruneCmd),
+    #[command(
+        about = "Reconcile outputs_extras.has_runes with the runes indexer outputs from --from-block"
+    )]
+    SyncExtras(SyncExtrasCmd),
+    #[command(about = "Index the txs skipped by the --indexer again, the indexer must be stopped")]
+    ReplayFailed(ReplayFailedCmd),
+}
+
+#[derive(Debug, Parser)]
+pub struct PruneCmd {
+    #[arg(long, default_value_t = 1000)]
+    keep_depth: u64,
+    #[arg(long, default_value_t = 10_000)]
+    batch_size: u64,
+}
+
+#[derive(Debug, Parser)]
+pub struct SyncExtrasCmd {
+    #[arg(long, default_value_t = 0)]
+    from_block: u64,
+}
+
+#[derive(Debug, Parser)]
+pub struct ReplayFailedCmd {
+    #[arg(long, value_enum)]
+    indexer: ReindexTarget,
 }
 
+/// Pruning is refused if any indexer is behind the node tip by more blocks than this.
+const PRUNE_MAX_LAG: u64 = 3;
+
 impl DbCmd {
     pub async fn run(&self, cfg_path: &str) -> anyhow::Result<()> {
         match self {
@@ -30,7 +66,104 @@ impl DbCmd {
                 }
                 Ok(())
             }
+            DbCmd::Prune(cmd) => cmd.run(cfg_path).await,
+            DbCmd::SyncExtras(cmd) => cmd.run(cfg_path).await,
+            DbCmd::ReplayFailed(cmd) => cmd.run(cfg_path).await,
+        }
+    }
+}
+
+impl PruneCmd {
+    pub async fn run(&self, cfg_path: &str) -> anyhow::Result<()> {
+        let cfg = Config::read(cfg_path)?;
+        if self.batch_size == 0 {
+            anyhow::bail!("--batch-size must be greater than 0");
+        }
+
+        let rpc = cfg.btc.rpc_client()?;
+        let tip = rpc.get_block_count()?;
+
+        let repo = db::open_postgres_db(&cfg.db).await?;
+        let indexed = repo.get_last_indexed_blocks().await?;
+        if indexed.is_empty() {
+            anyhow::bail!("nothing is indexed yet");
         }
+        for block in indexed.iter() {
+            let lag = tip.saturating_sub(block.height as u64);
+            if lag > PRUNE_MAX_LAG {
+                anyhow::bail!(
+                    "indexer({}) is {} blocks behind the node tip({}), wait until it is synced",
+                    block.indexer,
+                    lag,
+                    tip
+                );
+            }
+        }
+
+        let Some(max_height) = tip.checked_sub(self.keep_depth) else {
+            log::info!("chain is shorter than keep depth, nothing to prune");
+            return Ok(());
+        };
+        let max_height = max_height as i64;
+
+        repo.set_pruned_height(max_height).await?;
+        let mut remaining = repo.count_prunable_outputs(max_height).await?;
+        log::info!("prune outputs spent at or below block({max_height}): estimated={remaining}");
+
+        let mut total_outputs = 0;
+        let mut total_inputs = 0;
+        loop {
+            let (outputs, inputs) = repo
+                .prune_spent_outputs(max_height, self.batch_size as i64)
+                .await?;
+            if outputs == 0 && inputs == 0 {
+                break;
+            }
+
+            total_outputs += outputs;
+            total_inputs += inputs;
+            remaining = remaining.saturating_sub(outputs as i64);
+            log::info!(
+                "batch done: outputs={outputs} inputs={inputs} estimated_remaining={remaining}"
+            );
+        }
+
+        log::info!("pruning done: outputs={total_outputs} inputs={total_inputs}");
+        Ok(())
+    }
+}
+
+impl SyncExtrasCmd {
+    pub async fn run(&self, cfg_path: &str) -> anyhow::Result<()> {
+        let cfg = Config::read(cfg_path)?;
+        let repo = db::open_postgres_db(&cfg.db).await?;
+
+        log::info!("sync outputs extras from block({})", self.from_block);
+        let (updated, inserted) = repo
+            .sync_outputs_extras_runes(self.from_block as i64)
+            .await?;
+        log::info!("sync done: updated={updated} inserted={inserted}");
+        Ok(())
+    }
+}
+
+impl ReplayFailedCmd {
+    pub async fn run(&self, cfg_path: &str) -> anyhow::Result<()> {
+        let cfg = Config::read(cfg_path)?;
+        // the lock of the indexer is taken, so it can't run at the same time
+        let rt = indexer::BlockIndexerRt::new(&cfg.db, &cfg.btc, self.indexer.offline_opts(&cfg))?;
+
+        let cancel = CancellationToken::new();
+        let stop = cancel.clone();
+        tokio::spawn(async move {
+            crate::signal::ctrl_c().await;
+            stop.cancel();
+        });
+
+        let (replayed, failed) =
+            tokio::task::spawn_blocking(move || rt.replay_failed(cancel)).await??;
+        log::info!("replay done: replayed={replayed} failed={failed}");
+        Ok(())
     }
 }
 
@@ -4,8 +4,12 @@ use clap::Parser;
 use prometheus::Registry;
 use tokio_util::sync::CancellationToken;
 use tokio_util::task::TaskTracker;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
 
-use crate::config::Config;
+use crate::config::{Config, LogConfig, LogFormat, TxErrorPolicy};
 use crate::db;
 use crate::rest::api::Service;
 use crate::rest::metrics;
@@ -13,7 +17,8 @@ use crate::rest::metrics;
 pub mod api_keys;
 pub mod indexer;
 pub mod migrate;
-use indexer::{BtcIndexer, InscriptionsIndexer, RuneIndexer};
+pub mod verify;
+use indexer::{BtcIndexer, InscriptionsIndexer, ReindexCmd, RuneIndexer};
 
 #[derive(Debug, Parser)]
 pub enum Subcommand {
@@ -31,6 +36,9 @@ pub enum Subcommand {
     #[command(about = "Run indexer that marks utxo with inscriptions")]
     InscriptionsIndexer(InscriptionsIndexer),
 
+    #[command(about = "Reindex blocks range of one indexer without moving its tip")]
+    Reindex(ReindexCmd),
+
     #[command(about = "Run dummy indexer; only for tests")]
     Dummy(BtcIndexer),
 
@@ -45,6 +53,9 @@ pub enum Subcommand {
 
     #[command(subcommand, about = "Manage Indexer DB")]
     Db(migrate::DbCmd),
+
+    #[command(about = "Cross-check indexed utxos of addresses against the node utxo set")]
+    Verify(verify::VerifyCmd),
 }
 
 impl Subcommand {
@@ -55,9 +66,11 @@ impl Subcommand {
             Subcommand::Indexer(cmd) => cmd.run(cfg_path).await,
             Subcommand::RuneIndexer(cmd) => cmd.run(cfg_path).await,
             Subcommand::InscriptionsIndexer(cmd) => cmd.run(cfg_path).await,
+            Subcommand::Reindex(cmd) => cmd.run(cfg_path).await,
             Subcommand::ExtractTx(cmd) => cmd.run(),
             Subcommand::ApiKey(cmd) => cmd.run(cfg_path).await,
             Subcommand::Db(cmd) => cmd.run(cfg_path).await,
+            Subcommand::Verify(cmd) => cmd.run(cfg_path).await,
             Subcommand::ExampleConfig => {
                 let cfg = Config::default();
                 let output = toml::to_string_pretty(&cfg)?;
@@ -105,6 +118,33 @@ impl ExtractTxCmd {
     }
 }
 
+/// Sets up `tracing` with the `RUST_LOG` filter (`error` by default),
+/// `log` records are forwarded to it. Errors are sent to sentry if it's enabled.
+pub fn init_log(
+    cfg: &LogConfig,
+    sentry_enabled: bool,
+) -> Result<(), tracing_subscriber::util::TryInitError> {
+    let fmt = tracing_subscriber::fmt::layer();
+    let fmt = match cfg.format {
+        LogFormat::Text => fmt.boxed(),
+        LogFormat::Json => fmt
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .boxed(),
+    };
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::ERROR.into())
+        .from_env_lossy();
+
+    // sentry gets warnings and infos as breadcrumbs regardless of `RUST_LOG`
+    tracing_subscriber::registry()
+        .with(fmt.with_filter(filter))
+        .with(sentry_enabled.then(sentry_tracing::layer))
+        .try_init()
+}
+
 pub async fn run_api_server(cfg_path: &str) -> anyhow::Result<()> {
     let cfg = Config::read(cfg_path).context("unable to read config file")?;
     if cfg.db.automigrate {
@@ -143,6 +183,20 @@ pub async fn run_api_server(cfg_path: &str) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Indexers write metrics into the shared registry, this exposes them
+/// for subcommands that don't run the API.
+pub(crate) fn spawn_metrics_server(cfg: &Config, tasker: &TaskTracker, cancel: &CancellationToken) {
+    if !cfg.metrics.enable {
+        return;
+    }
+
+    tasker.spawn_local(run_metrics_server(
+        cfg.metrics.clone(),
+        cancel.clone(),
+        metrics::registry(),
+    ));
+}
+
 async fn run_api(
     cfg: Config,
     cancel: CancellationToken,
@@ -165,7 +219,8 @@ pub async fn run_all_indexer_services(
     cancel: CancellationToken,
 ) -> anyhow::Result<()> {
     use crate::indexer;
-    let _ = env_logger::try_init();
+    // a no-op if the logger is already set by the binary
+    let _ = init_log(&cfg.log, false);
 
     // create db and apply migrations if there is any
     db::apply_migrations(&cfg.db).await?;
@@ -178,12 +233,20 @@ pub async fn run_all_indexer_services(
         skip_inputs: false,
         retry_on_fail: true,
         ord_address: None,
+        ord_failure_policy: Default::default(),
+        on_tx_error: TxErrorPolicy::Halt,
         use_firehose: false,
         firehose_api_key: None,
+        firehose_endpoint: None,
+        prefetch_blocks: cfg.indexer.prefetch_blocks,
+        reorg_webhook_url: cfg.webhooks.reorg_url.clone(),
+        wait_for_lock: false,
+        stop_at_height: None,
+        disable_commitment_validation: false,
     };
 
-    let btc_indexer = indexer::BlockIndexerRt::new(&cfg.db, &cfg.btc, opts);
-    btc_indexer.start(&tasker, cancel.clone());
+    let btc_indexer = indexer::BlockIndexerRt::new(&cfg.db, &cfg.btc, opts)?;
+    btc_indexer.start(&tasker, cancel.clone())?;
 
     log::info!("Starting runes indexer");
     let opts = indexer::IndexingOpts {
@@ -193,16 +256,27 @@ pub async fn run_all_indexer_services(
         skip_inputs: true,
         retry_on_fail: true,
         ord_address: None,
+        ord_failure_policy: Default::default(),
+        on_tx_error: cfg.indexer.on_tx_error,
         use_firehose: false,
         firehose_api_key: None,
+        firehose_endpoint: None,
+        prefetch_blocks: cfg.indexer.prefetch_blocks,
+        reorg_webhook_url: cfg.webhooks.reorg_url.clone(),
+        wait_for_lock: false,
+        stop_at_height: None,
+        disable_commitment_validation: cfg.runes.disable_commitment_validation,
     };
 
-    let runes_indexer = indexer::BlockIndexerRt::new(&cfg.db, &cfg.btc, opts);
-    runes_indexer.start(&tasker, cancel.clone());
+    let runes_indexer = indexer::BlockIndexerRt::new(&cfg.db, &cfg.btc, opts)?;
+    runes_indexer.start(&tasker, cancel.clone())?;
+
+    spawn_metrics_server(&cfg, &tasker, &cancel);
+    let registry = cfg.metrics.enable.then(metrics::registry);
 
     let api_service = Service::new(cfg.clone()).await?;
     api_service.spawn_jobs(cancel.clone());
-    let api_future = run_server(cfg.api, cancel.clone(), api_service, None);
+    let api_future = run_server(cfg.api, cancel.clone(), api_service, registry);
 
     match api_future.await {
         Ok(_) => (),
@@ -0,0 +1,255 @@
+use std::collections::BTreeMap;
+
+use bitcoincore_rpc::json::ScanTxOutRequest;
+use bitcoincore_rpc::RpcApi;
+use clap::Parser;
+
+use crate::config::Config;
+use crate::db;
+use crate::indexer::BITCOIN_INDEX;
+
+#[derive(Debug, Parser)]
+pub struct VerifyCmd {
+    /// Address to verify, can be repeated. Disables random sampling.
+    #[arg(long)]
+    address: Vec<String>,
+    /// File with addresses to verify, one per line.
+    #[arg(long)]
+    file: Option<String>,
+    /// Number of random indexed addresses to verify when no addresses are passed.
+    #[arg(long, default_value_t = 100)]
+    sample_size: u64,
+    /// Stop on the first address with discrepancies.
+    #[arg(long, default_value_t = false)]
+    fail_fast: bool,
+}
+
+/// Unspent outputs of an address keyed by `(txid, vout)`, values are amounts in sats.
+type UtxoSet = BTreeMap<(String, u32), i64>;
+
+#[derive(Debug, PartialEq, Eq)]
+enum Mismatch {
+    MissingInIndex {
+        utxo: String,
+        amount: i64,
+    },
+    MissingInNode {
+        utxo: String,
+        amount: i64,
+    },
+    Amount {
+        utxo: String,
+        index: i64,
+        node: i64,
+    },
+    BalanceView {
+        view: (i64, i64),
+        outputs: (i64, i64),
+    },
+}
+
+impl std::fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Mismatch::MissingInIndex { utxo, amount } => {
+                write!(f, "utxo({utxo}) amount={amount} is unspent in node, missing in index")
+            }
+            Mismatch::MissingInNode { utxo, amount } => {
+                write!(f, "utxo({utxo}) amount={amount} is unspent in index, missing in node")
+            }
+            Mismatch::Amount { utxo, index, node } => {
+                write!(f, "utxo({utxo}) amount differs: index={index} node={node}")
+            }
+            Mismatch::BalanceView { view, outputs } => write!(
+                f,
+                "balances view differs from outputs: view(balance={}, utxo_count={}) outputs(balance={}, utxo_count={})",
+                view.0, view.1, outputs.0, outputs.1
+            ),
+        }
+    }
+}
+
+impl VerifyCmd {
+    pub async fn run(&self, cfg_path: &str) -> anyhow::Result<()> {
+        let cfg = Config::read(cfg_path)?;
+        let rpc = cfg.btc.rpc_client()?;
+        let repo = db::open_postgres_db(&cfg.db).await?;
+
+        let addresses = self.addresses(&repo).await?;
+        if addresses.is_empty() {
+            anyhow::bail!("no addresses to verify");
+        }
+        log::info!("verify addresses: count={}", addresses.len());
+
+        let mut failed = 0;
+        let mut skipped = 0;
+        for address in addresses.iter() {
+            let indexed_height = repo.get_last_indexed_block(BITCOIN_INDEX).await?;
+            let index_utxos: UtxoSet = repo
+                .select_address_unspent_outputs(address)
+                .await?
+                .into_iter()
+                .map(|u| ((u.tx_hash.to_string(), u.vout as u32), u.amount))
+                .collect();
+            let balance = repo.get_balance(address).await?;
+
+            let scan = rpc.scan_tx_out_set_blocking(&[ScanTxOutRequest::Single(format!(
+                "addr({address})"
+            ))])?;
+            if !scan.success.unwrap_or(true) {
+                anyhow::bail!("scantxoutset failed for address({address})");
+            }
+            let scan_height = scan.height.unwrap_or_default();
+            if scan_height != indexed_height {
+                log::warn!(
+                    "skip address({address}): node height({scan_height}) differs from indexed height({indexed_height})"
+                );
+                skipped += 1;
+                continue;
+            }
+            let node_utxos: UtxoSet = scan
+                .unspents
+                .iter()
+                .map(|u| ((u.txid.to_string(), u.vout), u.amount.to_sat() as i64))
+                .collect();
+
+            let mut mismatches = compare_utxos(&index_utxos, &node_utxos);
+            let outputs = (index_utxos.values().sum::<i64>(), index_utxos.len() as i64);
+            let view = (balance.balance, balance.utxo_count);
+            if view != outputs {
+                mismatches.push(Mismatch::BalanceView { view, outputs });
+            }
+
+            if mismatches.is_empty() {
+                log::debug!("address({address}) is ok: utxo_count={}", index_utxos.len());
+                continue;
+            }
+
+            failed += 1;
+            println!(
+                "MISMATCH address({address}) height={indexed_height} index_utxos={} node_utxos={}",
+                index_utxos.len(),
+                node_utxos.len()
+            );
+            for m in mismatches.iter() {
+                println!("  -> {m}");
+            }
+            if self.fail_fast {
+                break;
+            }
+        }
+
+        log::info!(
+            "verification done: checked={} failed={failed} skipped={skipped}",
+            addresses.len()
+        );
+        if failed > 0 {
+            anyhow::bail!("found discrepancies for {failed} addresses");
+        }
+
+        Ok(())
+    }
+
+    async fn addresses(&self, repo: &db::Repo) -> anyhow::Result<Vec<String>> {
+        let mut addresses = self.address.clone();
+        if let Some(path) = &self.file {
+            let data = std::fs::read_to_string(path)?;
+            addresses.extend(parse_addresses(&data));
+        }
+        if !addresses.is_empty() {
+            return Ok(addresses);
+        }
+
+        Ok(repo
+            .select_random_addresses(self.sample_size as i64)
+            .await?)
+    }
+}
+
+/// Skips empty lines and lines starting with `#`.
+fn parse_addresses(data: &str) -> impl Iterator<Item = String> + '_ {
+    data.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+}
+
+fn compare_utxos(index: &UtxoSet, node: &UtxoSet) -> Vec<Mismatch> {
+    let mut result = Vec::new();
+    for ((txid, vout), amount) in index.iter() {
+        let utxo = format!("{txid}:{vout}");
+        match node.get(&(txid.clone(), *vout)) {
+            None => result.push(Mismatch::MissingInNode {
+                utxo,
+                amount: *amount,
+            }),
+            Some(node_amount) if node_amount != amount => result.push(Mismatch::Amount {
+                utxo,
+                index: *amount,
+                node: *node_amount,
+            }),
+            Some(_) => (),
+        }
+    }
+
+    for ((txid, vout), amount) in node.iter() {
+        if !index.contains_key(&(txid.clone(), *vout)) {
+            result.push(Mismatch::MissingInIndex {
+                utxo: format!("{txid}:{vout}"),
+                amount: *amount,
+            });
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_utxos() {
+        let index: UtxoSet = [
+            (("aa".to_string(), 0), 1000),
+            (("bb".to_string(), 1), 2000),
+            (("cc".to_string(), 2), 3000),
+        ]
+        .into();
+        let node: UtxoSet = [
+            (("aa".to_string(), 0), 1000),
+            (("bb".to_string(), 1), 2500),
+            (("dd".to_string(), 0), 4000),
+        ]
+        .into();
+
+        assert!(compare_utxos(&index, &index).is_empty());
+        assert_eq!(
+            compare_utxos(&index, &node),
+            vec![
+                Mismatch::Amount {
+                    utxo: "bb:1".into(),
+                    index: 2000,
+                    node: 2500
+                },
+                Mismatch::MissingInNode {
+                    utxo: "cc:2".into(),
+                    amount: 3000
+                },
+                Mismatch::MissingInIndex {
+                    utxo: "dd:0".into(),
+                    amount: 4000
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_addresses() {
+        let data = "# regtest fixtures\nbcrt1qaddr1\n\n  bcrt1qaddr2  \n";
+        assert_eq!(
+            parse_addresses(data).collect::<Vec<_>>(),
+            vec!["bcrt1qaddr1", "bcrt1qaddr2"]
+        );
+    }
+}
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS rune_events (
+    id         BIGSERIAL PRIMARY KEY,
+    block      BIGINT    NOT NULL,
+    tx_id      INT       NOT NULL,
+    tx_hash    BYTEA     NOT NULL,
+    rune       VARCHAR   NOT NULL REFERENCES runes (name),
+    rune_id    VARCHAR   NOT NULL,
+    event_type VARCHAR   NOT NULL, -- "etch", "mint" or "burn"
+    amount     NUMERIC   NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_rune_events_block ON rune_events (block);
+CREATE INDEX IF NOT EXISTS idx_rune_events_rune_type ON rune_events (rune, event_type);
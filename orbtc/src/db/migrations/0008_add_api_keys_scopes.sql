@@ -0,0 +1,5 @@
+ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS scopes TEXT[] NOT NULL DEFAULT '{read,broadcast}';
+ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS networks TEXT[] NOT NULL DEFAULT '{}';
+
+UPDATE api_keys SET scopes = array_append(scopes, 'lock') WHERE can_lock_utxo;
+UPDATE api_keys SET scopes = array_append(scopes, 'admin') WHERE is_admin;
@@ -0,0 +1,6 @@
+-- each indexer drops its own blocks on reorg, so the same height can be orphaned several times
+ALTER TABLE orphaned_blocks ADD COLUMN IF NOT EXISTS indexer VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE orphaned_blocks ADD COLUMN IF NOT EXISTS orphaned_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+ALTER TABLE orphaned_blocks DROP CONSTRAINT IF EXISTS orphaned_blocks_pkey;
+ALTER TABLE orphaned_blocks ADD PRIMARY KEY (height, hash, indexer);
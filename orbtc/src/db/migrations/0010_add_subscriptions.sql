@@ -0,0 +1,31 @@
+CREATE TABLE IF NOT EXISTS subscriptions (
+    id           BIGSERIAL   PRIMARY KEY,
+    address      VARCHAR     NOT NULL,
+    url          VARCHAR     NOT NULL,
+    api_key_name VARCHAR     NOT NULL,
+    created_at   TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+    UNIQUE (address, url)
+);
+
+CREATE INDEX IF NOT EXISTS idx_subscriptions_api_key ON subscriptions (api_key_name);
+
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    id              BIGSERIAL   PRIMARY KEY,
+    subscription_id BIGINT      NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    block           BIGINT      NOT NULL,
+    tx_hash         BYTEA       NOT NULL,
+    vout            INT         NOT NULL,
+    address         VARCHAR     NOT NULL,
+    amount          BIGINT      NOT NULL,
+    status          VARCHAR     NOT NULL DEFAULT 'pending', -- "pending", "delivered" or "failed"
+    attempts        INT         NOT NULL DEFAULT 0,
+    last_error      VARCHAR     NULL,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+    UNIQUE (subscription_id, tx_hash, vout)
+);
+
+CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_pending
+    ON webhook_deliveries (next_attempt_at) WHERE status = 'pending';
@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS idx_rune_events_tx_hash ON rune_events (tx_hash);
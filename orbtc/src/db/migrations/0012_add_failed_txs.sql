@@ -0,0 +1,15 @@
+-- txs skipped by indexers with `SkipAndRecord` policy, `db replay-failed` indexes them again
+CREATE TABLE IF NOT EXISTS failed_txs (
+    id         BIGSERIAL   PRIMARY KEY,
+    indexer    VARCHAR     NOT NULL,
+    block      BIGINT      NOT NULL,
+    tx_id      INT         NOT NULL,
+    tx_hash    BYTEA       NOT NULL,
+    error      VARCHAR     NOT NULL,
+    attempts   INT         NOT NULL DEFAULT 1,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+    UNIQUE (indexer, tx_hash)
+);
+
+CREATE INDEX IF NOT EXISTS idx_failed_txs_indexer_block ON failed_txs (indexer, block);
@@ -0,0 +1,6 @@
+-- duplicates were left by crashes between the block data commit and the block insert
+DELETE FROM runes_outputs AS a
+    USING runes_outputs AS b
+    WHERE a.id > b.id AND a.tx_hash = b.tx_hash AND a.vout = b.vout AND a.rune = b.rune;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_runes_outputs_tx_vout_rune ON runes_outputs (tx_hash, vout, rune);
@@ -0,0 +1 @@
+ALTER TABLE last_indexed_block ADD COLUMN IF NOT EXISTS updated_at TIMESTAMPTZ NOT NULL DEFAULT now();
@@ -0,0 +1,14 @@
+-- fees of the blocks indexed by the bitcoin indexer, fee rates are in sat/vB
+CREATE TABLE IF NOT EXISTS block_stats (
+    height       BIGINT           NOT NULL PRIMARY KEY,
+    tx_count     INT              NOT NULL,
+    fee_tx_count INT              NOT NULL,
+    total_fees   BIGINT           NOT NULL,
+    fee_rate_min DOUBLE PRECISION NOT NULL,
+    fee_rate_p10 DOUBLE PRECISION NOT NULL,
+    fee_rate_p25 DOUBLE PRECISION NOT NULL,
+    fee_rate_p50 DOUBLE PRECISION NOT NULL,
+    fee_rate_p75 DOUBLE PRECISION NOT NULL,
+    fee_rate_p90 DOUBLE PRECISION NOT NULL,
+    fee_rate_max DOUBLE PRECISION NOT NULL
+);
@@ -0,0 +1,11 @@
+-- internal bookkeeping of the api users, indexers never read it
+CREATE TABLE IF NOT EXISTS address_labels (
+    address    VARCHAR     NOT NULL,
+    label      VARCHAR     NOT NULL,
+    created_by VARCHAR     NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+    PRIMARY KEY (address, label)
+);
+
+CREATE INDEX IF NOT EXISTS idx_address_labels_label ON address_labels (label);
@@ -0,0 +1,7 @@
+-- the highest block whose spent outputs may be deleted by `db prune`,
+-- history of outputs and inputs at or below it is incomplete
+CREATE TABLE IF NOT EXISTS prune_state (
+    id         BOOLEAN     NOT NULL PRIMARY KEY DEFAULT TRUE CHECK (id),
+    height     BIGINT      NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
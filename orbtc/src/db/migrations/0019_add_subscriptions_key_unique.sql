@@ -0,0 +1,10 @@
+-- subscriptions are unique per api key, so a key can't find out about subscriptions of other keys
+ALTER TABLE subscriptions DROP CONSTRAINT IF EXISTS subscriptions_address_url_key;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_subscriptions_api_key_address_url
+    ON subscriptions (api_key_name, address, url);
+-- covered by the unique index
+DROP INDEX IF EXISTS idx_subscriptions_api_key;
+
+-- keys that already have subscriptions keep managing them
+UPDATE api_keys SET scopes = array_append(scopes, 'subscriptions')
+    WHERE name IN (SELECT api_key_name FROM subscriptions) AND NOT 'subscriptions' = ANY(scopes);
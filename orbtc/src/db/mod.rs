@@ -1,6 +1,10 @@
 #![allow(clippy::too_many_arguments)]
 
+use std::time::Duration;
+
+use api_core::pages::{SortOrder, SqlOrder};
 use bigdecimal::BigDecimal;
+use futures::stream::BoxStream;
 use orbtc_indexer_api::types::Hash;
 use orbtc_indexer_api::*;
 use sqlx::migrate::{MigrateError, Migrator};
@@ -21,12 +25,25 @@ use seed_data::*;
 
 static MIGRATOR: Migrator = sqlx::migrate!("src/db/migrations");
 
+fn pool_options(config: &DBConfig) -> PgPoolOptions {
+    let mut options = PgPoolOptions::new()
+        .max_connections(config.max_connections)
+        .min_connections(config.min_connections);
+    if let Some(secs) = config.acquire_timeout_secs {
+        options = options.acquire_timeout(Duration::from_secs(secs));
+    }
+    if let Some(secs) = config.idle_timeout_secs {
+        options = options.idle_timeout(Duration::from_secs(secs));
+    }
+    options
+}
+
 pub async fn open_postgres_db(config: &DBConfig) -> Result<Repo> {
-    let pool = PgPoolOptions::new()
-        .max_connections(100)
-        .connect(&config.dsn)
-        .await?;
-    let repo = Repo { pool };
+    let pool = pool_options(config).connect(&config.dsn).await?;
+    let repo = Repo {
+        pool,
+        query_timeout: config.query_timeout_ms.map(Duration::from_millis),
+    };
 
     Ok(repo)
 }
@@ -44,18 +61,18 @@ pub fn get_migration_info() -> Vec<(
 }
 
 pub async fn apply_migrations(config: &DBConfig) -> Result<()> {
-    let pool = PgPoolOptions::new()
-        .max_connections(100)
-        .connect(&config.dsn)
-        .await?;
-    let repo = Repo { pool };
+    let pool = pool_options(config).connect(&config.dsn).await?;
+    let repo = Repo {
+        pool,
+        query_timeout: None,
+    };
 
     repo.migrate(config.force_migration).await?;
 
     if repo.get_rune(FIRST_RUNE).await?.is_none() {
         repo.insert_seed_data().await?;
-        #[cfg(feature = "test-tweak")]
-        {
+        if config.seed_test_api_key {
+            warn!("Inserting the test api key: name=test-client");
             let mut row = ApiKey::new("test-client");
             row.key = "TEST_API_KEY".into();
             repo.insert_api_key(row).await?;
@@ -65,17 +82,282 @@ pub async fn apply_migrations(config: &DBConfig) -> Result<()> {
     Ok(())
 }
 
+fn push_utxo_filters<'a>(
+    q: &mut QueryBuilder<'a, Postgres>,
+    address: &'a str,
+    amount: AmountRange,
+    skip_premature: Option<u64>,
+    exclude_inscriptions: bool,
+    exclude_runes: bool,
+) {
+    if exclude_inscriptions {
+        q.push(" LEFT JOIN outputs_extras AS e ON e.id = u.id ");
+    }
+
+    q.push(" WHERE u.address = ");
+    q.push_bind(address);
+    push_amount_range(q, sats_range(amount));
+
+    if let Some(block) = skip_premature {
+        q.push(" AND ((u.coinbase = true AND u.block < ");
+        q.push_bind(block as i64);
+        q.push(") OR u.coinbase = false) ");
+    }
+
+    if exclude_inscriptions {
+        q.push(" AND (e.has_inscriptions IS NULL OR e.has_inscriptions = false) ");
+    }
+
+    if exclude_runes {
+        q.push(
+            r#" AND NOT EXISTS (
+                SELECT 1 FROM runes_outputs AS ro
+                WHERE ro.tx_hash = u.tx_hash AND ro.vout = u.vout
+            ) "#,
+        );
+    }
+}
+
+fn push_amount_range<'a, T>(q: &mut QueryBuilder<'a, Postgres>, amount: AmountRange<T>)
+where
+    T: 'a + Send + sqlx::Encode<'a, Postgres> + sqlx::Type<Postgres>,
+{
+    if let Some(min) = amount.min {
+        q.push(" AND u.amount >= ");
+        q.push_bind(min);
+    }
+    if let Some(max) = amount.max {
+        q.push(" AND u.amount <= ");
+        q.push_bind(max);
+    }
+}
+
+/// Sats above `i64::MAX` can't be stored, so such a min bound matches nothing.
+fn sats_range(amount: AmountRange) -> AmountRange<i64> {
+    amount.map(|a| i64::try_from(a).unwrap_or(i64::MAX))
+}
+
+/// What prevents spending the utxo `u` joined with its `outputs_extras` as `e`,
+/// the first matching reason wins. `$2` is the first block with immature coinbase outputs.
+const UTXO_CATEGORY: &str = r#"
+    CASE
+        WHEN u.coinbase AND u.block >= $2 THEN 'immature'
+        WHEN EXISTS (
+            SELECT 1 FROM runes_outputs AS ro
+            WHERE ro.tx_hash = u.tx_hash AND ro.vout = u.vout
+        ) THEN 'runes'
+        WHEN e.has_inscriptions THEN 'inscriptions'
+        ELSE 'spendable'
+    END"#;
+
+/// Utxos with equal sort keys are ordered by the output position,
+/// so the pages are stable and rows don't repeat across them.
+fn utxo_sort_order(sorting: UtxoSortMode, order: OrderBy) -> SortOrder {
+    match sorting {
+        UtxoSortMode::Age => SortOrder::uniform(order),
+        // the oldest outputs go first among the equal amounts in both directions
+        UtxoSortMode::Amount => SortOrder::new(order, SqlOrder::ASC),
+        // order only breaks ties, small outputs always go first
+        UtxoSortMode::DustFirst => SortOrder::new(SqlOrder::ASC, order),
+    }
+}
+
+fn push_utxo_sorting(q: &mut QueryBuilder<'_, Postgres>, sorting: UtxoSortMode, order: OrderBy) {
+    // id is a tie-breaker, it makes the order stable for the keyset pagination
+    let SortOrder { primary, secondary } = utxo_sort_order(sorting, order);
+    match sorting {
+        UtxoSortMode::Age => {
+            q.push(format!(
+                " ORDER BY u.block {primary}, u.tx_id {secondary}, u.id {secondary} "
+            ));
+        }
+        UtxoSortMode::Amount => {
+            q.push(format!(" ORDER BY u.amount {primary}, u.id {secondary} "));
+        }
+        UtxoSortMode::DustFirst => {
+            q.push(format!(
+                " ORDER BY u.amount {primary}, u.block {secondary}, u.tx_id {secondary}, u.id {secondary} "
+            ));
+        }
+    }
+}
+
+fn push_runes_sorting(q: &mut QueryBuilder<'_, Postgres>, sort: RuneSortMode, order: OrderBy) {
+    // etching position is a tie-breaker, it makes the order stable for the pagination
+    let order = order.sql();
+    let metric = match sort {
+        RuneSortMode::Age => {
+            q.push(format!(" ORDER BY r.block {order}, r.tx_id {order} "));
+            return;
+        }
+        RuneSortMode::Holders => "COALESCE(h.holders, 0)",
+        RuneSortMode::Minted => "r.mints",
+        RuneSortMode::Supply => "r.in_circulation",
+        RuneSortMode::Progress => "r.minted / NULLIF(r.max_supply, 0)",
+    };
+    q.push(format!(
+        " ORDER BY {metric} {order} NULLS LAST, r.block {order}, r.tx_id {order} "
+    ));
+}
+
+/// Pushes keyset condition: only rows that go after the `after_id` row
+/// in the order defined by `sorting` and `order`.
+/// `table` is the raw outputs table, so the cursor row can be found even if it is already spent.
+fn push_utxo_cursor(
+    q: &mut QueryBuilder<'_, Postgres>,
+    table: &str,
+    sorting: UtxoSortMode,
+    order: OrderBy,
+    after_id: i64,
+) {
+    let SortOrder { primary, secondary } = utxo_sort_order(sorting, order);
+    let (p, s) = (primary.after_op(), secondary.after_op());
+
+    q.push(format!(
+        " AND EXISTS (SELECT 1 FROM {table} AS c WHERE c.id = "
+    ));
+    q.push_bind(after_id);
+    match sorting {
+        UtxoSortMode::Age => {
+            q.push(format!(
+                " AND (u.block, u.tx_id, u.id) {p} (c.block, c.tx_id, c.id)) "
+            ));
+        }
+        UtxoSortMode::Amount => {
+            q.push(format!(
+                " AND (u.amount {p} c.amount OR (u.amount = c.amount AND u.id {s} c.id))) "
+            ));
+        }
+        UtxoSortMode::DustFirst => {
+            q.push(format!(
+                " AND (u.amount {p} c.amount OR (u.amount = c.amount AND (u.block, u.tx_id, u.id) {s} (c.block, c.tx_id, c.id)))) "
+            ));
+        }
+    }
+}
+
 #[derive(FromRow)]
 struct Count {
     count: i64,
 }
 
-#[derive(Debug)]
+/// Outputs spent at or below `$1` height that are not referenced by the runes index.
+/// Incoming txs are found by outputs of the address and outgoing by inputs
+/// that spend them, a tx can be both.
+const ADDRESS_TXS: &str = r#"SELECT tx_hash, block, tx_id, bool_or(income) AS income, bool_or(spend) AS spend
+    FROM (
+        SELECT tx_hash, block, tx_id, true AS income, false AS spend
+        FROM outputs WHERE address = $1 AND block >= $2
+        UNION ALL
+        SELECT i.tx_hash, i.block, i.tx_id, false AS income, true AS spend
+        FROM outputs o JOIN inputs i ON i.parent_tx = o.tx_hash AND i.parent_vout = o.vout
+        WHERE o.address = $1 AND i.block >= $2
+    ) t
+    GROUP BY tx_hash, block, tx_id"#;
+
+// Blocks with activity of the address: $1 - address, $2 and $3 - inclusive block range.
+// Buckets start at `block - block % $4`, the first one is clamped to $2.
+const BTC_HISTORY_BUCKETS: &str = "
+SELECT DISTINCT GREATEST(t.block - t.block % $4, $2) AS bucket FROM (
+    SELECT o.block FROM outputs o
+    WHERE o.address = $1 AND o.block >= $2 AND o.block <= $3
+    UNION
+    SELECT i.block FROM inputs i
+    INNER JOIN outputs o
+        ON i.parent_tx = o.tx_hash AND i.parent_vout = o.vout
+    WHERE o.address = $1 AND i.block >= $2 AND i.block <= $3
+) t
+";
+
+// Same as `BTC_HISTORY_BUCKETS` for runes outputs: $1 - address, $2 - rune,
+// $3 and $4 - inclusive block range, $5 - bucket size.
+const RUNE_HISTORY_BUCKETS: &str = "
+SELECT DISTINCT GREATEST(t.block - t.block % $5, $3) AS bucket FROM (
+    SELECT o.block FROM runes_outputs o
+    WHERE o.address = $1 AND o.rune = $2 AND o.block >= $3 AND o.block <= $4
+    UNION
+    SELECT i.block FROM inputs i
+    INNER JOIN runes_outputs o
+        ON i.parent_tx = o.tx_hash AND i.parent_vout = o.vout
+    WHERE o.address = $1 AND o.rune = $2 AND i.block >= $3 AND i.block <= $4
+) t
+";
+
+// $2 includes spent outputs.
+const ADDRESS_OUTPUTS: &str = "
+FROM outputs AS o
+    JOIN addresses AS a
+        ON o.address = a.address
+    LEFT JOIN inputs AS i
+        ON o.tx_hash = i.parent_tx AND o.vout = i.parent_vout
+WHERE o.address = $1 AND ($2 OR i.id IS NULL) AND o.block >= $3
+";
+
+const PRUNABLE_OUTPUTS: &str = "
+FROM outputs AS o
+    JOIN inputs AS i
+        ON o.tx_hash = i.parent_tx AND o.vout = i.parent_vout
+WHERE i.block <= $1
+    AND NOT EXISTS (SELECT 1 FROM runes_outputs AS r WHERE r.tx_hash = o.tx_hash AND r.vout = o.vout)
+    AND NOT EXISTS (SELECT 1 FROM outputs_runes_ext AS x WHERE x.id = o.id)
+";
+
+// `updated_at` is read as unix timestamp.
+const LAST_INDEXED_BLOCK_COLUMNS: &str =
+    "indexer, height, extract(epoch from updated_at)::BIGINT AS updated_at";
+
+const BLOCK_STATS_COLUMNS: &str = "height, tx_count, fee_tx_count, total_fees, \
+    fee_rate_min, fee_rate_p10, fee_rate_p25, fee_rate_p50, fee_rate_p75, fee_rate_p90, fee_rate_max";
+
+// `created_at` is read as unix timestamp.
+const ADDRESS_LABEL_COLUMNS: &str =
+    "address, label, created_by, extract(epoch from created_at)::BIGINT AS created_at";
+
+// `expires_at` is read as unix timestamp.
+const API_KEY_COLUMNS: &str = "name, key, blocked, can_lock_utxo, is_admin, rate_limit_per_minute,
+    scopes, networks, extract(epoch from expires_at)::BIGINT AS expires_at";
+
+#[derive(Clone, Debug)]
 pub struct Repo {
     pub pool: PgPool,
+    /// `statement_timeout` of the heavy api queries, not limited if `None`.
+    pub query_timeout: Option<Duration>,
+}
+
+/// Returns `true` if the query was cancelled by the `statement_timeout`.
+pub fn is_timeout(err: &sqlx::Error) -> bool {
+    // query_canceled
+    err.as_database_error()
+        .and_then(|e| e.code())
+        .is_some_and(|code| code == "57014")
 }
 
 impl Repo {
+    /// Same repo with another timeout of the heavy queries, the pool is shared.
+    pub fn with_timeout(&self, timeout: Duration) -> Repo {
+        Repo {
+            pool: self.pool.clone(),
+            query_timeout: Some(timeout),
+        }
+    }
+
+    /// Starts a read transaction with the `statement_timeout` of the repo.
+    /// Postgres cancels the query when the timeout is exceeded, so it doesn't keep
+    /// running after the client is gone, the error is checked with [`is_timeout`].
+    /// The timeout is local to the transaction and is reset when it's dropped.
+    async fn begin_timed(&self) -> Result<sqlx::Transaction<'static, Postgres>> {
+        let mut tx = self.pool.begin().await?;
+        if let Some(timeout) = self.query_timeout {
+            sqlx::query(&format!(
+                "SET LOCAL statement_timeout = {}",
+                timeout.as_millis()
+            ))
+            .execute(&mut *tx)
+            .await?;
+        }
+        Ok(tx)
+    }
+
     pub async fn migrate(&self, force_migration: bool) -> Result<(), MigrateError> {
         loop {
             let Err(migrate_err) = MIGRATOR.run(&self.pool).await else {
@@ -147,37 +429,249 @@ impl Repo {
     }
 
     pub async fn get_last_indexed_blocks(&self) -> Result<Vec<LastIndexedBlock>> {
-        let result = sqlx::query_as::<_, LastIndexedBlock>("SELECT * FROM last_indexed_block")
-            .fetch_all(&self.pool)
-            .await?;
+        let result = sqlx::query_as::<_, LastIndexedBlock>(&format!(
+            "SELECT {LAST_INDEXED_BLOCK_COLUMNS} FROM last_indexed_block"
+        ))
+        .fetch_all(&self.pool)
+        .await?;
 
         Ok(result)
     }
 
     pub async fn get_last_indexed_block(&self, indexer: &str) -> Result<u64> {
-        let result = sqlx::query_as::<_, LastIndexedBlock>(
-            "SELECT * FROM last_indexed_block WHERE indexer = $1",
+        let result = self.get_last_indexed_block_info(indexer).await?;
+        Ok(result.map(|i| i.height as u64).unwrap_or_default())
+    }
+
+    /// Height of the indexer together with the time it was updated, `None` if nothing is indexed.
+    pub async fn get_last_indexed_block_info(
+        &self,
+        indexer: &str,
+    ) -> Result<Option<LastIndexedBlock>> {
+        sqlx::query_as::<_, LastIndexedBlock>(&format!(
+            "SELECT {LAST_INDEXED_BLOCK_COLUMNS} FROM last_indexed_block WHERE indexer = $1"
+        ))
+        .bind(indexer)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    pub async fn list_blocks(
+        &self,
+        order: OrderBy,
+        limit: u32,
+        offset: u32,
+        indexer: Option<String>,
+    ) -> Result<Vec<BlockInfo>> {
+        let mut q: DynamicQueryBuilder<Postgres> =
+            DynamicQueryBuilder::new("SELECT height, hash, blocktime, indexer FROM blocks");
+
+        q.add_and("indexer = ", indexer);
+        let q = q.query();
+
+        let SortOrder { primary, secondary } = SortOrder::new(order, SqlOrder::ASC);
+        q.push(format!(" ORDER BY height {primary}, indexer {secondary} "));
+        q.push(" LIMIT ");
+        q.push_bind(limit as i32);
+        q.push(" OFFSET ");
+        q.push_bind(offset as i32);
+
+        let result = q
+            .build_query_as::<BlockInfo>()
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(result)
+    }
+
+    /// Blocks of the indexer above `height` in ascending order.
+    pub async fn list_blocks_after(
+        &self,
+        indexer: &str,
+        height: i64,
+        limit: i64,
+    ) -> Result<Vec<BlockInfo>> {
+        sqlx::query_as::<_, BlockInfo>(
+            "SELECT height, hash, blocktime, indexer FROM blocks
+             WHERE indexer = $1 AND height > $2
+             ORDER BY height ASC
+             LIMIT $3",
+        )
+        .bind(indexer)
+        .bind(height)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn get_block_by_height(
+        &self,
+        height: i64,
+        indexer: &str,
+    ) -> Result<Option<BlockInfo>> {
+        sqlx::query_as::<_, BlockInfo>(
+            "SELECT height, hash, blocktime, indexer FROM blocks
+             WHERE height = $1 AND indexer = $2",
         )
+        .bind(height)
         .bind(indexer)
         .fetch_optional(&self.pool)
+        .await
+    }
+
+    pub async fn get_block_stats(&self, height: i64) -> Result<Option<BlockStats>> {
+        sqlx::query_as::<_, BlockStats>(&format!(
+            "SELECT {BLOCK_STATS_COLUMNS} FROM block_stats WHERE height = $1"
+        ))
+        .bind(height)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Stats of the blocks in `[from, to]` in ascending order, blocks without stats are skipped.
+    pub async fn list_block_stats(&self, from: i64, to: i64) -> Result<Vec<BlockStats>> {
+        sqlx::query_as::<_, BlockStats>(&format!(
+            "SELECT {BLOCK_STATS_COLUMNS} FROM block_stats
+             WHERE height BETWEEN $1 AND $2
+             ORDER BY height ASC"
+        ))
+        .bind(from)
+        .bind(to)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn get_blocks_at_height(&self, height: i64) -> Result<Vec<BlockInfo>> {
+        let result = sqlx::query_as::<_, BlockInfo>(
+            "SELECT height, hash, blocktime, indexer FROM blocks WHERE height = $1",
+        )
+        .bind(height)
+        .fetch_all(&self.pool)
         .await?;
 
-        Ok(result.map(|i| i.height as u64).unwrap_or_default())
+        Ok(result)
+    }
+
+    pub async fn count_blocks(&self, indexer: Option<String>) -> Result<i64> {
+        let mut q: DynamicQueryBuilder<Postgres> =
+            DynamicQueryBuilder::new("SELECT count(1) as count FROM blocks");
+
+        q.add_and("indexer = ", indexer);
+
+        let q = q.query();
+        let result = q.build_query_as::<Count>().fetch_one(&self.pool).await?;
+        Ok(result.count)
     }
 
+    /// `balances` is a plain view over unspent outputs, not a materialized one,
+    /// so it's always in sync with the indexed data and needs no refresh.
     pub async fn get_balance(&self, address: &str) -> Result<Balance> {
+        self.get_balance_with_labels(address, false).await
+    }
+
+    /// Balance of the address, its labels are joined if `include_labels` is set.
+    pub async fn get_balance_with_labels(
+        &self,
+        address: &str,
+        include_labels: bool,
+    ) -> Result<Balance> {
         let result = sqlx::query_as::<_, Balance>(
-            r#"SELECT address, balance::BIGINT, utxo_count
-               FROM balances WHERE address = $1"#,
+            r#"SELECT b.address, b.balance::BIGINT, b.utxo_count, l.labels
+               FROM balances AS b
+               LEFT JOIN LATERAL (
+                   SELECT COALESCE(array_agg(label ORDER BY label), '{}') AS labels
+                   FROM address_labels WHERE address = b.address
+               ) AS l ON $2
+               WHERE b.address = $1"#,
         )
         .bind(address)
+        .bind(include_labels)
         .fetch_optional(&self.pool)
         .await?;
 
-        Ok(result.unwrap_or(Balance {
-            address: address.into(),
-            ..Default::default()
-        }))
+        match result {
+            Some(balance) => Ok(Balance {
+                exists: Some(true),
+                ..balance
+            }),
+            None => {
+                let labels = match include_labels {
+                    true => Some(self.select_label_names(address).await?),
+                    false => None,
+                };
+                Ok(Balance {
+                    address: address.into(),
+                    exists: Some(self.address_exists(address).await?),
+                    labels,
+                    ..Default::default()
+                })
+            }
+        }
+    }
+
+    /// Names of the address labels in alphabetical order.
+    pub async fn select_label_names(&self, address: &str) -> Result<Vec<String>> {
+        sqlx::query_scalar("SELECT label FROM address_labels WHERE address = $1 ORDER BY label")
+            .bind(address)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    /// The address has received at least one output, even if all of them are spent.
+    pub async fn address_exists(&self, address: &str) -> Result<bool> {
+        sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM addresses WHERE address = $1)")
+            .bind(address)
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    /// Returns balances only for addresses that have at least one utxo.
+    pub async fn get_balances(&self, addresses: &[String]) -> Result<Vec<Balance>> {
+        let result = sqlx::query_as::<_, Balance>(
+            r#"SELECT address, balance::BIGINT, utxo_count
+               FROM balances WHERE address = ANY($1)"#,
+        )
+        .bind(addresses)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn select_address_short_utxos(&self, address: &str) -> Result<Vec<ShortUtxo>> {
+        sqlx::query_as::<_, ShortUtxo>(
+            r#"SELECT tx_hash, vout, amount
+               FROM utxos WHERE address = $1"#,
+        )
+        .bind(address)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Computes unspent outputs from the raw `outputs` and `inputs` tables,
+    /// bypassing the `utxos` view.
+    pub async fn select_address_unspent_outputs(&self, address: &str) -> Result<Vec<ShortUtxo>> {
+        sqlx::query_as::<_, ShortUtxo>(
+            r#"SELECT o.tx_hash, o.vout, o.amount
+               FROM outputs AS o
+               WHERE o.address = $1
+                 AND NOT EXISTS (
+                    SELECT 1 FROM inputs AS i
+                    WHERE i.parent_tx = o.tx_hash AND i.parent_vout = o.vout
+                 )"#,
+        )
+        .bind(address)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn select_random_addresses(&self, limit: i64) -> Result<Vec<String>> {
+        let rows: Vec<(String,)> =
+            sqlx::query_as("SELECT address FROM addresses ORDER BY random() LIMIT $1")
+                .bind(limit)
+                .fetch_all(&self.pool)
+                .await?;
+
+        Ok(rows.into_iter().map(|(address,)| address).collect())
     }
 
     pub async fn count_utxos(&self, address: &str) -> Result<i64> {
@@ -192,6 +686,95 @@ impl Repo {
         Ok(result.count)
     }
 
+    /// Coinbase outputs of blocks from `maturity_height` are immature.
+    pub async fn get_spendable_balance(
+        &self,
+        address: &str,
+        maturity_height: u64,
+    ) -> Result<SpendableBalance> {
+        let query = format!(
+            r#"
+            WITH c AS (
+                SELECT u.amount, {UTXO_CATEGORY} AS category
+                FROM utxos AS u
+                    LEFT JOIN outputs_extras AS e
+                        ON e.id = u.id
+                WHERE u.address = $1
+            )
+            SELECT
+                COALESCE(sum(amount), 0)::BIGINT AS balance,
+                COALESCE(sum(amount) FILTER (WHERE category = 'spendable'), 0)::BIGINT AS spendable,
+                COALESCE(sum(amount) FILTER (WHERE category = 'immature'), 0)::BIGINT AS immature,
+                COALESCE(sum(amount) FILTER (WHERE category = 'runes'), 0)::BIGINT AS with_runes,
+                COALESCE(sum(amount) FILTER (WHERE category = 'inscriptions'), 0)::BIGINT
+                    AS with_inscriptions
+            FROM c
+            "#
+        );
+        let result = sqlx::query_as::<_, SpendableBalance>(&query)
+            .bind(address)
+            .bind(maturity_height as i64)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    /// Utxos counted as `spendable` by [`Repo::get_spendable_balance`].
+    pub async fn select_spendable_short_utxos(
+        &self,
+        address: &str,
+        maturity_height: u64,
+    ) -> Result<Vec<ShortUtxo>> {
+        let query = format!(
+            r#"
+            SELECT u.tx_hash, u.vout, u.amount
+            FROM utxos AS u
+                LEFT JOIN outputs_extras AS e
+                    ON e.id = u.id
+            WHERE u.address = $1 AND {UTXO_CATEGORY} = 'spendable'
+            "#
+        );
+        sqlx::query_as::<_, ShortUtxo>(&query)
+            .bind(address)
+            .bind(maturity_height as i64)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    /// `mature_height` is the height of the last block with mature coinbase outputs.
+    pub async fn get_utxo_stats(&self, address: &str, mature_height: u64) -> Result<UtxoStats> {
+        let result = sqlx::query_as::<_, UtxoStats>(
+            r#"
+            SELECT
+                $1 AS address,
+                count(1) AS count,
+                COALESCE(sum(u.amount), 0)::BIGINT AS total_amount,
+                COALESCE(min(u.amount), 0) AS min_amount,
+                COALESCE(max(u.amount), 0) AS max_amount,
+                COALESCE(avg(u.amount), 0)::BIGINT AS avg_amount,
+                count(1) FILTER (WHERE u.coinbase AND u.block > $2) AS immature_count,
+                count(1) FILTER (
+                    WHERE EXISTS (
+                        SELECT 1 FROM runes_utxos AS r
+                        WHERE r.tx_hash = u.tx_hash AND r.vout = u.vout
+                    )
+                ) AS runes_count,
+                count(1) FILTER (WHERE e.has_inscriptions) AS inscriptions_count
+            FROM utxos AS u
+                LEFT JOIN outputs_extras AS e
+                    ON e.id = u.id
+            WHERE u.address = $1
+            "#,
+        )
+        .bind(address)
+        .bind(mature_height as i64)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(result)
+    }
+
     pub async fn get_address_btc_utxo_ge_amount(
         &self,
         address: &str,
@@ -215,42 +798,62 @@ impl Repo {
         Ok(result)
     }
 
+    pub async fn count_filtered_utxos(
+        &self,
+        address: &str,
+        amount: AmountRange,
+        skip_premature: Option<u64>,
+        exclude_inscriptions: bool,
+        exclude_runes: bool,
+    ) -> Result<i64> {
+        let mut q = QueryBuilder::new("SELECT count(1) as count FROM utxos AS u");
+        push_utxo_filters(
+            &mut q,
+            address,
+            amount,
+            skip_premature,
+            exclude_inscriptions,
+            exclude_runes,
+        );
+
+        let result = q.build_query_as::<Count>().fetch_one(&self.pool).await?;
+        Ok(result.count)
+    }
+
     pub async fn select_utxo_with_pagination(
         &self,
         address: &str,
         order: OrderBy,
-        amount_threshold: Option<u64>,
+        amount: AmountRange,
         skip_premature: Option<u64>,
+        exclude_inscriptions: bool,
+        exclude_runes: bool,
         sorting: UtxoSortMode,
+        after_id: Option<i64>,
         limit: u32,
         offset: u32,
     ) -> Result<Vec<BtcUtxo>> {
-        let mut q = QueryBuilder::new("SELECT * FROM utxos WHERE address = ");
-        q.push_bind(address);
-        if let Some(am) = amount_threshold {
-            q.push(" AND amount > ");
-            q.push_bind(am as i64);
-        }
-
-        if let Some(block) = skip_premature {
-            q.push(" AND ((coinbase = true AND block < ");
-            q.push_bind(block as i64);
-            q.push(") OR coinbase = false) ");
+        let mut q = QueryBuilder::new("SELECT u.* FROM utxos AS u");
+        push_utxo_filters(
+            &mut q,
+            address,
+            amount,
+            skip_premature,
+            exclude_inscriptions,
+            exclude_runes,
+        );
+        if let Some(id) = after_id {
+            push_utxo_cursor(&mut q, "outputs", sorting, order, id);
         }
 
-        match sorting {
-            UtxoSortMode::Age => {
-                q.push(format!(" ORDER BY block {order}, tx_id {order} "));
-            }
-            UtxoSortMode::Amount => {
-                q.push(format!(" ORDER BY amount {order} "));
-            }
-        }
+        push_utxo_sorting(&mut q, sorting, order);
 
         q.push(" LIMIT ");
         q.push_bind(limit as i32);
-        q.push(" OFFSET ");
-        q.push_bind(offset as i32);
+        if after_id.is_none() {
+            q.push(" OFFSET ");
+            q.push_bind(offset as i32);
+        }
 
         let result = q.build_query_as::<BtcUtxo>().fetch_all(&self.pool).await?;
         Ok(result)
@@ -279,7 +882,14 @@ impl Repo {
         .await
     }
 
-    pub async fn select_tx_outputs_sum(&self, address: &str) -> Result<Vec<BtcOutputsSum>> {
+    /// Sums outputs of the address by tx within the inclusive `[from_block, to_block]` range,
+    /// ordered by block.
+    pub fn stream_tx_outputs_sum<'a>(
+        &'a self,
+        address: &'a str,
+        from_block: i64,
+        to_block: i64,
+    ) -> BoxStream<'a, Result<BtcOutputsSum>> {
         sqlx::query_as::<_, BtcOutputsSum>(
             r#"SELECT
                 o.block,
@@ -288,13 +898,86 @@ impl Repo {
                 sum(o.amount)::BIGINT as amount,
                 count(*) as count
                FROM outputs o
-               WHERE o.address = $1
+               WHERE o.address = $1 AND o.block >= $2 AND o.block <= $3
                GROUP BY o.block, o.tx_id, o.address
                ORDER BY o.block, o.tx_id"#,
         )
         .bind(address)
-        .fetch_all(&self.pool)
-        .await
+        .bind(from_block)
+        .bind(to_block)
+        .fetch(&self.pool)
+    }
+
+    /// Btc balance of the address before the `block`.
+    pub async fn select_opening_balance(&self, address: &str, block: i64) -> Result<i64> {
+        let mut tx = self.begin_timed().await?;
+        let (balance,): (i64,) = sqlx::query_as(
+            r#"SELECT (
+                COALESCE((
+                    SELECT sum(o.amount) FROM outputs o
+                    WHERE o.address = $1 AND o.block < $2
+                ), 0) -
+                COALESCE((
+                    SELECT sum(o.amount) FROM inputs i
+                    INNER JOIN outputs o
+                       ON i.parent_tx = o.tx_hash AND i.parent_vout = o.vout
+                    WHERE o.address = $1 AND i.block < $2
+                ), 0)
+               )::BIGINT"#,
+        )
+        .bind(address)
+        .bind(block)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        Ok(balance)
+    }
+
+    /// Returns first blocks of the balance history buckets of the page.
+    pub async fn select_history_buckets(
+        &self,
+        address: &str,
+        from_block: i64,
+        to_block: i64,
+        bucket_size: i64,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<i64>> {
+        let mut tx = self.begin_timed().await?;
+        let rows: Vec<(i64,)> = sqlx::query_as(&format!(
+            "{BTC_HISTORY_BUCKETS} ORDER BY bucket LIMIT $5 OFFSET $6"
+        ))
+        .bind(address)
+        .bind(from_block)
+        .bind(to_block)
+        .bind(bucket_size)
+        .bind(limit as i64)
+        .bind(offset as i64)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        Ok(rows.into_iter().map(|(b,)| b).collect())
+    }
+
+    pub async fn count_history_buckets(
+        &self,
+        address: &str,
+        from_block: i64,
+        to_block: i64,
+        bucket_size: i64,
+    ) -> Result<i64> {
+        let mut tx = self.begin_timed().await?;
+        let row = sqlx::query_as::<_, Count>(&format!(
+            "SELECT count(*) AS count FROM ({BTC_HISTORY_BUCKETS}) b"
+        ))
+        .bind(address)
+        .bind(from_block)
+        .bind(to_block)
+        .bind(bucket_size)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        Ok(row.count)
     }
 
     pub async fn select_tx_inputs_ext(&self, tx_hash: &Hash) -> Result<Vec<InputFull>> {
@@ -325,7 +1008,14 @@ impl Repo {
         .await
     }
 
-    pub async fn select_tx_inputs_sum(&self, address: &str) -> Result<Vec<InputsSum>> {
+    /// Sums spent outputs of the address by spending tx within the inclusive `[from_block, to_block]` range,
+    /// ordered by block.
+    pub fn stream_tx_inputs_sum<'a>(
+        &'a self,
+        address: &'a str,
+        from_block: i64,
+        to_block: i64,
+    ) -> BoxStream<'a, Result<InputsSum>> {
         sqlx::query_as::<_, InputsSum>(
             r#"SELECT
                 i.block,
@@ -336,13 +1026,14 @@ impl Repo {
                FROM inputs i
                INNER JOIN outputs o
                   ON i.parent_tx = o.tx_hash AND i.parent_vout = o.vout
-               WHERE o.address = $1
+               WHERE o.address = $1 AND i.block >= $2 AND i.block <= $3
                GROUP BY i.block, i.tx_id, o.address
                ORDER by i.block, i.tx_id"#,
         )
         .bind(address)
-        .fetch_all(&self.pool)
-        .await
+        .bind(from_block)
+        .bind(to_block)
+        .fetch(&self.pool)
     }
 
     pub async fn select_utxos_with_amount_bounds(
@@ -435,21 +1126,55 @@ impl Repo {
         Ok(result)
     }
 
+    pub async fn select_runes_by_rune_ids(&self, rune_ids: &[String]) -> Result<Vec<Rune>> {
+        let result = sqlx::query_as::<_, Rune>("SELECT * FROM runes WHERE rune_id = ANY($1)")
+            .bind(rune_ids)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    pub async fn get_rune_by_rune_id(&self, rune_id: &str) -> Result<Option<Rune>> {
+        let result = sqlx::query_as::<_, Rune>("SELECT * FROM runes WHERE rune_id = $1")
+            .bind(rune_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(result)
+    }
+
     pub async fn list_runes(
         &self,
+        sort: RuneSortMode,
         order: OrderBy,
         limit: u32,
         offset: u32,
         name: Option<String>,
         is_featured: Option<bool>,
+        rune_ids: Vec<String>,
+        from_block: Option<i64>,
+        to_block: Option<i64>,
     ) -> Result<Vec<Rune>> {
-        let mut q: DynamicQueryBuilder<Postgres> = DynamicQueryBuilder::new("SELECT * FROM runes");
-
-        q.add_and("is_featured = ", is_featured)
-            .add_and("name ILIKE ", name.map(|n| format!("%{}%", n)));
+        // holders are aggregated only when they are needed, it's the most expensive part
+        let select = match sort {
+            RuneSortMode::Holders => {
+                "SELECT r.* FROM runes AS r
+                 LEFT JOIN (
+                    SELECT rune, count(*) AS holders FROM runes_balances GROUP BY rune
+                 ) AS h ON h.rune = r.name"
+            }
+            _ => "SELECT r.* FROM runes AS r",
+        };
+        let mut q: DynamicQueryBuilder<Postgres> = DynamicQueryBuilder::new(select);
+
+        q.add_and("r.is_featured = ", is_featured)
+            .add_and("r.name ILIKE ", name.map(|n| format!("%{}%", n)))
+            .add_and_in("r.rune_id", rune_ids)
+            .add_and_range("r.block", from_block, to_block);
         let q = q.query();
 
-        q.push(format!(" ORDER BY block {order}, tx_id {order} "));
+        push_runes_sorting(q, sort, order);
         q.push(" LIMIT ");
         q.push_bind(limit as i32);
         q.push(" OFFSET ");
@@ -460,16 +1185,75 @@ impl Repo {
         Ok(results)
     }
 
-    pub async fn count_runes(
+    /// Holders, utxos and supply of the runes page, filters and sorting are the same as in the `list_runes`.
+    pub async fn list_runes_stats(
         &self,
+        sort: RuneSortMode,
+        order: OrderBy,
+        limit: u32,
+        offset: u32,
         name: Option<String>,
         is_featured: Option<bool>,
-    ) -> Result<i64> {
-        let mut q: DynamicQueryBuilder<Postgres> =
-            DynamicQueryBuilder::new("SELECT count(1) as count FROM runes");
+        rune_ids: Vec<String>,
+        from_block: Option<i64>,
+        to_block: Option<i64>,
+    ) -> Result<Vec<RuneStats>> {
+        // holders are aggregated over all runes, last activity only for the runes of the page
+        let mut q: DynamicQueryBuilder<Postgres> = DynamicQueryBuilder::new(
+            "SELECT
+                r.name AS rune,
+                r.rune_id,
+                COALESCE(h.holders, 0) AS holders,
+                COALESCE(h.utxo_count, 0) AS utxo_count,
+                r.in_circulation,
+                r.minted,
+                r.burned,
+                a.last_activity_block
+             FROM runes AS r
+             LEFT JOIN (
+                SELECT rune, count(*) AS holders, sum(utxo_count)::BIGINT AS utxo_count
+                FROM runes_balances GROUP BY rune
+             ) AS h ON h.rune = r.name
+             LEFT JOIN LATERAL (
+                SELECT max(o.block) AS last_activity_block
+                FROM runes_outputs AS o WHERE o.rune = r.name
+             ) AS a ON true",
+        );
+
+        q.add_and("r.is_featured = ", is_featured)
+            .add_and("r.name ILIKE ", name.map(|n| format!("%{}%", n)))
+            .add_and_in("r.rune_id", rune_ids)
+            .add_and_range("r.block", from_block, to_block);
+        let q = q.query();
+
+        push_runes_sorting(q, sort, order);
+        q.push(" LIMIT ");
+        q.push_bind(limit as i32);
+        q.push(" OFFSET ");
+        q.push_bind(offset as i32);
+
+        let results = q
+            .build_query_as::<RuneStats>()
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(results)
+    }
+
+    pub async fn count_runes(
+        &self,
+        name: Option<String>,
+        is_featured: Option<bool>,
+        rune_ids: Vec<String>,
+        from_block: Option<i64>,
+        to_block: Option<i64>,
+    ) -> Result<i64> {
+        let mut q: DynamicQueryBuilder<Postgres> =
+            DynamicQueryBuilder::new("SELECT count(1) as count FROM runes");
 
         q.add_and("is_featured = ", is_featured)
-            .add_and("name ILIKE ", name.map(|n| format!("%{}%", n)));
+            .add_and("name ILIKE ", name.map(|n| format!("%{}%", n)))
+            .add_and_in("rune_id", rune_ids)
+            .add_and_range("block", from_block, to_block);
 
         let q = q.query();
         let query = q.build_query_as::<Count>();
@@ -488,48 +1272,150 @@ impl Repo {
         Ok(result)
     }
 
-    pub async fn count_runes_utxo(&self, rune: &str, address: &str) -> Result<i64> {
-        let mut q = QueryBuilder::new("SELECT count(1) as count FROM runes_utxos WHERE rune = ");
+    pub async fn count_runes_utxo(
+        &self,
+        rune: &str,
+        address: &str,
+        amount: AmountRange<BigDecimal>,
+    ) -> Result<i64> {
+        let mut q =
+            QueryBuilder::new("SELECT count(1) as count FROM runes_utxos AS u WHERE u.rune = ");
         q.push_bind(rune);
-        q.push(" AND address = ");
+        q.push(" AND u.address = ");
         q.push_bind(address);
+        push_amount_range(&mut q, amount);
 
         let result = q.build_query_as::<Count>().fetch_one(&self.pool).await?;
         Ok(result.count)
     }
 
+    pub async fn list_rune_activity(
+        &self,
+        rune: &str,
+        order: OrderBy,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<RuneActivity>> {
+        let mut q = QueryBuilder::new(
+            r#"
+            SELECT
+                block,
+                tx_hash,
+                tx_id,
+                sum(amount) AS total_amount,
+                count(*) AS outputs_count,
+                count(DISTINCT address) AS addresses_involved
+            FROM runes_outputs
+            WHERE rune = "#,
+        );
+        q.push_bind(rune);
+        q.push(format!(
+            " GROUP BY block, tx_id, tx_hash ORDER BY block {order}, tx_id {order} ",
+            order = order.sql()
+        ));
+        q.push(" LIMIT ");
+        q.push_bind(limit as i32);
+        q.push(" OFFSET ");
+        q.push_bind(offset as i32);
+
+        q.build_query_as::<RuneActivity>()
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    pub async fn count_rune_activity(&self, rune: &str) -> Result<i64> {
+        let row = sqlx::query_as::<_, Count>(
+            "SELECT count(DISTINCT (block, tx_id)) AS count FROM runes_outputs WHERE rune = $1",
+        )
+        .bind(rune)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(row.count)
+    }
+
+    pub async fn list_rune_events(
+        &self,
+        rune: &str,
+        event_type: Option<RuneEventType>,
+        order: OrderBy,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<RuneEvent>> {
+        let mut q = QueryBuilder::new(
+            "SELECT block, tx_id, tx_hash, rune, rune_id, event_type, amount FROM rune_events WHERE rune = ",
+        );
+        q.push_bind(rune);
+        if let Some(event_type) = event_type {
+            q.push(" AND event_type = ");
+            q.push_bind(event_type.to_string());
+        }
+
+        q.push(format!(
+            " ORDER BY block {order}, tx_id {order}, id {order} ",
+            order = order.sql()
+        ));
+        q.push(" LIMIT ");
+        q.push_bind(limit as i32);
+        q.push(" OFFSET ");
+        q.push_bind(offset as i32);
+
+        q.build_query_as::<RuneEvent>().fetch_all(&self.pool).await
+    }
+
+    /// Mints, etchings and burns made by the tx.
+    pub async fn select_tx_rune_events(&self, tx_hash: &Hash) -> Result<Vec<RuneEvent>> {
+        sqlx::query_as::<_, RuneEvent>(
+            "SELECT block, tx_id, tx_hash, rune, rune_id, event_type, amount FROM rune_events WHERE tx_hash = $1 ORDER BY id",
+        )
+        .bind(tx_hash)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn count_rune_events(
+        &self,
+        rune: &str,
+        event_type: Option<RuneEventType>,
+    ) -> Result<i64> {
+        let mut q = QueryBuilder::new("SELECT count(*) AS count FROM rune_events WHERE rune = ");
+        q.push_bind(rune);
+        if let Some(event_type) = event_type {
+            q.push(" AND event_type = ");
+            q.push_bind(event_type.to_string());
+        }
+
+        let row = q.build_query_as::<Count>().fetch_one(&self.pool).await?;
+        Ok(row.count)
+    }
+
     pub async fn select_rune_utxo_with_pagination(
         &self,
         rune: &str,
         address: &str,
         order: OrderBy,
-        amount_threshold: Option<u64>,
+        amount: AmountRange<BigDecimal>,
         sorting: UtxoSortMode,
+        after_id: Option<i64>,
         limit: u32,
         offset: u32,
     ) -> Result<Vec<RuneUtxo>> {
-        let mut q = QueryBuilder::new("SELECT * FROM runes_utxos WHERE address = ");
+        let mut q = QueryBuilder::new("SELECT u.* FROM runes_utxos AS u WHERE u.address = ");
         q.push_bind(address);
-        q.push(" AND rune = ");
+        q.push(" AND u.rune = ");
         q.push_bind(rune);
-        if let Some(am) = amount_threshold {
-            q.push(" AND amount > ");
-            q.push_bind(am as i64);
+        push_amount_range(&mut q, amount);
+        if let Some(id) = after_id {
+            push_utxo_cursor(&mut q, "runes_outputs", sorting, order, id);
         }
 
-        match sorting {
-            UtxoSortMode::Age => {
-                q.push(format!(" ORDER BY block {order}, tx_id {order} "));
-            }
-            UtxoSortMode::Amount => {
-                q.push(format!(" ORDER BY amount {order} "));
-            }
-        }
+        push_utxo_sorting(&mut q, sorting, order);
 
         q.push(" LIMIT ");
         q.push_bind(limit as i32);
-        q.push(" OFFSET ");
-        q.push_bind(offset as i32);
+        if after_id.is_none() {
+            q.push(" OFFSET ");
+            q.push_bind(offset as i32);
+        }
 
         let result = q.build_query_as::<RuneUtxo>().fetch_all(&self.pool).await?;
         Ok(result)
@@ -583,13 +1469,14 @@ impl Repo {
         )
         .bind(address)
         .bind(rune)
-        .bind(amount as i64)
+        .bind(BigDecimal::from(amount))
         .fetch_optional(&self.pool)
         .await?;
 
         Ok(result)
     }
 
+    /// `runes_balances` is a plain view like `balances`, it needs no refresh.
     pub async fn get_runes_balances(&self, address: &str) -> Result<Vec<RuneBalance>> {
         let result = sqlx::query_as::<_, RuneBalance>(
             r#"
@@ -616,6 +1503,44 @@ impl Repo {
         Ok(result)
     }
 
+    pub async fn get_runes_balances_multi(
+        &self,
+        addresses: &[String],
+        runes: Option<&[String]>,
+    ) -> Result<Vec<RuneBalance>> {
+        let mut q = QueryBuilder::new(
+            r#"
+            SELECT
+                b.address,
+                b.rune,
+                b.rune_id,
+                r.symbol,
+                r.divisibility,
+                b.balance,
+                b.btc_balance::bigint,
+                b.utxo_count
+            FROM
+                runes_balances b
+            JOIN
+                runes r ON b.rune = r.name
+            WHERE
+                b.address = ANY("#,
+        );
+        q.push_bind(addresses);
+        q.push(")");
+        if let Some(runes) = runes {
+            q.push(" AND b.rune = ANY(");
+            q.push_bind(runes);
+            q.push(")");
+        }
+
+        let result = q
+            .build_query_as::<RuneBalance>()
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(result)
+    }
+
     pub async fn get_rune_balance(&self, address: &str, rune: &str) -> Result<RuneBalance> {
         let result = sqlx::query_as::<_, RuneBalance>(
             r#"
@@ -641,57 +1566,85 @@ impl Repo {
         .fetch_optional(&self.pool)
         .await?;
 
-        Ok(result.unwrap_or(RuneBalance {
-            address: address.into(),
-            rune: rune.into(),
-            ..Default::default()
-        }))
+        match result {
+            Some(balance) => Ok(RuneBalance {
+                exists: Some(true),
+                ..balance
+            }),
+            None => Ok(RuneBalance {
+                address: address.into(),
+                rune: rune.into(),
+                exists: Some(self.address_exists(address).await?),
+                ..Default::default()
+            }),
+        }
     }
 
     pub async fn get_rune_holders(
         &self,
         rune: &str,
         order: OrderBy,
-        amount_threshold: Option<u64>,
+        amount_threshold: Option<BigDecimal>,
         limit: u32,
         offset: u32,
-    ) -> Result<Vec<RuneBalance>> {
+    ) -> Result<Vec<RuneHolder>> {
+        let mut tx = self.begin_timed().await?;
+        // rank is computed over all filtered holders, so it doesn't depend on the page
         let mut q = QueryBuilder::new(
             r#"
-            SELECT
-                b.address,
-                b.rune,
-                b.rune_id,
-                r.symbol,
-                r.divisibility,
-                b.balance,
-                b.btc_balance::bigint,
-                b.utxo_count
-            FROM
-                runes_balances b
-            JOIN runes r ON b.rune = r.name
-            WHERE b.rune = "#,
+            SELECT * FROM (
+                SELECT
+                    b.address,
+                    b.rune,
+                    b.rune_id,
+                    r.symbol,
+                    r.divisibility,
+                    b.balance,
+                    b.btc_balance::bigint,
+                    b.utxo_count,
+                    ROW_NUMBER() OVER (ORDER BY b.balance DESC, b.address ASC) AS rank
+                FROM
+                    runes_balances b
+                JOIN runes r ON b.rune = r.name
+                WHERE b.rune = "#,
         );
         q.push_bind(rune);
 
         if let Some(am) = amount_threshold {
             q.push(" AND b.balance > ");
-            q.push_bind(am as i64);
+            q.push_bind(am);
         }
 
-        q.push(format!(" ORDER BY b.balance {order} "));
+        // the biggest holder has the first rank
+        q.push(format!(") AS h ORDER BY h.rank {} ", order.sql().reverse()));
         q.push(" LIMIT ");
         q.push_bind(limit as i32);
         q.push(" OFFSET ");
         q.push_bind(offset as i32);
 
-        let result = q
-            .build_query_as::<RuneBalance>()
-            .fetch_all(&self.pool)
-            .await?;
+        let result = q.build_query_as::<RuneHolder>().fetch_all(&mut *tx).await?;
         Ok(result)
     }
 
+    pub async fn count_rune_holders(
+        &self,
+        rune: &str,
+        amount_threshold: Option<BigDecimal>,
+    ) -> Result<i64> {
+        let mut tx = self.begin_timed().await?;
+        let mut q =
+            QueryBuilder::new("SELECT count(*) AS count FROM runes_balances b WHERE b.rune = ");
+        q.push_bind(rune);
+
+        if let Some(am) = amount_threshold {
+            q.push(" AND b.balance > ");
+            q.push_bind(am);
+        }
+
+        let row = q.build_query_as::<Count>().fetch_one(&mut *tx).await?;
+        Ok(row.count)
+    }
+
     pub async fn select_runes_utxo_for_txs(&self, tx_ids: &[&Hash]) -> Result<Vec<ShortTxOut>> {
         sqlx::query_as::<_, ShortTxOut>(
             r#"SELECT tx_hash, vout FROM runes_utxos
@@ -702,6 +1655,24 @@ impl Repo {
         .await
     }
 
+    /// `tx_hashes` and `vouts` are pairs of the outpoints.
+    pub async fn select_outputs_addresses(
+        &self,
+        tx_hashes: &[Hash],
+        vouts: &[i32],
+    ) -> Result<Vec<ShortTxOutAddress>> {
+        sqlx::query_as::<_, ShortTxOutAddress>(
+            r#"SELECT o.tx_hash, o.vout, o.address
+            FROM outputs o
+            JOIN UNNEST($1::BYTEA[], $2::INT[]) AS t(tx_hash, vout)
+              ON o.tx_hash = t.tx_hash AND o.vout = t.vout"#,
+        )
+        .bind(tx_hashes)
+        .bind(vouts)
+        .fetch_all(&self.pool)
+        .await
+    }
+
     pub async fn select_outputs_extras(&self, ids: &[i64]) -> Result<Vec<OutputExtras>> {
         sqlx::query_as::<_, OutputExtras>(
             r#"SELECT id, has_runes, has_inscriptions
@@ -713,18 +1684,20 @@ impl Repo {
         .await
     }
 
+    /// Returns extras of the outputs that hold given runes utxos.
+    /// `OutputExtras::id` is the id of the runes utxo, not of the output.
     pub async fn select_outputs_extras_by_rune_ids(
         &self,
         ids: &[i64],
     ) -> Result<Vec<OutputExtras>> {
         sqlx::query_as::<_, OutputExtras>(
-            r#"SELECT * FROM outputs_extras
-               WHERE id IN (
-                  SELECT o.id FROM runes_outputs ro
-                  JOIN outputs o
-                  ON o.tx_hash = ro.tx_hash AND o.vout = ro.vout
-                  WHERE ro.id = ANY($1)
-            )"#,
+            r#"SELECT ro.id, e.has_runes, e.has_inscriptions
+               FROM runes_outputs ro
+               JOIN outputs o
+                 ON o.tx_hash = ro.tx_hash AND o.vout = ro.vout
+               JOIN outputs_extras e
+                 ON e.id = o.id
+               WHERE ro.id = ANY($1)"#,
         )
         .bind(ids)
         .fetch_all(&self.pool)
@@ -755,11 +1728,13 @@ impl Repo {
         .await
     }
 
-    pub async fn select_tx_runes_outputs_sum(
-        &self,
-        address: &str,
-        rune: &str,
-    ) -> Result<Vec<RuneOutputsSum>> {
+    pub fn stream_tx_runes_outputs_sum<'a>(
+        &'a self,
+        address: &'a str,
+        rune: &'a str,
+        from_block: i64,
+        to_block: i64,
+    ) -> BoxStream<'a, Result<RuneOutputsSum>> {
         sqlx::query_as::<_, RuneOutputsSum>(
             r#"SELECT
                 o.block,
@@ -769,16 +1744,99 @@ impl Repo {
                 sum(o.amount) as amount,
                 count(*)
                FROM runes_outputs o
-               WHERE o.address = $1 AND o.rune = $2
+               WHERE o.address = $1 AND o.rune = $2 AND o.block >= $3 AND o.block <= $4
                GROUP BY o.block, o.tx_id, o.address
                ORDER BY o.block, o.tx_id"#,
         )
         .bind(address)
         .bind(rune)
-        .fetch_all(&self.pool)
+        .bind(from_block)
+        .bind(to_block)
+        .fetch(&self.pool)
+    }
+
+    /// Rune and btc balances of the address before the `block`.
+    pub async fn select_rune_opening_balance(
+        &self,
+        address: &str,
+        rune: &str,
+        block: i64,
+    ) -> Result<(BigDecimal, i64)> {
+        let mut tx = self.begin_timed().await?;
+        sqlx::query_as(
+            r#"SELECT
+                COALESCE(r.amount, 0) - COALESCE(s.amount, 0),
+                (COALESCE(r.btc_amount, 0) - COALESCE(s.btc_amount, 0))::BIGINT
+               FROM (
+                    SELECT sum(o.amount) AS amount, sum(o.btc_amount) AS btc_amount
+                    FROM runes_outputs o
+                    WHERE o.address = $1 AND o.rune = $2 AND o.block < $3
+               ) r, (
+                    SELECT sum(o.amount) AS amount, sum(o.btc_amount) AS btc_amount
+                    FROM inputs i
+                    INNER JOIN runes_outputs o
+                       ON i.parent_tx = o.tx_hash AND i.parent_vout = o.vout
+                    WHERE o.address = $1 AND o.rune = $2 AND i.block < $3
+               ) s"#,
+        )
+        .bind(address)
+        .bind(rune)
+        .bind(block)
+        .fetch_one(&mut *tx)
         .await
     }
 
+    /// Returns first blocks of the rune balance history buckets of the page.
+    pub async fn select_rune_history_buckets(
+        &self,
+        address: &str,
+        rune: &str,
+        from_block: i64,
+        to_block: i64,
+        bucket_size: i64,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<i64>> {
+        let mut tx = self.begin_timed().await?;
+        let rows: Vec<(i64,)> = sqlx::query_as(&format!(
+            "{RUNE_HISTORY_BUCKETS} ORDER BY bucket LIMIT $6 OFFSET $7"
+        ))
+        .bind(address)
+        .bind(rune)
+        .bind(from_block)
+        .bind(to_block)
+        .bind(bucket_size)
+        .bind(limit as i64)
+        .bind(offset as i64)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        Ok(rows.into_iter().map(|(b,)| b).collect())
+    }
+
+    pub async fn count_rune_history_buckets(
+        &self,
+        address: &str,
+        rune: &str,
+        from_block: i64,
+        to_block: i64,
+        bucket_size: i64,
+    ) -> Result<i64> {
+        let mut tx = self.begin_timed().await?;
+        let row = sqlx::query_as::<_, Count>(&format!(
+            "SELECT count(*) AS count FROM ({RUNE_HISTORY_BUCKETS}) b"
+        ))
+        .bind(address)
+        .bind(rune)
+        .bind(from_block)
+        .bind(to_block)
+        .bind(bucket_size)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        Ok(row.count)
+    }
+
     pub async fn select_tx_runes_inputs_ext(&self, tx_hash: &Hash) -> Result<Vec<RuneInputFull>> {
         sqlx::query_as::<_, RuneInputFull>(
             r#" SELECT
@@ -809,11 +1867,13 @@ impl Repo {
         .await
     }
 
-    pub async fn select_tx_runes_inputs_sum(
-        &self,
-        address: &str,
-        rune: &str,
-    ) -> Result<Vec<RuneInputsSum>> {
+    pub fn stream_tx_runes_inputs_sum<'a>(
+        &'a self,
+        address: &'a str,
+        rune: &'a str,
+        from_block: i64,
+        to_block: i64,
+    ) -> BoxStream<'a, Result<RuneInputsSum>> {
         sqlx::query_as::<_, RuneInputsSum>(
             r#" SELECT
                 i.block,
@@ -825,30 +1885,81 @@ impl Repo {
                FROM inputs i
                INNER JOIN runes_outputs o
                   ON i.parent_tx = o.tx_hash AND i.parent_vout = o.vout
-               WHERE o.address = $1 and o.rune = $2
+               WHERE o.address = $1 and o.rune = $2 AND i.block >= $3 AND i.block <= $4
                GROUP BY i.block, i.tx_id, o.address ORDER BY i.block, i.tx_id"#,
         )
         .bind(address)
         .bind(rune)
-        .fetch_all(&self.pool)
-        .await
+        .bind(from_block)
+        .bind(to_block)
+        .fetch(&self.pool)
     }
 
     pub async fn insert_api_key(&self, row: ApiKey) -> Result<()> {
         let _ = sqlx::query(
-            "INSERT INTO api_keys (name, key, blocked, can_lock_utxo)
-             VALUES($1, $2, $3, $4)",
+            "INSERT INTO api_keys
+                (name, key, blocked, can_lock_utxo, is_admin, rate_limit_per_minute, scopes, networks,
+                 expires_at)
+             VALUES($1, $2, $3, $4, $5, $6, $7, $8, to_timestamp($9::BIGINT))",
         )
         .bind(row.name)
         .bind(row.key)
         .bind(row.blocked)
         .bind(row.can_lock_utxo)
+        .bind(row.is_admin)
+        .bind(row.rate_limit_per_minute)
+        .bind(row.scopes)
+        .bind(row.networks)
+        .bind(row.expires_at)
         .execute(&self.pool)
         .await?;
 
         Ok(())
     }
 
+    /// Updates all fields of the key with the same name.
+    /// Returns false if there is no such key.
+    pub async fn update_api_key(&self, row: &ApiKey) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE api_keys SET key = $2, blocked = $3, can_lock_utxo = $4, is_admin = $5,
+                rate_limit_per_minute = $6, scopes = $7, networks = $8,
+                expires_at = to_timestamp($9::BIGINT)
+             WHERE name = $1",
+        )
+        .bind(&row.name)
+        .bind(&row.key)
+        .bind(row.blocked)
+        .bind(row.can_lock_utxo)
+        .bind(row.is_admin)
+        .bind(row.rate_limit_per_minute)
+        .bind(&row.scopes)
+        .bind(&row.networks)
+        .bind(row.expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Returns false if there is no such key.
+    pub async fn delete_api_key(&self, name: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM api_keys WHERE name = $1")
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_api_key_by_name(&self, name: &str) -> Result<Option<ApiKey>> {
+        sqlx::query_as::<_, ApiKey>(&format!(
+            "SELECT {API_KEY_COLUMNS} FROM api_keys WHERE name = $1"
+        ))
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
     pub async fn block_api_key(&self, name: &str) -> Result<()> {
         let _ = sqlx::query("UPDATE api_keys SET blocked = TRUE WHERE name = $1")
             .bind(name)
@@ -858,66 +1969,1494 @@ impl Repo {
         Ok(())
     }
 
-    pub async fn select_api_keys(&self) -> Result<Vec<ApiKey>> {
-        sqlx::query_as::<_, ApiKey>("SELECT * FROM api_keys ")
-            .fetch_all(&self.pool)
-            .await
+    /// Expired keys are skipped unless `with_expired` is set.
+    pub async fn select_api_keys(&self, with_expired: bool) -> Result<Vec<ApiKey>> {
+        sqlx::query_as::<_, ApiKey>(&format!(
+            "SELECT {API_KEY_COLUMNS} FROM api_keys
+             WHERE $1 OR expires_at IS NULL OR expires_at > now()"
+        ))
+        .bind(with_expired)
+        .fetch_all(&self.pool)
+        .await
     }
 
-    pub async fn list_address_incoming_txs(
+    /// Returns `None` if the address is already subscribed with the same url.
+    pub async fn insert_subscription(
         &self,
         address: &str,
-        order: OrderBy,
-        limit: u32,
-        offset: u32,
-    ) -> Result<Vec<AddressTx>> {
-        let mut q = QueryBuilder::new(
-            r#"SELECT address, block, tx_hash FROM outputs
-                WHERE address = "#,
-        );
-        q.push_bind(address);
+        url: &str,
+        api_key_name: &str,
+    ) -> Result<Option<Subscription>> {
+        sqlx::query_as::<_, Subscription>(
+            "INSERT INTO subscriptions (address, url, api_key_name)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (api_key_name, address, url) DO NOTHING
+             RETURNING id, address, url, api_key_name,
+                extract(epoch from created_at)::BIGINT as created_at",
+        )
+        .bind(address)
+        .bind(url)
+        .bind(api_key_name)
+        .fetch_optional(&self.pool)
+        .await
+    }
 
-        q.push(" GROUP BY address, block, tx_hash ");
-        q.push(format!(" ORDER BY block {order} "));
-        q.push(" LIMIT ");
-        q.push_bind(limit as i32);
-        q.push(" OFFSET ");
-        q.push_bind(offset as i32);
+    /// Lists subscriptions of the api key, or all if `api_key_name` is `None`.
+    pub async fn select_subscriptions(
+        &self,
+        api_key_name: Option<&str>,
+    ) -> Result<Vec<Subscription>> {
+        sqlx::query_as::<_, Subscription>(
+            "SELECT id, address, url, api_key_name,
+                extract(epoch from created_at)::BIGINT as created_at
+             FROM subscriptions
+             WHERE $1::VARCHAR IS NULL OR api_key_name = $1
+             ORDER BY id",
+        )
+        .bind(api_key_name)
+        .fetch_all(&self.pool)
+        .await
+    }
 
-        let result = q
-            .build_query_as::<AddressTx>()
-            .fetch_all(&self.pool)
-            .await?;
+    /// Deletes the subscription together with its pending deliveries.
+    /// Returns false if there is no such subscription or it belongs to other api key.
+    pub async fn delete_subscription(&self, id: i64, api_key_name: Option<&str>) -> Result<bool> {
+        let result = sqlx::query(
+            "DELETE FROM subscriptions WHERE id = $1 AND ($2::VARCHAR IS NULL OR api_key_name = $2)",
+        )
+        .bind(id)
+        .bind(api_key_name)
+        .execute(&self.pool)
+        .await?;
 
-        Ok(result)
+        Ok(result.rows_affected() > 0)
     }
 
-    pub async fn list_address_outgoing_txs(
+    /// Sets the label of the address, the author of an existing label is replaced.
+    pub async fn upsert_address_label(
         &self,
         address: &str,
-        order: OrderBy,
-        limit: u32,
-        offset: u32,
-    ) -> Result<Vec<AddressTx>> {
-        let mut q = QueryBuilder::new(
-            r#"SELECT o.address, i.block, i.tx_hash
-               FROM outputs o JOIN inputs i ON i.parent_tx = o.tx_hash AND i.parent_vout = o.vout
-               WHERE o.address = "#,
-        );
-        q.push_bind(address);
+        label: &str,
+        created_by: &str,
+    ) -> Result<AddressLabel> {
+        sqlx::query_as::<_, AddressLabel>(&format!(
+            "INSERT INTO address_labels (address, label, created_by)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (address, label) DO UPDATE
+             SET created_by = EXCLUDED.created_by, created_at = now()
+             RETURNING {ADDRESS_LABEL_COLUMNS}"
+        ))
+        .bind(address)
+        .bind(label)
+        .bind(created_by)
+        .fetch_one(&self.pool)
+        .await
+    }
 
-        q.push(" GROUP by o.address, i.block, i.tx_hash ");
-        q.push(format!(" ORDER BY i.block {order} "));
-        q.push(" LIMIT ");
-        q.push_bind(limit as i32);
-        q.push(" OFFSET ");
-        q.push_bind(offset as i32);
+    pub async fn delete_address_label(&self, address: &str, label: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM address_labels WHERE address = $1 AND label = $2")
+            .bind(address)
+            .bind(label)
+            .execute(&self.pool)
+            .await?;
 
-        let result = q
-            .build_query_as::<AddressTx>()
-            .fetch_all(&self.pool)
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn select_labels_by_address(&self, address: &str) -> Result<Vec<AddressLabel>> {
+        sqlx::query_as::<_, AddressLabel>(&format!(
+            "SELECT {ADDRESS_LABEL_COLUMNS} FROM address_labels WHERE address = $1 ORDER BY label"
+        ))
+        .bind(address)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn select_labels_by_label(&self, label: &str) -> Result<Vec<AddressLabel>> {
+        sqlx::query_as::<_, AddressLabel>(&format!(
+            "SELECT {ADDRESS_LABEL_COLUMNS} FROM address_labels WHERE label = $1 ORDER BY address"
+        ))
+        .bind(label)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Takes pending deliveries that are due and postpones them by `lease_secs`,
+    /// so other API replicas don't send them at the same time.
+    pub async fn claim_webhook_deliveries(
+        &self,
+        limit: i64,
+        lease_secs: i64,
+    ) -> Result<Vec<WebhookDelivery>> {
+        sqlx::query_as::<_, WebhookDelivery>(
+            "WITH claimed AS (
+                UPDATE webhook_deliveries
+                SET next_attempt_at = now() + make_interval(secs => $2)
+                WHERE id IN (
+                    SELECT id FROM webhook_deliveries
+                    WHERE status = 'pending' AND next_attempt_at <= now()
+                    ORDER BY next_attempt_at
+                    LIMIT $1
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING *
+             )
+             SELECT c.id, c.subscription_id, s.url, c.block, c.tx_hash, c.vout,
+                c.address, c.amount, c.attempts
+             FROM claimed c
+             INNER JOIN subscriptions s ON s.id = c.subscription_id
+             ORDER BY c.id",
+        )
+        .bind(limit)
+        .bind(lease_secs as f64)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn mark_webhook_delivered(&self, id: i64) -> Result<()> {
+        let _ = sqlx::query(
+            "UPDATE webhook_deliveries
+             SET status = 'delivered', attempts = attempts + 1, last_error = NULL
+             WHERE id = $1",
+        )
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Schedules the next attempt in `retry_in_secs`, `None` marks the delivery as failed.
+    pub async fn mark_webhook_attempt_failed(
+        &self,
+        id: i64,
+        error: &str,
+        retry_in_secs: Option<i64>,
+    ) -> Result<()> {
+        let _ = sqlx::query(
+            "UPDATE webhook_deliveries
+             SET attempts = attempts + 1,
+                last_error = $2,
+                status = CASE WHEN $3::BIGINT IS NULL THEN 'failed' ELSE 'pending' END,
+                next_attempt_at = now() + make_interval(secs => COALESCE($3, 0)::DOUBLE PRECISION)
+             WHERE id = $1",
+        )
+        .bind(id)
+        .bind(error)
+        .bind(retry_in_secs)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Txs that send btc to the address or spend its outputs, one row per tx.
+    pub async fn list_address_txs(
+        &self,
+        address: &str,
+        incoming_only: bool,
+        min_height: Option<u64>,
+        order: OrderBy,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<TxInfo>> {
+        let having = if incoming_only {
+            "HAVING bool_or(income)"
+        } else {
+            ""
+        };
+        sqlx::query_as::<_, TxInfo>(&format!(
+            "{ADDRESS_TXS} {having} ORDER BY block {order}, tx_id {order} LIMIT $3 OFFSET $4",
+            order = order.sql()
+        ))
+        .bind(address)
+        .bind(min_height.unwrap_or_default() as i64)
+        .bind(limit as i64)
+        .bind(offset as i64)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn count_address_txs(
+        &self,
+        address: &str,
+        incoming_only: bool,
+        min_height: Option<u64>,
+    ) -> Result<i64> {
+        let having = if incoming_only {
+            "HAVING bool_or(income)"
+        } else {
+            ""
+        };
+        let row = sqlx::query_as::<_, Count>(&format!(
+            "SELECT count(*) AS count FROM ({ADDRESS_TXS} {having}) txs"
+        ))
+        .bind(address)
+        .bind(min_height.unwrap_or_default() as i64)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(row.count)
+    }
+
+    pub async fn list_address_outputs(
+        &self,
+        address: &str,
+        include_spent: bool,
+        min_height: Option<u64>,
+        order: OrderBy,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<BtcOutputHistory>> {
+        sqlx::query_as::<_, BtcOutputHistory>(&format!(
+            r#"SELECT
+                o.id,
+                o.block,
+                o.tx_id,
+                o.tx_hash,
+                o.vout,
+                a.address,
+                a.pk_script,
+                o.amount,
+                o.coinbase,
+                i.id IS NOT NULL AS spent,
+                i.tx_hash AS spent_in_tx,
+                i.block AS spent_block
+            {ADDRESS_OUTPUTS}
+            ORDER BY o.block {order}, o.tx_id {order}, o.vout {order}
+            LIMIT $4 OFFSET $5"#,
+            order = order.sql()
+        ))
+        .bind(address)
+        .bind(include_spent)
+        .bind(min_height.unwrap_or_default() as i64)
+        .bind(limit as i64)
+        .bind(offset as i64)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn count_address_outputs(
+        &self,
+        address: &str,
+        include_spent: bool,
+        min_height: Option<u64>,
+    ) -> Result<i64> {
+        let row =
+            sqlx::query_as::<_, Count>(&format!("SELECT count(*) AS count {ADDRESS_OUTPUTS}"))
+                .bind(address)
+                .bind(include_spent)
+                .bind(min_height.unwrap_or_default() as i64)
+                .fetch_one(&self.pool)
+                .await?;
+
+        Ok(row.count)
+    }
+
+    pub async fn count_prunable_outputs(&self, max_height: i64) -> Result<i64> {
+        let row =
+            sqlx::query_as::<_, Count>(&format!("SELECT count(*) AS count {PRUNABLE_OUTPUTS}"))
+                .bind(max_height)
+                .fetch_one(&self.pool)
+                .await?;
+
+        Ok(row.count)
+    }
+
+    /// Must be recorded before pruning starts, so an interrupted prune is taken into account.
+    pub async fn set_pruned_height(&self, height: i64) -> Result<()> {
+        sqlx::query(
+            r#"INSERT INTO prune_state (height) VALUES ($1)
+               ON CONFLICT (id) DO UPDATE
+               SET height = GREATEST(prune_state.height, excluded.height), updated_at = now()"#,
+        )
+        .bind(height)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Spent outputs and their inputs at or below this height may be deleted,
+    /// `None` if the db was never pruned.
+    pub async fn get_pruned_height(&self) -> Result<Option<i64>> {
+        let row: Option<(i64,)> = sqlx::query_as("SELECT height FROM prune_state")
+            .fetch_optional(&self.pool)
             .await?;
+        Ok(row.map(|(height,)| height))
+    }
 
-        Ok(result)
+    /// Deletes up to `batch_size` spent outputs together with their inputs in one transaction.
+    /// Returns the number of deleted outputs and inputs.
+    pub async fn prune_spent_outputs(
+        &self,
+        max_height: i64,
+        batch_size: i64,
+    ) -> Result<(u64, u64)> {
+        let mut tx = self.pool.begin().await?;
+
+        let rows: Vec<(i64, i64)> =
+            sqlx::query_as(&format!("SELECT o.id, i.id {PRUNABLE_OUTPUTS} LIMIT $2"))
+                .bind(max_height)
+                .bind(batch_size)
+                .fetch_all(&mut *tx)
+                .await?;
+        if rows.is_empty() {
+            tx.commit().await?;
+            return Ok((0, 0));
+        }
+
+        let (outputs, inputs): (Vec<i64>, Vec<i64>) = rows.into_iter().unzip();
+        sqlx::query("DELETE FROM outputs_extras WHERE id = ANY($1)")
+            .bind(&outputs)
+            .execute(&mut *tx)
+            .await?;
+        let deleted_outputs = sqlx::query("DELETE FROM outputs WHERE id = ANY($1)")
+            .bind(&outputs)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        let deleted_inputs = sqlx::query("DELETE FROM inputs WHERE id = ANY($1)")
+            .bind(&inputs)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+
+        tx.commit().await?;
+        Ok((deleted_outputs, deleted_inputs))
+    }
+
+    /// Reconciles `outputs_extras.has_runes` of outputs from `from_block` with `runes_outputs`,
+    /// the runes indexer is the source of truth. Divergent flags are fixed, outputs with runes
+    /// but without extras get a row. Returns numbers of updated and inserted rows.
+    pub async fn sync_outputs_extras_runes(&self, from_block: i64) -> Result<(u64, u64)> {
+        let mut tx = self.pool.begin().await?;
+
+        let updated = sqlx::query(
+            r#"UPDATE outputs_extras AS e
+               SET has_runes = t.has_runes
+               FROM (
+                   SELECT o.id, EXISTS (
+                       SELECT 1 FROM runes_outputs ro
+                       WHERE ro.tx_hash = o.tx_hash AND ro.vout = o.vout
+                   ) AS has_runes
+                   FROM outputs_extras x
+                   JOIN outputs o ON o.id = x.id
+                   WHERE o.block >= $1
+               ) AS t
+               WHERE e.id = t.id AND e.has_runes <> t.has_runes"#,
+        )
+        .bind(from_block)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        let inserted = sqlx::query(
+            r#"INSERT INTO outputs_extras (id, has_runes, has_inscriptions)
+               SELECT DISTINCT o.id, true, false
+               FROM runes_outputs ro
+               JOIN outputs o ON o.tx_hash = ro.tx_hash AND o.vout = ro.vout
+               WHERE ro.block >= $1
+               ON CONFLICT (id) DO NOTHING"#,
+        )
+        .bind(from_block)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        tx.commit().await?;
+        Ok((updated, inserted))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorting_clause(sorting: UtxoSortMode, order: OrderBy) -> String {
+        let mut q = QueryBuilder::new("SELECT * FROM utxos");
+        push_utxo_sorting(&mut q, sorting, order);
+        q.sql()
+            .trim_start_matches("SELECT * FROM utxos")
+            .to_string()
+    }
+
+    #[test]
+    fn test_runes_sorting_clause() {
+        let clause = |sort, order| {
+            let mut q = QueryBuilder::new("SELECT * FROM runes");
+            push_runes_sorting(&mut q, sort, order);
+            q.sql()
+                .trim_start_matches("SELECT * FROM runes")
+                .to_string()
+        };
+        assert_eq!(
+            clause(RuneSortMode::Age, OrderBy::Desc),
+            " ORDER BY r.block DESC, r.tx_id DESC "
+        );
+        assert_eq!(
+            clause(RuneSortMode::Holders, OrderBy::Desc),
+            " ORDER BY COALESCE(h.holders, 0) DESC NULLS LAST, r.block DESC, r.tx_id DESC "
+        );
+        assert_eq!(
+            clause(RuneSortMode::Progress, OrderBy::Asc),
+            " ORDER BY r.minted / NULLIF(r.max_supply, 0) ASC NULLS LAST, r.block ASC, r.tx_id ASC "
+        );
+    }
+
+    #[test]
+    fn test_utxo_sorting_clause() {
+        assert_eq!(
+            sorting_clause(UtxoSortMode::Age, OrderBy::Asc),
+            " ORDER BY u.block ASC, u.tx_id ASC, u.id ASC "
+        );
+        assert_eq!(
+            sorting_clause(UtxoSortMode::Amount, OrderBy::Desc),
+            " ORDER BY u.amount DESC, u.id ASC "
+        );
+        assert_eq!(
+            sorting_clause(UtxoSortMode::DustFirst, OrderBy::Asc),
+            " ORDER BY u.amount ASC, u.block ASC, u.tx_id ASC, u.id ASC "
+        );
+        assert_eq!(
+            sorting_clause(UtxoSortMode::DustFirst, OrderBy::Desc),
+            " ORDER BY u.amount ASC, u.block DESC, u.tx_id DESC, u.id DESC "
+        );
+    }
+
+    #[test]
+    fn test_utxo_cursor_clause() {
+        let mut q = QueryBuilder::new("");
+        push_utxo_cursor(&mut q, "outputs", UtxoSortMode::Amount, OrderBy::Desc, 42);
+        assert_eq!(
+            q.sql(),
+            " AND EXISTS (SELECT 1 FROM outputs AS c WHERE c.id = $1 AND (u.amount < c.amount OR (u.amount = c.amount AND u.id > c.id))) "
+        );
+
+        let mut q = QueryBuilder::new("");
+        push_utxo_cursor(&mut q, "runes_outputs", UtxoSortMode::Age, OrderBy::Asc, 42);
+        assert_eq!(
+            q.sql(),
+            " AND EXISTS (SELECT 1 FROM runes_outputs AS c WHERE c.id = $1 AND (u.block, u.tx_id, u.id) > (c.block, c.tx_id, c.id)) "
+        );
+    }
+
+    #[test]
+    fn test_utxo_sort_order() {
+        assert_eq!(
+            utxo_sort_order(UtxoSortMode::Age, OrderBy::Desc),
+            SortOrder::uniform(OrderBy::Desc)
+        );
+        assert_eq!(
+            utxo_sort_order(UtxoSortMode::Amount, OrderBy::Desc),
+            SortOrder::new(SqlOrder::DESC, SqlOrder::ASC)
+        );
+        assert_eq!(
+            utxo_sort_order(UtxoSortMode::DustFirst, OrderBy::Desc),
+            SortOrder::new(SqlOrder::ASC, SqlOrder::DESC)
+        );
+
+        // the cursor follows the per-column order of the sorting
+        let mut q = QueryBuilder::new("");
+        push_utxo_cursor(
+            &mut q,
+            "outputs",
+            UtxoSortMode::DustFirst,
+            OrderBy::Desc,
+            42,
+        );
+        assert_eq!(
+            q.sql(),
+            " AND EXISTS (SELECT 1 FROM outputs AS c WHERE c.id = $1 AND (u.amount > c.amount OR (u.amount = c.amount AND (u.block, u.tx_id, u.id) < (c.block, c.tx_id, c.id)))) "
+        );
+    }
+
+    const ADDRESS: &str = "bc1qtest";
+
+    /// Creates a clean database with txs of [`ADDRESS`], one per block:
+    /// 1 - receives, 2 - spends 1 with change, 3 - receives, 4 - spends 3, 5 - receives.
+    async fn setup_address_txs() -> Repo {
+        let dsn = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let db = open_postgres_db(&DBConfig {
+            dsn,
+            automigrate: false,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        db.reset_schema().await.unwrap();
+
+        let outputs = [
+            (1, ADDRESS),
+            (2, ADDRESS),
+            (3, ADDRESS),
+            (4, "other"),
+            (5, ADDRESS),
+        ];
+        for (block, address) in outputs {
+            let tx_hash = Hash::sha2(block.to_string());
+            db.exec_raw(&format!(
+                "INSERT INTO outputs (block, tx_id, tx_hash, vout, address, amount, coinbase) \
+                 VALUES ({block}, 1, '\\x{tx_hash}', 0, '{address}', 1000, false);"
+            ))
+            .await
+            .unwrap();
+        }
+        for (block, parent_block) in [(2, 1), (4, 3)] {
+            let tx_hash = Hash::sha2(block.to_string());
+            let parent_tx = Hash::sha2(parent_block.to_string());
+            db.exec_raw(&format!(
+                "INSERT INTO inputs (block, tx_id, tx_hash, vin, parent_tx, parent_vout) \
+                 VALUES ({block}, 1, '\\x{tx_hash}', 0, '\\x{parent_tx}', 0);"
+            ))
+            .await
+            .unwrap();
+        }
+        db
+    }
+
+    fn tx_flags(txs: &[TxInfo]) -> Vec<(i64, bool, bool)> {
+        txs.iter().map(|t| (t.block, t.income, t.spend)).collect()
+    }
+
+    #[tokio::test]
+    #[ignore = "requires postgres, set DATABASE_URL"]
+    async fn test_list_address_txs_pages() {
+        let db = setup_address_txs().await;
+        assert_eq!(db.count_address_txs(ADDRESS, false, None).await.unwrap(), 5);
+        assert_eq!(db.count_address_txs(ADDRESS, true, None).await.unwrap(), 4);
+
+        let mut pages = Vec::new();
+        for offset in [0, 2, 4] {
+            let txs = db
+                .list_address_txs(ADDRESS, false, None, OrderBy::Asc, 2, offset)
+                .await
+                .unwrap();
+            pages.push(tx_flags(&txs));
+        }
+        assert_eq!(
+            pages,
+            vec![
+                vec![(1, true, false), (2, true, true)],
+                vec![(3, true, false), (4, false, true)],
+                vec![(5, true, false)],
+            ]
+        );
+
+        let txs = db
+            .list_address_txs(ADDRESS, false, None, OrderBy::Desc, 3, 0)
+            .await
+            .unwrap();
+        assert_eq!(
+            tx_flags(&txs),
+            vec![(5, true, false), (4, false, true), (3, true, false)]
+        );
+
+        let txs = db
+            .list_address_txs(ADDRESS, true, None, OrderBy::Asc, 3, 1)
+            .await
+            .unwrap();
+        assert_eq!(
+            tx_flags(&txs),
+            vec![(2, true, true), (3, true, false), (5, true, false)]
+        );
+
+        assert_eq!(
+            db.count_address_txs(ADDRESS, false, Some(3)).await.unwrap(),
+            3
+        );
+        let txs = db
+            .list_address_txs(ADDRESS, false, Some(4), OrderBy::Asc, 10, 0)
+            .await
+            .unwrap();
+        assert_eq!(tx_flags(&txs), vec![(4, false, true), (5, true, false)]);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires postgres, set DATABASE_URL"]
+    async fn test_list_address_outputs() {
+        let db = setup_address_txs().await;
+        db.exec_raw(&format!(
+            "INSERT INTO addresses (address, address_type, pk_script) \
+             VALUES ('{ADDRESS}', 'p2wpkh', '\\x00');"
+        ))
+        .await
+        .unwrap();
+
+        let count = |include_spent, min_height| {
+            db.count_address_outputs(ADDRESS, include_spent, min_height)
+        };
+        assert_eq!(count(false, None).await.unwrap(), 2);
+        assert_eq!(count(true, None).await.unwrap(), 4);
+        assert_eq!(count(true, Some(3)).await.unwrap(), 2);
+        assert_eq!(count(true, Some(6)).await.unwrap(), 0);
+
+        let outputs = db
+            .list_address_outputs(ADDRESS, false, None, OrderBy::Asc, 10, 0)
+            .await
+            .unwrap();
+        let blocks: Vec<_> = outputs.iter().map(|o| (o.block, o.spent)).collect();
+        assert_eq!(blocks, vec![(2, false), (5, false)]);
+
+        let outputs = db
+            .list_address_outputs(ADDRESS, true, None, OrderBy::Desc, 3, 0)
+            .await
+            .unwrap();
+        let spent: Vec<_> = outputs
+            .iter()
+            .map(|o| (o.block, o.spent_in_tx.clone(), o.spent_block))
+            .collect();
+        assert_eq!(
+            spent,
+            vec![
+                (5, None, None),
+                (3, Some(Hash::sha2("4")), Some(4)),
+                (2, None, None),
+            ]
+        );
+
+        let outputs = db
+            .list_address_outputs(ADDRESS, true, Some(3), OrderBy::Asc, 10, 0)
+            .await
+            .unwrap();
+        let blocks: Vec<_> = outputs.iter().map(|o| o.block).collect();
+        assert_eq!(blocks, vec![3, 5]);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires postgres, set DATABASE_URL"]
+    async fn test_sync_outputs_extras_runes() {
+        let db = setup_address_txs().await;
+        db.exec_raw(
+            "INSERT INTO runes (block, tx_id, rune_id, name, display_name, symbol, max_supply, \
+             etching_tx, commitment_tx, raw_data) \
+             VALUES (1, 1, '1:1', 'RUNE', 'RUNE', 'R', 1000, '\\x00', '\\x00', '\\x00');",
+        )
+        .await
+        .unwrap();
+        // outputs of blocks 1 and 2 hold runes
+        for block in [1, 2] {
+            let tx_hash = Hash::sha2(block.to_string());
+            db.exec_raw(&format!(
+                "INSERT INTO runes_outputs (block, tx_id, tx_hash, vout, rune, rune_id, address, amount, btc_amount) \
+                 VALUES ({block}, 1, '\\x{tx_hash}', 0, 'RUNE', '1:1', '{ADDRESS}', 10, 1000);"
+            ))
+            .await
+            .unwrap();
+        }
+        let output_id = |block: i64| format!("(SELECT id FROM outputs WHERE block = {block})");
+        // block 1 has a stale flag, block 3 has a wrong one, block 2 has no extras
+        for (block, has_runes) in [(1, false), (3, true), (5, false)] {
+            db.exec_raw(&format!(
+                "INSERT INTO outputs_extras (id, has_runes, has_inscriptions) \
+                 VALUES ({}, {has_runes}, true);",
+                output_id(block)
+            ))
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(db.sync_outputs_extras_runes(0).await.unwrap(), (2, 1));
+        // already in sync
+        assert_eq!(db.sync_outputs_extras_runes(0).await.unwrap(), (0, 0));
+
+        let rows: Vec<(i64, bool, bool)> = sqlx::query_as(
+            "SELECT o.block, e.has_runes, e.has_inscriptions \
+             FROM outputs_extras e JOIN outputs o ON o.id = e.id ORDER BY o.block",
+        )
+        .fetch_all(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                (1, true, true),
+                (2, true, false),
+                (3, false, true),
+                (5, false, true),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires postgres, set DATABASE_URL"]
+    async fn test_utxo_amount_range() {
+        let db = setup_address_txs().await;
+        db.exec_raw(&format!(
+            "INSERT INTO addresses (address, address_type, pk_script) \
+             VALUES ('{ADDRESS}', 'p2wpkh', '\\x00');"
+        ))
+        .await
+        .unwrap();
+        // 2 utxos of 1000 sats from the setup
+        let tx_hash = Hash::sha2("amounts");
+        for (vout, amount) in [546, 547, 999].into_iter().enumerate() {
+            db.exec_raw(&format!(
+                "INSERT INTO outputs (block, tx_id, tx_hash, vout, address, amount, coinbase) \
+                 VALUES (6, 1, '\\x{tx_hash}', {vout}, '{ADDRESS}', {amount}, false);"
+            ))
+            .await
+            .unwrap();
+        }
+
+        let range = |min, max| AmountRange { min, max };
+        let cases = [
+            (AmountRange::default(), 5),
+            (range(Some(546), None), 5),
+            (range(Some(547), None), 4),
+            (range(None, Some(546)), 1),
+            (range(Some(547), Some(999)), 2),
+            (range(Some(1000), Some(1000)), 2),
+            (range(Some(u64::MAX), None), 0),
+            // the deprecated threshold is exclusive
+            (AmountRange::above(Some(546)), 4),
+        ];
+        for (amount, expected) in cases {
+            let count = db
+                .count_filtered_utxos(ADDRESS, amount, None, false, false)
+                .await
+                .unwrap();
+            assert_eq!(count, expected, "{amount:?}");
+
+            let rows = db
+                .select_utxo_with_pagination(
+                    ADDRESS,
+                    OrderBy::Asc,
+                    amount,
+                    None,
+                    false,
+                    false,
+                    UtxoSortMode::Amount,
+                    None,
+                    100,
+                    0,
+                )
+                .await
+                .unwrap();
+            assert_eq!(rows.len() as i64, expected, "{amount:?}");
+            let in_range = |a: i64| {
+                amount.min.into_iter().all(|min| a as u64 >= min)
+                    && amount.max.into_iter().all(|max| a as u64 <= max)
+            };
+            assert!(rows.iter().all(|u| in_range(u.amount)), "{amount:?}");
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires postgres, set DATABASE_URL"]
+    async fn test_spendable_balance() {
+        let db = setup_address_txs().await;
+        db.exec_raw(&format!(
+            "INSERT INTO addresses (address, address_type, pk_script) \
+             VALUES ('{ADDRESS}', 'p2wpkh', '\\x00');"
+        ))
+        .await
+        .unwrap();
+        // utxos of the setup: 1000 sats in the blocks 2 and 5
+        let outputs = [(6, 300, false), (10, 5000, true)];
+        for (block, amount, coinbase) in outputs {
+            let tx_hash = Hash::sha2(block.to_string());
+            db.exec_raw(&format!(
+                "INSERT INTO outputs (block, tx_id, tx_hash, vout, address, amount, coinbase) \
+                 VALUES ({block}, 1, '\\x{tx_hash}', 0, '{ADDRESS}', {amount}, {coinbase});"
+            ))
+            .await
+            .unwrap();
+        }
+        db.exec_raw(&format!(
+            "INSERT INTO runes_outputs \
+                (block, tx_id, tx_hash, vout, rune, rune_id, address, amount, btc_amount) \
+             VALUES (5, 1, '\\x{}', 0, 'AAAA', '1:0', '{ADDRESS}', 1, 1000);",
+            Hash::sha2("5")
+        ))
+        .await
+        .unwrap();
+        db.exec_raw(
+            "INSERT INTO outputs_extras (id, has_runes, has_inscriptions) \
+             SELECT id, false, true FROM outputs WHERE block = 6;",
+        )
+        .await
+        .unwrap();
+
+        let balance = db.get_spendable_balance(ADDRESS, 10).await.unwrap();
+        assert_eq!(balance.balance, 7300);
+        assert_eq!(balance.spendable, 1000);
+        assert_eq!(balance.immature, 5000);
+        assert_eq!(balance.with_runes, 1000);
+        assert_eq!(balance.with_inscriptions, 300);
+
+        let utxos = db.select_spendable_short_utxos(ADDRESS, 10).await.unwrap();
+        assert_eq!(utxos.len(), 1);
+        assert_eq!(utxos[0].tx_hash, Hash::sha2("2"));
+
+        // the coinbase output is mature in the next block
+        let balance = db.get_spendable_balance(ADDRESS, 11).await.unwrap();
+        assert_eq!((balance.spendable, balance.immature), (6000, 0));
+        let utxos = db.select_spendable_short_utxos(ADDRESS, 11).await.unwrap();
+        assert_eq!(utxos.len(), 2);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires postgres, set DATABASE_URL"]
+    async fn test_utxo_pages_with_equal_amounts() {
+        let db = setup_address_txs().await;
+        db.exec_raw(&format!(
+            "INSERT INTO addresses (address, address_type, pk_script) \
+             VALUES ('{ADDRESS}', 'p2wpkh', '\\x00');"
+        ))
+        .await
+        .unwrap();
+        // 2 utxos of the setup and 20 more with the same amount in one tx
+        let tx_hash = Hash::sha2("equal");
+        for vout in 0..20 {
+            db.exec_raw(&format!(
+                "INSERT INTO outputs (block, tx_id, tx_hash, vout, address, amount, coinbase) \
+                 VALUES (6, 1, '\\x{tx_hash}', {vout}, '{ADDRESS}', 1000, false);"
+            ))
+            .await
+            .unwrap();
+        }
+
+        for sorting in [
+            UtxoSortMode::Age,
+            UtxoSortMode::Amount,
+            UtxoSortMode::DustFirst,
+        ] {
+            for order in [OrderBy::Asc, OrderBy::Desc] {
+                let all = db
+                    .select_utxo_with_pagination(
+                        ADDRESS,
+                        order,
+                        AmountRange::default(),
+                        None,
+                        false,
+                        false,
+                        sorting,
+                        None,
+                        100,
+                        0,
+                    )
+                    .await
+                    .unwrap();
+                let all: Vec<_> = all.into_iter().map(|u| u.id).collect();
+                assert_eq!(all.len(), 22);
+
+                let (mut by_offset, mut by_cursor) = (Vec::new(), Vec::new());
+                let mut after_id = None;
+                for page in 0..5 {
+                    let rows = db
+                        .select_utxo_with_pagination(
+                            ADDRESS,
+                            order,
+                            AmountRange::default(),
+                            None,
+                            false,
+                            false,
+                            sorting,
+                            None,
+                            5,
+                            page * 5,
+                        )
+                        .await
+                        .unwrap();
+                    by_offset.extend(rows.into_iter().map(|u| u.id));
+
+                    let rows = db
+                        .select_utxo_with_pagination(
+                            ADDRESS,
+                            order,
+                            AmountRange::default(),
+                            None,
+                            false,
+                            false,
+                            sorting,
+                            after_id,
+                            5,
+                            0,
+                        )
+                        .await
+                        .unwrap();
+                    after_id = rows.last().map(|u| u.id);
+                    by_cursor.extend(rows.into_iter().map(|u| u.id));
+                }
+                assert_eq!(by_offset, all, "sorting={sorting:?} order={order}");
+                assert_eq!(by_cursor, all, "sorting={sorting:?} order={order}");
+            }
+        }
+
+        // equal amounts keep the output order in both directions
+        let rows = db
+            .select_utxo_with_pagination(
+                ADDRESS,
+                OrderBy::Desc,
+                AmountRange::default(),
+                None,
+                false,
+                false,
+                UtxoSortMode::Amount,
+                None,
+                100,
+                0,
+            )
+            .await
+            .unwrap();
+        let ids: Vec<_> = rows.iter().map(|u| u.id).collect();
+        let mut sorted = ids.clone();
+        sorted.sort();
+        assert_eq!(ids, sorted);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires postgres, set DATABASE_URL"]
+    async fn test_query_timeout() {
+        let db = setup_address_txs().await;
+        // every read of the outputs takes at least 500ms
+        db.exec_raw(
+            "ALTER TABLE outputs RENAME TO outputs_data; \
+             CREATE VIEW outputs AS SELECT o.* FROM outputs_data o, (SELECT pg_sleep(0.5)) s;",
+        )
+        .await
+        .unwrap();
+
+        let err = db
+            .with_timeout(Duration::from_millis(100))
+            .count_history_buckets(ADDRESS, 0, i64::MAX, 1)
+            .await
+            .unwrap_err();
+        assert!(is_timeout(&err), "{err:?}");
+
+        assert!(db.query_timeout.is_none());
+        db.count_history_buckets(ADDRESS, 0, i64::MAX, 1)
+            .await
+            .unwrap();
+        assert!(!is_timeout(&sqlx::Error::RowNotFound));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires postgres, set DATABASE_URL"]
+    async fn test_balance_exists() {
+        let db = setup_address_txs().await;
+        for address in [ADDRESS, "bc1qspent"] {
+            db.exec_raw(&format!(
+                "INSERT INTO addresses (address, address_type, pk_script) \
+                 VALUES ('{address}', 'p2wpkh', '\\x00');"
+            ))
+            .await
+            .unwrap();
+        }
+
+        let balance = db.get_balance(ADDRESS).await.unwrap();
+        assert_eq!((balance.balance, balance.exists), (2000, Some(true)));
+
+        let balance = db.get_balance("bc1qspent").await.unwrap();
+        assert_eq!((balance.balance, balance.exists), (0, Some(true)));
+
+        let balance = db.get_balance("bc1qunknown").await.unwrap();
+        assert_eq!((balance.balance, balance.exists), (0, Some(false)));
+
+        let balance = db.get_rune_balance("bc1qspent", "RUNE").await.unwrap();
+        assert_eq!(balance.exists, Some(true));
+
+        let balance = db.get_rune_balance("bc1qunknown", "RUNE").await.unwrap();
+        assert_eq!(balance.exists, Some(false));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires postgres, set DATABASE_URL"]
+    async fn test_pruned_height() {
+        let db = setup_address_txs().await;
+        assert_eq!(db.get_pruned_height().await.unwrap(), None);
+
+        db.set_pruned_height(100).await.unwrap();
+        assert_eq!(db.get_pruned_height().await.unwrap(), Some(100));
+        // rows below the previous prune are already deleted
+        db.set_pruned_height(50).await.unwrap();
+        assert_eq!(db.get_pruned_height().await.unwrap(), Some(100));
+        db.set_pruned_height(150).await.unwrap();
+        assert_eq!(db.get_pruned_height().await.unwrap(), Some(150));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires postgres, set DATABASE_URL"]
+    async fn test_select_api_keys_skips_expired() {
+        let db = setup_address_txs().await;
+        let tomorrow = ApiKey::expiry_in_days(1);
+        let yesterday = tomorrow - 2 * 24 * 60 * 60;
+        for (name, expires_at) in [
+            ("forever", None),
+            ("active", Some(tomorrow)),
+            ("expired", Some(yesterday)),
+        ] {
+            db.insert_api_key(ApiKey {
+                expires_at,
+                ..ApiKey::new(name)
+            })
+            .await
+            .unwrap();
+        }
+
+        let names = |keys: Vec<ApiKey>| {
+            let mut names: Vec<_> = keys.into_iter().map(|k| k.name).collect();
+            names.sort();
+            names
+        };
+        let keys = db.select_api_keys(false).await.unwrap();
+        assert_eq!(names(keys), ["active", "forever"]);
+        let keys = db.select_api_keys(true).await.unwrap();
+        assert_eq!(names(keys), ["active", "expired", "forever"]);
+
+        let key = db.get_api_key_by_name("expired").await.unwrap().unwrap();
+        assert!(key.is_expired());
+        let key = db.get_api_key_by_name("active").await.unwrap().unwrap();
+        assert!(!key.is_expired());
+        assert_eq!(key.expires_at, Some(tomorrow));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires postgres, set DATABASE_URL"]
+    async fn test_last_indexed_block_updated_at() {
+        let db = setup_address_txs().await;
+        db.exec_raw(
+            "INSERT INTO last_indexed_block (indexer, height, updated_at)
+             VALUES ('stalled', 10, to_timestamp(1700000000))
+             ON CONFLICT (indexer) DO UPDATE SET height = 10, updated_at = to_timestamp(1700000000)",
+        )
+        .await
+        .unwrap();
+
+        let block = db
+            .get_last_indexed_block_info("stalled")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(block.height, 10);
+        assert_eq!(block.updated_at, Some(1700000000));
+        assert!(db
+            .get_last_indexed_block_info("missing")
+            .await
+            .unwrap()
+            .is_none());
+
+        let blocks = db.get_last_indexed_blocks().await.unwrap();
+        assert!(blocks.iter().all(|b| b.updated_at.is_some()));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires postgres, set DATABASE_URL"]
+    async fn test_address_labels() {
+        let db = setup_address_txs().await;
+        db.upsert_address_label(ADDRESS, "treasury", "ops")
+            .await
+            .unwrap();
+        db.upsert_address_label(ADDRESS, "hot-wallet", "ops")
+            .await
+            .unwrap();
+        db.upsert_address_label("other", "treasury", "ops")
+            .await
+            .unwrap();
+        // the label is kept, the author is replaced
+        let row = db
+            .upsert_address_label(ADDRESS, "treasury", "admin")
+            .await
+            .unwrap();
+        assert_eq!(row.created_by, "admin");
+
+        let labels: Vec<String> = db
+            .select_labels_by_address(ADDRESS)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|l| l.label)
+            .collect();
+        assert_eq!(labels, vec!["hot-wallet", "treasury"]);
+        let addresses: Vec<String> = db
+            .select_labels_by_label("treasury")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|l| l.address)
+            .collect();
+        assert_eq!(addresses.len(), 2);
+
+        let balance = db.get_balance_with_labels(ADDRESS, true).await.unwrap();
+        assert_eq!(balance.labels, Some(labels));
+        assert_eq!(db.get_balance(ADDRESS).await.unwrap().labels, None);
+        // addresses without utxos still get their labels
+        db.upsert_address_label("empty", "customer-x", "ops")
+            .await
+            .unwrap();
+        let balance = db.get_balance_with_labels("empty", true).await.unwrap();
+        assert_eq!(balance.exists, Some(false));
+        assert_eq!(balance.labels, Some(vec!["customer-x".to_string()]));
+
+        assert!(db.delete_address_label(ADDRESS, "treasury").await.unwrap());
+        assert!(!db.delete_address_label(ADDRESS, "treasury").await.unwrap());
+        assert_eq!(
+            db.select_label_names(ADDRESS).await.unwrap(),
+            vec!["hot-wallet"]
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires postgres, set DATABASE_URL"]
+    async fn test_subscriptions_are_unique_per_key() {
+        let db = setup_address_txs().await;
+        let url = "https://example.com/hook";
+        let row = db.insert_subscription(ADDRESS, url, "alice").await.unwrap();
+        assert!(row.is_some());
+        assert!(db
+            .insert_subscription(ADDRESS, url, "alice")
+            .await
+            .unwrap()
+            .is_none());
+        // other key doesn't see that the url is already subscribed
+        let row = db.insert_subscription(ADDRESS, url, "bob").await.unwrap();
+        assert_eq!(row.unwrap().api_key_name, "bob");
+
+        assert_eq!(db.select_subscriptions(Some("bob")).await.unwrap().len(), 1);
+        assert_eq!(db.select_subscriptions(None).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires postgres, set DATABASE_URL"]
+    async fn test_block_stats() {
+        let db = setup_address_txs().await;
+        db.exec_raw(
+            "INSERT INTO block_stats VALUES
+                (10, 5, 4, 4000, 1, 2, 3, 4, 5, 6, 7),
+                (12, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0)
+             ON CONFLICT (height) DO NOTHING",
+        )
+        .await
+        .unwrap();
+
+        let stats = db.get_block_stats(10).await.unwrap().unwrap();
+        assert_eq!((stats.tx_count, stats.total_fees), (5, 4000));
+        assert_eq!(stats.fee_rate_p50, 4.0);
+        assert!(db.get_block_stats(11).await.unwrap().is_none());
+
+        let heights: Vec<i64> = db
+            .list_block_stats(10, 12)
+            .await
+            .unwrap()
+            .iter()
+            .map(|s| s.height)
+            .collect();
+        assert_eq!(heights, vec![10, 12]);
+    }
+
+    /// Creates a clean database with 3 runes etched in blocks 1-3:
+    /// AAAA with 1 holder, BBBB with 3 holders and CCCC without holders.
+    async fn setup_runes() -> Repo {
+        let dsn = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let db = open_postgres_db(&DBConfig {
+            dsn,
+            automigrate: false,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        db.reset_schema().await.unwrap();
+
+        // name, mints, in_circulation, minted, max_supply, holders
+        let runes = [
+            ("AAAA", 10, 100, 100, 1000, 1),
+            ("BBBB", 5, 500, 500, 500, 3),
+            ("CCCC", 20, 50, 0, 0, 0),
+        ];
+        for (i, (name, mints, supply, minted, max_supply, holders)) in runes.iter().enumerate() {
+            let block = i as i64 + 1;
+            db.insert_rune(&Rune {
+                block,
+                rune_id: format!("{block}:0"),
+                name: name.to_string(),
+                display_name: name.to_string(),
+                mints: *mints,
+                in_circulation: (*supply).into(),
+                minted: (*minted).into(),
+                max_supply: (*max_supply).into(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+            for h in 0..*holders {
+                let address = format!("{name}_{h}");
+                db.exec_raw(&format!(
+                    "INSERT INTO addresses (address, address_type, pk_script) \
+                     VALUES ('{address}', 'p2tr', '\\x00');"
+                ))
+                .await
+                .unwrap();
+                db.exec_raw(&format!(
+                    "INSERT INTO runes_outputs \
+                        (block, tx_id, tx_hash, vout, rune, rune_id, address, amount, btc_amount) \
+                     VALUES ({block}, 1, '\\x{}', 0, '{name}', '{block}:0', '{address}', 1, 546);",
+                    Hash::sha2(&address)
+                ))
+                .await
+                .unwrap();
+            }
+        }
+        db
+    }
+
+    #[tokio::test]
+    #[ignore = "requires postgres, set DATABASE_URL"]
+    async fn test_rune_amounts_above_u64() {
+        let db = setup_runes().await;
+        // 2^70 + 1 and 2^70 + 2 with the seeded outputs of 1
+        let big = BigDecimal::from(1u128 << 70);
+        for (vout, address) in ["BBBB_0", "BBBB_1"].into_iter().enumerate() {
+            let amount = &big + BigDecimal::from(vout as u32);
+            db.exec_raw(&format!(
+                "INSERT INTO runes_outputs \
+                    (block, tx_id, tx_hash, vout, rune, rune_id, address, amount, btc_amount) \
+                 VALUES (2, 2, '\\x{}', {vout}, 'BBBB', '2:0', '{address}', {amount}, 546);",
+                Hash::sha2("big")
+            ))
+            .await
+            .unwrap();
+        }
+
+        let cases = [
+            (None, 3),
+            (Some(BigDecimal::from(u64::MAX)), 2),
+            (Some(&big + BigDecimal::from(1)), 1),
+            (Some(&big + BigDecimal::from(2)), 0),
+        ];
+        for (threshold, expected) in cases {
+            let count = db
+                .count_rune_holders("BBBB", threshold.clone())
+                .await
+                .unwrap();
+            assert_eq!(count, expected, "{threshold:?}");
+            let holders = db
+                .get_rune_holders("BBBB", OrderBy::Desc, threshold.clone(), 10, 0)
+                .await
+                .unwrap();
+            assert_eq!(holders.len() as i64, expected, "{threshold:?}");
+        }
+
+        let utxos = |min: u128| {
+            let amount = AmountRange {
+                min: Some(BigDecimal::from(min)),
+                max: None,
+            };
+            db.select_rune_utxo_with_pagination(
+                "BBBB",
+                "BBBB_1",
+                OrderBy::Desc,
+                amount,
+                UtxoSortMode::Amount,
+                None,
+                10,
+                0,
+            )
+        };
+        let rows = utxos((1 << 70) + 1).await.unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].amount, &big + BigDecimal::from(1));
+        assert!(utxos((1 << 70) + 2).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires postgres, set DATABASE_URL"]
+    async fn test_rune_utxo_amount_range() {
+        let db = setup_runes().await;
+        // 1 from the setup
+        for (vout, amount) in [546, 547, 999].into_iter().enumerate() {
+            db.exec_raw(&format!(
+                "INSERT INTO runes_outputs \
+                    (block, tx_id, tx_hash, vout, rune, rune_id, address, amount, btc_amount) \
+                 VALUES (2, 2, '\\x{}', {vout}, 'BBBB', '2:0', 'BBBB_0', {amount}, 546);",
+                Hash::sha2("amounts")
+            ))
+            .await
+            .unwrap();
+        }
+
+        let range = |min: Option<u32>, max: Option<u32>| AmountRange {
+            min: min.map(BigDecimal::from),
+            max: max.map(BigDecimal::from),
+        };
+        let cases = [
+            (range(None, None), 4),
+            (range(Some(546), None), 3),
+            (range(Some(547), None), 2),
+            (range(None, Some(546)), 2),
+            (range(Some(547), Some(999)), 2),
+            (range(Some(999), Some(999)), 1),
+            (range(Some(1000), None), 0),
+        ];
+        for (amount, expected) in cases {
+            let count = db
+                .count_runes_utxo("BBBB", "BBBB_0", amount.clone())
+                .await
+                .unwrap();
+            assert_eq!(count, expected, "{amount:?}");
+
+            let rows = db
+                .select_rune_utxo_with_pagination(
+                    "BBBB",
+                    "BBBB_0",
+                    OrderBy::Asc,
+                    amount.clone(),
+                    UtxoSortMode::Amount,
+                    None,
+                    100,
+                    0,
+                )
+                .await
+                .unwrap();
+            assert_eq!(rows.len() as i64, expected, "{amount:?}");
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires postgres, set DATABASE_URL"]
+    async fn test_list_runes_sorting() {
+        let db = setup_runes().await;
+
+        let names = |rows: Vec<Rune>| rows.into_iter().map(|r| r.name).collect::<Vec<_>>();
+        let cases = [
+            (RuneSortMode::Age, ["CCCC", "BBBB", "AAAA"]),
+            (RuneSortMode::Holders, ["BBBB", "AAAA", "CCCC"]),
+            (RuneSortMode::Minted, ["CCCC", "AAAA", "BBBB"]),
+            (RuneSortMode::Supply, ["BBBB", "AAAA", "CCCC"]),
+            // unlimited runes have no progress, they go last
+            (RuneSortMode::Progress, ["BBBB", "AAAA", "CCCC"]),
+        ];
+        for (sort, expected) in cases {
+            let rows = db
+                .list_runes(sort, OrderBy::Desc, 10, 0, None, None, vec![], None, None)
+                .await
+                .unwrap();
+            assert_eq!(names(rows), expected, "sort={sort}");
+        }
+
+        let rows = db
+            .list_runes(
+                RuneSortMode::Holders,
+                OrderBy::Asc,
+                2,
+                0,
+                Some("A".into()),
+                None,
+                vec![],
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(names(rows), ["AAAA"]);
+        assert_eq!(
+            db.count_runes(Some("A".into()), None, vec![], None, None)
+                .await
+                .unwrap(),
+            1
+        );
+
+        let ids = vec!["1:0".to_string(), "3:0".to_string()];
+        let rows = db
+            .list_runes(
+                RuneSortMode::Age,
+                OrderBy::Asc,
+                10,
+                0,
+                None,
+                None,
+                ids.clone(),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(names(rows), ["AAAA", "CCCC"]);
+        assert_eq!(
+            db.count_runes(None, None, ids, None, None).await.unwrap(),
+            2
+        );
+
+        let rows = db
+            .list_runes(
+                RuneSortMode::Age,
+                OrderBy::Asc,
+                10,
+                0,
+                None,
+                None,
+                vec![],
+                Some(2),
+                Some(3),
+            )
+            .await
+            .unwrap();
+        assert_eq!(names(rows), ["BBBB", "CCCC"]);
+        assert_eq!(
+            db.count_runes(None, None, vec![], Some(2), None)
+                .await
+                .unwrap(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires postgres, set DATABASE_URL"]
+    async fn test_list_runes_stats() {
+        let db = setup_runes().await;
+
+        let stats = db
+            .list_runes_stats(
+                RuneSortMode::Holders,
+                OrderBy::Desc,
+                10,
+                0,
+                None,
+                None,
+                vec![],
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        let rows: Vec<_> = stats
+            .iter()
+            .map(|s| {
+                (
+                    s.rune.as_str(),
+                    s.holders,
+                    s.utxo_count,
+                    s.last_activity_block,
+                )
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ("BBBB", 3, 3, Some(2)),
+                ("AAAA", 1, 1, Some(1)),
+                ("CCCC", 0, 0, None)
+            ]
+        );
+        assert_eq!(stats[0].rune_id, "2:0");
+        assert_eq!(stats[0].in_circulation, BigDecimal::from(500));
+        assert_eq!(stats[0].minted, BigDecimal::from(500));
+
+        let stats = db
+            .list_runes_stats(
+                RuneSortMode::Age,
+                OrderBy::Asc,
+                10,
+                0,
+                Some("A".into()),
+                None,
+                vec![],
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(stats.len(), 1);
+        assert_eq!((stats[0].rune.as_str(), stats[0].holders), ("AAAA", 1));
     }
 }
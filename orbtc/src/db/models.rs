@@ -1,8 +1,11 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use api_core::serde_utils::bytevec_as_hex;
 use bigdecimal::BigDecimal;
 use orbtc_indexer_api::types::Hash;
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
+use utoipa::ToSchema;
 
 #[derive(Default, Clone, Debug, FromRow, Serialize, Deserialize)]
 pub struct RuneShortRow {
@@ -23,41 +26,170 @@ pub struct ShortTxOut {
     pub vout: i32,
 }
 
+#[derive(Default, Clone, Debug, FromRow, Serialize)]
+pub struct ShortTxOutAddress {
+    pub tx_hash: Hash,
+    pub vout: i32,
+    pub address: String,
+}
+
+#[derive(Default, Clone, Debug, FromRow, Serialize)]
+pub struct ShortUtxo {
+    pub tx_hash: Hash,
+    pub vout: i32,
+    pub amount: i64,
+}
+
+/// Utxo amounts of the address split by what prevents spending them.
+/// Each utxo is counted once, in the first matching category of
+/// `immature`, `with_runes`, `with_inscriptions`.
+#[derive(Default, Clone, Debug, FromRow, Serialize)]
+pub struct SpendableBalance {
+    pub balance: i64,
+    pub spendable: i64,
+    pub immature: i64,
+    pub with_runes: i64,
+    pub with_inscriptions: i64,
+}
+
+/// Flags of an output, a missing row means both are `false`.
 #[derive(Default, Clone, Debug, FromRow, Serialize)]
 pub struct OutputExtras {
     pub id: i64,
+    /// Set by the inscriptions indexer from ord and by `db sync-extras` from `runes_outputs`.
+    /// Nothing filters by it: `skip_runes` checks `runes_outputs` directly,
+    /// it's kept for the external consumers of the table.
     pub has_runes: bool,
+    /// Set by the inscriptions indexer. Used by `skip_inscriptions` of the utxo collection
+    /// and listings, and by the inscriptions count of the balance.
     pub has_inscriptions: bool,
 }
 
-#[derive(Default, Clone, Debug, FromRow, Serialize, Deserialize)]
-pub struct AddressTx {
-    pub address: String,
-    pub tx_hash: Hash,
-    pub block: i64,
-}
-
-#[derive(Default, Clone, Debug, FromRow, Serialize, Deserialize)]
+#[derive(Default, Clone, Debug, FromRow, Serialize, Deserialize, ToSchema)]
 pub struct ApiKey {
     pub name: String,
     pub key: String,
     pub blocked: bool,
     pub can_lock_utxo: bool,
+    pub is_admin: bool,
+    /// Overrides `api.rate_limit.requests_per_minute` for this key, 0 disables the limit.
+    pub rate_limit_per_minute: Option<i32>,
+    /// Allowed groups of endpoints, see [`scopes`].
+    pub scopes: Vec<String>,
+    /// Networks where the key is accepted, empty means any.
+    pub networks: Vec<String>,
+    /// Unix timestamp, expired keys are rejected like blocked ones. Never expires if not set.
+    pub expires_at: Option<i64>,
+}
+
+/// Webhook subscription for new outputs of the address.
+#[derive(Default, Clone, Debug, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct Subscription {
+    pub id: i64,
+    pub address: String,
+    pub url: String,
+    pub api_key_name: String,
+    /// Unix timestamp.
+    pub created_at: i64,
+}
+
+/// Label of the address set by an api user, it never affects indexing.
+#[derive(Default, Clone, Debug, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct AddressLabel {
+    pub address: String,
+    pub label: String,
+    /// Name of the api key that set the label.
+    pub created_by: String,
+    /// Unix timestamp.
+    pub created_at: i64,
+}
+
+#[derive(Default, Clone, Debug, FromRow)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub subscription_id: i64,
+    pub url: String,
+    pub block: i64,
+    pub tx_hash: Hash,
+    pub vout: i32,
+    pub address: String,
+    pub amount: i64,
+    pub attempts: i32,
+}
+
+pub mod scopes {
+    /// All GET endpoints and POST endpoints that don't change anything.
+    pub const READ: &str = "read";
+    /// Utxo collection with locks, unlocking and psbt building.
+    pub const LOCK: &str = "lock";
+    /// Sending transactions and testing them against the node.
+    pub const BROADCAST: &str = "broadcast";
+    /// `/v1/admin` endpoints.
+    pub const ADMIN: &str = "admin";
+    /// Managing and reading address labels.
+    pub const LABELS: &str = "labels";
+    /// Managing webhook subscriptions, the service sends requests to their urls.
+    pub const SUBSCRIPTIONS: &str = "subscriptions";
+
+    pub const ALL: [&str; 6] = [READ, LOCK, BROADCAST, ADMIN, LABELS, SUBSCRIPTIONS];
+    pub const DEFAULT: [&str; 2] = [READ, BROADCAST];
+
+    pub fn is_valid(scope: &str) -> bool {
+        ALL.contains(&scope)
+    }
 }
 
 impl ApiKey {
     pub fn new(name: &str) -> Self {
-        use base64::Engine;
-        use rand::Rng;
-
-        let mut rng = rand::thread_rng();
-        let key: Vec<u8> = (0..24).map(|_| rng.gen::<u8>()).collect();
-        let key = base64::prelude::BASE64_URL_SAFE.encode(key);
         Self {
             name: name.into(),
-            key,
+            key: Self::generate_key(),
             blocked: false,
             can_lock_utxo: false,
+            is_admin: false,
+            rate_limit_per_minute: None,
+            scopes: scopes::DEFAULT.iter().map(|s| s.to_string()).collect(),
+            networks: Vec::new(),
+            expires_at: None,
+        }
+    }
+
+    /// Unix timestamp `days` from now.
+    pub fn expiry_in_days(days: u32) -> i64 {
+        unix_now() + days as i64 * 24 * 60 * 60
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|t| t <= unix_now())
+    }
+
+    /// `can_lock_utxo` and `is_admin` flags are kept as aliases
+    /// of the `lock` and `admin` scopes.
+    pub fn has_scope(&self, scope: &str) -> bool {
+        match scope {
+            scopes::LOCK if self.can_lock_utxo => true,
+            scopes::ADMIN if self.is_admin => true,
+            _ => self.scopes.iter().any(|s| s == scope),
         }
     }
+
+    pub fn allows_network(&self, net: &str) -> bool {
+        self.networks.is_empty() || self.networks.iter().any(|n| n == net)
+    }
+
+    pub fn generate_key() -> String {
+        use base64::Engine;
+        use rand::Rng;
+
+        let mut rng = rand::thread_rng();
+        let key: Vec<u8> = (0..24).map(|_| rng.gen::<u8>()).collect();
+        base64::prelude::BASE64_URL_SAFE.encode(key)
+    }
+}
+
+pub fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
 }
@@ -1,15 +1,28 @@
 use sqlx::{Database, QueryBuilder};
 
+/// Builds the `WHERE` clause from the optional filters, filters without values are skipped.
+/// Conditions are joined with `AND`, [`DynamicQueryBuilder::add_or_group`] adds
+/// a parenthesized group of conditions joined with `OR`.
 pub struct DynamicQueryBuilder<'a, DB: Database> {
     query: QueryBuilder<'a, DB>,
-    has_where: bool,
+    /// Open groups of conditions, the first one is the `WHERE` clause itself.
+    groups: Vec<Group>,
+}
+
+struct Group {
+    separator: &'static str,
+    /// The group has a condition, so its opening is already in the query.
+    started: bool,
 }
 
 impl<'a, DB: Database> DynamicQueryBuilder<'a, DB> {
     pub fn new(initial_query: &str) -> Self {
         Self {
             query: QueryBuilder::new(initial_query),
-            has_where: false,
+            groups: vec![Group {
+                separator: " AND ",
+                started: false,
+            }],
         }
     }
 
@@ -18,18 +31,252 @@ impl<'a, DB: Database> DynamicQueryBuilder<'a, DB> {
         T: 'a + Send + sqlx::Encode<'a, DB> + sqlx::Type<DB>,
     {
         if let Some(val) = value {
-            if self.has_where {
-                self.query.push(" AND ");
-            } else {
-                self.query.push(" WHERE ");
-                self.has_where = true;
-            }
+            self.begin_condition();
             self.query.push(condition).push_bind(val);
         }
         self
     }
 
+    /// `column IN (...)` condition, skipped if `values` is empty.
+    pub fn add_and_in<T>(&mut self, column: &str, values: Vec<T>) -> &mut Self
+    where
+        T: 'a + Send + sqlx::Encode<'a, DB> + sqlx::Type<DB>,
+    {
+        if values.is_empty() {
+            return self;
+        }
+        self.begin_condition();
+        self.query.push(column).push(" IN (");
+        let mut list = self.query.separated(", ");
+        for val in values {
+            list.push_bind(val);
+        }
+        list.push_unseparated(")");
+        self
+    }
+
+    /// Inclusive range of the `column`, an unset bound isn't checked.
+    pub fn add_and_range<T>(&mut self, column: &str, min: Option<T>, max: Option<T>) -> &mut Self
+    where
+        T: 'a + Send + sqlx::Encode<'a, DB> + sqlx::Type<DB>,
+    {
+        if min.is_some() && max.is_some() {
+            return self.add_and_group(|g| {
+                g.add_and(&format!("{column} >= "), min)
+                    .add_and(&format!("{column} <= "), max);
+            });
+        }
+        self.add_and(&format!("{column} >= "), min)
+            .add_and(&format!("{column} <= "), max)
+    }
+
+    /// Parenthesized group of conditions joined with `OR`, skipped if it has no conditions.
+    pub fn add_or_group<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnOnce(&mut OrGroup<'_, 'a, DB>),
+    {
+        self.group(" OR ", |q| f(&mut OrGroup(q)))
+    }
+
+    /// Parenthesized group of conditions joined with `AND`, it's useful inside of an `OR` group.
+    pub fn add_and_group<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnOnce(&mut Self),
+    {
+        self.group(" AND ", f)
+    }
+
     pub fn query(&mut self) -> &mut QueryBuilder<'a, DB> {
         &mut self.query
     }
+
+    fn group<F>(&mut self, separator: &'static str, f: F) -> &mut Self
+    where
+        F: FnOnce(&mut Self),
+    {
+        self.groups.push(Group {
+            separator,
+            started: false,
+        });
+        f(self);
+        if self.groups.pop().is_some_and(|g| g.started) {
+            self.query.push(")");
+        }
+        self
+    }
+
+    /// Pushes the separator of the innermost group before the next condition.
+    /// Groups are opened only with their first condition, so empty groups leave no trace.
+    fn begin_condition(&mut self) {
+        // a group is started only after its parent, so the started ones go first
+        let Some(first) = self.groups.iter().position(|g| !g.started) else {
+            let separator = self.groups[self.groups.len() - 1].separator;
+            self.query.push(separator);
+            return;
+        };
+
+        for i in first..self.groups.len() {
+            if i == 0 {
+                self.query.push(" WHERE ");
+            } else {
+                if i == first {
+                    let separator = self.groups[i - 1].separator;
+                    self.query.push(separator);
+                }
+                self.query.push("(");
+            }
+            self.groups[i].started = true;
+        }
+    }
+}
+
+/// Conditions of the [`DynamicQueryBuilder::add_or_group`], they are joined with `OR`.
+pub struct OrGroup<'q, 'a, DB: Database>(&'q mut DynamicQueryBuilder<'a, DB>);
+
+impl<'a, DB: Database> OrGroup<'_, 'a, DB> {
+    pub fn add_or<T>(&mut self, condition: &str, value: Option<T>) -> &mut Self
+    where
+        T: 'a + Send + sqlx::Encode<'a, DB> + sqlx::Type<DB>,
+    {
+        self.0.add_and(condition, value);
+        self
+    }
+
+    pub fn add_or_in<T>(&mut self, column: &str, values: Vec<T>) -> &mut Self
+    where
+        T: 'a + Send + sqlx::Encode<'a, DB> + sqlx::Type<DB>,
+    {
+        self.0.add_and_in(column, values);
+        self
+    }
+
+    /// The range is a single condition of the group, both bounds are checked together.
+    pub fn add_or_range<T>(&mut self, column: &str, min: Option<T>, max: Option<T>) -> &mut Self
+    where
+        T: 'a + Send + sqlx::Encode<'a, DB> + sqlx::Type<DB>,
+    {
+        self.0.add_and_range(column, min, max);
+        self
+    }
+
+    pub fn add_and_group<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnOnce(&mut DynamicQueryBuilder<'a, DB>),
+    {
+        self.0.add_and_group(f);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::Postgres;
+
+    use super::*;
+
+    fn sql(q: &mut DynamicQueryBuilder<Postgres>) -> String {
+        q.query().sql().to_string()
+    }
+
+    #[test]
+    fn test_add_and() {
+        let mut q = DynamicQueryBuilder::<Postgres>::new("SELECT * FROM runes");
+        q.add_and("is_featured = ", Some(true))
+            .add_and("name = ", None::<String>)
+            .add_and("block = ", Some(1i64));
+        assert_eq!(
+            sql(&mut q),
+            "SELECT * FROM runes WHERE is_featured = $1 AND block = $2"
+        );
+
+        let mut q = DynamicQueryBuilder::<Postgres>::new("SELECT * FROM runes");
+        q.add_and("name = ", None::<String>);
+        assert_eq!(sql(&mut q), "SELECT * FROM runes");
+    }
+
+    #[test]
+    fn test_add_and_in() {
+        let mut q = DynamicQueryBuilder::<Postgres>::new("SELECT * FROM runes");
+        q.add_and_in("rune_id", Vec::<String>::new());
+        assert_eq!(sql(&mut q), "SELECT * FROM runes");
+
+        q.add_and_in("rune_id", vec!["1:0".to_string(), "2:0".to_string()])
+            .add_and_in("name", vec!["A".to_string()]);
+        assert_eq!(
+            sql(&mut q),
+            "SELECT * FROM runes WHERE rune_id IN ($1, $2) AND name IN ($3)"
+        );
+    }
+
+    #[test]
+    fn test_add_and_range() {
+        let mut q = DynamicQueryBuilder::<Postgres>::new("SELECT * FROM runes");
+        q.add_and_range("block", Some(10i64), None);
+        assert_eq!(sql(&mut q), "SELECT * FROM runes WHERE block >= $1");
+
+        let mut q = DynamicQueryBuilder::<Postgres>::new("SELECT * FROM runes");
+        q.add_and_range("block", None, Some(20i64));
+        assert_eq!(sql(&mut q), "SELECT * FROM runes WHERE block <= $1");
+
+        let mut q = DynamicQueryBuilder::<Postgres>::new("SELECT * FROM runes");
+        q.add_and("is_featured = ", Some(true))
+            .add_and_range("block", Some(10i64), Some(20i64));
+        assert_eq!(
+            sql(&mut q),
+            "SELECT * FROM runes WHERE is_featured = $1 AND (block >= $2 AND block <= $3)"
+        );
+
+        let mut q = DynamicQueryBuilder::<Postgres>::new("SELECT * FROM runes");
+        q.add_and_range("block", None::<i64>, None);
+        assert_eq!(sql(&mut q), "SELECT * FROM runes");
+    }
+
+    #[test]
+    fn test_add_or_group() {
+        let mut q = DynamicQueryBuilder::<Postgres>::new("SELECT * FROM runes");
+        q.add_or_group(|g| {
+            g.add_or("name = ", None::<String>);
+        })
+        .add_and("is_featured = ", Some(true));
+        assert_eq!(sql(&mut q), "SELECT * FROM runes WHERE is_featured = $1");
+
+        let mut q = DynamicQueryBuilder::<Postgres>::new("SELECT * FROM runes");
+        q.add_and("is_featured = ", Some(true)).add_or_group(|g| {
+            g.add_or("name ILIKE ", Some("%A%".to_string()))
+                .add_or_in("rune_id", vec!["1:0".to_string(), "2:0".to_string()])
+                .add_or_range("block", Some(10i64), Some(20i64));
+        });
+        assert_eq!(
+            sql(&mut q),
+            "SELECT * FROM runes WHERE is_featured = $1 \
+             AND (name ILIKE $2 OR rune_id IN ($3, $4) OR (block >= $5 AND block <= $6))"
+        );
+    }
+
+    #[test]
+    fn test_nested_groups() {
+        let mut q = DynamicQueryBuilder::<Postgres>::new("SELECT * FROM runes");
+        q.add_or_group(|g| {
+            g.add_or("name = ", Some("A".to_string()))
+                .add_and_group(|g| {
+                    g.add_and("is_featured = ", Some(true)).add_or_group(|g| {
+                        g.add_or("block = ", Some(1i64))
+                            .add_or("block = ", Some(2i64));
+                    });
+                });
+        });
+        assert_eq!(
+            sql(&mut q),
+            "SELECT * FROM runes WHERE (name = $1 OR (is_featured = $2 AND (block = $3 OR block = $4)))"
+        );
+
+        // only the innermost group has conditions
+        let mut q = DynamicQueryBuilder::<Postgres>::new("SELECT * FROM runes");
+        q.add_or_group(|g| {
+            g.add_or("name = ", None::<String>).add_and_group(|g| {
+                g.add_and("block = ", Some(1i64));
+            });
+        });
+        assert_eq!(sql(&mut q), "SELECT * FROM runes WHERE ((block = $1))");
+    }
 }
@@ -20,6 +20,64 @@ pub struct Block {
     pub indexer: String,
 }
 
+#[derive(Default, Clone, Debug, Queryable, Selectable, Insertable)]
+#[diesel(table_name = tables::orphaned_blocks)]
+pub struct OrphanedBlock {
+    pub height: i64,
+    pub hash: Hash,
+    pub blocktime: i64,
+    pub indexer: String,
+}
+
+#[derive(Default, Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = tables::failed_txs)]
+pub struct FailedTx {
+    pub id: i64,
+    pub indexer: String,
+    pub block: i64,
+    pub tx_id: i32,
+    pub tx_hash: Hash,
+    pub error: String,
+    pub attempts: i32,
+}
+
+/// Fees of the block, txs spending outputs that aren't indexed are skipped.
+#[derive(Default, Clone, Debug, PartialEq, Queryable, Selectable, Insertable, AsChangeset)]
+#[diesel(table_name = tables::block_stats, primary_key(height))]
+pub struct BlockStats {
+    pub height: i64,
+    pub tx_count: i32,
+    pub fee_tx_count: i32,
+    pub total_fees: i64,
+    pub fee_rate_min: f64,
+    pub fee_rate_p10: f64,
+    pub fee_rate_p25: f64,
+    pub fee_rate_p50: f64,
+    pub fee_rate_p75: f64,
+    pub fee_rate_p90: f64,
+    pub fee_rate_max: f64,
+}
+
+#[derive(Default, Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = tables::subscriptions)]
+pub struct Subscription {
+    pub id: i64,
+    pub address: String,
+    pub url: String,
+    pub api_key_name: String,
+}
+
+#[derive(Default, Clone, Debug, Insertable)]
+#[diesel(table_name = tables::webhook_deliveries)]
+pub struct NewWebhookDelivery {
+    pub subscription_id: i64,
+    pub block: i64,
+    pub tx_hash: Hash,
+    pub vout: i32,
+    pub address: String,
+    pub amount: i64,
+}
+
 #[derive(Default, Clone, Debug, Queryable, Selectable, Insertable)]
 #[diesel(table_name = tables::addresses)]
 pub struct Address {
@@ -121,14 +179,20 @@ impl Rune {
     }
 
     pub fn terms(&self) -> Option<Terms> {
-        if self.raw_data.is_empty() {
-            return None;
-        }
-        let runestone: Runestone = serde_json::from_slice(&self.raw_data).ok()?;
-        runestone.etching.and_then(|e| e.terms)
+        decode_terms(&self.raw_data)
     }
 }
 
+/// Extracts mint terms from the runestone stored in `runes.raw_data`,
+/// it's empty for cenotaphs.
+pub fn decode_terms(raw_data: &[u8]) -> Option<Terms> {
+    if raw_data.is_empty() {
+        return None;
+    }
+    let runestone: Runestone = serde_json::from_slice(raw_data).ok()?;
+    runestone.etching.and_then(|e| e.terms)
+}
+
 #[derive(Default, Clone, Debug)]
 pub struct RuneShortRow {
     pub rune: String,
@@ -188,6 +252,20 @@ pub struct OutputExtras {
     pub has_inscriptions: bool,
 }
 
+#[derive(Default, Clone, Debug, Queryable, Selectable, Insertable)]
+#[diesel(table_name = tables::rune_events)]
+pub struct RuneEvent {
+    #[diesel(deserialize_as = i64)]
+    pub id: Option<i64>,
+    pub block: i64,
+    pub tx_id: i32,
+    pub tx_hash: Hash,
+    pub rune: String,
+    pub rune_id: String,
+    pub event_type: String,
+    pub amount: Amount,
+}
+
 pub mod tables {
     use diesel::prelude::*;
 
@@ -207,6 +285,15 @@ pub mod tables {
        }
     }
 
+    table! {
+       orphaned_blocks(height, hash, indexer) {
+           height -> BigInt,
+           hash -> Bytea,
+           blocktime -> BigInt,
+           indexer -> VarChar,
+       }
+    }
+
     table! {
         addresses {
             id -> BigSerial,
@@ -297,4 +384,66 @@ pub mod tables {
             has_inscriptions -> Bool
         }
     }
+
+    table! {
+        rune_events {
+            id -> BigSerial,
+            block -> BigInt,
+            tx_id -> Integer,
+            tx_hash -> Bytea,
+            rune -> VarChar,
+            rune_id -> VarChar,
+            event_type -> VarChar,
+            amount -> Numeric,
+        }
+    }
+
+    table! {
+        failed_txs {
+            id -> BigSerial,
+            indexer -> VarChar,
+            block -> BigInt,
+            tx_id -> Integer,
+            tx_hash -> Bytea,
+            error -> VarChar,
+            attempts -> Integer,
+        }
+    }
+
+    table! {
+        block_stats (height) {
+            height -> BigInt,
+            tx_count -> Integer,
+            fee_tx_count -> Integer,
+            total_fees -> BigInt,
+            fee_rate_min -> Double,
+            fee_rate_p10 -> Double,
+            fee_rate_p25 -> Double,
+            fee_rate_p50 -> Double,
+            fee_rate_p75 -> Double,
+            fee_rate_p90 -> Double,
+            fee_rate_max -> Double,
+        }
+    }
+
+    table! {
+        subscriptions {
+            id -> BigSerial,
+            address -> VarChar,
+            url -> VarChar,
+            api_key_name -> VarChar,
+        }
+    }
+
+    table! {
+        webhook_deliveries {
+            id -> BigSerial,
+            subscription_id -> BigInt,
+            block -> BigInt,
+            tx_hash -> Bytea,
+            vout -> Integer,
+            address -> VarChar,
+            amount -> BigInt,
+        }
+    }
 }
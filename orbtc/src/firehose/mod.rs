@@ -1,86 +1,182 @@
 use std::str::FromStr;
+use std::time::Duration;
 
-use bitcoin::hashes::Hash;
+use anyhow::Context;
 use hex;
-use sf::firehose::v2::SingleBlockResponse;
+use tokio::runtime::Handle;
 use tonic::transport::{Channel, ClientTlsConfig};
-use tonic::Request;
+use tonic::{Request, Streaming};
 
 use prost::Message;
 
 mod sf;
 
 // Generated from Firehose proto
-use sf::bitcoin::v1::Block as BtcBlock;
+use sf::bitcoin::v1::{Block as BtcBlock, Transaction as ProtoTx};
 use sf::firehose::v2::{
     fetch_client::FetchClient as FirehoseClient,
     single_block_request::{BlockNumber, Reference},
-    SingleBlockRequest,
+    stream_client::StreamClient as FirehoseStreamClient,
+    ForkStep, Request as StreamRequest, Response as StreamResponse, SingleBlockRequest,
 };
 
-const FIREHOSE_BTC: &str = "https://mainnet.btc.streamingfast.io:443";
+const MAX_MESSAGE_SIZE: usize = 30417402;
+/// Max time to wait for the next block in the stream,
+/// after that caller falls back to the single block fetch.
+const STREAM_TIMEOUT: Duration = Duration::from_secs(60);
 
+/// Firehose client for the blocking indexer.
+/// All requests are driven by the `handle` of the app runtime.
 pub struct FHClient {
     api_key: String,
+    net: bitcoin::Network,
+    handle: Handle,
     client: FirehoseClient<Channel>,
+    stream_client: FirehoseStreamClient<Channel>,
 }
 
 impl FHClient {
-    pub fn new(api_key: &str) -> anyhow::Result<Self> {
-        use tokio::runtime::Runtime;
-        let rt = Runtime::new()?;
-        rt.block_on(async {
-            let channel = Channel::from_static(FIREHOSE_BTC)
-                .tls_config(ClientTlsConfig::new().with_webpki_roots())?
-                .connect()
-                .await?;
-
-            let client = FirehoseClient::new(channel)
-                .max_decoding_message_size(30417402)
-                .accept_compressed(tonic::codec::CompressionEncoding::Gzip);
-            Ok(Self {
-                api_key: api_key.to_owned(),
-                client,
-            })
+    /// Connection is established lazily on the first request,
+    /// so the client can be created inside of the async context.
+    pub fn new(
+        endpoint: &str,
+        api_key: &str,
+        net: bitcoin::Network,
+        handle: Handle,
+    ) -> anyhow::Result<Self> {
+        let channel = Channel::from_shared(endpoint.to_owned())
+            .with_context(|| format!("invalid firehose endpoint: {endpoint}"))?
+            .tls_config(ClientTlsConfig::new().with_webpki_roots())?
+            .connect_lazy();
+
+        let client = FirehoseClient::new(channel.clone())
+            .max_decoding_message_size(MAX_MESSAGE_SIZE)
+            .accept_compressed(tonic::codec::CompressionEncoding::Gzip);
+        let stream_client = FirehoseStreamClient::new(channel)
+            .max_decoding_message_size(MAX_MESSAGE_SIZE)
+            .accept_compressed(tonic::codec::CompressionEncoding::Gzip);
+
+        Ok(Self {
+            api_key: api_key.to_owned(),
+            net,
+            handle,
+            client,
+            stream_client,
         })
     }
 
+    fn request<T>(&self, message: T) -> anyhow::Result<Request<T>> {
+        let mut request = Request::new(message);
+        request
+            .metadata_mut()
+            .insert("authorization", format!("Bearer {}", self.api_key).parse()?);
+        Ok(request)
+    }
+
+    pub async fn block(
+        &mut self,
+        block_num: u64,
+    ) -> anyhow::Result<(bitcoin::BlockHash, bitcoin::Block)> {
+        let request = self.request(SingleBlockRequest {
+            reference: Some(Reference::BlockNumber(BlockNumber { num: block_num })),
+            ..Default::default()
+        })?;
+
+        let response = self.client.block(request).await?.into_inner();
+        let Some(data) = response.block else {
+            anyhow::bail!("empty block")
+        };
+
+        let proto_block = BtcBlock::decode(data.value.as_slice())?;
+        if proto_block.height != block_num as i64 {
+            anyhow::bail!(
+                "unexpected block height: requested={block_num} got={}",
+                proto_block.height
+            );
+        }
+        proto_block_to_btc(proto_block, self.net)
+    }
+
+    pub async fn blocks(&mut self, start: u64) -> anyhow::Result<Streaming<StreamResponse>> {
+        let request = self.request(StreamRequest {
+            start_block_num: start as i64,
+            ..Default::default()
+        })?;
+
+        let response = self.stream_client.blocks(request).await?;
+        Ok(response.into_inner())
+    }
+
+    /// Blocking version of [`Self::block`].
     pub fn get_block(
         &mut self,
         block_num: u64,
     ) -> anyhow::Result<(bitcoin::BlockHash, bitcoin::Block)> {
-        use tokio::runtime::Runtime;
-        let rt = Runtime::new()?;
+        let handle = self.handle.clone();
+        handle.block_on(self.block(block_num))
+    }
 
-        let block: anyhow::Result<SingleBlockResponse> = rt.block_on(async {
-            let mut request = Request::new(SingleBlockRequest {
-                reference: Some(Reference::BlockNumber(BlockNumber { num: block_num })),
-                ..Default::default()
-            });
-            request
-                .metadata_mut()
-                .insert("authorization", format!("Bearer {}", self.api_key).parse()?);
-
-            let response = self.client.block(request).await?;
-            let block = response.into_inner();
-            Ok(block)
-        });
-
-        let block = block?;
-        let Some(data) = block.block else {
+    /// Opens the stream of blocks starting from `start` (inclusively).
+    pub fn stream_blocks(&mut self, start: u64) -> anyhow::Result<BlockStream> {
+        let handle = self.handle.clone();
+        let inner = handle.block_on(self.blocks(start))?;
+        Ok(BlockStream {
+            net: self.net,
+            handle,
+            inner,
+            next_height: start,
+        })
+    }
+}
+
+/// Blocking iterator over the firehose stream of new blocks.
+pub struct BlockStream {
+    net: bitcoin::Network,
+    handle: Handle,
+    inner: Streaming<StreamResponse>,
+    next_height: u64,
+}
+
+impl BlockStream {
+    /// Height of the block that is expected to be returned by [`Self::next_block`].
+    pub fn next_height(&self) -> u64 {
+        self.next_height
+    }
+
+    /// Waits for the next block in the stream.
+    /// Reorgs are handled by the caller, so the stream is not usable after the undo step.
+    pub fn next_block(&mut self) -> anyhow::Result<(u64, bitcoin::BlockHash, bitcoin::Block)> {
+        let response = self
+            .handle
+            .block_on(tokio::time::timeout(STREAM_TIMEOUT, self.inner.message()))
+            .map_err(|_| anyhow::anyhow!("no blocks in the stream for {STREAM_TIMEOUT:?}"))??;
+        let Some(response) = response else {
+            anyhow::bail!("stream is closed");
+        };
+        if response.step == ForkStep::StepUndo as i32 {
+            anyhow::bail!("reorg in the stream");
+        }
+        let Some(data) = response.block else {
             anyhow::bail!("empty block")
         };
 
         let proto_block = BtcBlock::decode(data.value.as_slice())?;
-        let block = proto_block_to_btc(proto_block)?;
-        Ok(block)
+        let height = proto_block.height as u64;
+        let (hash, block) = proto_block_to_btc(proto_block, self.net)?;
+        self.next_height = height + 1;
+        Ok((height, hash, block))
     }
 }
 
-fn proto_block_to_btc(problock: BtcBlock) -> anyhow::Result<(bitcoin::BlockHash, bitcoin::Block)> {
+fn proto_block_to_btc(
+    problock: BtcBlock,
+    net: bitcoin::Network,
+) -> anyhow::Result<(bitcoin::BlockHash, bitcoin::Block)> {
     use bitcoin::block::{Header, Version};
     use bitcoin::{BlockHash, CompactTarget, Transaction, TxMerkleNode};
 
+    check_network(&problock, net)?;
+
     let block_hash = BlockHash::from_str(&problock.hash)?;
     let mut block = bitcoin::Block {
         header: Header {
@@ -107,32 +203,35 @@ fn proto_block_to_btc(problock: BtcBlock) -> anyhow::Result<(bitcoin::BlockHash,
         for vin in ptx.vin.iter() {
             use bitcoin::{OutPoint, ScriptBuf, Sequence, TxIn, Txid, Witness};
 
-            let script_sig = if let Some(sig) = vin.script_sig.as_ref() {
+            // coinbase input has no script_sig, its script is in the `coinbase` field
+            let script_sig = if !vin.coinbase.is_empty() {
+                ScriptBuf::from_hex(&vin.coinbase)?
+            } else if let Some(sig) = vin.script_sig.as_ref() {
                 ScriptBuf::from_hex(&sig.hex)?
             } else {
                 ScriptBuf::default()
             };
-            let witnesses: Vec<_> = vin
+            let witnesses = vin
                 .txinwitness
                 .iter()
-                .filter_map(|e| hex::decode(e).ok())
-                .collect();
+                .map(hex::decode)
+                .collect::<Result<Vec<_>, _>>()?;
 
             let witness = if witnesses.is_empty() {
                 Witness::default()
             } else {
                 Witness::from_slice(&witnesses)
             };
-            let txid = if !vin.coinbase.is_empty() {
-                Txid::all_zeros()
+            let previous_output = if !vin.coinbase.is_empty() {
+                OutPoint::null()
             } else {
-                Txid::from_str(&vin.txid)?
+                OutPoint {
+                    txid: Txid::from_str(&vin.txid)?,
+                    vout: vin.vout,
+                }
             };
             let input = TxIn {
-                previous_output: OutPoint {
-                    txid,
-                    vout: vin.vout,
-                },
+                previous_output,
                 script_sig,
                 sequence: Sequence(vin.sequence),
                 witness,
@@ -156,8 +255,243 @@ fn proto_block_to_btc(problock: BtcBlock) -> anyhow::Result<(bitcoin::BlockHash,
             tx.output.push(out);
         }
 
+        check_tx(ptx, &tx)?;
         block.txdata.push(tx);
     }
 
+    // Lossy conversion must not get into the index.
+    let computed_hash = block.block_hash();
+    if computed_hash != block_hash {
+        anyhow::bail!("block hash mismatch: expected={block_hash} computed={computed_hash}");
+    }
+    let computed_root = block.compute_merkle_root();
+    if computed_root != Some(block.header.merkle_root) {
+        anyhow::bail!(
+            "merkle root mismatch: block={block_hash} expected={} computed={computed_root:?}",
+            block.header.merkle_root
+        );
+    }
+
     Ok((block_hash, block))
 }
+
+/// Txid and wtxid are optional in the proto, so they are checked only if present.
+fn check_tx(ptx: &ProtoTx, tx: &bitcoin::Transaction) -> anyhow::Result<()> {
+    let txid = tx.compute_txid();
+    if !ptx.txid.is_empty() && txid.to_string() != ptx.txid {
+        anyhow::bail!("txid mismatch: expected={} computed={txid}", ptx.txid);
+    }
+    let wtxid = tx.compute_wtxid();
+    if !ptx.hash.is_empty() && wtxid.to_string() != ptx.hash {
+        anyhow::bail!(
+            "wtxid mismatch: txid={txid} expected={} computed={wtxid}",
+            ptx.hash
+        );
+    }
+    Ok(())
+}
+
+/// Endpoint of another network still returns valid blocks,
+/// so the network is checked by the first address found in the outputs.
+fn check_network(problock: &BtcBlock, net: bitcoin::Network) -> anyhow::Result<()> {
+    if problock.height < 0 {
+        anyhow::bail!("invalid block height: {}", problock.height);
+    }
+
+    let address = problock
+        .tx
+        .iter()
+        .flat_map(|tx| tx.vout.iter())
+        .filter_map(|vout| vout.script_pub_key.as_ref())
+        .map(|script| script.address.as_str())
+        .find(|address| !address.is_empty());
+    let Some(address) = address else {
+        return Ok(());
+    };
+
+    let parsed = bitcoin::Address::from_str(address)
+        .with_context(|| format!("invalid address in block({}): {address}", problock.hash))?;
+    if !parsed.is_valid_for_network(net) {
+        anyhow::bail!(
+            "block({}) at height={} doesn't belong to {net}: address={address}, check `firehose_endpoint` config",
+            problock.hash,
+            problock.height
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sf::bitcoin::v1::{ScriptPubKey, ScriptSig, Transaction, Vin, Vout};
+    use super::*;
+
+    /// Same fields as the firehose node fills from bitcoind json.
+    fn block_to_proto(block: &bitcoin::Block, height: i64) -> BtcBlock {
+        let tx = block
+            .txdata
+            .iter()
+            .map(|tx| Transaction {
+                txid: tx.compute_txid().to_string(),
+                hash: tx.compute_wtxid().to_string(),
+                version: tx.version.0 as u32,
+                locktime: tx.lock_time.to_consensus_u32(),
+                vin: tx
+                    .input
+                    .iter()
+                    .map(|vin| {
+                        let witness = vin.witness.iter().map(hex::encode).collect();
+                        if tx.is_coinbase() {
+                            return Vin {
+                                coinbase: hex::encode(vin.script_sig.as_bytes()),
+                                sequence: vin.sequence.0,
+                                txinwitness: witness,
+                                ..Default::default()
+                            };
+                        }
+                        Vin {
+                            txid: vin.previous_output.txid.to_string(),
+                            vout: vin.previous_output.vout,
+                            script_sig: Some(ScriptSig {
+                                hex: hex::encode(vin.script_sig.as_bytes()),
+                                ..Default::default()
+                            }),
+                            sequence: vin.sequence.0,
+                            txinwitness: witness,
+                            ..Default::default()
+                        }
+                    })
+                    .collect(),
+                vout: tx
+                    .output
+                    .iter()
+                    .enumerate()
+                    .map(|(n, out)| Vout {
+                        value: out.value.to_btc(),
+                        n: n as u32,
+                        script_pub_key: Some(ScriptPubKey {
+                            hex: hex::encode(out.script_pubkey.as_bytes()),
+                            ..Default::default()
+                        }),
+                    })
+                    .collect(),
+                ..Default::default()
+            })
+            .collect();
+
+        BtcBlock {
+            hash: block.block_hash().to_string(),
+            height,
+            version: block.header.version.to_consensus(),
+            merkle_root: block.header.merkle_root.to_string(),
+            tx,
+            time: block.header.time as i64,
+            nonce: block.header.nonce,
+            bits: format!("{:08x}", block.header.bits.to_consensus()),
+            previous_hash: block.header.prev_blockhash.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Genesis coinbase and a segwit spend of it.
+    fn fixture_block() -> bitcoin::Block {
+        use bitcoin::{transaction, Amount, OutPoint, ScriptBuf, Sequence, TxIn, TxOut, Witness};
+
+        let genesis = bitcoin::constants::genesis_block(bitcoin::Network::Regtest);
+        let coinbase = genesis.txdata[0].clone();
+        let spend = bitcoin::Transaction {
+            version: transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: coinbase.compute_txid(),
+                    vout: 0,
+                },
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::from_slice(&[vec![0x30; 71], vec![0x02; 33]]),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(4_999_990_000),
+                script_pubkey: ScriptBuf::from_hex("0014751e76e8199196d454941c45d1b3a323f1433bd6")
+                    .unwrap(),
+            }],
+        };
+
+        let mut block = bitcoin::Block {
+            header: genesis.header,
+            txdata: vec![coinbase, spend],
+        };
+        block.header.prev_blockhash = genesis.block_hash();
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+        block
+    }
+
+    #[test]
+    fn test_proto_block_to_btc() {
+        let block = fixture_block();
+        let proto = block_to_proto(&block, 1);
+
+        let (hash, converted) = proto_block_to_btc(proto, bitcoin::Network::Regtest).unwrap();
+        assert_eq!(hash, block.block_hash());
+        assert_eq!(converted, block);
+    }
+
+    #[test]
+    fn test_proto_block_to_btc_mutated_tx() {
+        let block = fixture_block();
+
+        // witness is not covered by the block hash, but covered by wtxid
+        let mut proto = block_to_proto(&block, 1);
+        proto.tx[1].vin[0].txinwitness.pop();
+        let err = proto_block_to_btc(proto, bitcoin::Network::Regtest).unwrap_err();
+        assert!(err.to_string().contains("wtxid mismatch"), "{err}");
+
+        // without txids in the proto it's caught by the merkle root
+        let mut proto = block_to_proto(&block, 1);
+        proto.tx[1].vout[0].value = 1.0;
+        proto.tx[1].txid.clear();
+        proto.tx[1].hash.clear();
+        let err = proto_block_to_btc(proto, bitcoin::Network::Regtest).unwrap_err();
+        assert!(err.to_string().contains("merkle root mismatch"), "{err}");
+
+        // header fields are covered by the block hash
+        let mut proto = block_to_proto(&block, 1);
+        proto.nonce += 1;
+        let err = proto_block_to_btc(proto, bitcoin::Network::Regtest).unwrap_err();
+        assert!(err.to_string().contains("block hash mismatch"), "{err}");
+    }
+
+    fn block_with_address(address: &str) -> BtcBlock {
+        BtcBlock {
+            height: 1,
+            tx: vec![Transaction {
+                vout: vec![Vout {
+                    script_pub_key: Some(ScriptPubKey {
+                        address: address.into(),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_check_network() {
+        use bitcoin::Network;
+
+        let mainnet = block_with_address("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq");
+        assert!(check_network(&mainnet, Network::Bitcoin).is_ok());
+        assert!(check_network(&mainnet, Network::Testnet4).is_err());
+
+        let testnet = block_with_address("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx");
+        assert!(check_network(&testnet, Network::Testnet4).is_ok());
+        assert!(check_network(&testnet, Network::Bitcoin).is_err());
+
+        let no_address = block_with_address("");
+        assert!(check_network(&no_address, Network::Regtest).is_ok());
+    }
+}
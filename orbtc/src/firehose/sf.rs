@@ -1,4 +1,6 @@
 pub mod firehose {
+    // generated from the upstream proto, variant names can't be changed
+    #[allow(clippy::enum_variant_names)]
     pub mod v2 {
         tonic::include_proto!("sf.firehose.v2");
     }
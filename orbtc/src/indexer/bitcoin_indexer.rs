@@ -1,9 +1,10 @@
-use bitcoin::Address;
+use bitcoin::{Address, Script};
 use orbtc_indexer_api::types::Hash;
+use rayon::prelude::*;
 
-use super::bitcoin_indexer_state::StateProvider;
+use super::bitcoin_indexer_state::{StateProvider, TxFee};
 use super::db;
-use super::rt::{TxIndexer, TxInfo};
+use super::rt::{PreparedOutput, PreparedTx, TxIndexer, TxInfo};
 use crate::config;
 use crate::db::schema;
 
@@ -17,12 +18,12 @@ pub struct BitcoinUtxoIndexer {
 }
 
 impl BitcoinUtxoIndexer {
-    pub fn new(net: bitcoin::Network, db_cfg: &config::DBConfig) -> Self {
-        let db = db::DB::establish_connection(&db_cfg.dsn);
-        Self {
+    pub fn new(net: bitcoin::Network, db_cfg: &config::DBConfig) -> anyhow::Result<Self> {
+        let db = db::DB::establish_connection(&db_cfg.dsn)?;
+        Ok(Self {
             net,
             state: StateProvider::new(db),
-        }
+        })
     }
 }
 
@@ -31,8 +32,39 @@ impl TxIndexer for BitcoinUtxoIndexer {
         BITCOIN_INDEX.into()
     }
 
+    /// Address extraction is the most CPU heavy part of the indexing,
+    /// so it's done for all block txs in parallel.
+    fn prepare_transactions(&self, txs: &[TxInfo]) -> Vec<PreparedTx> {
+        let net = self.net;
+        txs.par_iter()
+            .map(|tx_info| PreparedTx {
+                outputs: tx_info
+                    .tx
+                    .output
+                    .iter()
+                    .map(|out| classify_output(&out.script_pubkey, net))
+                    .collect(),
+            })
+            .collect()
+    }
+
     fn index_transaction(&mut self, tx_info: &TxInfo) -> anyhow::Result<()> {
         let coinbase = tx_info.tx.is_coinbase();
+        self.state.dataset.tx_count += 1;
+        if !coinbase {
+            self.state.dataset.tx_fees.push(TxFee {
+                tx_hash: tx_info.txid.into(),
+                vsize: tx_info.tx.vsize() as u64,
+                inputs: tx_info.tx.input.len(),
+                output_amount: tx_info
+                    .tx
+                    .output
+                    .iter()
+                    .map(|out| out.value.to_sat() as i64)
+                    .sum(),
+            });
+        }
+
         for (n, input) in tx_info.tx.input.iter().enumerate() {
             if coinbase {
                 break;
@@ -52,24 +84,12 @@ impl TxIndexer for BitcoinUtxoIndexer {
         }
 
         for (n, out) in tx_info.tx.output.iter().enumerate() {
-            let (address_type, address) = match Address::from_script(&out.script_pubkey, self.net) {
-                Ok(a) => (
-                    a.address_type()
-                        .map(|a| a.to_string())
-                        .unwrap_or("non_standard".into()),
-                    a.to_string(),
-                ),
-                Err(_) => {
-                    let at = if out.script_pubkey.is_op_return() {
-                        "op_return"
-                    } else if out.script_pubkey.is_multisig() {
-                        "multisig"
-                    } else {
-                        "non_standard"
-                    };
-                    let address_id = Hash::sha2(out.script_pubkey.as_bytes());
-                    (at.into(), format!("nsa_{}", address_id))
-                }
+            let PreparedOutput {
+                address,
+                address_type,
+            } = match tx_info.prepared.and_then(|p| p.outputs.get(n)) {
+                Some(prepared) => prepared.clone(),
+                None => classify_output(&out.script_pubkey, self.net),
             };
             if !self.state.address_index.contains(&address) {
                 let address_row = schema::Address {
@@ -97,11 +117,37 @@ impl TxIndexer for BitcoinUtxoIndexer {
         Ok(())
     }
 
-    fn commit_state(&mut self) -> anyhow::Result<()> {
-        self.state.commit_state()
+    fn commit_state(&mut self, block: Option<&schema::Block>) -> anyhow::Result<()> {
+        self.state.commit_state(block)
     }
 
     fn reset_state(&mut self) {
         self.state.reset_state();
     }
 }
+
+fn classify_output(script: &Script, net: bitcoin::Network) -> PreparedOutput {
+    match Address::from_script(script, net) {
+        Ok(a) => PreparedOutput {
+            address_type: a
+                .address_type()
+                .map(|a| a.to_string())
+                .unwrap_or("non_standard".into()),
+            address: a.to_string(),
+        },
+        Err(_) => {
+            let at = if script.is_op_return() {
+                "op_return"
+            } else if script.is_multisig() {
+                "multisig"
+            } else {
+                "non_standard"
+            };
+            let address_id = Hash::sha2(script.as_bytes());
+            PreparedOutput {
+                address_type: at.into(),
+                address: format!("nsa_{}", address_id),
+            }
+        }
+    }
+}
@@ -1,9 +1,13 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
-use diesel::Connection;
+use orbtc_indexer_api::types::Hash;
+use rand::Rng;
 
 use super::db::*;
-use crate::db::schema::{Address as AddressRow, Input, Output};
+use crate::db::schema::{Address as AddressRow, Block, BlockStats, Input, Output};
+
+/// Number of the tx fee rates of a block the percentiles are computed from.
+const FEE_RATES_SAMPLE_SIZE: usize = 1_000;
 
 pub struct StateProvider {
     pub db: DB,
@@ -19,20 +23,20 @@ impl StateProvider {
                 new_addresses: Vec::with_capacity(16_000),
                 new_inputs: Vec::with_capacity(16_000),
                 new_outputs: Vec::with_capacity(16_000),
+                ..Default::default()
             },
             address_index: HashSet::with_capacity(10_000_000),
         }
     }
 
-    pub fn commit_state(&mut self) -> anyhow::Result<()> {
+    pub fn commit_state(&mut self, block: Option<&Block>) -> anyhow::Result<()> {
         info!(
             "Commiting indexer state: new_outputs={} new_inputs={}",
             self.dataset.new_outputs.len(),
             self.dataset.new_inputs.len(),
         );
 
-        let conn = &mut self.db.conn;
-        conn.transaction(|conn| {
+        self.db.transaction(|conn| {
             if let Err(err) = DB::insert_addresses(conn, &self.dataset.new_addresses) {
                 error!(
                     "can't insert new addresses: len={} err={}",
@@ -60,6 +64,30 @@ impl StateProvider {
                 return diesel::result::QueryResult::Err(err);
             }
 
+            // in the same transaction, so deliveries are neither lost nor duplicated
+            // when indexer restarts in the middle of the block
+            match DB::enqueue_webhook_deliveries(conn, &self.dataset.new_outputs) {
+                Ok(0) => {}
+                Ok(count) => debug!("Enqueued webhook deliveries: count={count}"),
+                Err(err) => {
+                    error!("can't enqueue webhook deliveries: err={}", err);
+                    return diesel::result::QueryResult::Err(err);
+                }
+            }
+
+            if let Some(block) = block {
+                // inputs of the block are inserted, so their amounts are known
+                let input_amounts = DB::select_block_input_amounts(conn, block.height)?;
+                let stats = block_stats(
+                    block.height,
+                    self.dataset.tx_count,
+                    &self.dataset.tx_fees,
+                    &input_amounts,
+                );
+                DB::upsert_block_stats(conn, &stats)?;
+                DB::commit_block(conn, block)?;
+            }
+
             diesel::result::QueryResult::Ok(())
         })?;
 
@@ -71,6 +99,8 @@ impl StateProvider {
         self.dataset.new_addresses.clear();
         self.dataset.new_outputs.clear();
         self.dataset.new_inputs.clear();
+        self.dataset.tx_count = 0;
+        self.dataset.tx_fees.clear();
 
         if self.address_index.len() > 10_000_000 {
             self.address_index.clear();
@@ -83,4 +113,204 @@ pub struct BlockData {
     pub new_addresses: Vec<AddressRow>,
     pub new_inputs: Vec<Input>,
     pub new_outputs: Vec<Output>,
+    /// Number of txs of the block including the coinbase.
+    pub tx_count: usize,
+    pub tx_fees: Vec<TxFee>,
+}
+
+/// Fee data of a non coinbase tx, the amounts of its inputs are known only at the commit.
+#[derive(Debug, Clone)]
+pub struct TxFee {
+    pub tx_hash: Hash,
+    pub vsize: u64,
+    pub inputs: usize,
+    pub output_amount: i64,
+}
+
+/// Resolved inputs of the tx and their amount.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct InputAmount {
+    pub resolved: usize,
+    pub amount: i64,
+}
+
+/// Uniform sample of the fee rates of a block, keeps the memory and the sort bounded.
+struct FeeRateReservoir {
+    rates: Vec<f64>,
+    seen: usize,
+}
+
+impl FeeRateReservoir {
+    fn new() -> Self {
+        Self {
+            rates: Vec::with_capacity(FEE_RATES_SAMPLE_SIZE),
+            seen: 0,
+        }
+    }
+
+    fn add(&mut self, rate: f64, rng: &mut impl Rng) {
+        self.seen += 1;
+        if self.rates.len() < FEE_RATES_SAMPLE_SIZE {
+            self.rates.push(rate);
+            return;
+        }
+        let i = rng.gen_range(0..self.seen);
+        if i < FEE_RATES_SAMPLE_SIZE {
+            self.rates[i] = rate;
+        }
+    }
+
+    /// Sorted sample.
+    fn into_sorted(mut self) -> Vec<f64> {
+        self.rates.sort_by(f64::total_cmp);
+        self.rates
+    }
+}
+
+/// Nearest rank percentile of the sorted sample, 0 if it's empty.
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (p / 100.0 * (sorted.len() - 1) as f64).round() as usize;
+    sorted[rank.min(sorted.len() - 1)]
+}
+
+/// Fees of the block from its txs and the amounts of their inputs.
+/// Txs with inputs that spend not indexed outputs have unknown fee, so they are skipped.
+pub fn block_stats(
+    height: i64,
+    tx_count: usize,
+    txs: &[TxFee],
+    input_amounts: &HashMap<Hash, InputAmount>,
+) -> BlockStats {
+    let mut rng = rand::thread_rng();
+    let mut reservoir = FeeRateReservoir::new();
+    let mut fee_tx_count = 0;
+    let mut total_fees = 0;
+    for tx in txs {
+        let Some(inputs) = input_amounts.get(&tx.tx_hash) else {
+            continue;
+        };
+        if inputs.resolved != tx.inputs {
+            continue;
+        }
+        let fee = (inputs.amount - tx.output_amount).max(0);
+        fee_tx_count += 1;
+        total_fees += fee;
+        reservoir.add(fee as f64 / tx.vsize.max(1) as f64, &mut rng);
+    }
+
+    let rates = reservoir.into_sorted();
+    BlockStats {
+        height,
+        tx_count: tx_count as i32,
+        fee_tx_count,
+        total_fees,
+        fee_rate_min: percentile(&rates, 0.0),
+        fee_rate_p10: percentile(&rates, 10.0),
+        fee_rate_p25: percentile(&rates, 25.0),
+        fee_rate_p50: percentile(&rates, 50.0),
+        fee_rate_p75: percentile(&rates, 75.0),
+        fee_rate_p90: percentile(&rates, 90.0),
+        fee_rate_max: percentile(&rates, 100.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(n: u8) -> Hash {
+        Hash::sha2([n])
+    }
+
+    fn tx_fee(n: u8, vsize: u64, inputs: usize, output_amount: i64) -> TxFee {
+        TxFee {
+            tx_hash: hash(n),
+            vsize,
+            inputs,
+            output_amount,
+        }
+    }
+
+    #[test]
+    fn test_block_stats() {
+        let txs = vec![
+            tx_fee(1, 100, 1, 9_000),
+            tx_fee(2, 200, 2, 18_000),
+            tx_fee(3, 100, 1, 5_000),
+            // one of the inputs spends a not indexed output
+            tx_fee(4, 100, 2, 1_000),
+            // nothing is resolved
+            tx_fee(5, 100, 1, 1_000),
+        ];
+        let amounts = HashMap::from([
+            (
+                hash(1),
+                InputAmount {
+                    resolved: 1,
+                    amount: 10_000,
+                },
+            ),
+            (
+                hash(2),
+                InputAmount {
+                    resolved: 2,
+                    amount: 20_000,
+                },
+            ),
+            (
+                hash(3),
+                InputAmount {
+                    resolved: 1,
+                    amount: 10_000,
+                },
+            ),
+            (
+                hash(4),
+                InputAmount {
+                    resolved: 1,
+                    amount: 10_000,
+                },
+            ),
+        ]);
+
+        let stats = block_stats(100, 6, &txs, &amounts);
+        assert_eq!(stats.height, 100);
+        assert_eq!(stats.tx_count, 6);
+        assert_eq!(stats.fee_tx_count, 3);
+        assert_eq!(stats.total_fees, 1_000 + 2_000 + 5_000);
+        assert_eq!(stats.fee_rate_min, 10.0);
+        assert_eq!(stats.fee_rate_p50, 10.0);
+        assert_eq!(stats.fee_rate_max, 50.0);
+
+        let empty = block_stats(101, 1, &[], &HashMap::new());
+        assert_eq!((empty.fee_tx_count, empty.total_fees), (0, 0));
+        assert_eq!(empty.fee_rate_max, 0.0);
+    }
+
+    #[test]
+    fn test_percentile() {
+        let sorted: Vec<f64> = (1..=11).map(|v| v as f64).collect();
+        assert_eq!(percentile(&sorted, 0.0), 1.0);
+        assert_eq!(percentile(&sorted, 10.0), 2.0);
+        assert_eq!(percentile(&sorted, 50.0), 6.0);
+        assert_eq!(percentile(&sorted, 100.0), 11.0);
+        assert_eq!(percentile(&[], 50.0), 0.0);
+    }
+
+    #[test]
+    fn test_fee_rate_reservoir() {
+        let mut rng = rand::thread_rng();
+        let mut reservoir = FeeRateReservoir::new();
+        for rate in 0..FEE_RATES_SAMPLE_SIZE * 3 {
+            reservoir.add(rate as f64, &mut rng);
+        }
+        assert_eq!(reservoir.seen, FEE_RATES_SAMPLE_SIZE * 3);
+
+        let sorted = reservoir.into_sorted();
+        assert_eq!(sorted.len(), FEE_RATES_SAMPLE_SIZE);
+        assert!(sorted.windows(2).all(|w| w[0] <= w[1]));
+    }
 }
@@ -0,0 +1,100 @@
+use std::time::Duration;
+
+/// Waits for new blocks at the node tip.
+/// With zmq the node notifies about new blocks with `hashblock` messages,
+/// otherwise or if a message is lost the wait ends by timeout.
+pub struct BlockNotifier {
+    #[cfg(feature = "zmq")]
+    socket: Option<zmq::Socket>,
+}
+
+impl BlockNotifier {
+    /// `zmq_address` is the `zmqpubhashblock` endpoint of the node,
+    /// it requires the binary built with the `zmq` feature.
+    pub fn new(zmq_address: Option<&str>) -> anyhow::Result<Self> {
+        #[cfg(feature = "zmq")]
+        {
+            let socket = match zmq_address {
+                Some(address) => Some(subscribe_hashblock(address)?),
+                None => None,
+            };
+            Ok(Self { socket })
+        }
+
+        #[cfg(not(feature = "zmq"))]
+        match zmq_address {
+            Some(_) => {
+                anyhow::bail!("zmq address is set, but the binary is built without `zmq` feature")
+            }
+            None => Ok(Self {}),
+        }
+    }
+
+    /// Blocks until the node notifies about a new block or `timeout` passes.
+    pub fn wait(&mut self, timeout: Duration) {
+        #[cfg(feature = "zmq")]
+        if let Some(socket) = self.socket.as_ref() {
+            match socket.poll(zmq::POLLIN, timeout.as_millis() as i64) {
+                Ok(0) => {}
+                Ok(_) => drain_messages(socket),
+                Err(err) => {
+                    warn!("Can't poll zmq socket: error={err}");
+                    std::thread::sleep(timeout);
+                }
+            }
+            return;
+        }
+
+        std::thread::sleep(timeout);
+    }
+}
+
+#[cfg(feature = "zmq")]
+fn subscribe_hashblock(address: &str) -> anyhow::Result<zmq::Socket> {
+    use anyhow::Context;
+
+    let socket = zmq::Context::new()
+        .socket(zmq::SUB)
+        .context("can't create zmq socket")?;
+    socket
+        .connect(address)
+        .with_context(|| format!("can't connect to zmq: address={address}"))?;
+    socket
+        .set_subscribe(b"hashblock")
+        .context("can't subscribe to zmq hashblock")?;
+    Ok(socket)
+}
+
+/// A few blocks can be notified while the indexer is busy, one wake up is enough for all of them.
+#[cfg(feature = "zmq")]
+fn drain_messages(socket: &zmq::Socket) {
+    while let Ok(parts) = socket.recv_multipart(zmq::DONTWAIT) {
+        if let Some(hash) = parts.get(1) {
+            debug!("New block notified by zmq: hash={}", hex::encode(hash));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn test_wait_without_zmq() {
+        let mut notifier = BlockNotifier::new(None).unwrap();
+        let started = Instant::now();
+        notifier.wait(Duration::from_millis(50));
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[cfg(not(feature = "zmq"))]
+    #[test]
+    fn test_zmq_requires_feature() {
+        let Err(err) = BlockNotifier::new(Some("tcp://127.0.0.1:28332")) else {
+            panic!("zmq address must be rejected without `zmq` feature");
+        };
+        assert!(err.to_string().contains("`zmq` feature"));
+    }
+}
@@ -1,19 +1,46 @@
+use std::collections::{HashMap, HashSet};
+use std::thread::sleep;
+use std::time::Duration;
+
 use diesel;
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
 use orbtc_indexer_api::types::{Amount, Hash};
 
+use super::bitcoin_indexer_state::InputAmount;
 use crate::db::schema::{tables, *};
 
 pub struct DB {
     pub conn: PgConnection,
+    database_url: String,
 }
 
 impl DB {
-    pub fn establish_connection(database_url: &str) -> Self {
-        let conn = PgConnection::establish(database_url)
-            .unwrap_or_else(|_| panic!("Error connecting to {}", database_url));
-        Self { conn }
+    pub fn establish_connection(database_url: &str) -> anyhow::Result<Self> {
+        let conn = connect_with_backoff(&Backoff::default(), || {
+            PgConnection::establish(database_url)
+        })
+        .map_err(|err| anyhow::anyhow!("can't connect to db: {err}"))?;
+        Ok(Self {
+            conn,
+            database_url: database_url.to_string(),
+        })
+    }
+
+    /// Runs `f` in a transaction. If the connection is lost, it is re-established
+    /// and the transaction is retried once.
+    pub fn transaction<T, F>(&mut self, mut f: F) -> QueryResult<T>
+    where
+        F: FnMut(&mut PgConnection) -> QueryResult<T>,
+    {
+        let database_url = &self.database_url;
+        with_reconnect(
+            &mut self.conn,
+            &Backoff::default(),
+            || PgConnection::establish(database_url),
+            |conn| conn.transaction(&mut f),
+        )
     }
 
     pub fn insert_addresses(conn: &mut PgConnection, rows: &Vec<Address>) -> QueryResult<()> {
@@ -62,6 +89,64 @@ impl DB {
         Ok(())
     }
 
+    /// Enqueues webhook deliveries for the outputs sent to subscribed addresses,
+    /// they are sent by the API service, see `service::webhooks`.
+    pub fn enqueue_webhook_deliveries(
+        conn: &mut PgConnection,
+        rows: &[Output],
+    ) -> QueryResult<usize> {
+        if rows.is_empty() {
+            return Ok(0);
+        }
+
+        let addresses: HashSet<&str> = rows.iter().map(|r| r.address.as_str()).collect();
+        use tables::subscriptions::dsl as subs_dsl;
+        let subs: Vec<Subscription> = subs_dsl::subscriptions
+            .filter(subs_dsl::address.eq_any(addresses))
+            .load(conn)?;
+        if subs.is_empty() {
+            return Ok(0);
+        }
+
+        let mut subs_by_address: HashMap<&str, Vec<i64>> = HashMap::new();
+        for sub in subs.iter() {
+            subs_by_address
+                .entry(sub.address.as_str())
+                .or_default()
+                .push(sub.id);
+        }
+
+        let deliveries: Vec<NewWebhookDelivery> = rows
+            .iter()
+            .flat_map(|r| {
+                let ids = subs_by_address
+                    .get(r.address.as_str())
+                    .map(|ids| ids.as_slice())
+                    .unwrap_or_default();
+                ids.iter().map(|id| NewWebhookDelivery {
+                    subscription_id: *id,
+                    block: r.block,
+                    tx_hash: r.tx_hash.clone(),
+                    vout: r.vout,
+                    address: r.address.clone(),
+                    amount: r.amount,
+                })
+            })
+            .collect();
+
+        use tables::webhook_deliveries::dsl as deliveries_dsl;
+        let mut count = 0;
+        for chunk in deliveries.chunks(3000) {
+            // the same output is delivered once, even if the block is reindexed after reorg
+            count += diesel::insert_into(deliveries_dsl::webhook_deliveries)
+                .values(chunk)
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+        }
+
+        Ok(count)
+    }
+
     pub fn select_output_ids(&mut self, tx_hash_v: &Hash) -> QueryResult<Vec<(i64, i32)>> {
         use tables::outputs::dsl::*;
         outputs
@@ -108,36 +193,98 @@ impl DB {
         Ok(row.height)
     }
 
+    /// Sets the tip of the indexer and the time it was updated.
     pub fn update_last_block(&mut self, name: &str, height_v: i64) -> anyhow::Result<()> {
-        use tables::last_indexed_block::dsl::*;
-
         // this implements an UPSERT (insert if doesn't exist, update if exists).
-        diesel::insert_into(last_indexed_block)
-            .values((indexer.eq(name), height.eq(height_v)))
-            .on_conflict(indexer)
-            .do_update()
-            .set(height.eq(height_v))
-            .execute(&mut self.conn)?;
+        // `updated_at` isn't a part of the diesel table, so it's a raw query.
+        diesel::sql_query(
+            r#"
+            INSERT INTO last_indexed_block (indexer, height, updated_at) VALUES ($1, $2, now())
+            ON CONFLICT (indexer) DO UPDATE
+            SET height = EXCLUDED.height, updated_at = now()
+            "#,
+        )
+        .bind::<diesel::sql_types::Text, _>(name)
+        .bind::<diesel::sql_types::BigInt, _>(height_v)
+        .execute(&mut self.conn)?;
 
         Ok(())
     }
 
-    pub fn insert_block(
-        &mut self,
-        height: i64,
-        hash: &Hash,
-        time: i64,
-        indexer: &str,
-    ) -> anyhow::Result<()> {
+    /// Inserts the block and moves `last_indexed_block` of its indexer up to it.
+    /// Called within the transaction of the block data, so the block is marked
+    /// as indexed together with its data. The tip never goes down here,
+    /// so reindexing of old blocks keeps it, reorgs lower it with `update_last_block`.
+    pub fn commit_block(conn: &mut PgConnection, block: &Block) -> QueryResult<()> {
         use tables::blocks::dsl;
         diesel::insert_into(dsl::blocks)
-            .values((
-                dsl::height.eq(height),
-                dsl::hash.eq(hash),
-                dsl::blocktime.eq(time),
-                dsl::indexer.eq(indexer),
-            ))
-            .execute(&mut self.conn)?;
+            .values(block)
+            .execute(conn)?;
+
+        diesel::sql_query(
+            r#"
+            INSERT INTO last_indexed_block (indexer, height, updated_at) VALUES ($1, $2, now())
+            ON CONFLICT (indexer) DO UPDATE
+            SET height = GREATEST(last_indexed_block.height, EXCLUDED.height),
+                updated_at = now()
+            "#,
+        )
+        .bind::<diesel::sql_types::Text, _>(&block.indexer)
+        .bind::<diesel::sql_types::BigInt, _>(block.height)
+        .execute(conn)?;
+
+        Ok(())
+    }
+
+    /// Resolved inputs and their amount by the txs of the block.
+    /// Inputs spend outputs of the indexed blocks, so the block inputs must be inserted.
+    pub fn select_block_input_amounts(
+        conn: &mut PgConnection,
+        height: i64,
+    ) -> QueryResult<HashMap<Hash, InputAmount>> {
+        #[derive(QueryableByName)]
+        struct Row {
+            #[diesel(sql_type = diesel::sql_types::Bytea)]
+            tx_hash: Hash,
+            #[diesel(sql_type = diesel::sql_types::BigInt)]
+            resolved: i64,
+            #[diesel(sql_type = diesel::sql_types::BigInt)]
+            amount: i64,
+        }
+
+        let rows: Vec<Row> = diesel::sql_query(
+            r#"
+            SELECT i.tx_hash, count(o.id) AS resolved, COALESCE(sum(o.amount), 0)::BIGINT AS amount
+            FROM inputs AS i
+            LEFT JOIN outputs AS o ON o.tx_hash = i.parent_tx AND o.vout = i.parent_vout
+            WHERE i.block = $1
+            GROUP BY i.tx_hash
+            "#,
+        )
+        .bind::<diesel::sql_types::BigInt, _>(height)
+        .load(conn)?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| {
+                let amount = InputAmount {
+                    resolved: r.resolved as usize,
+                    amount: r.amount,
+                };
+                (r.tx_hash, amount)
+            })
+            .collect())
+    }
+
+    /// Stats of a reindexed block are replaced.
+    pub fn upsert_block_stats(conn: &mut PgConnection, stats: &BlockStats) -> QueryResult<()> {
+        use tables::block_stats::dsl::*;
+        diesel::insert_into(block_stats)
+            .values(stats)
+            .on_conflict(height)
+            .do_update()
+            .set(stats)
+            .execute(conn)?;
 
         Ok(())
     }
@@ -152,59 +299,103 @@ impl DB {
         Ok(row)
     }
 
-    pub fn drop_blocks(&mut self, height: i64, indexer: &str) -> anyhow::Result<()> {
+    /// Drops all indexer data starting from `height`, returns the orphaned blocks.
+    pub fn drop_blocks(&mut self, height: i64, indexer: &str) -> anyhow::Result<Vec<Block>> {
         let conn = &mut self.conn;
-        conn.transaction(|conn| {
-            use tables::blocks::dsl as blocks_dsl;
-            diesel::delete(blocks_dsl::blocks)
-                .filter(blocks_dsl::height.ge(height))
-                .filter(blocks_dsl::indexer.eq(indexer))
-                .execute(conn)?;
+        let orphans = conn.transaction(|conn| {
+            let orphans = Self::archive_orphaned_blocks(conn, height, indexer)?;
+            Self::delete_blocks_range(conn, height, i64::MAX, indexer)?;
+            Self::delete_utxo_range(conn, height, i64::MAX)?;
+            Self::delete_runes_range(conn, height, i64::MAX)?;
 
-            use tables::inputs::dsl as inputs_dsl;
-            diesel::delete(inputs_dsl::inputs)
-                .filter(inputs_dsl::block.ge(height))
-                .execute(conn)?;
+            diesel::result::QueryResult::Ok(orphans)
+        })?;
 
-            use tables::outputs::dsl as outputs_dsl;
-            diesel::delete(outputs_dsl::outputs)
-                .filter(outputs_dsl::block.ge(height))
-                .execute(conn)?;
+        Ok(orphans)
+    }
 
-            use tables::runes_outputs::dsl as runes_outs_dsl;
-            diesel::delete(runes_outs_dsl::runes_outputs)
-                .filter(runes_outs_dsl::block.ge(height))
-                .execute(conn)?;
+    pub fn drop_runes_blocks(&mut self, height: i64, indexer: &str) -> anyhow::Result<Vec<Block>> {
+        let conn = &mut self.conn;
+        let orphans = conn.transaction(|conn| {
+            let orphans = Self::archive_orphaned_blocks(conn, height, indexer)?;
+            Self::delete_blocks_range(conn, height, i64::MAX, indexer)?;
+            Self::delete_runes_range(conn, height, i64::MAX)?;
 
-            use tables::runes::dsl as runes_dsl;
-            diesel::delete(runes_dsl::runes)
-                .filter(runes_dsl::block.ge(height))
-                .execute(conn)?;
+            diesel::result::QueryResult::Ok(orphans)
+        })?;
 
-            diesel::result::QueryResult::Ok(())
+        Ok(orphans)
+    }
+
+    /// Drops bitcoin utxo data of blocks in `[from, to]`, blocks aren't archived.
+    pub fn drop_blocks_range(&mut self, from: i64, to: i64, indexer: &str) -> anyhow::Result<()> {
+        self.conn.transaction(|conn| {
+            Self::delete_blocks_range(conn, from, to, indexer)?;
+            Self::delete_utxo_range(conn, from, to)
         })?;
 
         Ok(())
     }
 
-    pub fn drop_runes_blocks(&mut self, height: i64, indexer: &str) -> anyhow::Result<()> {
-        let conn = &mut self.conn;
-        conn.transaction(|conn| {
-            use tables::blocks::dsl as blocks_dsl;
-            diesel::delete(blocks_dsl::blocks)
-                .filter(blocks_dsl::height.ge(height))
-                .filter(blocks_dsl::indexer.eq(indexer))
-                .execute(conn)?;
+    /// Drops runes data of blocks in `[from, to]`, blocks aren't archived.
+    /// Mint and burn counters of the runes etched before `from` are rolled back
+    /// by the events of the range.
+    pub fn drop_runes_blocks_range(
+        &mut self,
+        from: i64,
+        to: i64,
+        indexer: &str,
+    ) -> anyhow::Result<()> {
+        use diesel::sql_types::BigInt;
 
-            use tables::runes_outputs::dsl as runes_outs_dsl;
-            diesel::delete(runes_outs_dsl::runes_outputs)
-                .filter(runes_outs_dsl::block.ge(height))
-                .execute(conn)?;
+        self.conn.transaction(|conn| {
+            diesel::sql_query(
+                r#"
+                UPDATE runes AS r SET
+                    mints = r.mints - e.mints,
+                    minted = r.minted - e.minted,
+                    burned = r.burned - e.burned,
+                    in_circulation = r.in_circulation - e.minted + e.burned
+                FROM (
+                    SELECT
+                        rune,
+                        (count(*) FILTER (WHERE event_type = 'mint'))::INT AS mints,
+                        COALESCE(sum(amount) FILTER (WHERE event_type = 'mint'), 0) AS minted,
+                        COALESCE(sum(amount) FILTER (WHERE event_type = 'burn'), 0) AS burned
+                    FROM rune_events
+                    WHERE block BETWEEN $1 AND $2
+                    GROUP BY rune
+                ) AS e
+                WHERE r.name = e.rune AND r.block < $1
+                "#,
+            )
+            .bind::<BigInt, _>(from)
+            .bind::<BigInt, _>(to)
+            .execute(conn)?;
 
-            use tables::runes::dsl as runes_dsl;
-            diesel::delete(runes_dsl::runes)
-                .filter(runes_dsl::block.ge(height))
-                .execute(conn)?;
+            Self::delete_blocks_range(conn, from, to, indexer)?;
+            Self::delete_runes_range(conn, from, to)
+        })?;
+
+        Ok(())
+    }
+
+    /// Drops inscriptions cache of outputs created in `[from, to]`, blocks aren't archived.
+    pub fn drop_inscriptions_blocks_range(
+        &mut self,
+        from: i64,
+        to: i64,
+        indexer: &str,
+    ) -> anyhow::Result<()> {
+        self.conn.transaction(|conn| {
+            Self::delete_blocks_range(conn, from, to, indexer)?;
+
+            diesel::sql_query(
+                "DELETE FROM outputs_extras WHERE id IN (SELECT id FROM outputs WHERE block BETWEEN $1 AND $2)",
+            )
+            .bind::<diesel::sql_types::BigInt, _>(from)
+            .bind::<diesel::sql_types::BigInt, _>(to)
+            .execute(conn)?;
 
             diesel::result::QueryResult::Ok(())
         })?;
@@ -212,13 +403,208 @@ impl DB {
         Ok(())
     }
 
+    /// Counts runes etched in `[from, to]` that have events or outputs after `to`.
+    /// Such runes can't be dropped without the later blocks.
+    pub fn count_runes_referenced_after(&mut self, from: i64, to: i64) -> anyhow::Result<i64> {
+        #[derive(QueryableByName)]
+        struct Count {
+            #[diesel(sql_type = diesel::sql_types::BigInt)]
+            count: i64,
+        }
+
+        let row: Count = diesel::sql_query(
+            r#"
+            SELECT count(*) AS count
+            FROM runes AS r
+            WHERE r.block BETWEEN $1 AND $2
+              AND (
+                EXISTS (SELECT 1 FROM rune_events AS e WHERE e.rune = r.name AND e.block > $2)
+                OR EXISTS (SELECT 1 FROM runes_outputs AS o WHERE o.rune = r.name AND o.block > $2)
+              )
+            "#,
+        )
+        .bind::<diesel::sql_types::BigInt, _>(from)
+        .bind::<diesel::sql_types::BigInt, _>(to)
+        .get_result(&mut self.conn)?;
+
+        Ok(row.count)
+    }
+
+    /// Takes a session advisory lock keyed on the indexer name,
+    /// returns `false` if another connection holds it.
+    /// The lock is released when the connection is closed.
+    pub fn try_lock_indexer(&mut self, name: &str) -> anyhow::Result<bool> {
+        #[derive(QueryableByName)]
+        struct Lock {
+            #[diesel(sql_type = diesel::sql_types::Bool)]
+            locked: bool,
+        }
+
+        let lock: Lock = diesel::sql_query("SELECT pg_try_advisory_lock(hashtext($1)) AS locked")
+            .bind::<diesel::sql_types::Text, _>(name)
+            .get_result(&mut self.conn)?;
+
+        Ok(lock.locked)
+    }
+
+    pub fn unlock_indexer(&mut self, name: &str) -> anyhow::Result<()> {
+        diesel::sql_query("SELECT pg_advisory_unlock(hashtext($1))")
+            .bind::<diesel::sql_types::Text, _>(name)
+            .execute(&mut self.conn)?;
+
+        Ok(())
+    }
+
+    /// Records the tx skipped by the indexer, the error of a known tx is updated.
+    pub fn record_failed_tx(
+        &mut self,
+        indexer_v: &str,
+        block_v: i64,
+        tx_id_v: i32,
+        tx_hash_v: &Hash,
+        error_v: &str,
+    ) -> anyhow::Result<()> {
+        use tables::failed_txs::dsl::*;
+        diesel::insert_into(failed_txs)
+            .values((
+                indexer.eq(indexer_v),
+                block.eq(block_v),
+                tx_id.eq(tx_id_v),
+                tx_hash.eq(tx_hash_v),
+                error.eq(error_v),
+            ))
+            .on_conflict((indexer, tx_hash))
+            .do_update()
+            .set((error.eq(error_v), attempts.eq(attempts + 1)))
+            .execute(&mut self.conn)?;
+
+        Ok(())
+    }
+
+    /// Failed txs of the indexer in the chain order.
+    pub fn select_failed_txs(&mut self, indexer_v: &str) -> anyhow::Result<Vec<FailedTx>> {
+        use tables::failed_txs::dsl::*;
+        let rows = failed_txs
+            .filter(indexer.eq(indexer_v))
+            .order((block.asc(), tx_id.asc()))
+            .load(&mut self.conn)?;
+
+        Ok(rows)
+    }
+
+    pub fn delete_failed_tx(&mut self, id_v: i64) -> anyhow::Result<()> {
+        use tables::failed_txs::dsl::*;
+        diesel::delete(failed_txs.filter(id.eq(id_v))).execute(&mut self.conn)?;
+
+        Ok(())
+    }
+
+    /// Failed txs of the dropped blocks go together with them.
+    fn delete_blocks_range(
+        conn: &mut PgConnection,
+        from: i64,
+        to: i64,
+        indexer: &str,
+    ) -> QueryResult<()> {
+        use tables::blocks::dsl as blocks_dsl;
+        diesel::delete(blocks_dsl::blocks)
+            .filter(blocks_dsl::height.between(from, to))
+            .filter(blocks_dsl::indexer.eq(indexer))
+            .execute(conn)?;
+
+        use tables::failed_txs::dsl as failed_dsl;
+        diesel::delete(failed_dsl::failed_txs)
+            .filter(failed_dsl::block.between(from, to))
+            .filter(failed_dsl::indexer.eq(indexer))
+            .execute(conn)?;
+
+        Ok(())
+    }
+
+    fn delete_utxo_range(conn: &mut PgConnection, from: i64, to: i64) -> QueryResult<()> {
+        use tables::inputs::dsl as inputs_dsl;
+        diesel::delete(inputs_dsl::inputs)
+            .filter(inputs_dsl::block.between(from, to))
+            .execute(conn)?;
+
+        use tables::outputs::dsl as outputs_dsl;
+        diesel::delete(outputs_dsl::outputs)
+            .filter(outputs_dsl::block.between(from, to))
+            .execute(conn)?;
+
+        use tables::block_stats::dsl as stats_dsl;
+        diesel::delete(stats_dsl::block_stats)
+            .filter(stats_dsl::height.between(from, to))
+            .execute(conn)?;
+
+        Ok(())
+    }
+
+    fn delete_runes_range(conn: &mut PgConnection, from: i64, to: i64) -> QueryResult<()> {
+        use tables::runes_outputs::dsl as runes_outs_dsl;
+        diesel::delete(runes_outs_dsl::runes_outputs)
+            .filter(runes_outs_dsl::block.between(from, to))
+            .execute(conn)?;
+
+        // events reference runes, so they go first
+        use tables::rune_events::dsl as events_dsl;
+        diesel::delete(events_dsl::rune_events)
+            .filter(events_dsl::block.between(from, to))
+            .execute(conn)?;
+
+        use tables::runes::dsl as runes_dsl;
+        diesel::delete(runes_dsl::runes)
+            .filter(runes_dsl::block.between(from, to))
+            .execute(conn)?;
+
+        Ok(())
+    }
+
+    /// Copies blocks starting from `height` to `orphaned_blocks` for audit.
+    fn archive_orphaned_blocks(
+        conn: &mut PgConnection,
+        height: i64,
+        indexer: &str,
+    ) -> QueryResult<Vec<Block>> {
+        use tables::blocks::dsl as blocks_dsl;
+        let blocks: Vec<Block> = blocks_dsl::blocks
+            .filter(blocks_dsl::height.ge(height))
+            .filter(blocks_dsl::indexer.eq(indexer))
+            .order(blocks_dsl::height.asc())
+            .load(conn)?;
+        if blocks.is_empty() {
+            return Ok(blocks);
+        }
+
+        let rows: Vec<OrphanedBlock> = blocks
+            .iter()
+            .map(|b| OrphanedBlock {
+                height: b.height,
+                hash: b.hash.clone(),
+                blocktime: b.blocktime,
+                indexer: b.indexer.clone(),
+            })
+            .collect();
+        use tables::orphaned_blocks::dsl as orphans_dsl;
+        diesel::insert_into(orphans_dsl::orphaned_blocks)
+            .values(&rows)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+
+        Ok(blocks)
+    }
+
     pub fn insert_runes(conn: &mut PgConnection, rune_rows: &Vec<Rune>) -> QueryResult<()> {
         use tables::runes::dsl::*;
         if rune_rows.is_empty() {
             return Ok(());
         }
 
-        diesel::insert_into(runes).values(rune_rows).execute(conn)?;
+        // runes of a block that was committed before the crash are kept
+        diesel::insert_into(runes)
+            .values(rune_rows)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
         Ok(())
     }
 
@@ -267,21 +653,42 @@ impl DB {
         Ok(rune_rows)
     }
 
-    pub fn insert_rune_utxos(conn: &mut PgConnection, rows: &Vec<RuneUtxo>) -> QueryResult<()> {
+    pub fn insert_rune_utxos(conn: &mut PgConnection, rows: &[RuneUtxo]) -> QueryResult<()> {
         use tables::runes_outputs::dsl::*;
         if rows.is_empty() {
             return Ok(());
         }
         // There is a limit how many values we can insert in one query.
         // > driver error: "number of parameters must be between 0 and 65535"
+        // Outputs of a block that was committed before a crash are overwritten,
+        // rows MUST be unique by the key, see `merge_rune_utxos`.
+        for r in rows.chunks(3000) {
+            diesel::insert_into(runes_outputs)
+                .values(r)
+                .on_conflict((tx_hash, vout, rune))
+                .do_update()
+                .set(amount.eq(diesel::upsert::excluded(amount)))
+                .execute(conn)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn insert_rune_events(conn: &mut PgConnection, rows: &Vec<RuneEvent>) -> QueryResult<()> {
+        use tables::rune_events::dsl::*;
+        if rows.is_empty() {
+            return Ok(());
+        }
+        // There is a limit how many values we can insert in one query.
+        // > driver error: "number of parameters must be between 0 and 65535"
         if rows.len() > 3000 {
             for r in rows.chunks(3000) {
-                diesel::insert_into(runes_outputs).values(r).execute(conn)?;
+                diesel::insert_into(rune_events).values(r).execute(conn)?;
             }
             return Ok(());
         }
 
-        diesel::insert_into(runes_outputs)
+        diesel::insert_into(rune_events)
             .values(rows)
             .execute(conn)?;
 
@@ -341,3 +748,281 @@ impl DB {
         Ok(())
     }
 }
+
+/// Exponential backoff for the reconnection attempts.
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub attempts: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(500),
+            max: Duration::from_secs(30),
+            attempts: 8,
+        }
+    }
+}
+
+fn is_connection_lost(err: &DieselError) -> bool {
+    match err {
+        DieselError::DatabaseError(DatabaseErrorKind::ClosedConnection, _) => true,
+        DieselError::DatabaseError(_, info) => {
+            let message = info.message().to_lowercase();
+            message.contains("broken pipe") || message.contains("connection reset")
+        }
+        // transaction manager can't recover after the connection was lost in the middle of the transaction
+        DieselError::BrokenTransactionManager => true,
+        _ => false,
+    }
+}
+
+fn connect_with_backoff<C>(
+    backoff: &Backoff,
+    mut connect: impl FnMut() -> ConnectionResult<C>,
+) -> ConnectionResult<C> {
+    let mut delay = backoff.initial;
+    let mut attempt = 1;
+    loop {
+        match connect() {
+            Ok(conn) => return Ok(conn),
+            Err(err) if attempt >= backoff.attempts => return Err(err),
+            Err(err) => {
+                warn!(
+                    "can't connect to db, retry in {:?}: attempt={} error={}",
+                    delay, attempt, err
+                );
+                sleep(delay);
+                delay = (delay * 2).min(backoff.max);
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Runs `op` and, if the connection is lost, re-establishes it and retries `op` once.
+fn with_reconnect<C, T>(
+    conn: &mut C,
+    backoff: &Backoff,
+    connect: impl FnMut() -> ConnectionResult<C>,
+    mut op: impl FnMut(&mut C) -> QueryResult<T>,
+) -> QueryResult<T> {
+    match op(conn) {
+        Err(err) if is_connection_lost(&err) => {
+            warn!("db connection is lost, reconnecting: error={}", err);
+            match connect_with_backoff(backoff, connect) {
+                Ok(new_conn) => *conn = new_conn,
+                Err(conn_err) => {
+                    error!("can't re-establish db connection: error={}", conn_err);
+                    return Err(err);
+                }
+            }
+            op(conn)
+        }
+        res => res,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    struct MockConn {
+        alive: bool,
+    }
+
+    fn no_delay(attempts: u32) -> Backoff {
+        Backoff {
+            initial: Duration::ZERO,
+            max: Duration::ZERO,
+            attempts,
+        }
+    }
+
+    fn closed() -> DieselError {
+        DieselError::DatabaseError(
+            DatabaseErrorKind::ClosedConnection,
+            Box::new("server closed the connection unexpectedly".to_string()),
+        )
+    }
+
+    fn query(conn: &mut MockConn) -> QueryResult<u32> {
+        if conn.alive {
+            Ok(42)
+        } else {
+            Err(closed())
+        }
+    }
+
+    #[test]
+    fn test_reconnect_on_closed_connection() {
+        let reconnects = Cell::new(0);
+        let mut conn = MockConn { alive: false };
+        let res = with_reconnect(
+            &mut conn,
+            &no_delay(3),
+            || {
+                reconnects.set(reconnects.get() + 1);
+                // first attempt fails, as db is still restarting
+                if reconnects.get() < 2 {
+                    return Err(ConnectionError::BadConnection("db is down".to_string()));
+                }
+                Ok(MockConn { alive: true })
+            },
+            query,
+        );
+        assert_eq!(res.unwrap(), 42);
+        assert_eq!(reconnects.get(), 2);
+    }
+
+    #[test]
+    fn test_no_reconnect_on_query_error() {
+        let reconnects = Cell::new(0);
+        let mut conn = MockConn { alive: true };
+        let res: QueryResult<()> = with_reconnect(
+            &mut conn,
+            &no_delay(3),
+            || {
+                reconnects.set(reconnects.get() + 1);
+                Ok(MockConn { alive: true })
+            },
+            |_| Err(DieselError::NotFound),
+        );
+        assert!(matches!(res, Err(DieselError::NotFound)));
+        assert_eq!(reconnects.get(), 0);
+    }
+
+    #[test]
+    fn test_reconnect_gives_up() {
+        let reconnects = Cell::new(0);
+        let mut conn = MockConn { alive: false };
+        let res = with_reconnect(
+            &mut conn,
+            &no_delay(3),
+            || {
+                reconnects.set(reconnects.get() + 1);
+                Err(ConnectionError::BadConnection("db is down".to_string()))
+            },
+            query,
+        );
+        assert!(is_connection_lost(&res.unwrap_err()));
+        assert_eq!(reconnects.get(), 3);
+    }
+
+    #[test]
+    #[ignore = "requires postgres, set DATABASE_URL"]
+    fn test_failed_txs() {
+        let dsn = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let mut db = DB::establish_connection(&dsn).unwrap();
+        let indexer = "test_failed_txs_indexer";
+        let (first, second) = (Hash::sha2("failed-1"), Hash::sha2("failed-2"));
+
+        db.record_failed_tx(indexer, 900_000_011, 3, &second, "rune not found")
+            .unwrap();
+        db.record_failed_tx(indexer, 900_000_010, 5, &first, "ord is down")
+            .unwrap();
+        db.record_failed_tx(indexer, 900_000_010, 5, &first, "ord is still down")
+            .unwrap();
+
+        let rows = db.select_failed_txs(indexer).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!((rows[0].block, rows[0].tx_id), (900_000_010, 5));
+        assert_eq!(rows[0].error, "ord is still down");
+        assert_eq!(rows[0].attempts, 2);
+        assert_eq!(rows[1].tx_hash, second);
+
+        // failed txs of the orphaned blocks are dropped with them
+        db.drop_inscriptions_blocks_range(900_000_011, i64::MAX, indexer)
+            .unwrap();
+        let rows = db.select_failed_txs(indexer).unwrap();
+        assert_eq!(rows.len(), 1);
+
+        db.delete_failed_tx(rows[0].id).unwrap();
+        assert!(db.select_failed_txs(indexer).unwrap().is_empty());
+    }
+
+    #[test]
+    #[ignore = "requires postgres, set DATABASE_URL"]
+    fn test_block_stats() {
+        use tables::block_stats::dsl;
+
+        let dsn = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let mut db = DB::establish_connection(&dsn).unwrap();
+        let indexer = "test_block_stats_indexer";
+        let (parent, tx) = (Hash::sha2("stats-parent"), Hash::sha2("stats-tx"));
+        let height = 900_000_020;
+
+        let output = Output {
+            id: None,
+            block: height,
+            tx_id: 1,
+            tx_hash: parent.clone(),
+            vout: 0,
+            amount: 10_000,
+            coinbase: false,
+            address: "stats-address".into(),
+        };
+        let inputs = vec![
+            Input {
+                id: None,
+                block: height,
+                tx_id: 2,
+                tx_hash: tx.clone(),
+                vin: 0,
+                parent_tx: parent,
+                parent_vout: 0,
+            },
+            // spends an output that isn't indexed
+            Input {
+                id: None,
+                block: height,
+                tx_id: 2,
+                tx_hash: tx.clone(),
+                vin: 1,
+                parent_tx: Hash::sha2("stats-unknown"),
+                parent_vout: 0,
+            },
+        ];
+        DB::insert_outputs(&mut db.conn, &vec![output]).unwrap();
+        DB::insert_inputs(&mut db.conn, &inputs).unwrap();
+
+        let amounts = DB::select_block_input_amounts(&mut db.conn, height).unwrap();
+        assert_eq!(
+            amounts.get(&tx),
+            Some(&InputAmount {
+                resolved: 1,
+                amount: 10_000
+            })
+        );
+
+        let mut stats = BlockStats {
+            height,
+            tx_count: 3,
+            ..Default::default()
+        };
+        DB::upsert_block_stats(&mut db.conn, &stats).unwrap();
+        // reindexed block replaces its stats
+        stats.total_fees = 500;
+        DB::upsert_block_stats(&mut db.conn, &stats).unwrap();
+        let row: BlockStats = dsl::block_stats
+            .filter(dsl::height.eq(height))
+            .first(&mut db.conn)
+            .unwrap();
+        assert_eq!(row, stats);
+
+        db.drop_blocks_range(height, height, indexer).unwrap();
+        let rows: i64 = dsl::block_stats
+            .filter(dsl::height.eq(height))
+            .count()
+            .get_result(&mut db.conn)
+            .unwrap();
+        assert_eq!(rows, 0);
+        assert!(DB::select_block_input_amounts(&mut db.conn, height)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -1,13 +1,15 @@
 use std::collections::BTreeMap;
 use std::str::FromStr;
 
-use diesel::Connection;
+use anyhow::Context;
+use orbtc_indexer_api::Hash;
 use tokio_util::sync::CancellationToken;
 
 use super::db;
 use super::db::*;
-use super::rt::{TxIndexer, TxInfo};
-use crate::db::schema::OutputExtras;
+use super::rt::{TxIndexer, TxIndexerError, TxInfo};
+use crate::config::OrdFailurePolicy;
+use crate::db::schema::{Block, OutputExtras};
 use crate::{config, ord_api};
 
 // do not change this value. If you do, modify migration!
@@ -23,19 +25,21 @@ pub struct InscriptionsCacher {
 }
 
 impl InscriptionsCacher {
-    pub fn new(db_cfg: &config::DBConfig) -> Self {
-        let db = db::DB::establish_connection(&db_cfg.dsn);
+    pub fn new(db_cfg: &config::DBConfig) -> anyhow::Result<Self> {
+        let db = db::DB::establish_connection(&db_cfg.dsn)?;
 
-        Self {
+        Ok(Self {
             state: State {
                 db,
                 dataset: Vec::new(),
             },
-        }
+        })
     }
 }
 
 impl InscriptionsCacher {
+    /// Imports output flags from the dump, see `parse_dump_line` for the format.
+    /// Existing rows are kept, `db sync-extras` fixes `has_runes` afterwards.
     pub async fn quick_import(
         &mut self,
         cancel: CancellationToken,
@@ -52,16 +56,9 @@ impl InscriptionsCacher {
                 return Ok(());
             }
             let line = line?;
-            let mut parts = line.split(",");
-
-            let Some(txid) = parts.next() else {
-                continue;
-            };
-            let Some(vout) = parts.next() else {
+            let Some((hash, vout, has_inscriptions, has_runes)) = parse_dump_line(&line)? else {
                 continue;
             };
-            let vout: i32 = vout.parse()?;
-            let hash = orbtc_indexer_api::Hash::from_str(txid).expect("valid hash");
 
             info!("#[{wid}] [{id}] loading...");
             let utxo_id = match self.state.db.select_output_id(&hash, vout) {
@@ -77,8 +74,8 @@ impl InscriptionsCacher {
 
             let utxo = OutputExtras {
                 id: utxo_id,
-                has_runes: false,
-                has_inscriptions: true,
+                has_runes,
+                has_inscriptions,
             };
 
             self.state.dataset.push(utxo);
@@ -97,8 +94,10 @@ impl InscriptionsCacher {
             "#[{wid}] Committing indexer state: outputs={}",
             self.state.dataset.len(),
         );
-        let conn = &mut self.state.db.conn;
-        conn.transaction(|conn| DB::insert_utxo_extras(conn, &self.state.dataset))?;
+        let dataset = &self.state.dataset;
+        self.state
+            .db
+            .transaction(|conn| DB::insert_utxo_extras(conn, dataset))?;
 
         self.state.dataset.clear();
 
@@ -106,25 +105,64 @@ impl InscriptionsCacher {
     }
 }
 
+/// Parses a line of the dump: `txid,vout` marks an output with inscriptions,
+/// `txid,vout,has_inscriptions,has_runes` sets both flags (`true`/`false` or `1`/`0`).
+/// Returns `None` for incomplete lines.
+fn parse_dump_line(line: &str) -> anyhow::Result<Option<(Hash, i32, bool, bool)>> {
+    let mut parts = line.trim().split(',');
+    let (Some(txid), Some(vout)) = (parts.next(), parts.next()) else {
+        return Ok(None);
+    };
+    let hash = Hash::from_str(txid).with_context(|| format!("invalid txid({txid})"))?;
+    let vout: i32 = vout.parse()?;
+
+    let flags = match (parts.next(), parts.next()) {
+        (None, _) => (true, false),
+        (Some(inscriptions), Some(runes)) => (parse_flag(inscriptions)?, parse_flag(runes)?),
+        (Some(_), None) => anyhow::bail!("both has_inscriptions and has_runes must be set"),
+    };
+    Ok(Some((hash, vout, flags.0, flags.1)))
+}
+
+fn parse_flag(value: &str) -> anyhow::Result<bool> {
+    match value.trim() {
+        "true" | "1" => Ok(true),
+        "false" | "0" => Ok(false),
+        _ => anyhow::bail!("invalid flag({value})"),
+    }
+}
+
 pub struct InscriptionsCacheIndexer {
     state: State,
     // address: String,
     ord_client: ord_api::OrdClientSync,
+    failure_policy: OrdFailurePolicy,
+    /// Txs skipped since the last commit because ord has failed.
+    skipped_txs: usize,
 }
 
 impl InscriptionsCacheIndexer {
-    pub fn new(db_cfg: &config::DBConfig, ord_address: &str) -> Self {
-        let db = db::DB::establish_connection(&db_cfg.dsn);
-
+    /// Fails if ord at `ord_address` indexes another network than `net`.
+    pub fn new(
+        db_cfg: &config::DBConfig,
+        net: bitcoin::Network,
+        ord_address: &str,
+        failure_policy: OrdFailurePolicy,
+    ) -> anyhow::Result<Self> {
         let ord_client = ord_api::OrdClientSync::new(ord_address);
-        Self {
+        ord_client.check_chain(net)?;
+
+        let db = db::DB::establish_connection(&db_cfg.dsn)?;
+        Ok(Self {
             state: State {
                 db,
                 dataset: Vec::new(),
             },
             // address: ord_address.to_owned(),
             ord_client,
-        }
+            failure_policy,
+            skipped_txs: 0,
+        })
     }
 }
 
@@ -154,14 +192,29 @@ impl TxIndexer for InscriptionsCacheIndexer {
         }
         let res = match self.ord_client.get_details(&request) {
             Ok(v) => v,
-            Err(err) => {
-                // this is optional index, it's ok to skip on error.
-                warn!(
-                    "can't get details from ord for tx outputs: tx={} err={err:#?}",
-                    hash_str
-                );
-                return Ok(());
-            }
+            Err(err) => match self.failure_policy {
+                OrdFailurePolicy::Skip => {
+                    // this is optional index, it's ok to skip on error.
+                    // the runtime records the tx, so it can be replayed
+                    self.skipped_txs += 1;
+                    return Err(TxIndexerError::Recoverable(format!(
+                        "ord failed: tx={hash_str} error={err:#}"
+                    ))
+                    .into());
+                }
+                OrdFailurePolicy::RetryBlock => {
+                    return Err(TxIndexerError::RetryBlock(format!(
+                        "ord failed: tx={hash_str} error={err:#}"
+                    ))
+                    .into());
+                }
+                OrdFailurePolicy::Fail => {
+                    return Err(TxIndexerError::Halt(format!(
+                        "ord failed: tx={hash_str} error={err:#}"
+                    ))
+                    .into());
+                }
+            },
         };
 
         for i in res {
@@ -184,20 +237,61 @@ impl TxIndexer for InscriptionsCacheIndexer {
         Ok(())
     }
 
-    fn commit_state(&mut self) -> anyhow::Result<()> {
+    fn commit_state(&mut self, block: Option<&Block>) -> anyhow::Result<()> {
         info!(
-            "Committing indexer state: outputs={}",
+            "Committing indexer state: outputs={} skipped_txs={}",
             self.state.dataset.len(),
+            self.skipped_txs,
         );
-        let conn = &mut self.state.db.conn;
-        conn.transaction(|conn| DB::insert_utxo_extras(conn, &self.state.dataset))?;
+        let dataset = &self.state.dataset;
+        self.state.db.transaction(|conn| {
+            DB::insert_utxo_extras(conn, dataset)?;
+            if let Some(block) = block {
+                DB::commit_block(conn, block)?;
+            }
+            diesel::result::QueryResult::Ok(())
+        })?;
 
         self.state.dataset.clear();
+        self.skipped_txs = 0;
 
         Ok(())
     }
 
     fn reset_state(&mut self) {
         self.state.dataset.clear();
+        self.skipped_txs = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dump_line() {
+        let txid = Hash::sha2("1");
+        let (hash, vout, inscriptions, runes) =
+            parse_dump_line(&format!("{txid},3")).unwrap().unwrap();
+        assert_eq!(
+            (hash, vout, inscriptions, runes),
+            (txid.clone(), 3, true, false)
+        );
+
+        let line = format!("{txid},0,false,true");
+        assert_eq!(
+            parse_dump_line(&line).unwrap(),
+            Some((txid.clone(), 0, false, true))
+        );
+        let line = format!("{txid},0,1,1");
+        assert_eq!(
+            parse_dump_line(&line).unwrap(),
+            Some((txid.clone(), 0, true, true))
+        );
+
+        assert_eq!(parse_dump_line(&txid.to_string()).unwrap(), None);
+        assert!(parse_dump_line(&format!("{txid},0,true")).is_err());
+        assert!(parse_dump_line(&format!("{txid},0,yes,no")).is_err());
+        assert!(parse_dump_line("xyz,0").is_err());
     }
 }
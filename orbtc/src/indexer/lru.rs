@@ -0,0 +1,85 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// Fixed size cache that evicts the least recently used entry.
+pub struct LruCache<K, V> {
+    capacity: usize,
+    entries: HashMap<K, (V, u64)>,
+    /// Keys by the tick of their last use, the first one is evicted.
+    order: BTreeMap<u64, K>,
+    tick: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    /// Returns the value and marks it as the most recently used.
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        let tick = self.next_tick();
+        let (value, used_at) = self.entries.get_mut(key)?;
+        self.order.remove(used_at);
+        self.order.insert(tick, key.clone());
+        *used_at = tick;
+        Some(value)
+    }
+
+    pub fn put(&mut self, key: K, value: V) {
+        let tick = self.next_tick();
+        if let Some((_, used_at)) = self.entries.insert(key.clone(), (value, tick)) {
+            self.order.remove(&used_at);
+        }
+        self.order.insert(tick, key);
+
+        if self.entries.len() > self.capacity {
+            if let Some((_, oldest)) = self.order.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut cache = LruCache::new(2);
+        cache.put(1, "a");
+        cache.put(2, "b");
+        // 1 becomes the most recently used
+        assert_eq!(cache.get(&1), Some(&"a"));
+
+        cache.put(3, "c");
+        assert_eq!(cache.entries.len(), 2);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&1), Some(&"a"));
+        assert_eq!(cache.get(&3), Some(&"c"));
+    }
+
+    #[test]
+    fn test_put_replaces_value() {
+        let mut cache = LruCache::new(2);
+        cache.put(1, "a");
+        cache.put(2, "b");
+        cache.put(1, "c");
+        assert_eq!(cache.entries.len(), 2);
+
+        // 2 is the oldest after 1 was replaced
+        cache.put(3, "d");
+        assert_eq!(cache.get(&1), Some(&"c"));
+        assert_eq!(cache.get(&2), None);
+    }
+}
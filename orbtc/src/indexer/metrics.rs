@@ -0,0 +1,166 @@
+use std::collections::VecDeque;
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+
+use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts};
+
+/// Indexer metrics share the registry with the API metrics,
+/// so they are exported by the same metrics server when both run in one process.
+static STATE: LazyLock<State> = LazyLock::new(|| match State::new() {
+    Ok(state) => state,
+    Err(err) => {
+        error!("indexer metrics state can't be initialized: error={err:#}");
+        panic!("indexer metrics state can't be initialized");
+    }
+});
+
+pub fn block_fetched(indexer: &str, elapsed: Duration) {
+    STATE
+        .fetch_time
+        .with_label_values(&[indexer])
+        .observe(elapsed.as_secs_f64());
+}
+
+pub fn block_processed(indexer: &str, elapsed: Duration) {
+    STATE
+        .index_time
+        .with_label_values(&[indexer])
+        .observe(elapsed.as_secs_f64());
+}
+
+pub fn block_committed(indexer: &str, elapsed: Duration) {
+    STATE
+        .commit_time
+        .with_label_values(&[indexer])
+        .observe(elapsed.as_secs_f64());
+}
+
+pub fn block_indexed(indexer: &str, height: u64, blocks_per_minute: usize) {
+    STATE
+        .last_height
+        .with_label_values(&[indexer])
+        .set(height as i64);
+    STATE.blocks.with_label_values(&[indexer]).inc();
+    STATE
+        .blocks_per_minute
+        .with_label_values(&[indexer])
+        .set(blocks_per_minute as i64);
+}
+
+pub fn reorg(indexer: &str) {
+    STATE.reorgs.with_label_values(&[indexer]).inc();
+}
+
+/// Number of blocks indexed during the last minute.
+#[derive(Default)]
+pub struct BlockRate {
+    indexed_at: VecDeque<Instant>,
+}
+
+impl BlockRate {
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    pub fn add(&mut self, now: Instant) -> usize {
+        self.indexed_at.push_back(now);
+        while let Some(first) = self.indexed_at.front() {
+            if now.duration_since(*first) <= Self::WINDOW {
+                break;
+            }
+            self.indexed_at.pop_front();
+        }
+        self.indexed_at.len()
+    }
+}
+
+struct State {
+    last_height: IntGaugeVec,
+    blocks: IntCounterVec,
+    blocks_per_minute: IntGaugeVec,
+    fetch_time: HistogramVec,
+    index_time: HistogramVec,
+    commit_time: HistogramVec,
+    reorgs: IntCounterVec,
+}
+
+impl State {
+    fn new() -> anyhow::Result<Self> {
+        let registry = crate::rest::metrics::registry();
+        let labels = &["indexer"];
+        let buckets = prometheus::exponential_buckets(0.005, 2.0, 15)?;
+
+        let last_height = IntGaugeVec::new(
+            Opts::new("indexer_last_height", "Last indexed block"),
+            labels,
+        )?;
+        let blocks = IntCounterVec::new(
+            Opts::new("indexer_blocks_total", "Number of indexed blocks"),
+            labels,
+        )?;
+        let blocks_per_minute = IntGaugeVec::new(
+            Opts::new(
+                "indexer_blocks_per_minute",
+                "Number of blocks indexed during the last minute",
+            ),
+            labels,
+        )?;
+        let fetch_time = HistogramVec::new(
+            HistogramOpts::new("indexer_block_fetch_seconds", "Time to fetch a block")
+                .buckets(buckets.clone()),
+            labels,
+        )?;
+        let index_time = HistogramVec::new(
+            HistogramOpts::new(
+                "indexer_block_index_seconds",
+                "Time to process block transactions",
+            )
+            .buckets(buckets.clone()),
+            labels,
+        )?;
+        let commit_time = HistogramVec::new(
+            HistogramOpts::new(
+                "indexer_block_commit_seconds",
+                "Time to commit block data to the db",
+            )
+            .buckets(buckets),
+            labels,
+        )?;
+        let reorgs = IntCounterVec::new(
+            Opts::new("indexer_reorgs_total", "Number of handled reorgs"),
+            labels,
+        )?;
+
+        registry.register(Box::new(last_height.clone()))?;
+        registry.register(Box::new(blocks.clone()))?;
+        registry.register(Box::new(blocks_per_minute.clone()))?;
+        registry.register(Box::new(fetch_time.clone()))?;
+        registry.register(Box::new(index_time.clone()))?;
+        registry.register(Box::new(commit_time.clone()))?;
+        registry.register(Box::new(reorgs.clone()))?;
+        Ok(Self {
+            last_height,
+            blocks,
+            blocks_per_minute,
+            fetch_time,
+            index_time,
+            commit_time,
+            reorgs,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_rate() {
+        let mut rate = BlockRate::default();
+        let now = Instant::now();
+        assert_eq!(rate.add(now), 1);
+        assert_eq!(rate.add(now + Duration::from_secs(30)), 2);
+        assert_eq!(rate.add(now + Duration::from_secs(60)), 3);
+        // the first block is out of the window
+        assert_eq!(rate.add(now + Duration::from_secs(61)), 3);
+        assert_eq!(rate.add(now + Duration::from_secs(200)), 1);
+    }
+}
@@ -1,11 +1,16 @@
 mod bitcoin_indexer;
 mod bitcoin_indexer_state;
+mod block_notifier;
 mod inscriptions_index;
+mod lru;
+mod metrics;
+mod prefetch;
 
 pub mod db;
 mod rt;
 mod runes_indexer;
 mod runes_indexer_state;
+mod webhook;
 
 use std::time;
 
@@ -14,7 +19,10 @@ pub use inscriptions_index::{
     InscriptionsCacheIndexer, InscriptionsCacher, INSCRIPTIONS_CACHE_INDEX,
 };
 pub use rt::{BlockIndexerRt, IndexerType, IndexingOpts, TxIndexer, TxInfo};
-pub use runes_indexer::{RunesIndexer, RUNES_INDEX};
+pub use runes_indexer::{
+    allocate, find_commitment, AllocationTrace, CommitmentError, EdictAllocation, Issuance,
+    MintChecker, RunesIndexer, RUNES_INDEX,
+};
 
 static mut INDEXER_WAIT_INTERVAL: time::Duration = time::Duration::from_secs(5);
 
@@ -0,0 +1,121 @@
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread::{sleep, JoinHandle};
+use std::time::Duration;
+
+use bitcoin::BlockHash;
+use bitcoincore_rpc::{Client, RpcApi};
+use tokio_util::sync::CancellationToken;
+
+/// Max time to wait for the next block from the prefetcher,
+/// after that indexer fetches the block by itself.
+const PREFETCH_TIMEOUT: Duration = Duration::from_secs(60);
+const TIP_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+type FetchResult = anyhow::Result<(BlockHash, bitcoin::Block)>;
+
+/// Fetches blocks from the node in the background thread,
+/// so the rpc round trip is not on the indexing critical path.
+/// Queued blocks are dropped together with the prefetcher,
+/// so it must be recreated when indexer jumps to another height (e.g. on fork).
+pub struct Prefetcher {
+    next_height: u64,
+    rx: Option<Receiver<(u64, FetchResult)>>,
+    cancel: CancellationToken,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Prefetcher {
+    pub fn start(rpc: Arc<Client>, start: u64, depth: usize) -> Self {
+        let (tx, rx) = sync_channel(depth.max(1));
+        let cancel = CancellationToken::new();
+
+        let stop = cancel.clone();
+        let thread = std::thread::spawn(move || {
+            let mut best_block = 0;
+            let mut height = start;
+            while !stop.is_cancelled() {
+                if height > best_block {
+                    match rpc.get_block_count() {
+                        Ok(count) => best_block = count,
+                        Err(err) => {
+                            warn!("Prefetcher can't get best block: error={err}");
+                        }
+                    }
+                    if height > best_block {
+                        sleep(TIP_POLL_INTERVAL);
+                        continue;
+                    }
+                }
+
+                let result = fetch_block(&rpc, height);
+                let failed = result.is_err();
+                // receiver is dropped, nobody needs blocks anymore
+                if tx.send((height, result)).is_err() || failed {
+                    break;
+                }
+                height += 1;
+            }
+        });
+
+        Self {
+            next_height: start,
+            rx: Some(rx),
+            cancel,
+            thread: Some(thread),
+        }
+    }
+
+    /// Height of the block that will be returned by [`Self::next_block`].
+    pub fn next_height(&self) -> u64 {
+        self.next_height
+    }
+
+    pub fn next_block(&mut self) -> FetchResult {
+        let Some(rx) = self.rx.as_ref() else {
+            anyhow::bail!("prefetcher is stopped");
+        };
+
+        let (height, result) = match rx.recv_timeout(PREFETCH_TIMEOUT) {
+            Ok(v) => v,
+            Err(RecvTimeoutError::Timeout) => {
+                anyhow::bail!("no prefetched block for {PREFETCH_TIMEOUT:?}")
+            }
+            Err(RecvTimeoutError::Disconnected) => anyhow::bail!("prefetcher is stopped"),
+        };
+        self.next_height = height + 1;
+        result
+    }
+}
+
+impl Drop for Prefetcher {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+        // unblocks the thread waiting on the full queue
+        self.rx.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+pub fn fetch_block(rpc: &Client, height: u64) -> FetchResult {
+    let block_hash = match rpc.get_block_hash(height) {
+        Ok(hash) => hash,
+        Err(err) => {
+            anyhow::bail!("Can't get BTC block hash: height={height} error={:#?}", err,);
+        }
+    };
+
+    let block: bitcoin::Block = match rpc.get_by_id(&block_hash) {
+        Ok(block) => block,
+        Err(err) => {
+            anyhow::bail!(
+                "Can't get BTC block by hash for heigh({height}): hash={block_hash} error={:#?}",
+                err
+            );
+        }
+    };
+
+    Ok((block_hash, block))
+}
@@ -1,24 +1,59 @@
+use std::sync::Arc;
 use std::thread::sleep;
+use std::time::Instant;
 
+use anyhow::Context;
 use bitcoin::{BlockHash, Transaction, Txid};
-use bitcoincore_rpc::{Auth, Client, RpcApi};
+use bitcoincore_rpc::{Client, RpcApi};
+use orbtc_indexer_api::Hash;
+use rayon::prelude::*;
+use tokio::task::JoinHandle;
 use tokio_util::sync::CancellationToken;
 use tokio_util::task::TaskTracker;
 
 use super::bitcoin_indexer::BitcoinUtxoIndexer;
+use super::block_notifier::BlockNotifier;
 use super::db;
 use super::inscriptions_index::InscriptionsCacheIndexer;
+use super::metrics::{self, BlockRate};
+use super::prefetch::{self, Prefetcher};
 use super::runes_indexer::RunesIndexer;
+use super::webhook::{ReorgEvent, ReorgNotifier};
 use crate::config;
 use crate::db::schema;
 
-pub trait TxIndexer {
+pub trait TxIndexer: Send {
     fn name(&self) -> String;
+    /// Stateless part of the indexing that can be done for all block txs in parallel.
+    /// Result is passed to `index_transaction` with `TxInfo::prepared`.
+    /// Indexers with order dependent logic (e.g. runes) must keep the default.
+    fn prepare_transactions(&self, _txs: &[TxInfo]) -> Vec<PreparedTx> {
+        Vec::new()
+    }
     fn index_transaction(&mut self, tx_info: &TxInfo) -> anyhow::Result<()>;
-    fn commit_state(&mut self) -> anyhow::Result<()>;
+    /// Writes the state of the block, `block` MUST be committed with `DB::commit_block`
+    /// in the same db transaction, so a crash can't leave the data of a block
+    /// that is indexed again after restart. `None` commits the state only, e.g. on replay.
+    fn commit_state(&mut self, block: Option<&schema::Block>) -> anyhow::Result<()>;
     fn reset_state(&mut self);
 }
 
+/// Errors of `TxIndexer::index_transaction` that are handled by the runtime,
+/// any other error is treated as a bug.
+#[derive(Debug, thiserror::Error)]
+pub enum TxIndexerError {
+    /// Block data is dropped and the block is indexed again after a pause.
+    #[error("block must be indexed again: {0}")]
+    RetryBlock(String),
+    /// Indexing is stopped and the rest of the services are cancelled.
+    #[error("indexing must be halted: {0}")]
+    Halt(String),
+    /// The tx can be indexed later, it's skipped if `IndexingOpts::on_tx_error` allows.
+    /// MUST be returned before the indexer state is changed by the tx.
+    #[error("tx can't be indexed now: {0}")]
+    Recoverable(String),
+}
+
 #[derive(Default, Debug, Clone, Copy)]
 pub enum IndexerType {
     #[default]
@@ -36,8 +71,23 @@ pub struct IndexingOpts {
     pub retry_on_fail: bool,
     pub skip_inputs: bool,
     pub ord_address: Option<String>,
+    pub ord_failure_policy: config::OrdFailurePolicy,
+    /// What happens with the block when a tx fails with `TxIndexerError::Recoverable`.
+    pub on_tx_error: config::TxErrorPolicy,
     pub use_firehose: bool,
     pub firehose_api_key: Option<String>,
+    pub firehose_endpoint: Option<String>,
+    /// Number of blocks fetched ahead of indexing, 0 disables prefetch.
+    pub prefetch_blocks: usize,
+    /// Url notified about handled reorgs.
+    pub reorg_webhook_url: Option<String>,
+    /// Waits until another instance releases the indexer lock instead of failing.
+    pub wait_for_lock: bool,
+    /// The last block to index, the indexer stops and cancels the run
+    /// once it's processed instead of waiting for new blocks.
+    pub stop_at_height: Option<u64>,
+    /// Runes indexer accepts etchings without the commitment check, refused on mainnet.
+    pub disable_commitment_validation: bool,
 }
 
 pub struct TxInfo<'a> {
@@ -47,6 +97,19 @@ pub struct TxInfo<'a> {
     pub txid: Txid,
     pub tx: &'a Transaction,
     pub timestamp: i64,
+    pub prepared: Option<&'a PreparedTx>,
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct PreparedTx {
+    /// Same order as tx outputs.
+    pub outputs: Vec<PreparedOutput>,
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct PreparedOutput {
+    pub address: String,
+    pub address_type: String,
 }
 
 pub struct BlockIndexerRt {
@@ -56,20 +119,82 @@ pub struct BlockIndexerRt {
 }
 
 impl BlockIndexerRt {
-    pub fn new(db_cfg: &config::DBConfig, btc_cfg: &config::BTCConfig, opts: IndexingOpts) -> Self {
-        Self {
+    /// Validates the config, so a misconfigured indexer fails before anything is started.
+    pub fn new(
+        db_cfg: &config::DBConfig,
+        btc_cfg: &config::BTCConfig,
+        opts: IndexingOpts,
+    ) -> anyhow::Result<Self> {
+        btc_cfg.rpc_client()?;
+        BlockNotifier::new(btc_cfg.zmq_address.as_deref())?;
+        if matches!(opts.indexer_type, IndexerType::InscriptionsCache) && opts.ord_address.is_none()
+        {
+            anyhow::bail!("ord address isn't set, check the `ord_api.address` config");
+        }
+        if opts.use_firehose {
+            if !cfg!(feature = "firehose") {
+                anyhow::bail!(
+                    "firehose is requested, but the binary is built without `firehose` feature"
+                );
+            }
+            if opts.firehose_endpoint.is_none() {
+                anyhow::bail!("firehose endpoint isn't set, check the `firehose_endpoint` config");
+            }
+            if opts.firehose_api_key.is_none() {
+                anyhow::bail!("firehose api key isn't set, check the `firehose_api_key` config");
+            }
+        }
+
+        Ok(Self {
             db_cfg: db_cfg.clone(),
             btc_cfg: btc_cfg.clone(),
             opts,
-        }
+        })
+    }
+
+    /// Initializes the indexer and runs it in the background.
+    /// On failure `cancel` is triggered to stop the rest of the services.
+    /// The task resolves to `false` if the indexing was halted or failed.
+    pub fn start(
+        self,
+        tasker: &TaskTracker,
+        cancel: CancellationToken,
+    ) -> anyhow::Result<JoinHandle<bool>> {
+        let indexer_type = self.opts.indexer_type;
+        let rt = match Rt::new(&self.db_cfg, &self.btc_cfg, self.opts) {
+            Ok(rt) => rt,
+            Err(err) => {
+                error!("can't start indexer: type={indexer_type:?} error={err:#}");
+                cancel.cancel();
+                return Err(err.context(format!("can't start {indexer_type:?} indexer")));
+            }
+        };
+
+        Ok(tasker.spawn_blocking(move || {
+            let ok = rt.run(cancel);
+            info!("indexer stopped");
+            ok
+        }))
     }
 
-    pub fn start(self, tasker: &TaskTracker, cancel: CancellationToken) {
-        tasker.spawn_blocking(move || {
-            let rt = Rt::new(&self.db_cfg, &self.btc_cfg, self.opts);
-            rt.run(cancel);
-            info!("indexer stopped")
-        });
+    /// Drops the indexer data of blocks in `[from, to]` and indexes them again
+    /// in the current thread. `last_indexed_block` is left untouched,
+    /// so the range must be below the indexed tip.
+    pub fn reindex_range(
+        self,
+        from: u64,
+        to: u64,
+        cancel: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let mut rt = Rt::new(&self.db_cfg, &self.btc_cfg, self.opts)?;
+        rt.reindex_range(from, to, &cancel)
+    }
+
+    /// Indexes the txs from `failed_txs` again in the current thread,
+    /// returns the number of replayed and still failing txs.
+    pub fn replay_failed(self, cancel: CancellationToken) -> anyhow::Result<(usize, usize)> {
+        let mut rt = Rt::new(&self.db_cfg, &self.btc_cfg, self.opts)?;
+        rt.replay_failed(&cancel)
     }
 }
 
@@ -78,65 +203,140 @@ struct Rt {
     opts: IndexingOpts,
 
     db: db::DB,
-    rpc: Client,
+    rpc: Arc<Client>,
 
     // TODO: add option to run
     // multiple indexers within one instance of RT
     indexer: Box<dyn TxIndexer>,
 
     last_block: Option<BlockHash>,
+    /// Whether this instance holds the advisory lock of the indexer.
+    locked: bool,
+    /// Set when the indexer returned `TxIndexerError::Halt`, the run is not retried.
+    halted: bool,
+    /// Set when all blocks up to `IndexingOpts::stop_at_height` are processed.
+    reached_stop_height: bool,
+    prefetcher: Option<Prefetcher>,
+    block_notifier: BlockNotifier,
+    block_rate: BlockRate,
+    reorg_notifier: Option<ReorgNotifier>,
     #[allow(dead_code)]
     use_firehose: bool,
     #[cfg(feature = "firehose")]
-    fh_client: crate::firehose::FHClient,
+    fh_client: Option<crate::firehose::FHClient>,
+    #[cfg(feature = "firehose")]
+    fh_stream: Option<crate::firehose::BlockStream>,
 }
 
 impl Rt {
-    fn new(db_cfg: &config::DBConfig, btc_cfg: &config::BTCConfig, opts: IndexingOpts) -> Self {
+    fn new(
+        db_cfg: &config::DBConfig,
+        btc_cfg: &config::BTCConfig,
+        opts: IndexingOpts,
+    ) -> anyhow::Result<Self> {
         let net = btc_cfg.get_network();
-        let rpc = Client::new(
-            &btc_cfg.address,
-            Auth::UserPass(btc_cfg.rpc_user.clone(), btc_cfg.rpc_password.clone()),
-        )
-        .unwrap();
-        let db = db::DB::establish_connection(&db_cfg.dsn);
+        let rpc = Arc::new(btc_cfg.rpc_client()?);
+        let mut db = db::DB::establish_connection(&db_cfg.dsn)?;
 
         let indexer: Box<dyn TxIndexer> = match opts.indexer_type {
-            IndexerType::Dummy => Box::new(Dummy {}),
-            IndexerType::BitcoinUtxo => Box::new(BitcoinUtxoIndexer::new(net, db_cfg)),
-            IndexerType::Runes => Box::new(RunesIndexer::new(db_cfg, btc_cfg, opts.skip_inputs)),
-            IndexerType::InscriptionsCache => Box::new(InscriptionsCacheIndexer::new(
+            IndexerType::Dummy => Box::new(Dummy {
+                db: db::DB::establish_connection(&db_cfg.dsn)?,
+            }),
+            IndexerType::BitcoinUtxo => Box::new(BitcoinUtxoIndexer::new(net, db_cfg)?),
+            IndexerType::Runes => Box::new(RunesIndexer::new(
                 db_cfg,
-                opts.ord_address
-                    .clone()
-                    .expect("ord address isn't set")
-                    .as_str(),
-            )),
+                btc_cfg,
+                opts.skip_inputs,
+                opts.disable_commitment_validation,
+            )?),
+            IndexerType::InscriptionsCache => {
+                let ord_address = opts
+                    .ord_address
+                    .as_deref()
+                    .context("ord address isn't set")?;
+                Box::new(InscriptionsCacheIndexer::new(
+                    db_cfg,
+                    net,
+                    ord_address,
+                    opts.ord_failure_policy,
+                )?)
+            }
         };
 
+        let name = indexer.name();
+        let locked = db.try_lock_indexer(&name)?;
+        if !locked && !opts.wait_for_lock {
+            error!("Indexer lock is taken, another instance is running: indexer={name}");
+            anyhow::bail!("indexer({name}) is already running by another process");
+        }
+
         let use_firehose = opts.use_firehose;
 
         #[cfg(feature = "firehose")]
-        let fh_client = firehose::FHClient::new(opts.firehose_api_key.unwrap());
+        let fh_client = if use_firehose {
+            let endpoint = opts
+                .firehose_endpoint
+                .as_deref()
+                .context("firehose endpoint isn't set")?;
+            let api_key = opts
+                .firehose_api_key
+                .as_deref()
+                .context("firehose api key isn't set")?;
+            let handle = tokio::runtime::Handle::try_current()
+                .context("firehose client requires tokio runtime")?;
+            Some(crate::firehose::FHClient::new(
+                endpoint, api_key, net, handle,
+            )?)
+        } else {
+            None
+        };
+
+        let reorg_notifier = opts.reorg_webhook_url.as_deref().map(ReorgNotifier::new);
+        let block_notifier = BlockNotifier::new(btc_cfg.zmq_address.as_deref())?;
 
-        Self {
+        Ok(Self {
             db,
             rpc,
             opts,
             last_block: None,
-            name: indexer.name(),
+            locked,
+            halted: false,
+            reached_stop_height: false,
+            prefetcher: None,
+            block_notifier,
+            block_rate: BlockRate::default(),
+            reorg_notifier,
+            name,
             indexer,
             use_firehose,
             #[cfg(feature = "firehose")]
             fh_client,
-        }
+            #[cfg(feature = "firehose")]
+            fh_stream: None,
+        })
     }
 
-    fn run(self, cancel: CancellationToken) {
+    /// Returns `false` if the indexing was halted or failed without retry.
+    fn run(self, cancel: CancellationToken) -> bool {
         let mut indexer = self;
+        if !indexer.wait_for_lock(&cancel) {
+            return true;
+        }
 
+        let mut ok = true;
         while !cancel.is_cancelled() {
-            if !indexer._run(&cancel) && indexer.opts.retry_on_fail {
+            ok = indexer._run(&cancel);
+            if indexer.halted {
+                error!("Indexing halted, stopping: indexer={}", indexer.name);
+                cancel.cancel();
+                break;
+            }
+            if indexer.reached_stop_height {
+                // there are no more blocks to index, so the rest of the services are stopped too
+                cancel.cancel();
+                break;
+            }
+            if !ok && indexer.opts.retry_on_fail {
                 error!("Run failed. Retry");
                 unsafe {
                     sleep(super::INDEXER_WAIT_INTERVAL);
@@ -146,6 +346,31 @@ impl Rt {
             }
             break;
         }
+
+        if let Err(err) = indexer.db.unlock_indexer(&indexer.name) {
+            warn!("Can't release indexer lock: error={err:#}");
+        }
+        ok
+    }
+
+    /// Blocks until the indexer lock is acquired, returns `false` if cancelled.
+    fn wait_for_lock(&mut self, cancel: &CancellationToken) -> bool {
+        while !self.locked {
+            if cancel.is_cancelled() {
+                return false;
+            }
+
+            info!("Waiting for indexer lock: indexer={}", self.name);
+            unsafe {
+                sleep(super::INDEXER_WAIT_INTERVAL);
+            }
+            match self.db.try_lock_indexer(&self.name) {
+                Ok(locked) => self.locked = locked,
+                Err(err) => error!("Can't acquire indexer lock: error={err:#}"),
+            }
+        }
+
+        true
     }
 
     fn _run(&mut self, cancel: &CancellationToken) -> bool {
@@ -167,19 +392,33 @@ impl Rt {
 
         let mut current_block = first_block;
         while !cancel.is_cancelled() {
-            best_block = match self.rpc.get_block_count() {
-                Ok(count) => count,
-                Err(err) => {
-                    error!("Can't get best BTC block error={:#?}", err);
-                    return false;
+            if let Some(stop_height) = self.opts.stop_at_height {
+                if current_block > stop_height {
+                    info!(
+                        "Reached stop height, indexing stopped: indexer={} height={}",
+                        self.name, stop_height
+                    );
+                    self.reached_stop_height = true;
+                    return true;
                 }
-            };
+            }
 
-            if best_block < current_block {
-                unsafe {
-                    sleep(super::INDEXER_WAIT_INTERVAL);
+            // the node tip is checked only when the indexer catches up with the known one,
+            // so the initial sync doesn't make an extra rpc call per block
+            if current_block > best_block {
+                best_block = match self.rpc.get_block_count() {
+                    Ok(count) => count,
+                    Err(err) => {
+                        error!("Can't get best BTC block error={:#?}", err);
+                        return false;
+                    }
+                };
+
+                if best_block < current_block {
+                    self.block_notifier
+                        .wait(unsafe { super::INDEXER_WAIT_INTERVAL });
+                    continue;
                 }
-                continue;
             }
 
             debug!(
@@ -189,13 +428,34 @@ impl Rt {
 
             let (height, hash, tx_count) = match self.index_block(current_block) {
                 Ok(v) => v,
-                Err(err) => {
-                    error!("Block indexing failed. Retry.: error={err}");
-                    continue;
-                }
+                Err(err) => match err.downcast_ref::<TxIndexerError>() {
+                    Some(TxIndexerError::Halt(_)) => {
+                        error!("Block indexing failed: height={current_block} error={err}");
+                        self.halted = true;
+                        return false;
+                    }
+                    // tx errors are skipped or escalated inside of the block,
+                    // reaching this point is a bug, so just retry the block
+                    Some(TxIndexerError::RetryBlock(_) | TxIndexerError::Recoverable(_)) => {
+                        warn!("Block indexing failed. Retry after a pause: height={current_block} error={err}");
+                        unsafe {
+                            sleep(super::INDEXER_WAIT_INTERVAL);
+                        }
+                        continue;
+                    }
+                    None => {
+                        error!("Block indexing failed. Retry.: error={err}");
+                        // the block can be missing if the node switched to a shorter chain
+                        best_block = 0;
+                        continue;
+                    }
+                },
             };
 
             if height < current_block || tx_count == 0 {
+                // prefetched blocks belong to the orphaned branch
+                self.prefetcher = None;
+                metrics::reorg(&self.name);
                 info!(
                     "Fork occured. Reseting state to fork root: height={} hash={}",
                     current_block, hash,
@@ -216,13 +476,9 @@ impl Rt {
                 current_block, hash, tx_count
             );
 
-            if !self.opts.dry_run {
-                if let Err(err) = self.db.update_last_block(&self.name, current_block as i64) {
-                    error!("Unable to update last indexed block: error={:#?}", err);
-                }
-            }
-
             self.last_block = Some(hash);
+            let blocks_per_minute = self.block_rate.add(Instant::now());
+            metrics::block_indexed(&self.name, current_block, blocks_per_minute);
 
             current_block += 1;
         }
@@ -231,12 +487,147 @@ impl Rt {
         true
     }
 
+    fn reindex_range(
+        &mut self,
+        from: u64,
+        to: u64,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let tip = self.db.get_last_indexed_block(&self.name)? as u64;
+        if to > tip {
+            anyhow::bail!("range end({to}) is above the indexed tip({tip})");
+        }
+
+        // blocks of the range are dropped, so the fork detection can't work,
+        // instead the range must be on the node's best chain
+        let to_hash = self.rpc.get_block_hash(to)?;
+        if self.db.get_block(&to_hash.into(), &self.name).is_err() {
+            anyhow::bail!("block({to}) hash differs from the node, wait until reorg is handled");
+        }
+
+        let (from_i, to_i) = (from as i64, to as i64);
+        match self.opts.indexer_type {
+            IndexerType::BitcoinUtxo => self.db.drop_blocks_range(from_i, to_i, &self.name)?,
+            IndexerType::Runes => {
+                let referenced = self.db.count_runes_referenced_after(from_i, to_i)?;
+                if referenced > 0 {
+                    anyhow::bail!(
+                        "{referenced} runes etched in the range are used after block({to}), extend the range up to the tip"
+                    );
+                }
+                self.db.drop_runes_blocks_range(from_i, to_i, &self.name)?
+            }
+            IndexerType::InscriptionsCache => self
+                .db
+                .drop_inscriptions_blocks_range(from_i, to_i, &self.name)?,
+            IndexerType::Dummy => anyhow::bail!("dummy indexer can't be reindexed"),
+        }
+        info!(
+            "Dropped blocks data: indexer={} from={from} to={to}",
+            self.name
+        );
+
+        for height in from..=to {
+            if cancel.is_cancelled() {
+                anyhow::bail!("reindex cancelled at block({height}), range is partially indexed");
+            }
+
+            let (_, hash, tx_count) = self.index_block(height)?;
+            info!("Reindexed block: height={height} hash={hash} tx_count={tx_count}");
+        }
+
+        Ok(())
+    }
+
+    fn replay_failed(&mut self, cancel: &CancellationToken) -> anyhow::Result<(usize, usize)> {
+        let failed_txs = self.db.select_failed_txs(&self.name)?;
+        info!(
+            "Replaying failed txs: indexer={} count={}",
+            self.name,
+            failed_txs.len()
+        );
+
+        let (mut replayed, mut failed) = (0, 0);
+        for row in failed_txs {
+            if cancel.is_cancelled() {
+                anyhow::bail!("replay cancelled at tx({})", row.tx_hash);
+            }
+
+            let (block_hash, block) = prefetch::fetch_block(&self.rpc, row.block as u64)?;
+            if self.db.get_block(&block_hash.into(), &self.name).is_err() {
+                // orphaned rows are dropped on reorg, the indexer hasn't caught up yet
+                warn!(
+                    "Block of the failed tx isn't indexed, skip: block={} tx={}",
+                    row.block, row.tx_hash
+                );
+                continue;
+            }
+            let Some(tx) = block.txdata.get(row.tx_id as usize) else {
+                anyhow::bail!("block({}) has no tx({})", row.block, row.tx_id);
+            };
+            let txid = tx.compute_txid();
+            if Hash::from(txid) != row.tx_hash {
+                anyhow::bail!(
+                    "tx({}) of block({}) is {txid}, expected {}",
+                    row.tx_id,
+                    row.block,
+                    row.tx_hash
+                );
+            }
+
+            let mut tx_info = TxInfo {
+                block: row.block as u64,
+                tx_n: row.tx_id,
+                txid,
+                tx,
+                timestamp: block.header.time as i64,
+                prepared: None,
+            };
+            let prepared = self
+                .indexer
+                .prepare_transactions(std::slice::from_ref(&tx_info));
+            tx_info.prepared = prepared.first();
+
+            let res = self
+                .indexer
+                .index_transaction(&tx_info)
+                .and_then(|_| self.indexer.commit_state(None));
+            match res {
+                Ok(()) => {
+                    self.db.delete_failed_tx(row.id)?;
+                    info!("Replayed failed tx: block={} tx={txid}", row.block);
+                    replayed += 1;
+                }
+                Err(err) => {
+                    self.indexer.reset_state();
+                    warn!(
+                        "Failed tx replay failed: block={} tx={txid} error={err:#}",
+                        row.block
+                    );
+                    self.db.record_failed_tx(
+                        &self.name,
+                        row.block,
+                        row.tx_id,
+                        &row.tx_hash,
+                        &format!("{err:#}"),
+                    )?;
+                    failed += 1;
+                }
+            }
+        }
+
+        Ok((replayed, failed))
+    }
+
     fn starting_block(&mut self) -> u64 {
         let result = self.db.get_last_indexed_block(&self.name);
         let last_block = match result {
             #[rustfmt::skip] // starting from next after saved
             Ok(b) => if b > 0 { (b + 1) as u64 } else { 0 },
             Err(_) => {
+                if self.opts.dry_run {
+                    return self.opts.starting_height;
+                }
                 if let Err(err) = self.db.update_last_block(&self.name, 0) {
                     error!("Failed to insert indexer tip: err={err}");
                 }
@@ -266,32 +657,76 @@ impl Rt {
     fn fetch_block(&mut self, height: u64) -> anyhow::Result<(BlockHash, bitcoin::Block)> {
         #[cfg(feature = "firehose")]
         if self.use_firehose {
-            let block = self.fh_client.get_block(height)?;
-            return Ok(block);
+            match self.fetch_firehose_block(height) {
+                Ok(block) => return Ok(block),
+                Err(err) => {
+                    warn!("Can't fetch block from firehose, fallback to node rpc: height={height} error={err:#}");
+                }
+            }
         }
 
-        let block_hash = match self.rpc.get_block_hash(height) {
-            Ok(hash) => hash,
-            Err(err) => {
-                anyhow::bail!("Can't get BTC block hash: height={height} error={:#?}", err,);
+        if self.opts.prefetch_blocks > 0 && !self.use_firehose {
+            if self.prefetcher.as_ref().map(|p| p.next_height()) != Some(height) {
+                debug!("Start blocks prefetch: height={height}");
+                self.prefetcher = Some(Prefetcher::start(
+                    self.rpc.clone(),
+                    height,
+                    self.opts.prefetch_blocks,
+                ));
             }
-        };
 
-        let block: bitcoin::Block = match self.rpc.get_by_id(&block_hash) {
-            Ok(block) => block,
-            Err(err) => {
-                anyhow::bail!(
-                    "Can't get BTC block by hash for heigh({height}): hash={block_hash} error={:#?}",
-                    err
-                );
+            if let Some(prefetcher) = self.prefetcher.as_mut() {
+                match prefetcher.next_block() {
+                    Ok(block) => return Ok(block),
+                    Err(err) => {
+                        warn!("Blocks prefetch failed: height={height} error={err:#}");
+                        self.prefetcher = None;
+                    }
+                }
             }
+        }
+
+        prefetch::fetch_block(&self.rpc, height)
+    }
+
+    /// Takes the block from the firehose stream,
+    /// falls back to the single block request if the stream fails or is out of order.
+    #[cfg(feature = "firehose")]
+    fn fetch_firehose_block(&mut self, height: u64) -> anyhow::Result<(BlockHash, bitcoin::Block)> {
+        let Some(client) = self.fh_client.as_mut() else {
+            anyhow::bail!("firehose client isn't initialized");
         };
 
-        Ok((block_hash, block))
+        if self.fh_stream.as_ref().map(|s| s.next_height()) != Some(height) {
+            self.fh_stream = match client.stream_blocks(height) {
+                Ok(stream) => Some(stream),
+                Err(err) => {
+                    warn!("Can't open firehose stream: height={height} error={err:#}");
+                    None
+                }
+            };
+        }
+
+        if let Some(stream) = self.fh_stream.as_mut() {
+            match stream.next_block() {
+                Ok((h, hash, block)) if h == height => return Ok((hash, block)),
+                Ok((h, ..)) => {
+                    warn!("Unexpected block in firehose stream: expected={height} got={h}");
+                }
+                Err(err) => {
+                    warn!("Firehose stream failed, fallback to single block: height={height} error={err:#}");
+                }
+            }
+            self.fh_stream = None;
+        }
+
+        client.get_block(height)
     }
 
     fn index_block(&mut self, height: u64) -> anyhow::Result<(u64, BlockHash, usize)> {
+        let started = Instant::now();
         let (block_hash, block) = self.fetch_block(height)?;
+        metrics::block_fetched(&self.name, started.elapsed());
 
         debug!(
             "Fetch new block: height={} hash={} tx_count={}",
@@ -307,21 +742,39 @@ impl Rt {
         {
             match self.find_fork_root(block.header.prev_blockhash) {
                 Ok(root) => {
-                    if !self.opts.skip_inputs {
-                        if let Err(err) = self.db.drop_blocks(root.height + 1, &self.indexer.name())
-                        {
-                            anyhow::bail!("[BUG]: can't drop orphans: error={:#?}", err);
+                    let orphans = if !self.opts.skip_inputs {
+                        match self.db.drop_blocks(root.height + 1, &self.indexer.name()) {
+                            Ok(orphans) => orphans,
+                            Err(err) => {
+                                anyhow::bail!("[BUG]: can't drop orphans: error={:#?}", err);
+                            }
                         }
                     } else {
                         // TODO: will be improved and clarifyied in next release
-                        if let Err(err) = self
+                        match self
                             .db
                             .drop_runes_blocks(root.height + 1, &self.indexer.name())
                         {
-                            anyhow::bail!("[BUG]: can't drop orphans: error={:#?}", err);
+                            Ok(orphans) => orphans,
+                            Err(err) => {
+                                anyhow::bail!("[BUG]: can't drop orphans: error={:#?}", err);
+                            }
                         }
+                    };
+
+                    if let Some(notifier) = self.reorg_notifier.as_ref() {
+                        notifier.notify(ReorgEvent {
+                            indexer: self.name.clone(),
+                            fork_height: root.height as u64,
+                            old_tip_hash: self
+                                .last_block
+                                .map(|h| h.to_string())
+                                .unwrap_or_default(),
+                            new_tip_hash: block_hash.to_string(),
+                            depth: orphans.len() as u64,
+                        });
                     }
-                    // TODO: insert orphaned block
+
                     let hash = BlockHash::from(&root.hash);
                     return Ok((root.height as u64, hash, 0));
                 }
@@ -332,16 +785,62 @@ impl Rt {
             }
         }
 
-        for (txi, tx) in block.txdata.iter().enumerate() {
-            let tx_info = TxInfo {
+        let started = Instant::now();
+        let mut txs: Vec<TxInfo> = block
+            .txdata
+            .par_iter()
+            .enumerate()
+            .map(|(txi, tx)| TxInfo {
                 block: height,
                 tx_n: txi as i32,
                 txid: tx.compute_txid(),
                 tx,
                 timestamp: block.header.time as i64,
-            };
+                prepared: None,
+            })
+            .collect();
+        let prepared = self.indexer.prepare_transactions(&txs);
+        debug!(
+            "Prepared block txs: height={} tx_count={} elapsed={:?}",
+            height,
+            txs.len(),
+            started.elapsed()
+        );
 
-            if let Err(err) = self.indexer.index_transaction(&tx_info) {
+        for (txi, tx_info) in txs.iter_mut().enumerate() {
+            tx_info.prepared = prepared.get(txi);
+            if let Err(err) = self.indexer.index_transaction(tx_info) {
+                match err.downcast_ref::<TxIndexerError>() {
+                    Some(TxIndexerError::Recoverable(_))
+                        if self.opts.on_tx_error == config::TxErrorPolicy::SkipAndRecord =>
+                    {
+                        warn!(
+                            "Transaction skipped: block={height} tx_n={} tx={} error={err:#}",
+                            tx_info.tx_n, tx_info.txid
+                        );
+                        if !self.opts.dry_run {
+                            let hash = tx_info.txid.into();
+                            let error = format!("{err:#}");
+                            if let Err(err) = self.db.record_failed_tx(
+                                &self.name,
+                                height as i64,
+                                tx_info.tx_n,
+                                &hash,
+                                &error,
+                            ) {
+                                self.indexer.reset_state();
+                                return Err(err.context("can't record failed tx"));
+                            }
+                        }
+                        continue;
+                    }
+                    Some(TxIndexerError::RetryBlock(_) | TxIndexerError::Halt(_)) => {
+                        // the block is indexed again or not at all, its data must not leak
+                        self.indexer.reset_state();
+                        return Err(err);
+                    }
+                    _ => {}
+                }
                 error!(
                     "[BUG]: can't proceed without data corruption: error={:#?}",
                     err
@@ -349,12 +848,20 @@ impl Rt {
                 anyhow::bail!("[BUG]: can't proceed without data corruption");
             }
         }
+        metrics::block_processed(&self.name, started.elapsed());
 
         if self.opts.dry_run {
             return Ok((height, block_hash, block.txdata.len()));
         }
 
-        if let Err(err) = self.indexer.commit_state() {
+        let started = Instant::now();
+        let block_row = schema::Block {
+            height: height as i64,
+            hash: block_hash.into(),
+            blocktime: block.header.time as i64,
+            indexer: self.name.clone(),
+        };
+        if let Err(err) = self.indexer.commit_state(Some(&block_row)) {
             error!(
                 "[BUG] Can't commit block data error={:#}, hash={}",
                 err, block_hash
@@ -362,26 +869,15 @@ impl Rt {
 
             anyhow::bail!("[BUG] Can't commit block data",);
         }
-
-        let res = self.db.insert_block(
-            height as i64,
-            &block_hash.into(),
-            block.header.time as i64,
-            &self.indexer.name(),
-        );
-        if let Err(err) = res {
-            error!(
-                "[BUG] Can't insert block tip: hash={block_hash}, error={:#}",
-                err
-            );
-            anyhow::bail!("[BUG] Can't insert block tip: hash={block_hash}",);
-        }
+        metrics::block_committed(&self.name, started.elapsed());
 
         Ok((height, block_hash, block.txdata.len()))
     }
 }
 
-pub struct Dummy {}
+pub struct Dummy {
+    db: db::DB,
+}
 
 impl TxIndexer for Dummy {
     fn name(&self) -> String {
@@ -401,9 +897,88 @@ impl TxIndexer for Dummy {
         Ok(())
     }
 
-    fn commit_state(&mut self) -> anyhow::Result<()> {
+    fn commit_state(&mut self, block: Option<&schema::Block>) -> anyhow::Result<()> {
+        if let Some(block) = block {
+            self.db
+                .transaction(|conn| db::DB::commit_block(conn, block))?;
+        }
         Ok(())
     }
 
     fn reset_state(&mut self) {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "requires postgres, set DATABASE_URL"]
+    fn test_second_rt_refuses_to_run() {
+        let db_cfg = config::DBConfig {
+            dsn: std::env::var("DATABASE_URL").expect("DATABASE_URL must be set"),
+            automigrate: false,
+            ..Default::default()
+        };
+        let btc_cfg = config::BTCConfig {
+            address: "http://127.0.0.1:8332".into(),
+            rpc_user: "dev".into(),
+            rpc_password: "dev".into(),
+            ..Default::default()
+        };
+        let opts = IndexingOpts::default();
+
+        let first = Rt::new(&db_cfg, &btc_cfg, opts.clone()).unwrap();
+        let Err(err) = Rt::new(&db_cfg, &btc_cfg, opts.clone()) else {
+            panic!("second indexer must not start while the first one is running");
+        };
+        assert!(format!("{err:#}").contains("already running"));
+
+        // lock is released together with the connection
+        drop(first);
+        assert!(Rt::new(&db_cfg, &btc_cfg, opts).is_ok());
+    }
+
+    #[test]
+    #[ignore = "requires postgres and regtest node, set DATABASE_URL"]
+    fn test_rt_stops_at_height() {
+        use std::str::FromStr;
+
+        let db_cfg = config::DBConfig {
+            dsn: std::env::var("DATABASE_URL").expect("DATABASE_URL must be set"),
+            automigrate: false,
+            ..Default::default()
+        };
+        let btc_cfg = config::BTCConfig {
+            address: "http://127.0.0.1:8332".into(),
+            rpc_user: "dev".into(),
+            rpc_password: "dev".into(),
+            ..Default::default()
+        };
+        let address = bitcoin::Address::from_str("bcrt1q6rz28mcfaxtmd6v789l9rrlrusdprr9pz3cppk")
+            .unwrap()
+            .assume_checked();
+        btc_cfg
+            .rpc_client()
+            .unwrap()
+            .generate_to_address(150, &address)
+            .unwrap();
+
+        // the dummy indexer starts from the genesis
+        let mut db = db::DB::establish_connection(&db_cfg.dsn).unwrap();
+        let name = "dummy-indexer";
+        db.drop_blocks_range(0, i64::MAX, name).unwrap();
+        db.update_last_block(name, 0).unwrap();
+
+        let opts = IndexingOpts {
+            stop_at_height: Some(120),
+            ..Default::default()
+        };
+        let rt = Rt::new(&db_cfg, &btc_cfg, opts).unwrap();
+        let cancel = CancellationToken::new();
+        assert!(rt.run(cancel.clone()));
+        // the run is not waiting for new blocks, the process is shut down
+        assert!(cancel.is_cancelled());
+        assert_eq!(db.get_last_indexed_block(name).unwrap(), 120);
+    }
+}
@@ -1,19 +1,22 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
-use anyhow::Context;
 use bitcoin::hashes::Hash as _;
 use bitcoin::{Address, Transaction, Txid};
-use bitcoincore_rpc::{Auth, Client, RpcApi};
+use bitcoincore_rpc::{Client, RpcApi};
 use orbtc_indexer_api::types::{Amount, Hash};
+use orbtc_indexer_api::RuneEventType;
 use ordinals::{Artifact, Edict, RuneId, Runestone, SpacedRune};
 
 use super::db;
-use super::rt::{TxIndexer, TxInfo};
+use super::lru::LruCache;
+use super::rt::{TxIndexer, TxIndexerError, TxInfo};
 use super::runes_indexer_state::State;
 use crate::config;
 use crate::db::schema;
 
 pub const RUNES_INDEX: &str = "runes_utxo_index";
+/// Number of the commitment txs kept by the runes indexer.
+const COMMITMENT_TXS_CACHE_SIZE: usize = 10_000;
 
 #[derive(Default, Debug, Clone)]
 struct RuneTxsStats {
@@ -32,9 +35,10 @@ pub struct RunesIndexer {
     state: State,
     block_stats: RuneTxsStats,
     skip_inputs: bool,
-
-    #[cfg(feature = "test-tweak")]
-    pub disable_commitment_validation: bool,
+    /// Etchings are valid without the commitment, never set on mainnet.
+    disable_commitment_validation: bool,
+    /// Parents of the etching inputs, the same ones are checked by many etchings.
+    commitment_txs: LruCache<Txid, CommitmentTx>,
 }
 
 impl TxIndexer for RunesIndexer {
@@ -53,11 +57,11 @@ impl TxIndexer for RunesIndexer {
         self._index_transaction(tx_info)
     }
 
-    fn commit_state(&mut self) -> anyhow::Result<()> {
+    fn commit_state(&mut self, block: Option<&schema::Block>) -> anyhow::Result<()> {
         info!("Block stats: {:?}", self.block_stats);
         self.block_stats = RuneTxsStats::default();
 
-        self.state.commit_state(self.skip_inputs)
+        self.state.commit_state(self.skip_inputs, block)
     }
 
     fn reset_state(&mut self) {
@@ -66,35 +70,42 @@ impl TxIndexer for RunesIndexer {
 }
 
 impl RunesIndexer {
-    pub fn new(db_cfg: &config::DBConfig, cfg: &config::BTCConfig, ignore_inputs: bool) -> Self {
-        let db = db::DB::establish_connection(&db_cfg.dsn);
+    pub fn new(
+        db_cfg: &config::DBConfig,
+        cfg: &config::BTCConfig,
+        ignore_inputs: bool,
+        disable_commitment_validation: bool,
+    ) -> anyhow::Result<Self> {
+        let net = cfg.get_network();
+        if disable_commitment_validation && net == bitcoin::Network::Bitcoin {
+            anyhow::bail!("`runes.disable_commitment_validation` can't be used on mainnet");
+        }
+        if disable_commitment_validation {
+            warn!("Rune commitment validation is disabled: network={net}");
+        }
+
+        let rpc = cfg.rpc_client()?;
+        let db = db::DB::establish_connection(&db_cfg.dsn)?;
 
         let service_repo = State::new(db);
-        let net = cfg.get_network();
-        let rpc = Client::new(
-            &cfg.address,
-            Auth::UserPass(cfg.rpc_user.clone(), cfg.rpc_password.clone()),
-        )
-        .unwrap();
 
-        Self {
+        Ok(Self {
             net,
             rpc,
             state: service_repo,
             skip_inputs: ignore_inputs,
 
             block_stats: RuneTxsStats::default(),
-            #[cfg(feature = "test-tweak")]
-            disable_commitment_validation: false,
-        }
+            disable_commitment_validation,
+            commitment_txs: LruCache::new(COMMITMENT_TXS_CACHE_SIZE),
+        })
     }
 
     fn _index_transaction(&mut self, tx_info: &TxInfo) -> anyhow::Result<()> {
         let artifact = Runestone::decipher(tx_info.tx);
 
-        let mut unallocated = self.unallocated(tx_info.tx)?;
-        let mut allocated: Vec<HashMap<RuneId, u128>> =
-            vec![HashMap::new(); tx_info.tx.output.len()];
+        let inputs = self.unallocated(tx_info.tx)?;
+        let mut issuance = Issuance::default();
 
         if let Some(artifact) = &artifact {
             if let Some(id) = artifact.mint() {
@@ -104,94 +115,22 @@ impl RunesIndexer {
                 );
                 if let Some(amount) = self.mint(id, tx_info.block, &tx_info.txid)? {
                     self.block_stats.mints += 1;
-                    *unallocated.entry(id).or_default() += amount;
+                    issuance.mint = Some((id, amount));
+                    self.store_event(tx_info, &id, RuneEventType::Mint, amount);
                 }
             }
 
             let etched = self.etched(tx_info, artifact)?;
-            if let Artifact::Runestone(runestone) = artifact {
-                if let Some((id, ..)) = etched {
-                    *unallocated.entry(id).or_default() +=
-                        runestone.etching.unwrap().premine.unwrap_or_default();
-                }
-
-                for Edict { id, amount, output } in runestone.edicts.iter().copied() {
-                    self.block_stats.edicts += 1;
-                    debug!(
-                        "RUNE edict: block={} tx={} Edict({id}, {amount}, {output})",
-                        tx_info.block, tx_info.tx_n,
-                    );
-
-                    // edicts with output values greater than the number of outputs
-                    // should never be produced by the edict parser
-                    let output = usize::try_from(output).unwrap();
-                    assert!(output <= tx_info.tx.output.len());
-
-                    let id = if id == RuneId::default() {
-                        let Some((id, ..)) = etched else {
-                            continue;
-                        };
-
-                        id
-                    } else {
-                        id
-                    };
-
-                    let Some(balance) = unallocated.get_mut(&id) else {
-                        continue;
-                    };
-
-                    let mut allocate = |balance: &mut u128, amount: u128, output: usize| {
-                        if amount > 0 {
-                            *balance -= amount;
-                            *allocated[output].entry(id).or_default() += amount;
-                        }
-                    };
-
-                    if output == tx_info.tx.output.len() {
-                        // find non-OP_RETURN outputs
-                        let destinations = tx_info
-                            .tx
-                            .output
-                            .iter()
-                            .enumerate()
-                            .filter_map(|(output, tx_out)| {
-                                (!tx_out.script_pubkey.is_op_return()).then_some(output)
-                            })
-                            .collect::<Vec<usize>>();
-
-                        if !destinations.is_empty() {
-                            if amount == 0 {
-                                // if amount is zero, divide balance between eligible outputs
-                                let amount = *balance / destinations.len() as u128;
-                                let remainder =
-                                    usize::try_from(*balance % destinations.len() as u128).unwrap();
-
-                                for (i, output) in destinations.iter().enumerate() {
-                                    allocate(
-                                        balance,
-                                        if i < remainder { amount + 1 } else { amount },
-                                        *output,
-                                    );
-                                }
-                            } else {
-                                // if amount is non-zero, distribute amount to eligible outputs
-                                for output in destinations {
-                                    allocate(balance, amount.min(*balance), output);
-                                }
-                            }
-                        }
-                    } else {
-                        // Get the allocatable amount
-                        let amount = if amount == 0 {
-                            *balance
-                        } else {
-                            amount.min(*balance)
-                        };
-
-                        allocate(balance, amount, output);
-                    }
-                }
+            if let Some((id, ..)) = etched {
+                let premine = match artifact {
+                    Artifact::Runestone(runestone) => runestone
+                        .etching
+                        .and_then(|etching| etching.premine)
+                        .unwrap_or_default(),
+                    Artifact::Cenotaph(_) => 0,
+                };
+                issuance.etching = Some((id, premine));
+                self.store_event(tx_info, &id, RuneEventType::Etch, premine);
             }
 
             // if let Some((id, rune)) = etched {
@@ -199,87 +138,29 @@ impl RunesIndexer {
             // }
         }
 
-        let mut burned: HashMap<RuneId, u128> = HashMap::new();
-
         if let Some(Artifact::Cenotaph(_)) = artifact {
             debug!(
                 "CENOTAPH was made: block={}:{} tx={} ",
                 tx_info.block, tx_info.tx_n, tx_info.txid,
             );
-            for (id, balance) in unallocated {
-                *burned.entry(id).or_default() += balance;
-            }
-        } else {
-            let pointer = artifact
-                .map(|artifact| match artifact {
-                    Artifact::Runestone(runestone) => runestone.pointer,
-                    Artifact::Cenotaph(_) => unreachable!(),
-                })
-                .unwrap_or_default();
-
-            // assign all un-allocated runes to the default output, or the first non
-            // OP_RETURN output if there is no default
-            if let Some(vout) = pointer
-                .map(|pointer| pointer as usize)
-                .inspect(|&pointer| assert!(pointer < allocated.len()))
-                .or_else(|| {
-                    tx_info
-                        .tx
-                        .output
-                        .iter()
-                        .enumerate()
-                        .find(|(_vout, tx_out)| !tx_out.script_pubkey.is_op_return())
-                        .map(|(vout, _tx_out)| vout)
-                })
-            {
-                for (id, balance) in unallocated {
-                    if balance > 0 {
-                        *allocated[vout].entry(id).or_default() += balance;
-                    }
-                }
-            } else {
-                for (id, balance) in unallocated {
-                    if balance > 0 {
-                        *burned.entry(id).or_default() += balance;
-                    }
-                }
-            }
+        }
+
+        let trace = allocate(tx_info.tx, inputs, artifact.as_ref(), issuance);
+        for step in &trace.edicts {
+            self.block_stats.edicts += 1;
+            let Edict { id, amount, output } = step.edict;
+            debug!(
+                "RUNE edict: block={} tx={} Edict({id}, {amount}, {output})",
+                tx_info.block, tx_info.tx_n,
+            );
         }
 
         // update outpoint balances
-        let mut buffer: Vec<u8> = Vec::new();
-        for (vout, balances) in allocated.into_iter().enumerate() {
+        for (vout, balances) in trace.allocated.into_iter().enumerate() {
             if balances.is_empty() {
                 continue;
             }
 
-            // increment burned balances
-            if tx_info.tx.output[vout].script_pubkey.is_op_return() {
-                for (id, balance) in &balances {
-                    *burned.entry(*id).or_default() += *balance;
-                }
-                continue;
-            }
-
-            buffer.clear();
-
-            let mut balances = balances.into_iter().collect::<Vec<(RuneId, u128)>>();
-
-            // Sort balances by id so tests can assert balances in a fixed order
-            balances.sort();
-
-            // let outpoint = OutPoint {
-            //     txid: tx_info.tx.txid(),
-            //     vout: vout.try_into().unwrap(),
-            // };
-
-            // for (id, balance) in balances {
-            //     Index::encode_rune_balance(id, balance.n(), &mut buffer);
-            // }
-
-            // self.outpoint_to_balances
-            //     .insert(&outpoint.store(), buffer.as_slice())?;
-
             let out = &tx_info.tx.output[vout];
 
             let (address_type, address) = match Address::from_script(&out.script_pubkey, self.net) {
@@ -310,6 +191,7 @@ impl RunesIndexer {
                 };
                 self.state.add_address(address_row);
             }
+            // balances are sorted by id
             for (id, balance) in balances {
                 let rune = self.state.get_rune_name_by_id(&id).unwrap();
                 let rune_utxo = schema::RuneUtxo {
@@ -329,21 +211,51 @@ impl RunesIndexer {
             }
         }
 
-        if !burned.is_empty() {
+        if !trace.burned.is_empty() {
             self.block_stats.burned_txs += 1;
         }
         // increment entries with burned runes
-        for (id, amount) in burned {
+        for (id, amount) in trace.burned {
             // *self.burned.entry(id).or_default() += amount;
             self.state.burn_rune_by_id(&id, amount)?;
+            if amount > 0 {
+                self.store_event(tx_info, &id, RuneEventType::Burn, amount);
+            }
         }
 
         Ok(())
     }
 
-    fn unallocated(&mut self, tx: &Transaction) -> anyhow::Result<HashMap<RuneId, u128>> {
+    fn store_event(
+        &mut self,
+        tx_info: &TxInfo,
+        id: &RuneId,
+        event_type: RuneEventType,
+        amount: u128,
+    ) {
+        let Some(rune) = self.state.get_rune_name_by_id(id) else {
+            warn!(
+                "can't record rune event: unknown rune {}:{} tx={}",
+                id.block, id.tx, tx_info.txid
+            );
+            return;
+        };
+
+        self.state.store_rune_event(schema::RuneEvent {
+            id: None,
+            block: tx_info.block as i64,
+            tx_id: tx_info.tx_n,
+            tx_hash: tx_info.txid.into(),
+            rune,
+            rune_id: format!("{}:{}", id.block, id.tx),
+            event_type: event_type.to_string(),
+            amount: Amount(amount),
+        });
+    }
+
+    fn unallocated(&mut self, tx: &Transaction) -> anyhow::Result<BTreeMap<RuneId, u128>> {
         // map of rune ID to un-allocated balance of that rune
-        let mut unallocated: HashMap<RuneId, u128> = HashMap::new();
+        let mut unallocated: BTreeMap<RuneId, u128> = BTreeMap::new();
 
         // increment unallocated runes with the runes in tx inputs
         for input in &tx.input {
@@ -352,15 +264,18 @@ impl RunesIndexer {
             };
 
             for utxo in utxo_list.iter() {
-                let rune_id = self
-                    .state
-                    .get_rune_by_name(&utxo.rune)
-                    .context(format!(
-                        "get rune({}) for the tx({}) input ",
-                        utxo.rune,
-                        tx.compute_txid()
-                    ))?
-                    .rune_id();
+                // the rune of the parent output may be not committed yet
+                let rune_id = match self.state.get_rune_by_name(&utxo.rune) {
+                    Ok(rune) => rune.rune_id(),
+                    Err(err) => {
+                        return Err(TxIndexerError::Recoverable(format!(
+                            "get rune({}) for the tx({}) input: {err:#}",
+                            utxo.rune,
+                            tx.compute_txid()
+                        ))
+                        .into())
+                    }
+                };
 
                 let value = unallocated.entry(rune_id).or_default();
                 *value += utxo.amount.0;
@@ -543,87 +458,380 @@ impl RunesIndexer {
         Ok(Some(amount))
     }
 
-    fn validate_commitment(&self, tx_info: &TxInfo, rune: ordinals::Rune) -> Option<Txid> {
-        #[cfg(feature = "test-tweak")]
+    /// Returns the confirmed taproot input that commits to the `rune`.
+    fn validate_commitment(&mut self, tx_info: &TxInfo, rune: ordinals::Rune) -> Option<Txid> {
         if self.disable_commitment_validation {
             return Some(Txid::all_zeros());
         }
 
-        let commitment = rune.commitment();
+        let mut source = CachedCommitmentTxs {
+            source: &self.rpc,
+            cache: &mut self.commitment_txs,
+        };
+        match find_commitment(&mut source, tx_info.tx, rune, tx_info.block) {
+            Ok(txid) => Some(txid),
+            Err(rejected) => {
+                let failed = rejected
+                    .iter()
+                    .filter(|(_, err)| matches!(err, CommitmentError::Node(_)))
+                    .count();
+                if failed > 0 {
+                    error!(
+                        "Etching is rejected, commitment txs can't be loaded: etching_tx={} failed={} rejected={:?}",
+                        tx_info.txid, failed, rejected,
+                    );
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Parent txs of the etching inputs, the node or a mock in tests.
+pub trait CommitmentTxSource {
+    /// Outputs and the height of the tx, `None` if it's unconfirmed.
+    fn commitment_tx(&mut self, txid: &Txid) -> anyhow::Result<Option<CommitmentTx>>;
+}
 
-        for input in &tx_info.tx.input {
-            // extracting a tapscript does not indicate that the input being spent
-            // was actually a taproot output. this is checked below, when we load the
-            // output's entry from the database
+impl<R: RpcApi> CommitmentTxSource for &R {
+    fn commitment_tx(&mut self, txid: &Txid) -> anyhow::Result<Option<CommitmentTx>> {
+        let info = self.get_raw_transaction_info(txid, None)?;
+        let Some(block_hash) = info.blockhash else {
+            return Ok(None);
+        };
+        let header = self.get_block_header_info(&block_hash)?;
+
+        Ok(Some(CommitmentTx {
+            taproot_outputs: info
+                .vout
+                .iter()
+                .map(|out| out.script_pub_key.script().unwrap_or_default().is_p2tr())
+                .collect(),
+            height: header.height as u64,
+        }))
+    }
+}
+
+/// Keeps the confirmed txs of the `source`, the same ones are checked by many etchings.
+struct CachedCommitmentTxs<'a, S> {
+    source: S,
+    cache: &'a mut LruCache<Txid, CommitmentTx>,
+}
+
+impl<S: CommitmentTxSource> CommitmentTxSource for CachedCommitmentTxs<'_, S> {
+    fn commitment_tx(&mut self, txid: &Txid) -> anyhow::Result<Option<CommitmentTx>> {
+        if let Some(tx) = self.cache.get(txid) {
+            return Ok(Some(tx.clone()));
+        }
+
+        let tx = self.source.commitment_tx(txid)?;
+        // unconfirmed tx can be confirmed later
+        if let Some(tx) = &tx {
+            self.cache.put(*txid, tx.clone());
+        }
+        Ok(tx)
+    }
+}
+
+/// Parent tx of an etching input, a commitment if the spent output is taproot
+/// and the tx is confirmed deep enough.
+#[derive(Debug, Clone)]
+pub struct CommitmentTx {
+    /// `is_p2tr` of each output.
+    pub taproot_outputs: Vec<bool>,
+    /// Height of the block with the tx.
+    pub height: u64,
+}
 
-            #[allow(deprecated)]
-            let Some(tapscript) = input.witness.tapscript() else {
+/// Why the input isn't a valid commitment.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum CommitmentError {
+    #[error("commitment tx is unconfirmed")]
+    Unconfirmed,
+    #[error("spent output is not taproot")]
+    NotTaproot,
+    #[error(
+        "commitment tx has {0} confirmations, {required} required",
+        required = Runestone::COMMIT_CONFIRMATIONS
+    )]
+    Immature(u64),
+    #[error("commitment tx can't be loaded: {0}")]
+    Node(String),
+}
+
+/// Returns the parent tx of the first input that commits to the `rune`
+/// in a taproot output with enough confirmations for an etching at the `height`.
+/// A node error of one candidate doesn't reject the etching while there are others,
+/// otherwise returns the reasons of all candidates, no candidates if it's empty.
+pub fn find_commitment<S: CommitmentTxSource>(
+    source: &mut S,
+    tx: &Transaction,
+    rune: ordinals::Rune,
+    height: u64,
+) -> Result<Txid, Vec<(bitcoin::OutPoint, CommitmentError)>> {
+    let mut rejected = Vec::new();
+    for out_point in commitment_candidates(tx, rune) {
+        let commitment_tx = match source.commitment_tx(&out_point.txid) {
+            Ok(Some(tx)) => tx,
+            Ok(None) => {
+                rejected.push((out_point, CommitmentError::Unconfirmed));
+                continue;
+            }
+            Err(err) => {
+                rejected.push((out_point, CommitmentError::Node(format!("{err:#}"))));
                 continue;
+            }
+        };
+
+        let taproot = commitment_tx
+            .taproot_outputs
+            .get(out_point.vout as usize)
+            .copied()
+            .unwrap_or_default();
+        if !taproot {
+            rejected.push((out_point, CommitmentError::NotTaproot));
+            continue;
+        }
+
+        let confirmations = (height + 1).saturating_sub(commitment_tx.height);
+        if confirmations < Runestone::COMMIT_CONFIRMATIONS as u64 {
+            rejected.push((out_point, CommitmentError::Immature(confirmations)));
+            continue;
+        }
+
+        return Ok(out_point.txid);
+    }
+
+    Err(rejected)
+}
+
+/// Outputs spent by the inputs which tapscript pushes the commitment of the `rune`.
+/// Extracting a tapscript does not indicate that the output being spent
+/// was actually a taproot output, it has to be checked by the caller.
+fn commitment_candidates(tx: &Transaction, rune: ordinals::Rune) -> Vec<bitcoin::OutPoint> {
+    let commitment = rune.commitment();
+    let mut candidates = Vec::new();
+
+    for input in &tx.input {
+        #[allow(deprecated)]
+        let Some(tapscript) = input.witness.tapscript() else {
+            continue;
+        };
+
+        for instruction in tapscript.instructions() {
+            // ignore errors, since the extracted script may not be valid
+            let Ok(instruction) = instruction else {
+                break;
             };
 
-            for instruction in tapscript.instructions() {
-                // ignore errors, since the extracted script may not be valid
-                let Ok(instruction) = instruction else {
-                    break;
-                };
+            let Some(pushbytes) = instruction.push_bytes() else {
+                continue;
+            };
 
-                let Some(pushbytes) = instruction.push_bytes() else {
-                    continue;
-                };
+            if pushbytes.as_bytes() == commitment {
+                candidates.push(input.previous_output);
+                break;
+            }
+        }
+    }
 
-                if pushbytes.as_bytes() != commitment {
-                    continue;
-                }
-                let commitment_tx = input.previous_output.txid;
-                let commitment_tx_info = {
-                    let res = self
-                        .rpc
-                        .get_raw_transaction_info(&input.previous_output.txid, None);
-                    match res {
-                        Ok(info) => info,
-                        Err(err) => {
-                            error!(
-                                "Can't get parent_tx({}) for etching_tx({}) error={:#?}",
-                                input.previous_output.txid, tx_info.txid, err,
-                            );
-                            return None;
-                        }
-                    }
-                };
+    candidates
+}
 
-                let taproot = commitment_tx_info.vout[input.previous_output.vout as usize]
-                    .script_pub_key
-                    .script()
-                    .unwrap_or_default()
-                    .is_p2tr();
+/// Runes issued by the tx itself, they are checked against the indexer state by the caller.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Issuance {
+    /// Rune and amount of a valid mint.
+    pub mint: Option<(RuneId, u128)>,
+    /// Id of a valid etching and its premine.
+    pub etching: Option<(RuneId, u128)>,
+}
 
-                if !taproot {
-                    continue;
-                }
+/// Allocation made by one edict.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EdictAllocation {
+    pub edict: Edict,
+    /// Rune of the edict, the default id refers to the etched rune.
+    /// `None` if the edict refers to an etching that didn't happen.
+    pub id: Option<RuneId>,
+    /// Outputs and amounts, empty if there was nothing to allocate.
+    pub allocations: Vec<(usize, u128)>,
+}
 
-                let commit_tx_height = match self
-                    .rpc
-                    .get_block_header_info(&commitment_tx_info.blockhash.unwrap())
-                {
-                    Ok(bh) => bh.height,
-                    Err(err) => {
-                        error!(
-                            "Can't get block with commitment_tx({}) err={}",
-                            commitment_tx, err
-                        );
-                        return None;
+/// Step by step allocation of runes of a tx.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AllocationTrace {
+    /// Runes of the inputs, the mint and the premine before the edicts.
+    pub unallocated: BTreeMap<RuneId, u128>,
+    pub edicts: Vec<EdictAllocation>,
+    pub cenotaph: bool,
+    pub pointer: Option<u32>,
+    /// Output that got the runes left after the edicts: the pointer
+    /// or the first non OP_RETURN output. `None` if they were burned.
+    pub default_output: Option<usize>,
+    /// Runes left after the edicts.
+    pub remainder: BTreeMap<RuneId, u128>,
+    /// Balances of the outputs, OP_RETURN ones are always empty, their runes are burned.
+    pub allocated: Vec<BTreeMap<RuneId, u128>>,
+    /// Runes of cenotaphs, OP_RETURN outputs, or of txs without non OP_RETURN outputs.
+    pub burned: BTreeMap<RuneId, u128>,
+}
+
+/// Allocates runes of the tx `inputs` and its `issuance` to the tx outputs
+/// by the `artifact`, follows the allocation of the `ord`.
+/// It has no side effects, so it's shared by the indexer and the trace api.
+pub fn allocate(
+    tx: &Transaction,
+    inputs: BTreeMap<RuneId, u128>,
+    artifact: Option<&Artifact>,
+    issuance: Issuance,
+) -> AllocationTrace {
+    let mut unallocated = inputs;
+    let mut allocated: Vec<BTreeMap<RuneId, u128>> = vec![BTreeMap::new(); tx.output.len()];
+    let mut trace = AllocationTrace {
+        cenotaph: matches!(artifact, Some(Artifact::Cenotaph(_))),
+        ..Default::default()
+    };
+
+    if let Some((id, amount)) = issuance.mint {
+        *unallocated.entry(id).or_default() += amount;
+    }
+    let etched = issuance.etching.map(|(id, _)| id);
+    if let Some(Artifact::Runestone(runestone)) = artifact {
+        if let Some((id, premine)) = issuance.etching {
+            *unallocated.entry(id).or_default() += premine;
+        }
+        trace.unallocated = unallocated.clone();
+        trace.pointer = runestone.pointer;
+
+        for edict in runestone.edicts.iter().copied() {
+            // edicts with output values greater than the number of outputs
+            // should never be produced by the edict parser
+            let output = usize::try_from(edict.output).unwrap();
+            assert!(output <= tx.output.len());
+
+            let id = if edict.id == RuneId::default() {
+                etched
+            } else {
+                Some(edict.id)
+            };
+            let mut step = EdictAllocation {
+                edict,
+                id,
+                allocations: Vec::new(),
+            };
+            if let Some(id) = id {
+                if let Some(balance) = unallocated.get_mut(&id) {
+                    step.allocations = allocate_edict(tx, balance, edict.amount, output);
+                    for (output, amount) in &step.allocations {
+                        *allocated[*output].entry(id).or_default() += amount;
                     }
-                };
+                }
+            }
+            trace.edicts.push(step);
+        }
+    } else {
+        trace.unallocated = unallocated.clone();
+    }
+    trace.remainder = unallocated.clone();
+
+    if trace.cenotaph {
+        for (id, balance) in unallocated {
+            *trace.burned.entry(id).or_default() += balance;
+        }
+    } else {
+        // assign all un-allocated runes to the default output, or the first non
+        // OP_RETURN output if there is no default
+        trace.default_output = trace
+            .pointer
+            .map(|pointer| pointer as usize)
+            .inspect(|&pointer| assert!(pointer < allocated.len()))
+            .or_else(|| {
+                tx.output
+                    .iter()
+                    .position(|tx_out| !tx_out.script_pubkey.is_op_return())
+            });
+        for (id, balance) in unallocated {
+            if balance == 0 {
+                continue;
+            }
+            match trace.default_output {
+                Some(vout) => *allocated[vout].entry(id).or_default() += balance,
+                None => *trace.burned.entry(id).or_default() += balance,
+            }
+        }
+    }
+
+    // runes of OP_RETURN outputs are burned
+    for (vout, balances) in allocated.iter_mut().enumerate() {
+        if !balances.is_empty() && tx.output[vout].script_pubkey.is_op_return() {
+            for (id, balance) in std::mem::take(balances) {
+                *trace.burned.entry(id).or_default() += balance;
+            }
+        }
+    }
+    trace.allocated = allocated;
+
+    trace
+}
 
-                let confirmations = tx_info.block - commit_tx_height as u64 + 1;
-                if confirmations >= Runestone::COMMIT_CONFIRMATIONS as u64 {
-                    return Some(commitment_tx);
+/// Takes the edict `amount` from the `balance`, returns outputs and amounts.
+/// `output` equal to the number of outputs splits it between all non OP_RETURN outputs.
+fn allocate_edict(
+    tx: &Transaction,
+    balance: &mut u128,
+    amount: u128,
+    output: usize,
+) -> Vec<(usize, u128)> {
+    let mut allocations = Vec::new();
+    let mut allocate = |balance: &mut u128, amount: u128, output: usize| {
+        if amount > 0 {
+            *balance -= amount;
+            allocations.push((output, amount));
+        }
+    };
+
+    if output == tx.output.len() {
+        // find non-OP_RETURN outputs
+        let destinations = tx
+            .output
+            .iter()
+            .enumerate()
+            .filter_map(|(output, tx_out)| (!tx_out.script_pubkey.is_op_return()).then_some(output))
+            .collect::<Vec<usize>>();
+
+        if !destinations.is_empty() {
+            if amount == 0 {
+                // if amount is zero, divide balance between eligible outputs
+                let amount = *balance / destinations.len() as u128;
+                let remainder = usize::try_from(*balance % destinations.len() as u128).unwrap();
+
+                for (i, output) in destinations.iter().enumerate() {
+                    allocate(
+                        balance,
+                        if i < remainder { amount + 1 } else { amount },
+                        *output,
+                    );
+                }
+            } else {
+                // if amount is non-zero, distribute amount to eligible outputs
+                for output in destinations {
+                    allocate(balance, amount.min(*balance), output);
                 }
             }
         }
+    } else {
+        // Get the allocatable amount
+        let amount = if amount == 0 {
+            *balance
+        } else {
+            amount.min(*balance)
+        };
 
-        None
+        allocate(balance, amount, output);
     }
+
+    allocations
 }
 
 pub struct MintChecker {
@@ -742,3 +950,409 @@ impl std::fmt::Display for MintError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use bitcoin::absolute::LockTime;
+    use bitcoin::hashes::Hash as _;
+    use bitcoin::transaction::Version;
+    use bitcoin::{ScriptBuf, TxOut};
+    use ordinals::{Cenotaph, Flaw};
+
+    use super::*;
+
+    const A: RuneId = RuneId {
+        block: 840_000,
+        tx: 1,
+    };
+    const B: RuneId = RuneId {
+        block: 840_000,
+        tx: 2,
+    };
+    const C: RuneId = RuneId {
+        block: 840_010,
+        tx: 5,
+    };
+
+    #[test]
+    fn test_disabled_commitment_validation_fails_on_mainnet() {
+        // fails before connecting to the node and the db
+        let db_cfg = config::DBConfig {
+            dsn: "postgres://127.0.0.1:1/orbtc".into(),
+            ..Default::default()
+        };
+        let btc_cfg = config::BTCConfig {
+            network: Some("mainnet".into()),
+            ..Default::default()
+        };
+        let Err(err) = RunesIndexer::new(&db_cfg, &btc_cfg, true, true) else {
+            panic!("runes indexer must refuse to start on mainnet without commitment validation");
+        };
+        assert!(err.to_string().contains("mainnet"));
+
+        // unset network is mainnet too
+        let btc_cfg = config::BTCConfig {
+            network: None,
+            ..Default::default()
+        };
+        let Err(err) = RunesIndexer::new(&db_cfg, &btc_cfg, true, true) else {
+            panic!("runes indexer must refuse to start on mainnet without commitment validation");
+        };
+        assert!(err.to_string().contains("mainnet"));
+    }
+
+    /// Input with a script path spend that pushes `push` in the tapscript.
+    fn script_path_input(previous_output: bitcoin::OutPoint, push: Vec<u8>) -> bitcoin::TxIn {
+        let tapscript = ScriptBuf::builder()
+            .push_slice(bitcoin::script::PushBytesBuf::try_from(push).unwrap())
+            .push_opcode(bitcoin::opcodes::all::OP_DROP)
+            .into_script();
+        bitcoin::TxIn {
+            previous_output,
+            witness: bitcoin::Witness::from_slice(&[
+                vec![1; 64],
+                tapscript.into_bytes(),
+                vec![0xc0; 33],
+            ]),
+            ..Default::default()
+        }
+    }
+
+    fn tx_with_inputs(input: Vec<bitcoin::TxIn>) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input,
+            output: vec![],
+        }
+    }
+
+    #[test]
+    fn test_commitment_candidates() {
+        use bitcoin::OutPoint;
+
+        let rune: ordinals::Rune = "AAAAAAAAAAAAAAAAAAAAAAA".parse().unwrap();
+        let out_point = |vout| OutPoint::new(Txid::all_zeros(), vout);
+        let tx = tx_with_inputs(vec![
+            script_path_input(out_point(0), vec![1, 2, 3]),
+            script_path_input(out_point(1), rune.commitment()),
+            // key path spend has no tapscript
+            bitcoin::TxIn {
+                previous_output: out_point(2),
+                witness: bitcoin::Witness::from_slice(&[vec![1; 64]]),
+                ..Default::default()
+            },
+            script_path_input(out_point(3), rune.commitment()),
+        ]);
+
+        let candidates = commitment_candidates(&tx, rune);
+        let vouts: Vec<_> = candidates.iter().map(|o| o.vout).collect();
+        assert_eq!(vouts, [1, 3]);
+    }
+
+    /// Node with the confirmed txs, other txs fail to load.
+    #[derive(Default)]
+    struct MockNode {
+        txs: HashMap<Txid, Option<CommitmentTx>>,
+        calls: usize,
+    }
+
+    impl CommitmentTxSource for MockNode {
+        fn commitment_tx(&mut self, txid: &Txid) -> anyhow::Result<Option<CommitmentTx>> {
+            self.calls += 1;
+            match self.txs.get(txid) {
+                Some(tx) => Ok(tx.clone()),
+                None => anyhow::bail!("node is not responding"),
+            }
+        }
+    }
+
+    fn txid(n: u8) -> Txid {
+        Txid::from_byte_array([n; 32])
+    }
+
+    #[test]
+    fn test_find_commitment() {
+        use bitcoin::OutPoint;
+
+        let rune: ordinals::Rune = "AAAAAAAAAAAAAAAAAAAAAAA".parse().unwrap();
+        let mut node = MockNode::default();
+        let confirmed = |height| {
+            Some(CommitmentTx {
+                taproot_outputs: vec![false, true],
+                height,
+            })
+        };
+        node.txs.insert(txid(2), None);
+        node.txs.insert(txid(3), confirmed(100));
+        node.txs.insert(txid(4), confirmed(95));
+        let etching = |txids: &[(u8, u32)]| {
+            tx_with_inputs(
+                txids
+                    .iter()
+                    .map(|(n, vout)| {
+                        script_path_input(OutPoint::new(txid(*n), *vout), rune.commitment())
+                    })
+                    .collect(),
+            )
+        };
+
+        // the node fails for the first candidate, the second one is valid
+        let tx = etching(&[(1, 1), (4, 1)]);
+        assert_eq!(find_commitment(&mut node, &tx, rune, 100), Ok(txid(4)));
+
+        let tx = etching(&[(1, 1), (2, 1), (3, 0), (3, 1)]);
+        let Err(rejected) = find_commitment(&mut node, &tx, rune, 100) else {
+            panic!("etching without a mature commitment must be rejected");
+        };
+        let reasons: Vec<_> = rejected.into_iter().map(|(_, err)| err).collect();
+        assert!(matches!(reasons[0], CommitmentError::Node(_)));
+        assert_eq!(
+            reasons[1..],
+            [
+                CommitmentError::Unconfirmed,
+                CommitmentError::NotTaproot,
+                CommitmentError::Immature(1),
+            ]
+        );
+
+        // 6 confirmations in the block 105
+        assert_eq!(find_commitment(&mut node, &tx, rune, 105), Ok(txid(3)));
+        assert_eq!(
+            find_commitment(&mut node, &tx_with_inputs(vec![]), rune, 100),
+            Err(vec![])
+        );
+    }
+
+    #[test]
+    fn test_cached_commitment_txs() {
+        let mut node = MockNode::default();
+        node.txs.insert(txid(1), None);
+        node.txs.insert(
+            txid(2),
+            Some(CommitmentTx {
+                taproot_outputs: vec![true],
+                height: 100,
+            }),
+        );
+        let mut cache = LruCache::new(10);
+        let mut source = CachedCommitmentTxs {
+            source: node,
+            cache: &mut cache,
+        };
+        for _ in 0..3 {
+            assert!(source.commitment_tx(&txid(1)).unwrap().is_none());
+            assert!(source.commitment_tx(&txid(2)).unwrap().is_some());
+            assert!(source.commitment_tx(&txid(3)).is_err());
+        }
+        // only the confirmed tx is cached
+        assert_eq!(source.source.calls, 7);
+    }
+
+    fn p2wpkh() -> ScriptBuf {
+        ScriptBuf::from_hex("0014751e76e8199196d454941c45d1b3a323f1433bd6").unwrap()
+    }
+
+    fn op_return() -> ScriptBuf {
+        Runestone::default().encipher()
+    }
+
+    fn tx(outputs: Vec<ScriptBuf>) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: Vec::new(),
+            output: outputs
+                .into_iter()
+                .map(|script_pubkey| TxOut {
+                    value: bitcoin::Amount::from_sat(546),
+                    script_pubkey,
+                })
+                .collect(),
+        }
+    }
+
+    fn runestone(edicts: Vec<Edict>, pointer: Option<u32>) -> Artifact {
+        Artifact::Runestone(Runestone {
+            edicts,
+            pointer,
+            ..Default::default()
+        })
+    }
+
+    fn balances<const N: usize>(entries: [(RuneId, u128); N]) -> BTreeMap<RuneId, u128> {
+        BTreeMap::from(entries)
+    }
+
+    #[test]
+    fn test_allocate_cenotaph() {
+        let tx = tx(vec![p2wpkh(), op_return()]);
+        let artifact = Artifact::Cenotaph(Cenotaph {
+            flaw: Some(Flaw::UnrecognizedEvenTag),
+            mint: Some(B),
+            ..Default::default()
+        });
+        let issuance = Issuance {
+            mint: Some((B, 10)),
+            etching: Some((C, 0)),
+        };
+        let trace = allocate(&tx, balances([(A, 100)]), Some(&artifact), issuance);
+
+        assert!(trace.cenotaph);
+        // minted runes are burned as well
+        assert_eq!(trace.unallocated, balances([(A, 100), (B, 10)]));
+        assert_eq!(trace.burned, balances([(A, 100), (B, 10)]));
+        assert!(trace.edicts.is_empty());
+        assert_eq!(trace.default_output, None);
+        assert!(trace.allocated.iter().all(|b| b.is_empty()));
+    }
+
+    #[test]
+    fn test_allocate_default_output() {
+        let tx = tx(vec![op_return(), p2wpkh(), p2wpkh()]);
+        let artifact = runestone(
+            vec![Edict {
+                id: A,
+                amount: 30,
+                output: 2,
+            }],
+            None,
+        );
+        let inputs = balances([(A, 100), (B, 5)]);
+        let trace = allocate(&tx, inputs.clone(), Some(&artifact), Issuance::default());
+
+        assert_eq!(trace.edicts[0].id, Some(A));
+        assert_eq!(trace.edicts[0].allocations, vec![(2, 30)]);
+        assert_eq!(trace.remainder, balances([(A, 70), (B, 5)]));
+        // no pointer, the first non OP_RETURN output gets the rest
+        assert_eq!(trace.default_output, Some(1));
+        assert_eq!(trace.allocated[1], balances([(A, 70), (B, 5)]));
+        assert_eq!(trace.allocated[2], balances([(A, 30)]));
+        assert!(trace.burned.is_empty());
+
+        // without a runestone everything goes to the first non OP_RETURN output
+        let trace = allocate(&tx, inputs, None, Issuance::default());
+        assert_eq!(trace.default_output, Some(1));
+        assert_eq!(trace.allocated[1], balances([(A, 100), (B, 5)]));
+
+        // without non OP_RETURN outputs runes are burned
+        let tx = self::tx(vec![op_return()]);
+        let trace = allocate(&tx, balances([(A, 100)]), None, Issuance::default());
+        assert_eq!(trace.default_output, None);
+        assert_eq!(trace.burned, balances([(A, 100)]));
+    }
+
+    #[test]
+    fn test_allocate_zero_amount_edicts() {
+        let tx = tx(vec![p2wpkh(), p2wpkh(), p2wpkh(), op_return()]);
+        let artifact = runestone(
+            vec![
+                // the whole balance goes to the output
+                Edict {
+                    id: A,
+                    amount: 0,
+                    output: 1,
+                },
+                // the balance is split between non OP_RETURN outputs
+                Edict {
+                    id: B,
+                    amount: 0,
+                    output: 4,
+                },
+                // nothing is left
+                Edict {
+                    id: A,
+                    amount: 0,
+                    output: 0,
+                },
+            ],
+            Some(2),
+        );
+        let trace = allocate(
+            &tx,
+            balances([(A, 100), (B, 10)]),
+            Some(&artifact),
+            Issuance::default(),
+        );
+
+        assert_eq!(trace.edicts[0].allocations, vec![(1, 100)]);
+        assert_eq!(trace.edicts[1].allocations, vec![(0, 4), (1, 3), (2, 3)]);
+        assert!(trace.edicts[2].allocations.is_empty());
+        assert_eq!(trace.remainder, balances([(A, 0), (B, 0)]));
+        assert_eq!(trace.allocated[0], balances([(B, 4)]));
+        assert_eq!(trace.allocated[1], balances([(A, 100), (B, 3)]));
+        assert_eq!(trace.allocated[2], balances([(B, 3)]));
+        assert!(trace.burned.is_empty());
+    }
+
+    #[test]
+    fn test_allocate_repeated_edicts() {
+        let tx = tx(vec![p2wpkh(), p2wpkh(), op_return()]);
+        let edict = |amount, output| Edict {
+            id: A,
+            amount,
+            output,
+        };
+        let artifact = runestone(vec![edict(30, 0), edict(20, 1), edict(40, 0)], Some(1));
+        let trace = allocate(
+            &tx,
+            balances([(A, 100)]),
+            Some(&artifact),
+            Issuance::default(),
+        );
+
+        let allocations: Vec<_> = trace.edicts.iter().map(|e| e.allocations.clone()).collect();
+        assert_eq!(
+            allocations,
+            vec![vec![(0, 30)], vec![(1, 20)], vec![(0, 40)]]
+        );
+        // one balance per output and rune, so the indexer writes one row for them
+        assert_eq!(trace.allocated[0], balances([(A, 70)]));
+        assert_eq!(trace.allocated[1], balances([(A, 30)]));
+        assert!(trace.allocated[2].is_empty());
+        assert!(trace.burned.is_empty());
+    }
+
+    #[test]
+    fn test_allocate_etching_and_burn() {
+        let tx = tx(vec![p2wpkh(), op_return()]);
+        let artifact = runestone(
+            vec![
+                // the default id refers to the etched rune
+                Edict {
+                    id: RuneId::default(),
+                    amount: 40,
+                    output: 1,
+                },
+                Edict {
+                    id: B,
+                    amount: 10,
+                    output: 0,
+                },
+            ],
+            Some(0),
+        );
+        let issuance = Issuance {
+            mint: None,
+            etching: Some((C, 100)),
+        };
+        let trace = allocate(&tx, BTreeMap::new(), Some(&artifact), issuance);
+
+        assert_eq!(trace.edicts[0].id, Some(C));
+        assert_eq!(trace.edicts[0].allocations, vec![(1, 40)]);
+        // the rune is not in the inputs
+        assert_eq!(trace.edicts[1].id, Some(B));
+        assert!(trace.edicts[1].allocations.is_empty());
+        assert_eq!(trace.allocated[0], balances([(C, 60)]));
+        // runes of OP_RETURN outputs are burned
+        assert!(trace.allocated[1].is_empty());
+        assert_eq!(trace.burned, balances([(C, 40)]));
+
+        // the etching failed
+        let trace = allocate(&tx, BTreeMap::new(), Some(&artifact), Issuance::default());
+        assert_eq!(trace.edicts[0].id, None);
+        assert!(trace.allocated.iter().all(|b| b.is_empty()));
+    }
+}
@@ -1,6 +1,7 @@
+use std::collections::hash_map::Entry;
 use std::collections::{BTreeSet, HashMap, HashSet};
 
-use diesel::Connection;
+use orbtc_indexer_api::types::{Amount, Hash};
 use ordinals::RuneId;
 
 use super::db::*;
@@ -21,6 +22,7 @@ impl State {
                 new_runes: HashMap::with_capacity(10_000),
                 rune_updates: HashMap::with_capacity(10_000),
                 new_utxos: Vec::with_capacity(16_000),
+                new_events: Vec::with_capacity(1_000),
                 new_inputs: Vec::with_capacity(16_000),
                 new_addresses: Vec::with_capacity(10_000),
             },
@@ -123,6 +125,10 @@ impl State {
         self.dataset.new_utxos.push(utxo);
     }
 
+    pub fn store_rune_event(&mut self, event: RuneEvent) {
+        self.dataset.new_events.push(event);
+    }
+
     pub fn get_parent_utxos(&mut self, input: &bitcoin::TxIn) -> Option<BTreeSet<RuneUtxo>> {
         use orbtc_indexer_api::types::Hash;
         let parent_txid: Hash = input.previous_output.txid.into();
@@ -150,7 +156,7 @@ impl State {
         Some(res_list)
     }
 
-    pub fn commit_state(&mut self, skip_inputs: bool) -> anyhow::Result<()> {
+    pub fn commit_state(&mut self, skip_inputs: bool, block: Option<&Block>) -> anyhow::Result<()> {
         info!(
             "Commiting indexer state: new_runes={} upd_runes={} new_utxos={}",
             self.dataset.new_runes.len(),
@@ -158,8 +164,7 @@ impl State {
             self.dataset.new_utxos.len(),
         );
 
-        let conn = &mut self.db.conn;
-        conn.transaction(|conn| {
+        self.db.transaction(|conn| {
             let runes: Vec<Rune> = self.dataset.new_runes.values().cloned().collect();
 
             if let Err(err) = DB::insert_runes(conn, &runes) {
@@ -167,6 +172,15 @@ impl State {
                 return diesel::result::QueryResult::Err(err);
             }
 
+            if let Err(err) = DB::insert_rune_events(conn, &self.dataset.new_events) {
+                error!(
+                    "can't insert rune events: len={} err={}",
+                    self.dataset.new_events.len(),
+                    err
+                );
+                return diesel::result::QueryResult::Err(err);
+            }
+
             for v in self.dataset.rune_updates.values() {
                 DB::update_rune(
                     conn,
@@ -186,21 +200,7 @@ impl State {
                 return diesel::result::QueryResult::Err(err);
             }
 
-            let mut unique = HashSet::new();
-            let mut rows: Vec<RuneUtxo> = Vec::new();
-            for u in self.dataset.new_utxos.iter() {
-                let k = (&u.tx_hash, u.vout, &u.rune);
-
-                if !unique.contains(&k) {
-                    unique.insert(k);
-                    rows.push(u.clone());
-                    continue;
-                }
-
-                if u.amount.0 == 0 {
-                    continue;
-                }
-            }
+            let rows = merge_rune_utxos(&self.dataset.new_utxos);
 
             if let Err(err) = DB::insert_rune_utxos(conn, &rows) {
                 error!(
@@ -221,6 +221,10 @@ impl State {
                 }
             }
 
+            if let Some(block) = block {
+                DB::commit_block(conn, block)?;
+            }
+
             diesel::result::QueryResult::Ok(())
         })?;
 
@@ -232,6 +236,7 @@ impl State {
         self.dataset.new_runes.clear();
         self.dataset.rune_updates.clear();
         self.dataset.new_utxos.clear();
+        self.dataset.new_events.clear();
         self.dataset.new_inputs.clear();
         self.dataset.new_addresses.clear();
 
@@ -241,12 +246,149 @@ impl State {
     }
 }
 
+/// Rows with the same `(tx_hash, vout, rune)` are merged into the first one
+/// with the sum of amounts, so the output keeps every allocation of the rune.
+fn merge_rune_utxos(utxos: &[RuneUtxo]) -> Vec<RuneUtxo> {
+    let mut index: HashMap<(&Hash, i32, &str), usize> = HashMap::new();
+    let mut rows: Vec<RuneUtxo> = Vec::with_capacity(utxos.len());
+    for u in utxos {
+        match index.entry((&u.tx_hash, u.vout, u.rune.as_str())) {
+            Entry::Occupied(entry) => {
+                let row = &mut rows[*entry.get()];
+                row.amount = Amount(row.amount.0 + u.amount.0);
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(rows.len());
+                rows.push(u.clone());
+            }
+        }
+    }
+    rows
+}
+
 #[derive(Default)]
 pub struct BlockData {
     runes_index: HashMap<RuneId, String>,
     new_runes: HashMap<String, Rune>,
     rune_updates: HashMap<String, Rune>,
     new_utxos: Vec<RuneUtxo>,
+    new_events: Vec<RuneEvent>,
     new_inputs: Vec<Input>,
     new_addresses: Vec<Address>,
 }
+
+#[cfg(test)]
+mod tests {
+    use diesel::prelude::*;
+
+    use super::*;
+
+    const INDEXER: &str = "test_runes_commit_indexer";
+    const HEIGHT: i64 = 900_000_100;
+
+    fn fill_dataset(state: &mut State, tx_hash: &Hash) {
+        let rune = Rune {
+            block: HEIGHT,
+            tx_id: 1,
+            rune_id: format!("{HEIGHT}:1"),
+            name: "TESTCOMMITRUNE".into(),
+            ..Default::default()
+        };
+        state.dataset.new_utxos.push(RuneUtxo {
+            block: HEIGHT,
+            tx_id: 1,
+            tx_hash: tx_hash.clone(),
+            vout: 0,
+            rune: rune.name.clone(),
+            rune_id: rune.rune_id.clone(),
+            address: "bc1qtest".into(),
+            amount: Amount(1000),
+            btc_amount: 546,
+            ..Default::default()
+        });
+        state.dataset.new_runes.insert(rune.name.clone(), rune);
+    }
+
+    #[test]
+    fn test_merge_rune_utxos() {
+        let utxo = |tx: &str, vout: i32, rune: &str, amount: u128| RuneUtxo {
+            tx_hash: Hash::sha2(tx),
+            vout,
+            rune: rune.into(),
+            amount: Amount(amount),
+            ..Default::default()
+        };
+        let rows = merge_rune_utxos(&[
+            utxo("a", 0, "X", 100),
+            utxo("a", 1, "X", 7),
+            utxo("a", 0, "Y", 5),
+            utxo("a", 0, "X", 50),
+            utxo("b", 0, "X", 1),
+            utxo("a", 0, "X", 0),
+        ]);
+        assert_eq!(
+            rows,
+            vec![
+                utxo("a", 0, "X", 150),
+                utxo("a", 1, "X", 7),
+                utxo("a", 0, "Y", 5),
+                utxo("b", 0, "X", 1),
+            ]
+        );
+    }
+
+    #[test]
+    #[ignore = "requires postgres, set DATABASE_URL"]
+    fn test_commit_state_crash_recovery() {
+        let dsn = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let mut state = State::new(DB::establish_connection(&dsn).unwrap());
+        state
+            .db
+            .drop_runes_blocks_range(HEIGHT, HEIGHT, INDEXER)
+            .unwrap();
+
+        let tx_hash = Hash::sha2("test_commit_state_crash_recovery");
+        let block = Block {
+            height: HEIGHT,
+            hash: Hash::sha2("block"),
+            blocktime: 0,
+            indexer: INDEXER.into(),
+        };
+
+        // the block row is left by another process, nothing of the block is committed
+        state
+            .db
+            .transaction(|conn| DB::commit_block(conn, &block))
+            .unwrap();
+        fill_dataset(&mut state, &tx_hash);
+        assert!(state.commit_state(true, Some(&block)).is_err());
+        assert!(state
+            .db
+            .select_runes_outputs(&tx_hash, 0)
+            .unwrap()
+            .is_empty());
+
+        // restart after the block is dropped
+        state
+            .db
+            .drop_runes_blocks_range(HEIGHT, HEIGHT, INDEXER)
+            .unwrap();
+        state.commit_state(true, Some(&block)).unwrap();
+        assert_eq!(state.db.select_runes_outputs(&tx_hash, 0).unwrap().len(), 1);
+        assert_eq!(state.db.get_last_indexed_block(INDEXER).unwrap(), HEIGHT);
+
+        // data committed without the block by an older version is not duplicated
+        diesel::sql_query("DELETE FROM blocks WHERE indexer = $1")
+            .bind::<diesel::sql_types::Text, _>(INDEXER)
+            .execute(&mut state.db.conn)
+            .unwrap();
+        fill_dataset(&mut state, &tx_hash);
+        state.commit_state(true, Some(&block)).unwrap();
+        assert_eq!(state.db.select_runes_outputs(&tx_hash, 0).unwrap().len(), 1);
+
+        state
+            .db
+            .drop_runes_blocks_range(HEIGHT, HEIGHT, INDEXER)
+            .unwrap();
+    }
+}
@@ -0,0 +1,78 @@
+use std::thread::sleep;
+use std::time::Duration;
+
+use serde::Serialize;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReorgEvent {
+    pub indexer: String,
+    /// Height of the last block common for both branches.
+    pub fork_height: u64,
+    pub old_tip_hash: String,
+    /// First block of the new branch seen by the indexer.
+    pub new_tip_hash: String,
+    /// Number of orphaned blocks.
+    pub depth: u64,
+}
+
+/// Posts reorg events to the configured url.
+/// Delivery happens in the background thread, so a slow or broken receiver
+/// never blocks indexing, failed events are only logged.
+pub struct ReorgNotifier {
+    url: String,
+}
+
+impl ReorgNotifier {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_owned(),
+        }
+    }
+
+    pub fn notify(&self, event: ReorgEvent) {
+        let url = self.url.clone();
+        std::thread::spawn(move || deliver(&url, &event));
+    }
+}
+
+fn deliver(url: &str, event: &ReorgEvent) {
+    let client = match reqwest::blocking::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+    {
+        Ok(client) => client,
+        Err(err) => {
+            error!("Can't create webhook client: error={err}");
+            return;
+        }
+    };
+
+    for attempt in 1..=MAX_ATTEMPTS {
+        let result = client
+            .post(url)
+            .json(event)
+            .send()
+            .and_then(|resp| resp.error_for_status());
+        match result {
+            Ok(_) => {
+                debug!(
+                    "Reorg webhook delivered: indexer={} fork_height={}",
+                    event.indexer, event.fork_height
+                );
+                return;
+            }
+            Err(err) => {
+                warn!("Reorg webhook failed: attempt={attempt} url={url} error={err}");
+                if attempt < MAX_ATTEMPTS {
+                    sleep(RETRY_DELAY * attempt);
+                }
+            }
+        }
+    }
+
+    error!("Reorg webhook wasn't delivered: event={event:?}");
+}
@@ -1,7 +1,6 @@
 #[macro_use]
 extern crate log;
 
-extern crate env_logger;
 extern crate sentry;
 
 pub mod cache;
@@ -1,8 +1,10 @@
+use std::collections::BTreeMap;
+
 use anyhow::Context;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
-use crate::rest::requests::FeeRate;
+use crate::rest::requests::{FeeRate, FEE_TARGETS};
 
 #[derive(Clone)]
 pub struct MempoolClient {
@@ -36,28 +38,71 @@ impl MempoolClient {
         Ok(result)
     }
 
-    pub async fn get_fee(&self) -> anyhow::Result<FeeRate> {
-        let req = match self.net {
-            bitcoin::Network::Testnet => "https://mempool.space/testnet/api/v1/fees/recommended",
-            bitcoin::Network::Testnet4 => "https://mempool.space/testnet4/api/v1/fees/recommended",
-            bitcoin::Network::Bitcoin => "https://mempool.space/api/v1/fees/recommended",
+    fn base_url(&self) -> anyhow::Result<&'static str> {
+        match self.net {
+            bitcoin::Network::Testnet => Ok("https://mempool.space/testnet/api"),
+            bitcoin::Network::Testnet4 => Ok("https://mempool.space/testnet4/api"),
+            bitcoin::Network::Bitcoin => Ok("https://mempool.space/api"),
             _ => {
                 anyhow::bail!("network({}) is not supported", self.net)
             }
-        };
+        }
+    }
+
+    pub async fn get_fee(&self) -> anyhow::Result<FeeRate> {
+        let base_url = self.base_url()?;
+        let req = format!("{base_url}/v1/fees/recommended");
+        let fee_response = self.request::<FeesRecommended>(&req).await?;
 
-        let fee_response = self.request::<FeesRecommended>(req).await?;
+        // Extended data is optional, the recommended fees are enough to answer.
+        let req = format!("{base_url}/v1/fees/mempool-blocks");
+        let targets = match self.request::<Vec<MempoolBlock>>(&req).await {
+            Ok(blocks) => fee_targets(&blocks, self.fee_adjustment),
+            Err(err) => {
+                warn!("can't fetch mempool blocks: error={err:#}");
+                None
+            }
+        };
+        let req = format!("{base_url}/mempool");
+        let histogram = match self.request::<MempoolInfo>(&req).await {
+            Ok(info) => Some(info.fee_histogram),
+            Err(err) => {
+                warn!("can't fetch mempool fee histogram: error={err:#}");
+                None
+            }
+        };
 
         let fee = FeeRate {
             fast: adjust_fee(fee_response.fastest_fee, self.fee_adjustment),
             normal: adjust_fee(fee_response.half_hour_fee, self.fee_adjustment),
             min: adjust_fee(fee_response.hour_fee, self.fee_adjustment),
+            economy: Some(adjust_fee(fee_response.economy_fee, self.fee_adjustment)),
+            targets,
+            histogram,
         };
 
         Ok(fee)
     }
 }
 
+/// Takes the median fee of the projected block for each confirmation target.
+/// Targets beyond the projection get the fee of the last projected block.
+fn fee_targets(blocks: &[MempoolBlock], adjustment: u64) -> Option<BTreeMap<u16, u64>> {
+    let last = blocks.len().checked_sub(1)?;
+    let targets = FEE_TARGETS
+        .iter()
+        .map(|target| {
+            let block = &blocks[(*target as usize - 1).min(last)];
+            (
+                *target,
+                adjust_fee(block.median_fee.ceil() as u64, adjustment),
+            )
+        })
+        .collect();
+
+    Some(targets)
+}
+
 fn adjust_fee(val: u64, adjustment: u64) -> u64 {
     val + (val * adjustment / 10)
 }
@@ -72,3 +117,36 @@ struct FeesRecommended {
     economy_fee: u64,
     minimum_fee: u64,
 }
+
+// [{"blockSize":1779311,"blockVSize":997968.25,"nTx":3291,"totalFees":8134557,"medianFee":5.02,"feeRange":[4.01, ...]}, ...]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MempoolBlock {
+    median_fee: f64,
+}
+
+// {"count":25134,"vsize":10785421,"total_fee":12345678,"fee_histogram":[[53.4,50166],[41.1,50019], ...]}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct MempoolInfo {
+    fee_histogram: Vec<(f64, u64)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fee_targets() {
+        assert_eq!(fee_targets(&[], 0), None);
+
+        let blocks = [5.1, 4.0, 3.0, 2.5]
+            .into_iter()
+            .map(|median_fee| MempoolBlock { median_fee })
+            .collect::<Vec<_>>();
+        let targets = fee_targets(&blocks, 0).unwrap();
+        assert_eq!(
+            targets,
+            BTreeMap::from([(1, 6), (3, 3), (6, 3), (12, 3), (24, 3)])
+        );
+    }
+}
@@ -68,6 +68,24 @@ impl OrdClient {
     }
 }
 
+/// Max number of outpoints in one `/outputs` request, big txs are split.
+const MAX_OUTPOINTS_PER_REQUEST: usize = 200;
+/// Retries of a failed request, so it's sent up to `MAX_RETRIES + 1` times.
+const MAX_RETRIES: u32 = 3;
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+const POST_TIMEOUT: Duration = Duration::from_secs(60);
+
+fn retry_delay(attempt: u32) -> Duration {
+    RETRY_BASE_DELAY * 2u32.pow(attempt)
+}
+
+/// Ord may be overloaded or restarting, other errors won't go away on retry.
+fn is_retryable(err: &reqwest::Error) -> bool {
+    err.is_timeout()
+        || err.is_connect()
+        || err.status().is_some_and(|status| status.is_server_error())
+}
+
 #[derive(Clone)]
 pub struct OrdClientSync {
     address: String,
@@ -84,19 +102,34 @@ impl OrdClientSync {
         }
     }
 
+    /// Retries 5xx responses, timeouts and connection errors with exponential backoff.
     fn post<T: DeserializeOwned>(&self, path: &str, body: impl Serialize) -> anyhow::Result<T> {
         let url = format!("{}{}", self.address, path);
-        let result = self
-            .client
-            .post(&url)
-            .json(&body)
-            .header("Accept", "application/json")
-            .send()
-            .with_context(|| format!("attempt to POST {} failed", url))?
-            .json::<T>()
-            .with_context(|| format!("unable to parse json response from {}", url))?;
+        let mut attempt = 0;
+        loop {
+            let result = self
+                .client
+                .post(&url)
+                .json(&body)
+                .header("Accept", "application/json")
+                .timeout(POST_TIMEOUT)
+                .send()
+                .and_then(|res| res.error_for_status())
+                .and_then(|res| res.json::<T>());
 
-        Ok(result)
+            match result {
+                Ok(result) => return Ok(result),
+                Err(err) if is_retryable(&err) && attempt < MAX_RETRIES => {
+                    let delay = retry_delay(attempt);
+                    warn!("POST {url} failed, retry in {delay:?}: attempt={attempt} error={err}");
+                    std::thread::sleep(delay);
+                    attempt += 1;
+                }
+                Err(err) => {
+                    return Err(err).with_context(|| format!("attempt to POST {} failed", url))
+                }
+            }
+        }
     }
 
     fn get<T: DeserializeOwned>(&self, path: &str) -> anyhow::Result<T> {
@@ -114,8 +147,13 @@ impl OrdClientSync {
         Ok(result)
     }
 
+    /// Details of the outpoints, requested by chunks of `MAX_OUTPOINTS_PER_REQUEST`.
     pub fn get_details(&self, outpoints: &[String]) -> anyhow::Result<Vec<OutputInfo>> {
-        self.post("/outputs", outpoints)
+        let mut details = Vec::with_capacity(outpoints.len());
+        for chunk in outpoints.chunks(MAX_OUTPOINTS_PER_REQUEST) {
+            details.extend(self.post::<Vec<OutputInfo>>("/outputs", chunk)?);
+        }
+        Ok(details)
     }
 
     pub fn output_details(&self, outpoint: &str) -> anyhow::Result<OutputInfo> {
@@ -125,6 +163,45 @@ impl OrdClientSync {
     pub fn get_inscription(&self, id: u64) -> anyhow::Result<Option<Inscription>> {
         self.get(format!("/inscription/{id}").as_str())
     }
+
+    pub fn status(&self) -> anyhow::Result<Status> {
+        self.get("/status")
+    }
+
+    /// Fails if ord indexes another network than `net`.
+    pub fn check_chain(&self, net: bitcoin::Network) -> anyhow::Result<()> {
+        let status = self.status().context("can't get ord status")?;
+        check_chain(&status, net)
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Status {
+    pub chain: String,
+    #[serde(default)]
+    pub height: Option<u64>,
+}
+
+/// Name of the network in ord, testnet3 is `testnet` there.
+fn chain_name(net: bitcoin::Network) -> &'static str {
+    match net {
+        bitcoin::Network::Bitcoin => "mainnet",
+        bitcoin::Network::Testnet => "testnet",
+        bitcoin::Network::Testnet4 => "testnet4",
+        bitcoin::Network::Signet => "signet",
+        bitcoin::Network::Regtest => "regtest",
+    }
+}
+
+fn check_chain(status: &Status, net: bitcoin::Network) -> anyhow::Result<()> {
+    let expected = chain_name(net);
+    if status.chain != expected {
+        anyhow::bail!(
+            "ord indexes {} but the indexer is configured for {expected}, check the `ord_api.address` and `btc.network` config",
+            status.chain
+        );
+    }
+    Ok(())
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -166,3 +243,117 @@ pub struct Rune {
     // pub divisibility: i64,
     pub symbol: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[test]
+    fn test_check_chain() {
+        let status: Status =
+            serde_json::from_str(r#"{"chain":"regtest","height":110,"initial_sync_time":{}}"#)
+                .unwrap();
+        assert!(check_chain(&status, bitcoin::Network::Regtest).is_ok());
+        let err = check_chain(&status, bitcoin::Network::Bitcoin).unwrap_err();
+        assert!(err.to_string().contains("ord indexes regtest"));
+
+        let status = Status {
+            chain: "testnet".into(),
+            height: None,
+        };
+        assert!(check_chain(&status, bitcoin::Network::Testnet).is_ok());
+        assert!(check_chain(&status, bitcoin::Network::Testnet4).is_err());
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(0), Duration::from_millis(500));
+        assert_eq!(retry_delay(1), Duration::from_secs(1));
+        assert_eq!(retry_delay(2), Duration::from_secs(2));
+    }
+
+    /// Serves `responses` one per connection and records the request bodies.
+    fn serve(responses: Vec<(u16, String)>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let bodies = Arc::new(Mutex::new(Vec::new()));
+        let recorded = bodies.clone();
+        std::thread::spawn(move || {
+            for (status, body) in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim().to_ascii_lowercase();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(len) = line.strip_prefix("content-length:") {
+                        content_length = len.trim().parse().unwrap();
+                    }
+                }
+                let mut request = vec![0; content_length];
+                reader.read_exact(&mut request).unwrap();
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8(request).unwrap());
+
+                let response = format!(
+                    "HTTP/1.1 {status} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (address, bodies)
+    }
+
+    fn outputs(outpoints: &[String]) -> String {
+        let outputs: Vec<_> = outpoints
+            .iter()
+            .map(|outpoint| OutputInfo {
+                outpoint: outpoint.clone(),
+                ..Default::default()
+            })
+            .collect();
+        serde_json::to_string(&outputs).unwrap()
+    }
+
+    #[test]
+    fn test_get_details_chunks_and_retries() {
+        let outpoints: Vec<_> = (0..250).map(|vout| format!("abcd:{vout}")).collect();
+        let (first, second) = outpoints.split_at(MAX_OUTPOINTS_PER_REQUEST);
+        let (address, bodies) = serve(vec![
+            (200, outputs(first)),
+            (502, "bad gateway".into()),
+            (200, outputs(second)),
+        ]);
+
+        let client = OrdClientSync::new(&address);
+        let details = client.get_details(&outpoints).unwrap();
+        let received: Vec<_> = details.into_iter().map(|d| d.outpoint).collect();
+        assert_eq!(received, outpoints);
+
+        let bodies = bodies.lock().unwrap();
+        assert_eq!(bodies.len(), 3);
+        let sent: Vec<String> = serde_json::from_str(&bodies[0]).unwrap();
+        assert_eq!(sent, first);
+        // the failed chunk is sent again
+        assert_eq!(bodies[1], bodies[2]);
+    }
+
+    #[test]
+    fn test_get_details_client_error() {
+        let (address, bodies) = serve(vec![(400, "bad request".into())]);
+        let client = OrdClientSync::new(&address);
+        assert!(client.get_details(&["abcd:0".into()]).is_err());
+        assert_eq!(bodies.lock().unwrap().len(), 1);
+    }
+}
@@ -1,17 +1,27 @@
 use actix_web::middleware::from_fn;
-use actix_web::web::{get, post, resource, scope, Data, Json};
+use actix_web::web::{delete, get, post, resource, scope, Data, Json, ServiceConfig};
 use actix_web::{HttpResponse, Responder, Scope};
 use api_core::server::APIProvider;
 use bitcoin::Network;
 use orbtc_indexer_api::StatusResponse;
 use serde::Serialize;
 use tokio_util::sync::CancellationToken;
+use utoipa::ToSchema;
 
+use super::api_admin::*;
 use super::api_btc::*;
+use super::api_labels::*;
 use super::api_runes::*;
-use super::auth_middleware::ensure_api_key;
-use super::context::{update_metrics, Context};
+use super::api_stream::stream_blocks;
+use super::api_subscriptions::*;
+use super::auth_middleware::{ensure_admin_key, ensure_api_key};
+use super::context::{refresh_api_keys, update_metrics, Context};
+use super::http_cache::no_store_by_default;
+use super::rate_limit::rate_limit;
+use super::swagger::NetworkPath;
 use super::{mempool_cache, swagger};
+use crate::db::scopes;
+use crate::service::webhooks;
 
 #[derive(Clone)]
 pub struct Service {
@@ -33,12 +43,17 @@ impl Service {
         ));
         tokio::spawn(update_metrics(
             self.context.metrics_collector.clone(),
-            cancel,
+            cancel.clone(),
+        ));
+        tokio::spawn(webhooks::delivery_routine(
+            self.context.db.clone(),
+            cancel.clone(),
         ));
+        tokio::spawn(refresh_api_keys(self.context.clone(), cancel));
     }
 }
 
-const fn net_as_str(net: Network) -> &'static str {
+pub(crate) const fn net_as_str(net: Network) -> &'static str {
     match net {
         Network::Bitcoin => "mainnet",
         Network::Testnet4 => "testnet4",
@@ -48,6 +63,88 @@ const fn net_as_str(net: Network) -> &'static str {
     }
 }
 
+/// Declares the resources of a scope once: as the `ServiceConfig` function that registers
+/// them and as the `(path, [(method, scope)])` list, which the openapi spec is checked against.
+/// The scope after the handler is what the api key must have to call the route,
+/// mutating network routes without it are rejected by [`ensure_api_key`].
+macro_rules! routes {
+    (@scope) => { None };
+    (@scope $scope:ident) => { Some(scopes::$scope) };
+    ($configure:ident, $list:ident, { $($path:literal => $($method:ident($handler:expr) $(: $scope:ident)?),+;)* }) => {
+        pub const $list: &[(&str, &[(&str, Option<&str>)])] =
+            &[$(($path, &[$((stringify!($method), routes!(@scope $($scope)?))),+])),*];
+
+        fn $configure(cfg: &mut ServiceConfig) {
+            $(cfg.service(resource($path)$(.route($method().to($handler)))+);)*
+        }
+    };
+}
+
+routes!(system_routes, SYSTEM_ROUTES, {
+    "/healthcheck" => get(healthcheck);
+    "/version" => get(version);
+    "/swagger" => get(swagger::ui);
+    "/swagger/swagger.yaml" => get(swagger::spec);
+    "/swagger/openapi.json" => get(swagger::openapi_json);
+});
+
+routes!(admin_routes, ADMIN_ROUTES, {
+    "/api-keys" => get(list_api_keys): ADMIN, post(create_api_key): ADMIN;
+    "/api-keys/{name}" => delete(delete_api_key): ADMIN;
+    "/api-keys/{name}/{action}" => post(update_api_key): ADMIN;
+});
+
+routes!(network_routes, NETWORK_ROUTES, {
+    "/status" => get(service_status): READ;
+    "/blocks" => get(list_blocks): READ;
+    // before `/blocks/{height}`, so `stats` isn't taken for a height
+    "/blocks/stats" => get(list_block_stats): READ;
+    "/blocks/{height}" => get(get_block): READ;
+    "/blocks/{height}/stats" => get(get_block_stats): READ;
+    "/stream/blocks" => get(stream_blocks): READ;
+    "/utxos/unlock" => post(unlock_utxos): LOCK, delete(unlock_utxos): LOCK;
+    "/utxos/{address}" => get(list_utxos): READ, post(list_utxos_with_lock): LOCK;
+    "/utxos/{address}/stats" => get(get_utxo_stats): READ;
+    "/utxos/{address}/consolidation-plan" => get(get_consolidation_plan): READ;
+    "/utxos/{address}/locks" => get(list_utxo_locks): LOCK;
+    "/outputs/{address}" => get(list_address_outputs): READ;
+    "/balance/{address}" => get(get_balance): READ;
+    "/balances" => post(get_balances): READ;
+    "/address/{address}/summary" => get(get_address_summary): READ;
+    "/balance-history/{address}" => get(get_balance_history): READ;
+    "/fee-rate" => get(btc_fee_rate): READ;
+    "/runes" => get(list_runes): READ;
+    "/runes/search" => get(list_runes): READ;
+    "/runes/stats" => get(list_runes_stats): READ;
+    "/runes/balances" => post(list_runes_balances_multi): READ;
+    "/runes/decode" => post(decode_runestone): READ;
+    "/runes/validate-etching" => post(validate_etching): BROADCAST;
+    "/runes/trace/{txid}" => get(trace_rune_allocation): READ;
+    "/runes/{rune}" => get(get_rune): READ;
+    "/runes/{rune}/details" => get(get_rune_details): READ;
+    "/runes/{rune}/utxos/{address}" => get(list_rune_utxos): READ, post(list_rune_utxos_with_lock): LOCK;
+    "/runes/{rune}/balance" => get(list_rune_holders): READ;
+    "/runes/{rune}/activity" => get(list_rune_activity): READ;
+    "/runes/{rune}/events" => get(list_rune_events): READ;
+    "/runes/{rune}/psbt/build" => post(build_rune_psbt): LOCK;
+    "/runes/{rune}/balance/{address}" => get(get_rune_balance): READ;
+    "/runes/{rune}/balance-history/{address}" => get(get_rune_balance_history): READ;
+    "/runes/balance/{address}" => get(list_runes_balances): READ, post(list_filtered_runes_balances): READ;
+    "/txs/address/{address}" => get(list_address_txs): READ;
+    "/tx" => post(send_raw_transaction): BROADCAST;
+    "/psbt/build" => post(build_btc_psbt): LOCK;
+    "/tx/test" => post(test_raw_transaction): BROADCAST;
+    "/tx/{txid}" => get(get_transaction): READ;
+    "/tx/{txid}/ins-outs" => get(get_tx_in_outs): READ;
+    "/tx/{txid}/ins-outs/runes" => get(get_tx_runes_utxos): READ;
+    "/subscriptions" => get(list_subscriptions): SUBSCRIPTIONS, post(create_subscription): SUBSCRIPTIONS;
+    "/subscriptions/{id}" => delete(delete_subscription): SUBSCRIPTIONS;
+    "/labels" => get(list_labels): LABELS, post(create_label): LABELS;
+    "/labels/{address}/{label}" => delete(delete_label): LABELS;
+    "/mempool/tx-list" => get(get_txs_in_mempool): READ;
+    "/mempool/address/{address}" => get(get_address_mempool_txs): READ;
+});
+
 impl APIProvider for Service {
     fn name(&self) -> &'static str {
         "orbtc_api"
@@ -59,60 +156,31 @@ impl APIProvider for Service {
 
         scope("/v1")
             .app_data(Data::new(self.context.clone()))
-            .service(resource("/healthcheck").route(get().to(healthcheck)))
-            .service(resource("/version").route(get().to(version)))
-            .service(resource("/swagger").route(get().to(swagger::ui)))
-            .service(resource("/swagger/swagger.yaml").route(get().to(swagger::spec)))
+            .configure(system_routes)
+            .service(
+                scope("/admin")
+                    .wrap(from_fn(ensure_admin_key))
+                    .wrap(from_fn(rate_limit))
+                    .configure(admin_routes),
+            )
             .service(
                 scope(&format!("/{}", net))
                     .wrap(from_fn(ensure_api_key))
-                    .service(resource("/status").route(get().to(service_status)))
-                    .service(
-                        resource("/utxos/{address}")
-                            .route(get().to(list_utxos))
-                            .route(post().to(list_utxos_with_lock)),
-                    )
-                    .service(resource("/balance/{address}").route(get().to(get_balance)))
-                    .service(
-                        resource("/balance-history/{address}").route(get().to(get_balance_history)),
-                    )
-                    .service(resource("/balance/{address}").route(get().to(get_balance)))
-                    .service(resource("/fee-rate").route(get().to(btc_fee_rate)))
-                    .service(resource("/runes").route(get().to(list_runes)))
-                    .service(resource("/runes/search").route(get().to(list_runes)))
-                    .service(resource("/runes/{rune}").route(get().to(get_rune)))
-                    .service(
-                        resource("/runes/{rune}/utxos/{address}")
-                            .route(get().to(list_rune_utxos))
-                            .route(post().to(list_rune_utxos_with_lock)),
-                    )
-                    .service(resource("/runes/{rune}/balance").route(get().to(list_rune_holders)))
-                    .service(
-                        resource("/runes/{rune}/balance/{address}")
-                            .route(get().to(get_rune_balance)),
-                    )
-                    .service(
-                        resource("/runes/{rune}/balance-history/{address}")
-                            .route(get().to(get_rune_balance_history)),
-                    )
-                    .service(
-                        resource("/runes/balance/{address}")
-                            .route(get().to(list_runes_balances))
-                            .route(post().to(list_filtered_runes_balances)),
-                    )
-                    .service(resource("/txs/address/{address}").route(get().to(list_address_txs)))
-                    .service(resource("/tx").route(post().to(send_raw_transaction)))
-                    .service(resource("/tx/{txid}").route(get().to(get_transaction)))
-                    .service(resource("/tx/{txid}/ins-outs").route(get().to(get_tx_in_outs)))
-                    .service(
-                        resource("/tx/{txid}/ins-outs/runes").route(get().to(get_tx_runes_utxos)),
-                    )
-                    .service(resource("/mempool/tx-list").route(get().to(get_txs_in_mempool))),
+                    .wrap(from_fn(rate_limit))
+                    .wrap(from_fn(no_store_by_default))
+                    .configure(network_routes),
             )
     }
 }
 
-async fn healthcheck(state: Data<Context>) -> impl Responder {
+#[utoipa::path(
+    get,
+    path = "/v1/healthcheck",
+    tag = "system",
+    security(()),
+    responses((status = 200, description = "Service is healthy"), (status = 503, description = "Service is unhealthy"))
+)]
+pub(crate) async fn healthcheck(state: Data<Context>) -> impl Responder {
     let status = state.metrics_collector.service_status().await;
     if status.healthy {
         HttpResponse::Ok().finish()
@@ -121,17 +189,31 @@ async fn healthcheck(state: Data<Context>) -> impl Responder {
     }
 }
 
-async fn version() -> impl Responder {
+#[utoipa::path(
+    get,
+    path = "/v1/version",
+    tag = "system",
+    security(()),
+    responses((status = 200, body = AppInfo))
+)]
+pub(crate) async fn version() -> impl Responder {
     let info = get_app_info();
     HttpResponse::Ok().json(info)
 }
 
-async fn service_status(state: Data<Context>) -> Json<StatusResponse> {
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/status",
+    tag = "system",
+    params(NetworkPath),
+    responses((status = 200, body = StatusResponse))
+)]
+pub(crate) async fn service_status(state: Data<Context>) -> Json<StatusResponse> {
     let status = state.metrics_collector.service_status().await;
     Json(status)
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct AppInfo {
     pub app: &'static str,
     pub version: &'static str,
@@ -0,0 +1,196 @@
+use actix_web::web::{Data, Json, Path};
+use actix_web::HttpResponse;
+use orbtc_indexer_api::FBtcApiError;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::context::Context;
+use crate::db::{scopes, ApiKey};
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    #[serde(default)]
+    pub can_lock_utxo: bool,
+    #[serde(default)]
+    pub is_admin: bool,
+    /// Overrides the default rate limit, 0 disables it.
+    #[serde(default)]
+    pub rate_limit_per_minute: Option<i32>,
+    /// `read` and `broadcast` if not set.
+    #[serde(default)]
+    pub scopes: Option<Vec<String>>,
+    /// Any network if empty.
+    #[serde(default)]
+    pub networks: Vec<String>,
+    /// The key never expires if not set.
+    #[serde(default)]
+    pub expires_in_days: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiKeyAction {
+    Block,
+    Unblock,
+    /// Generates a new key value, the old one stops working.
+    Rotate,
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/admin/api-keys",
+    tag = "admin",
+    responses((status = 200, body = Vec<ApiKey>))
+)]
+pub async fn list_api_keys(state: Data<Context>) -> Result<Json<Vec<ApiKey>>, FBtcApiError> {
+    match state.db.select_api_keys(true).await {
+        Ok(keys) => Ok(Json(keys)),
+        Err(err) => {
+            error!("can't fetch api keys: error={:#?}", err);
+            Err(FBtcApiError::InternalError)
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/admin/api-keys",
+    tag = "admin",
+    request_body = CreateApiKeyRequest,
+    responses((status = 200, body = ApiKey))
+)]
+pub async fn create_api_key(
+    state: Data<Context>,
+    request: Json<CreateApiKeyRequest>,
+) -> Result<Json<ApiKey>, FBtcApiError> {
+    let name = request.name.trim();
+    if name.is_empty() {
+        return Err(FBtcApiError::BadInput("name can't be empty".into()));
+    }
+
+    if request.expires_in_days == Some(0) {
+        return Err(FBtcApiError::BadInput(
+            "expires_in_days must be positive".into(),
+        ));
+    }
+
+    if let Some(scope) = request
+        .scopes
+        .iter()
+        .flatten()
+        .find(|s| !scopes::is_valid(s))
+    {
+        return Err(FBtcApiError::BadInput(format!(
+            "invalid scope={scope}, allowed: {}",
+            scopes::ALL.join(", ")
+        )));
+    }
+
+    match state.db.get_api_key_by_name(name).await {
+        Ok(None) => {}
+        Ok(Some(_)) => {
+            return Err(FBtcApiError::BadInput(format!(
+                "api key with name={name} already exists"
+            )));
+        }
+        Err(err) => {
+            error!("can't fetch api key: name={name} error={:#?}", err);
+            return Err(FBtcApiError::InternalError);
+        }
+    }
+
+    let mut row = ApiKey {
+        can_lock_utxo: request.can_lock_utxo,
+        is_admin: request.is_admin,
+        rate_limit_per_minute: request.rate_limit_per_minute,
+        networks: request.networks.clone(),
+        expires_at: request.expires_in_days.map(ApiKey::expiry_in_days),
+        ..ApiKey::new(name)
+    };
+    if let Some(scopes) = request.scopes.as_ref() {
+        row.scopes = scopes.clone();
+    }
+    if let Err(err) = state.db.insert_api_key(row.clone()).await {
+        error!("can't insert api key: name={name} error={:#?}", err);
+        return Err(FBtcApiError::InternalError);
+    }
+
+    info!("api key was created: name={name} is_admin={}", row.is_admin);
+    reload_api_keys(&state).await;
+    Ok(Json(row))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/admin/api-keys/{name}",
+    tag = "admin",
+    params(("name" = String, Path)),
+    responses((status = 200, description = "Key is deleted"))
+)]
+pub async fn delete_api_key(
+    state: Data<Context>,
+    name: Path<String>,
+) -> Result<HttpResponse, FBtcApiError> {
+    match state.db.delete_api_key(&name).await {
+        Ok(true) => {}
+        Ok(false) => return Err(FBtcApiError::NotFound),
+        Err(err) => {
+            error!("can't delete api key: name={name} error={:#?}", err);
+            return Err(FBtcApiError::InternalError);
+        }
+    }
+
+    info!("api key was deleted: name={name}");
+    reload_api_keys(&state).await;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/admin/api-keys/{name}/{action}",
+    tag = "admin",
+    params(("name" = String, Path), ("action" = ApiKeyAction, Path)),
+    responses((status = 200, body = ApiKey))
+)]
+pub async fn update_api_key(
+    state: Data<Context>,
+    params: Path<(String, ApiKeyAction)>,
+) -> Result<Json<ApiKey>, FBtcApiError> {
+    let (name, action) = params.into_inner();
+    let mut row = match state.db.get_api_key_by_name(&name).await {
+        Ok(Some(row)) => row,
+        Ok(None) => return Err(FBtcApiError::NotFound),
+        Err(err) => {
+            error!("can't fetch api key: name={name} error={:#?}", err);
+            return Err(FBtcApiError::InternalError);
+        }
+    };
+
+    match action {
+        ApiKeyAction::Block => row.blocked = true,
+        ApiKeyAction::Unblock => row.blocked = false,
+        ApiKeyAction::Rotate => row.key = ApiKey::generate_key(),
+    }
+
+    match state.db.update_api_key(&row).await {
+        Ok(true) => {}
+        // was deleted in the meantime
+        Ok(false) => return Err(FBtcApiError::NotFound),
+        Err(err) => {
+            error!("can't update api key: name={name} error={:#?}", err);
+            return Err(FBtcApiError::InternalError);
+        }
+    }
+
+    info!("api key was updated: name={name} action={action:?}");
+    reload_api_keys(&state).await;
+    Ok(Json(row))
+}
+
+/// Changes must take effect right away, not on the next periodic refresh.
+async fn reload_api_keys(state: &Context) {
+    if let Err(err) = state.reload_api_keys().await {
+        error!("can't reload api keys: error={:#}", err);
+    }
+}
@@ -1,121 +1,511 @@
 use std::collections::BTreeMap;
-use std::collections::BTreeSet;
+use std::ops::Range;
 use std::str::FromStr;
 
-use actix_web::web::{self, Data, Json, Path, Query};
-use api_core::pages::{ListResponseMeta, ListResult};
+use actix_web::web::{self, Data, Json, Path, Query, ReqData};
+use api_core::pages::{ListResponseMeta, ListResult, OrderBy, PageParams};
+use bitcoin::TxOut;
 use bitcoincore_rpc::RpcApi;
+use futures::stream::{BoxStream, TryStreamExt};
 use orbtc_indexer_api::btc::*;
-use orbtc_indexer_api::{types, OrderBy, UtxoSortMode};
+use orbtc_indexer_api::{
+    types, AmountRange, BalanceHistoryQuery, BlockDetails, BlockInfo, BlockStats, BlockStatsQuery,
+    HistoryGranularity, IndexedBlock, ListBlocksQuery, UtxoSortMode,
+};
 use serde::Deserialize;
-
-use super::context::Context;
-use super::requests::{decode_address, FeeRate};
-use crate::service::utxo_collector::{min_utxos_to_reach_target, KnapsackError};
-
-#[derive(Deserialize)]
+use tracing::error;
+use utoipa::IntoParams;
+
+use super::context::{Context, HealthGate, MAX_LOCK_ATTEMPTS};
+use super::http_cache::{CachePolicy, CachedJson};
+use super::requests::{decode_address, parse_cursor, FeeRate, FeeRateQuery};
+use super::swagger::NetworkPath;
+use crate::db::{is_timeout, scopes, ApiKey};
+use crate::service::psbt::{self, PsbtError};
+use crate::service::utxo_collector::{CollectorError, FilterOpts};
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Path)]
 pub struct GetBalanceParams {
     pub address: String,
 }
 
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/balance/{address}",
+    tag = "btc",
+    params(NetworkPath, GetBalanceParams, BalanceQuery),
+    responses((status = 200, body = Balance))
+)]
 pub async fn get_balance(
     state: Data<Context>,
     params: Path<GetBalanceParams>,
+    query: Query<BalanceQuery>,
+    api_key: ReqData<ApiKey>,
 ) -> Result<Json<Balance>, FBtcApiError> {
-    if !state.is_healthy().await {
+    if !state.is_ready(HealthGate::DbOnly).await {
         return Err(FBtcApiError::ServiceUnavailable);
     }
-
-    if let Err(err) = decode_address(&params.address, state.net) {
-        return Err(FBtcApiError::InvalidAddress(format!("{err}")));
+    if query.include_labels && !api_key.has_scope(scopes::LABELS) {
+        return Err(FBtcApiError::Forbidden);
     }
 
-    match state.db.get_balance(&params.address).await {
-        Ok(balance) => Ok(Json(balance)),
+    let address = match decode_address(&params.address, state.net) {
+        Ok(address) => address,
+        Err(err) => {
+            return Err(FBtcApiError::InvalidAddress(format!("{err}")));
+        }
+    };
 
+    let mut balance = match state
+        .db
+        .get_balance_with_labels(&params.address, query.include_labels)
+        .await
+    {
+        Ok(balance) => balance,
         Err(err) => {
             error!(
                 "can't fetch btc balance: address={} error={:#?}",
                 params.address, err
             );
-            Err(FBtcApiError::InternalError)
+            return Err(FBtcApiError::InternalError);
+        }
+    };
+    if query.detailed {
+        add_spendable_balance(&state, &mut balance).await?;
+    }
+    if !query.include_mempool {
+        return Ok(Json(balance));
+    }
+
+    let utxos = match state.db.select_address_short_utxos(&params.address).await {
+        Ok(utxos) => utxos,
+        Err(err) => {
+            error!(
+                "can't fetch btc utxos: address={} error={:#?}",
+                params.address, err
+            );
+            return Err(FBtcApiError::InternalError);
+        }
+    };
+    let confirmed: Vec<_> = utxos
+        .iter()
+        .map(|u| {
+            let out = bitcoin::OutPoint {
+                txid: (&u.tx_hash).into(),
+                vout: u.vout as u32,
+            };
+            (out, u.amount as u64)
+        })
+        .collect();
+
+    let pending = state
+        .mempool_index
+        .pending_amounts(&address.script_pubkey(), &confirmed)
+        .await;
+    balance.pending_in = Some(pending.incoming as i64);
+    balance.pending_out = Some(pending.outgoing as i64);
+    balance.pending_balance =
+        Some(balance.balance + pending.incoming as i64 - pending.outgoing as i64);
+
+    Ok(Json(balance))
+}
+
+/// Splits the balance into the spendable part and the breakdown of the rest.
+async fn add_spendable_balance(state: &Context, balance: &mut Balance) -> Result<(), FBtcApiError> {
+    let Some(maturity_height) = state.coinbase_maturity_cutoff().await else {
+        error!(
+            "can't get coinbase maturity height: address={}",
+            balance.address
+        );
+        return Err(FBtcApiError::InternalError);
+    };
+
+    let address = &balance.address;
+    let spendable = match state
+        .db
+        .get_spendable_balance(address, maturity_height)
+        .await
+    {
+        Ok(spendable) => spendable,
+        Err(err) => {
+            error!(
+                "can't fetch spendable balance: address={address} error={:#?}",
+                err
+            );
+            return Err(FBtcApiError::InternalError);
+        }
+    };
+
+    let mut locked = 0;
+    if state.cache.is_some() {
+        let utxos = match state
+            .db
+            .select_spendable_short_utxos(address, maturity_height)
+            .await
+        {
+            Ok(utxos) => utxos,
+            Err(err) => {
+                error!(
+                    "can't fetch spendable utxos: address={address} error={:#?}",
+                    err
+                );
+                return Err(FBtcApiError::InternalError);
+            }
+        };
+        // locks are informational, the cache errors don't fail the balance
+        locked = match state.locked_amount(&utxos).await {
+            Ok(amount) => amount,
+            Err(err) => {
+                error!("can't check utxo locks: address={address} error={:#}", err);
+                0
+            }
+        };
+    }
+
+    balance.spendable_balance = Some(spendable.spendable - locked);
+    balance.unspendable_breakdown = Some(UnspendableBalance {
+        immature: spendable.immature,
+        with_runes: spendable.with_runes,
+        with_inscriptions: spendable.with_inscriptions,
+        locked,
+    });
+    Ok(())
+}
+
+const MAX_BALANCES_BATCH: usize = 500;
+
+#[utoipa::path(
+    post,
+    path = "/v1/{network}/balances",
+    tag = "btc",
+    params(NetworkPath),
+    request_body = BalancesRequest,
+    responses((status = 200, body = BalancesResponse))
+)]
+pub async fn get_balances(
+    state: Data<Context>,
+    req: Json<BalancesRequest>,
+) -> Result<Json<BalancesResponse>, FBtcApiError> {
+    if !state.is_ready(HealthGate::DbOnly).await {
+        return Err(FBtcApiError::ServiceUnavailable);
+    }
+
+    if req.addresses.len() > MAX_BALANCES_BATCH {
+        return Err(FBtcApiError::BadInput(format!(
+            "too many addresses({}), max allowed({MAX_BALANCES_BATCH})",
+            req.addresses.len()
+        )));
+    }
+
+    let mut addresses = Vec::new();
+    let mut errors = Vec::new();
+    for address in req.addresses.iter() {
+        if addresses.contains(address) {
+            continue;
+        }
+        match decode_address(address, state.net) {
+            Ok(_) => addresses.push(address.clone()),
+            Err(err) => errors.push(AddressError {
+                address: address.clone(),
+                error: format!("{err}"),
+            }),
         }
     }
+
+    let rows = match state.db.get_balances(&addresses).await {
+        Ok(rows) => rows,
+        Err(err) => {
+            error!(
+                "can't fetch btc balances: addresses={} error={:#?}",
+                addresses.len(),
+                err
+            );
+            return Err(FBtcApiError::InternalError);
+        }
+    };
+
+    let mut rows: BTreeMap<String, Balance> =
+        rows.into_iter().map(|b| (b.address.clone(), b)).collect();
+    let balances = addresses
+        .into_iter()
+        .map(|address| {
+            rows.remove(&address).unwrap_or(Balance {
+                address,
+                ..Default::default()
+            })
+        })
+        .collect();
+
+    Ok(Json(BalancesResponse { balances, errors }))
 }
 
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/balance-history/{address}",
+    tag = "btc",
+    params(NetworkPath, GetBalanceParams, BalanceHistoryQuery),
+    responses((status = 200, body = ListResult<BtcBalanceHistoryPoint>), (status = 410, description = "Queried heights are pruned by `db prune`"), (status = 504, description = "Query timeout"))
+)]
 pub async fn get_balance_history(
     state: Data<Context>,
     params: Path<GetBalanceParams>,
-) -> Result<Json<Vec<BtcBalanceHistoryPoint>>, FBtcApiError> {
-    if !state.is_healthy().await {
+    query: Query<BalanceHistoryQuery>,
+) -> Result<Json<ListResult<BtcBalanceHistoryPoint>>, FBtcApiError> {
+    if !state.is_ready(HealthGate::DbOnly).await {
         return Err(FBtcApiError::ServiceUnavailable);
     }
 
     if let Err(err) = decode_address(&params.address, state.net) {
         return Err(FBtcApiError::InvalidAddress(format!("{err}")));
     }
-    let outputs = match state.db.select_tx_outputs_sum(&params.address).await {
-        Ok(o) => o,
+    let (from_block, to_block) = match query.range() {
+        Ok(v) => v,
+        Err(err) => {
+            return Err(FBtcApiError::BadInput(format!("{err}")));
+        }
+    };
+    ensure_not_pruned(&state, from_block).await?;
+    let (limit, offset) = match query.limit_offset() {
+        Ok(v) => v,
+        Err(err) => {
+            return Err(FBtcApiError::BadInput(format!("{err}")));
+        }
+    };
+    let bucket_size = query.granularity.blocks();
+
+    let count = match state
+        .db
+        .count_history_buckets(&params.address, from_block, to_block, bucket_size)
+        .await
+    {
+        Ok(count) => count as u64,
         Err(err) => {
             error!(
-                "can't fetch btc outputs: address={} error={:#?}",
+                "can't count btc balance history: address={} error={:#?}",
                 params.address, err
             );
-            return Err(FBtcApiError::InternalError);
+            return Err(db_error(&err));
+        }
+    };
+    let buckets = match state
+        .db
+        .select_history_buckets(
+            &params.address,
+            from_block,
+            to_block,
+            bucket_size,
+            limit,
+            offset,
+        )
+        .await
+    {
+        Ok(b) => b,
+        Err(err) => {
+            error!(
+                "can't fetch btc balance history buckets: address={} error={:#?}",
+                params.address, err
+            );
+            return Err(db_error(&err));
         }
     };
 
-    let mut points: BTreeMap<i64, BtcBalanceHistoryPoint> = BTreeMap::new();
-    for o in outputs {
-        let entry = points.entry(o.block).or_default();
-        entry.block = o.block;
-        entry.balance += o.amount;
-        entry.income += o.amount;
-        entry.out_count += 1;
-    }
+    let meta = Some(ListResponseMeta::from_counts(limit, offset, count));
+    let (Some(&page_from), Some(&last)) = (buckets.first(), buckets.last()) else {
+        return Ok(Json(ListResult {
+            meta,
+            records: vec![],
+        }));
+    };
+    let page_to = (query.granularity.bucket(last) + bucket_size - 1).min(to_block);
+
+    // running balance of the page starts from everything below it
+    let opening_balance = if page_from > 0 {
+        match state
+            .db
+            .select_opening_balance(&params.address, page_from)
+            .await
+        {
+            Ok(b) => b,
+            Err(err) => {
+                error!(
+                    "can't fetch opening btc balance: address={} error={:#?}",
+                    params.address, err
+                );
+                return Err(db_error(&err));
+            }
+        }
+    } else {
+        0
+    };
 
-    let inputs = match state.db.select_tx_inputs_sum(&params.address).await {
-        Ok(o) => o,
+    let outputs = state
+        .db
+        .stream_tx_outputs_sum(&params.address, page_from, page_to);
+    let inputs = state
+        .db
+        .stream_tx_inputs_sum(&params.address, page_from, page_to);
+    let records = match balance_history(
+        outputs,
+        inputs,
+        opening_balance,
+        page_from,
+        query.granularity,
+    )
+    .await
+    {
+        Ok(r) => r,
         Err(err) => {
             error!(
-                "can't fetch btc inputs: address={} error={:#?}",
+                "can't fetch btc balance history: address={} error={:#?}",
                 params.address, err
             );
-            return Err(FBtcApiError::InternalError);
+            return Err(db_error(&err));
         }
     };
 
-    for i in inputs {
-        let entry = points.entry(i.block).or_default();
-        entry.block = i.block;
-        entry.balance -= i.amount;
-        entry.spent += i.amount;
-        entry.out_count += 1;
+    Ok(Json(ListResult { meta, records }))
+}
+
+/// Merges output and input sums ordered by block into points of the `granularity` buckets,
+/// the first bucket starts at `from_block`. Balance of each point is the running total
+/// starting from the `opening_balance`.
+async fn balance_history(
+    mut outputs: BoxStream<'_, sqlx::Result<BtcOutputsSum>>,
+    mut inputs: BoxStream<'_, sqlx::Result<InputsSum>>,
+    opening_balance: i64,
+    from_block: i64,
+    granularity: HistoryGranularity,
+) -> sqlx::Result<Vec<BtcBalanceHistoryPoint>> {
+    let mut result = Vec::new();
+    let mut current: Option<BtcBalanceHistoryPoint> = None;
+    let mut balance = opening_balance;
+
+    let mut next_output = outputs.try_next().await?;
+    let mut next_input = inputs.try_next().await?;
+    loop {
+        let (block, income, spent) = match (next_output.take(), next_input.take()) {
+            (None, None) => break,
+            (Some(o), Some(i)) if i.block < o.block => {
+                next_output = Some(o);
+                next_input = inputs.try_next().await?;
+                (i.block, 0, i.amount)
+            }
+            (Some(o), i) => {
+                next_input = i;
+                next_output = outputs.try_next().await?;
+                (o.block, o.amount, 0)
+            }
+            (None, Some(i)) => {
+                next_input = inputs.try_next().await?;
+                (i.block, 0, i.amount)
+            }
+        };
+
+        let bucket = granularity.bucket(block).max(from_block);
+        if current.as_ref().is_some_and(|p| p.block != bucket) {
+            result.extend(current.take());
+        }
+        let point = current.get_or_insert_with(|| BtcBalanceHistoryPoint {
+            block: bucket,
+            ..Default::default()
+        });
+        balance += income - spent;
+        point.balance = balance;
+        point.income += income;
+        point.spent += spent;
+        point.out_count += 1;
     }
+    result.extend(current);
+
+    Ok(result)
+}
+
+const SUMMARY_RECENT_TXS: u32 = 5;
 
-    let mut result = points.values().cloned().collect::<Vec<_>>();
-    result.sort_by(|a, b| a.block.cmp(&b.block));
-    let mut total_btc_balance = 0;
-    for p in result.iter_mut() {
-        total_btc_balance += p.income;
-        total_btc_balance -= p.spent;
-        p.balance = total_btc_balance;
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/address/{address}/summary",
+    tag = "btc",
+    params(NetworkPath, GetBalanceParams, AddressSummaryQuery),
+    responses((status = 200, body = AddressSummary))
+)]
+pub async fn get_address_summary(
+    state: Data<Context>,
+    params: Path<GetBalanceParams>,
+    query: Query<AddressSummaryQuery>,
+    api_key: ReqData<ApiKey>,
+) -> Result<Json<AddressSummary>, FBtcApiError> {
+    if !state.is_ready(HealthGate::DbOnly).await {
+        return Err(FBtcApiError::ServiceUnavailable);
+    }
+    if query.include_labels && !api_key.has_scope(scopes::LABELS) {
+        return Err(FBtcApiError::Forbidden);
+    }
+
+    if let Err(err) = decode_address(&params.address, state.net) {
+        return Err(FBtcApiError::InvalidAddress(format!("{err}")));
     }
 
-    Ok(Json(result))
+    let address = params.address.as_str();
+    let (mut balance, runes, recent_txs, indexer_height) = tokio::join!(
+        state
+            .db
+            .get_balance_with_labels(address, query.include_labels),
+        state.db.get_runes_balances(address),
+        state
+            .db
+            .list_address_txs(address, false, None, OrderBy::Desc, SUMMARY_RECENT_TXS, 0),
+        state
+            .db
+            .get_last_indexed_block(crate::indexer::BITCOIN_INDEX),
+    );
+
+    // each part degrades to null on its own, the rest of the summary is still useful
+    let mut errors = Vec::new();
+    let mut part = |name: &str, err: sqlx::Error| {
+        error!("can't fetch address summary {name}: address={address} error={err:#?}");
+        errors.push(format!("{name} is unavailable"));
+    };
+    // labels come with the balance in the same query
+    let labels = balance.as_mut().ok().and_then(|b| b.labels.take());
+    let balance = balance.map_err(|err| part("balance", err)).ok();
+    let runes = runes.map_err(|err| part("runes", err)).ok();
+    let recent_txs = recent_txs.map_err(|err| part("recent_txs", err)).ok();
+    let indexer_height = indexer_height
+        .map_err(|err| part("indexer_height", err))
+        .ok();
+
+    Ok(Json(AddressSummary {
+        address: params.address.clone(),
+        balance,
+        runes,
+        recent_txs,
+        indexer_height,
+        labels,
+        errors,
+    }))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Path)]
 pub struct UtxoRequest {
     pub address: String,
 }
 
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/utxos/{address}",
+    tag = "btc",
+    params(NetworkPath, UtxoRequest, UtxoQuery, PageParams),
+    responses((status = 200, body = ListResult<BtcUtxo>))
+)]
 pub async fn list_utxos(
     state: Data<Context>,
     params: Path<UtxoRequest>,
     query: Query<UtxoQuery>,
 ) -> Result<Json<ListResult<BtcUtxo>>, FBtcApiError> {
-    if !state.is_healthy().await {
+    if !state.is_ready(HealthGate::DbOnly).await {
         return Err(FBtcApiError::ServiceUnavailable);
     }
 
@@ -128,102 +518,287 @@ pub async fn list_utxos(
             return Err(FBtcApiError::BadInput(format!("{err}")));
         }
     };
+    let after_id = match parse_cursor(&query.page, &query.cursor) {
+        Ok(v) => v,
+        Err(err) => {
+            return Err(FBtcApiError::BadInput(format!("{err}")));
+        }
+    };
+    let amount = match query.amount_range() {
+        Ok(v) => v,
+        Err(err) => {
+            return Err(FBtcApiError::BadInput(format!("{err}")));
+        }
+    };
 
-    let count = match state.db.count_utxos(&params.address).await {
-        Ok(c) => c,
+    let older_than = if query.skip_premature {
+        state.coinbase_maturity_cutoff().await
+    } else {
+        None
+    };
+
+    // inscriptions and runes are filtered out on the db side,
+    // so the total count matches the pages we return.
+    let count_res = state
+        .db
+        .count_filtered_utxos(&params.address, amount, older_than, true, query.no_runes)
+        .await;
+    let count = match count_res {
+        Ok(c) => Some(c as u64),
         Err(err) => {
             error!("can't count utxos: error={:#?}", err);
-            0
+            None
         }
     };
 
-    #[rustfmt::skip]
-    let older_than = if query.skip_premature {
-         match state.btc_client.get_block_count() {
-            Ok(block) => if block > 100 { Some(block - 100) } else { None },
-            Err(_) => None,
+    let rows_res = state
+        .db
+        .select_utxo_with_pagination(
+            &params.address,
+            query.page.order,
+            amount,
+            older_than,
+            true,
+            query.no_runes,
+            query.sorting,
+            after_id,
+            limit,
+            offset,
+        )
+        .await;
+    let rows = match rows_res {
+        Ok(row) => row,
+        Err(err) => {
+            error!(
+                "failed to select btc utxos: address={} error={:#?}",
+                params.address, err
+            );
+            return Err(FBtcApiError::InternalError);
         }
-    } else { None };
+    };
 
-    let mut db_limit = limit;
-    let mut db_offset = offset;
-    let mut records = Vec::new();
-    'collector: loop {
-        let rows_res = state
-            .db
-            .select_utxo_with_pagination(
-                &params.address,
-                query.page.order,
-                query.amount_threshold,
-                older_than,
-                query.sorting,
-                db_limit,
-                db_offset,
-            )
-            .await;
-        let row = match rows_res {
-            Ok(row) => row,
-            Err(err) => {
-                error!(
-                    "failed to select btc utxos: address={} error={:#?}",
-                    params.address, err
-                );
-                return Err(FBtcApiError::InternalError);
-            }
-        };
-        if row.is_empty() {
-            break 'collector;
+    // cursor is taken before the mempool filtering, so the next page starts right after this one
+    let page_len = rows.len();
+    let next_cursor = match rows.last() {
+        Some(last) if page_len as u32 == limit => Some(last.id.to_string()),
+        _ => None,
+    };
+
+    // only mempool spends and locks are left to check
+    let mut records = match state.filter_used_btc_utxos(&rows, false, None).await {
+        Ok(r) => r,
+        Err(err) => {
+            error!(
+                "failed to filter used utxos: address={} error={:#?}",
+                params.address, err
+            );
+            return Err(FBtcApiError::InternalError);
+        }
+    };
+    let tip = state.chain_tip().await;
+    records.iter_mut().for_each(|u| u.set_confirmations(tip));
+
+    let meta = if after_id.is_some() {
+        ListResponseMeta {
+            total_is_exact: count.is_some(),
+            ..ListResponseMeta::with_cursor(limit, count.unwrap_or_default(), next_cursor)
+        }
+    } else {
+        ListResponseMeta {
+            next_cursor,
+            ..ListResponseMeta::from_optional_total(limit, offset, count, page_len)
         }
+    };
+    let resp = ListResult {
+        meta: Some(meta),
+        records,
+    };
 
-        let rows = match state
-            .filter_used_btc_utxos(&row, query.no_runes, None)
-            .await
-        {
-            Ok(r) => r,
-            Err(err) => {
-                error!(
-                    "failed to filter runes utxos: address={} error={:#?}",
-                    params.address, err
-                );
-                return Err(FBtcApiError::InternalError);
-            }
-        };
+    Ok(Json(resp))
+}
 
-        records.extend(rows);
-        if records.len() as u32 >= limit {
-            break 'collector;
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/utxos/{address}/stats",
+    tag = "btc",
+    params(NetworkPath, UtxoRequest),
+    responses((status = 200, body = UtxoStats))
+)]
+pub async fn get_utxo_stats(
+    state: Data<Context>,
+    params: Path<UtxoRequest>,
+) -> Result<Json<UtxoStats>, FBtcApiError> {
+    if !state.is_ready(HealthGate::DbOnly).await {
+        return Err(FBtcApiError::ServiceUnavailable);
+    }
+
+    if let Err(err) = decode_address(&params.address, state.net) {
+        return Err(FBtcApiError::InvalidAddress(format!("{err}")));
+    }
+
+    let mature_height = match state.btc_client.get_block_count() {
+        Ok(block) => block.saturating_sub(100),
+        Err(err) => {
+            error!("can't get block count: error={:#?}", err);
+            return Err(FBtcApiError::InternalError);
         }
+    };
 
-        db_offset += db_limit;
-        db_limit = limit - records.len() as u32;
+    match state
+        .db
+        .get_utxo_stats(&params.address, mature_height)
+        .await
+    {
+        Ok(stats) => Ok(Json(stats)),
+        Err(err) => {
+            error!(
+                "can't fetch utxo stats: address={} error={:#?}",
+                params.address, err
+            );
+            Err(FBtcApiError::InternalError)
+        }
     }
+}
 
-    let resp = ListResult {
-        meta: Some(ListResponseMeta {
-            page: query.page.page.unwrap_or_default(),
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/utxos/{address}/consolidation-plan",
+    tag = "btc",
+    params(NetworkPath, UtxoRequest, ConsolidationQuery),
+    responses((status = 200, body = ConsolidationPlan))
+)]
+pub async fn get_consolidation_plan(
+    state: Data<Context>,
+    params: Path<UtxoRequest>,
+    query: Query<ConsolidationQuery>,
+) -> Result<Json<ConsolidationPlan>, FBtcApiError> {
+    if !state.is_ready(HealthGate::DbOnly).await {
+        return Err(FBtcApiError::ServiceUnavailable);
+    }
+
+    if let Err(err) = decode_address(&params.address, state.net) {
+        return Err(FBtcApiError::InvalidAddress(format!("{err}")));
+    }
+
+    let older_than = state.coinbase_maturity_cutoff().await;
+
+    let opts = FilterOpts {
+        request_id: None,
+        skip_inscriptions: true,
+        skip_runes: true,
+        skip_premature: older_than,
+    };
+    let res = state
+        .collector
+        .plan_btc_consolidation(&params.address, query.target_count, query.fee_rate, &opts)
+        .await;
+    match res {
+        Ok(plan) => Ok(Json(plan)),
+        Err(CollectorError::BadInput(msg)) => Err(FBtcApiError::BadInput(msg)),
+        Err(err @ CollectorError::MaxUtxosExceeded { .. }) => {
+            Err(FBtcApiError::BadInput(err.to_string()))
+        }
+        Err(err) => {
+            error!(
+                "can't plan utxos consolidation: address={} error={:#}",
+                params.address, err
+            );
+            Err(FBtcApiError::InternalError)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/outputs/{address}",
+    tag = "btc",
+    params(NetworkPath, UtxoRequest, OutputsQuery, PageParams),
+    responses((status = 200, body = ListResult<BtcOutputHistory>), (status = 410, description = "Queried heights are pruned by `db prune`"))
+)]
+pub async fn list_address_outputs(
+    state: Data<Context>,
+    params: Path<UtxoRequest>,
+    query: Query<OutputsQuery>,
+) -> Result<Json<ListResult<BtcOutputHistory>>, FBtcApiError> {
+    if !state.is_ready(HealthGate::DbOnly).await {
+        return Err(FBtcApiError::ServiceUnavailable);
+    }
+
+    if let Err(err) = decode_address(&params.address, state.net) {
+        return Err(FBtcApiError::InvalidAddress(format!("{err}")));
+    }
+    let (limit, offset) = match query.page.limit_offset() {
+        Ok(v) => v,
+        Err(err) => {
+            return Err(FBtcApiError::BadInput(format!("{err}")));
+        }
+    };
+
+    // spent outputs are pruned together with their blocks, unspent ones are never pruned
+    if query.include_spent {
+        ensure_not_pruned(&state, query.min_height.unwrap_or_default() as i64).await?;
+    }
+
+    let count = match state
+        .db
+        .count_address_outputs(&params.address, query.include_spent, query.min_height)
+        .await
+    {
+        Ok(count) => count as u64,
+        Err(err) => {
+            error!(
+                "can't count address outputs: address={} error={:#?}",
+                params.address, err
+            );
+            return Err(FBtcApiError::InternalError);
+        }
+    };
+
+    let records = match state
+        .db
+        .list_address_outputs(
+            &params.address,
+            query.include_spent,
+            query.min_height,
+            query.page.order,
             limit,
-            offset: db_offset,
-            total_records: count as u64,
-            has_more: i64::from(db_offset + limit) < count,
-        }),
-        records,
+            offset,
+        )
+        .await
+    {
+        Ok(records) => records,
+        Err(err) => {
+            error!(
+                "can't fetch address outputs: address={} error={:#?}",
+                params.address, err
+            );
+            return Err(FBtcApiError::InternalError);
+        }
     };
 
-    Ok(Json(resp))
+    Ok(Json(ListResult {
+        meta: Some(ListResponseMeta::from_counts(limit, offset, count)),
+        records,
+    }))
 }
 
+#[utoipa::path(
+    post,
+    path = "/v1/{network}/utxos/{address}",
+    tag = "btc",
+    params(NetworkPath, UtxoRequest),
+    request_body = CollectUtxo,
+    responses((status = 200, body = ListResult<BtcUtxo>))
+)]
 pub async fn list_utxos_with_lock(
     state: Data<Context>,
     params: Path<UtxoRequest>,
     request: Json<CollectUtxo>,
-    api_key: super::auth_middleware::XApiKey,
+    api_key: ReqData<ApiKey>,
 ) -> Result<Json<ListResult<BtcUtxo>>, FBtcApiError> {
-    if !state.is_healthy().await {
+    if !state.is_ready(HealthGate::Full).await {
         return Err(FBtcApiError::ServiceUnavailable);
     }
-    let Some(apk) = state.get_api_key(&api_key.0) else {
-        return Err(FBtcApiError::Unauthorized);
-    };
     if let Err(err) = decode_address(&params.address, state.net) {
         return Err(FBtcApiError::InvalidAddress(format!("{err}")));
     }
@@ -237,194 +812,451 @@ pub async fn list_utxos_with_lock(
         ));
     }
 
-    let balance = match state.db.get_balance(&address).await {
-        Ok(b) => b,
-        Err(err) => {
-            error!("can't get balance: address={} err={err:#}", address);
+    let older_than = state.coinbase_maturity_cutoff().await;
+
+    let opts = FilterOpts {
+        request_id: Some(request.request_id.clone()),
+        skip_inscriptions: true,
+        skip_runes: true,
+        skip_premature: older_than,
+    };
+    let can_lock = api_key.has_scope(scopes::LOCK);
+    let mut attempt = 0;
+    let mut collected = loop {
+        let res = state
+            .collector
+            .collect_spendable_btc_utxo(&address, target_amount, &opts)
+            .await;
+        let collected = match res {
+            Ok(c) => c,
+            Err(err) => return Err(collector_error(err, &address)),
+        };
+
+        attempt += 1;
+        if lock_utxo(&state, can_lock, &collected.utxos, &request.request_id).await {
+            break collected;
+        }
+        if attempt >= MAX_LOCK_ATTEMPTS {
+            error!(
+                "unable to lock collected utxos, they are taken by concurrent requests: address={} attempts={}",
+                address, attempt
+            );
             return Err(FBtcApiError::InternalError);
         }
+        // utxos taken by others are skipped by the filter on the next run
+        debug!("collected utxos are locked by another request, retry: address={address} attempt={attempt}");
+    };
+    let tip = state.chain_tip().await;
+    collected
+        .utxos
+        .iter_mut()
+        .for_each(|u| u.set_confirmations(tip));
+
+    // the meta describes the returned set, not the pages scanned by the collector
+    let count = collected.utxos.len();
+    let resp = ListResult {
+        meta: Some(ListResponseMeta::from_counts(count as u32, 0, count as u64)),
+        records: collected.utxos,
     };
 
-    if (balance.balance as u64) < target_amount {
-        return Err(FBtcApiError::NotEnoughBalance {
-            required: target_amount as u128,
-            available: balance.balance as u128,
-        });
+    Ok(Json(resp))
+}
+
+/// Converts the error of the btc utxos collection, unexpected errors are logged.
+/// Heavy queries cancelled by the db timeout are reported with 504.
+fn db_error(err: &sqlx::Error) -> FBtcApiError {
+    if is_timeout(err) {
+        FBtcApiError::Timeout
+    } else {
+        FBtcApiError::InternalError
     }
+}
 
-    #[rustfmt::skip]
-    let older_than = match state.btc_client.get_block_count() {
-        Ok(block) => if block > 100 { Some(block - 100) } else { None },
-        Err(_) => None,
-    };
+/// Rejects queries that read outputs or inputs at or below the height pruned by `db prune`,
+/// otherwise they silently return incomplete history.
+async fn ensure_not_pruned(state: &Context, from_height: i64) -> Result<(), FBtcApiError> {
+    match state.pruned_height().await {
+        Ok(Some(pruned_height)) if from_height <= pruned_height => {
+            Err(FBtcApiError::HistoryPruned { pruned_height })
+        }
+        Ok(_) => Ok(()),
+        Err(err) => {
+            error!("can't fetch pruned height: error={:#?}", err);
+            Err(FBtcApiError::InternalError)
+        }
+    }
+}
 
-    let collected = coolect_utxo_shortcut(
-        &state,
-        &address,
-        apk.can_lock_utxo,
-        target_amount,
-        &request.request_id,
-        older_than.unwrap_or_default(),
-    )
-    .await?;
-    if let Some(resp) = collected {
-        return Ok(Json(resp));
+fn collector_error(err: CollectorError, address: &str) -> FBtcApiError {
+    match err {
+        CollectorError::NotEnoughBalance {
+            available,
+            collectable,
+            target,
+        } => FBtcApiError::NotEnoughBalance {
+            required: target,
+            available,
+            collectable,
+        },
+        CollectorError::NeedMoreUtxos {
+            total_utxo,
+            max,
+            collected,
+            target,
+        } => FBtcApiError::NeedMoreUtxos {
+            max,
+            total_utxos: total_utxo,
+            target,
+            collected,
+        },
+        CollectorError::BadInput(msg) => FBtcApiError::BadInput(msg),
+        err @ (CollectorError::ZeroTarget | CollectorError::MaxUtxosExceeded { .. }) => {
+            FBtcApiError::BadInput(err.to_string())
+        }
+        err => {
+            error!(
+                "failed to collect btc utxos: address={} error={:#}",
+                address, err
+            );
+            FBtcApiError::InternalError
+        }
     }
+}
 
-    let mut collected_utxos = Vec::new();
-    let limit = 200;
-    let mut offset = 0;
+/// Returns `false` if some of the utxos are already locked by another request.
+async fn lock_utxo(state: &Context, can_lock_utxo: bool, utxos: &[BtcUtxo], rid: &str) -> bool {
+    if !can_lock_utxo {
+        return true;
+    }
 
-    loop {
-        let rows_res = state
-            .db
-            .select_utxo_with_pagination(
-                &address,
-                OrderBy::Desc,
-                None,
-                older_than,
-                UtxoSortMode::Amount,
-                limit,
-                offset,
-            )
-            .await;
+    let outpoints: Vec<_> = utxos.iter().map(|u| (&u.tx_hash, u.vout)).collect();
+    state.lock_outpoints(&outpoints, rid).await
+}
 
-        let rows = match rows_res {
-            Ok(row) => row,
-            Err(err) => {
-                error!(
-                    "failed to select btc utxos: address={} error={:#?}",
-                    address, err
-                );
-                return Err(FBtcApiError::InternalError);
-            }
-        };
-        if rows.is_empty() {
-            return Err(FBtcApiError::NotEnoughBalance {
-                required: target_amount as u128,
-                available: collected_utxos
-                    .iter()
-                    .map(|e: &BtcUtxo| e.amount as u128)
-                    .sum::<u128>(),
-            });
-        }
-        match state
-            .filter_used_btc_utxos(&rows, true, Some(request.request_id.clone()))
-            .await
-        {
-            Ok(r) => collected_utxos.extend(r),
+#[utoipa::path(
+    post,
+    path = "/v1/{network}/psbt/build",
+    tag = "btc",
+    params(NetworkPath),
+    request_body = BuildPsbtRequest,
+    responses((status = 200, body = BuildPsbtResponse))
+)]
+pub async fn build_btc_psbt(
+    state: Data<Context>,
+    request: Json<BuildPsbtRequest>,
+) -> Result<Json<BuildPsbtResponse>, FBtcApiError> {
+    if !state.is_ready(HealthGate::Full).await {
+        return Err(FBtcApiError::ServiceUnavailable);
+    }
+
+    let from_address = match decode_address(&request.from_address, state.net) {
+        Ok(address) => address,
+        Err(err) => return Err(FBtcApiError::InvalidAddress(format!("{err}"))),
+    };
+    let change_script = from_address.script_pubkey();
+    // inputs get only `witness_utxo`, legacy ones require the whole parent tx
+    if !change_script.is_witness_program() && !change_script.is_p2sh() {
+        return Err(FBtcApiError::BadInput(
+            "from_address must be a segwit address".into(),
+        ));
+    }
+    if request.outputs.is_empty() {
+        return Err(FBtcApiError::BadInput("outputs must not be empty".into()));
+    }
+    if !request.fee_rate.is_finite() || request.fee_rate <= 0.0 {
+        return Err(FBtcApiError::BadInput(
+            "fee_rate must be positive number".into(),
+        ));
+    }
+
+    let mut outputs = Vec::with_capacity(request.outputs.len());
+    for output in &request.outputs {
+        let address = match decode_address(&output.address, state.net) {
+            Ok(address) => address,
             Err(err) => {
-                error!(
-                    "failed to filter btc utxos: address={} error={:#?}",
-                    params.address, err
-                );
-                return Err(FBtcApiError::InternalError);
+                return Err(FBtcApiError::InvalidAddress(format!(
+                    "{}: {err}",
+                    output.address
+                )))
             }
         };
+        outputs.push(TxOut {
+            value: bitcoin::Amount::from_sat(output.amount),
+            script_pubkey: address.script_pubkey(),
+        });
+    }
+    let output_amount: u64 = request.outputs.iter().map(|o| o.amount).sum();
+    let mut target = match psbt::min_fee(&change_script, &outputs, request.fee_rate) {
+        Ok(fee) => output_amount + fee,
+        Err(err) => return Err(psbt_error(err)),
+    };
 
-        let result = min_utxos_to_reach_target(&collected_utxos, request.amount as u128);
-        match result {
-            Ok(utxos) => {
-                lock_utxo(&state, apk.can_lock_utxo, &utxos, &request.request_id).await;
-                let resp = ListResult {
-                    meta: Some(ListResponseMeta::new(limit, offset, utxos.len() as u64)),
-                    records: utxos,
-                };
+    let older_than = state.coinbase_maturity_cutoff().await;
 
-                return Ok(Json(resp));
-            }
-            Err(KnapsackError::NotEnoughBalance { .. }) => {
-                // try to select more utxos
-                offset += limit;
+    let opts = FilterOpts {
+        request_id: Some(request.request_id.clone()),
+        skip_inscriptions: true,
+        skip_runes: true,
+        skip_premature: older_than,
+    };
+    let mut attempt = 0;
+    let (mut inputs, built) = loop {
+        let res = state
+            .collector
+            .collect_spendable_btc_utxo(&request.from_address, target, &opts)
+            .await;
+        let collected = match res {
+            Ok(c) => c,
+            Err(err) => return Err(collector_error(err, &request.from_address)),
+        };
+
+        let res = psbt::build_psbt(
+            &collected.utxos,
+            outputs.clone(),
+            &change_script,
+            request.fee_rate,
+        );
+        let built = match res {
+            Ok(built) => built,
+            // the collected inputs can't pay their own fee, collect more
+            Err(PsbtError::NotEnoughBalance { required, .. }) if required > target => {
+                target = required;
                 continue;
             }
+            Err(err) => return Err(psbt_error(err)),
+        };
+
+        attempt += 1;
+        if lock_utxo(&state, true, &collected.utxos, &request.request_id).await {
+            break (collected.utxos, built);
         }
+        if attempt >= MAX_LOCK_ATTEMPTS {
+            error!(
+                "unable to lock psbt inputs, they are taken by concurrent requests: address={} attempts={}",
+                request.from_address, attempt
+            );
+            return Err(FBtcApiError::InternalError);
+        }
+        // utxos taken by others are skipped by the filter on the next run
+        debug!(
+            "psbt inputs are locked by another request, retry: address={} attempt={attempt}",
+            request.from_address
+        );
+    };
+    let tip = state.chain_tip().await;
+    inputs.iter_mut().for_each(|u| u.set_confirmations(tip));
+
+    Ok(Json(BuildPsbtResponse {
+        psbt: built.psbt.to_string(),
+        inputs,
+        vsize: built.vsize,
+        fee: built.fee,
+        change: built.change,
+    }))
+}
+
+fn psbt_error(err: PsbtError) -> FBtcApiError {
+    match err {
+        PsbtError::NotEnoughBalance {
+            available,
+            required,
+        } => FBtcApiError::NotEnoughBalance {
+            required: required.into(),
+            available: available.into(),
+            collectable: available.into(),
+        },
+        PsbtError::BadInput(msg) => FBtcApiError::BadInput(msg),
     }
 }
 
-async fn coolect_utxo_shortcut(
-    state: &Context,
-    address: &str,
-    can_lock_utxo: bool,
-    target_amount: u64,
-    rid: &str,
-    older_than: u64,
-) -> Result<Option<ListResult<BtcUtxo>>, FBtcApiError> {
-    let lower_bound = target_amount / 10;
-    let upper_bound = target_amount * 4;
-    // shortcut
+const MAX_UNLOCK_BATCH: usize = 1000;
+
+#[utoipa::path(
+    method(post, delete),
+    path = "/v1/{network}/utxos/unlock",
+    tag = "btc",
+    params(NetworkPath),
+    request_body = UnlockUtxoRequest,
+    responses((status = 200, body = Vec<UnlockResult>))
+)]
+pub async fn unlock_utxos(
+    state: Data<Context>,
+    request: Json<UnlockUtxoRequest>,
+    api_key: ReqData<ApiKey>,
+) -> Result<Json<Vec<UnlockResult>>, FBtcApiError> {
+    if !api_key.has_scope(scopes::LOCK) {
+        return Err(FBtcApiError::Forbidden);
+    }
+
+    if request.request_id.is_empty() {
+        return Err(FBtcApiError::BadInput("request_id can't be empty".into()));
+    }
+    if request.outpoints.len() > MAX_UNLOCK_BATCH {
+        return Err(FBtcApiError::BadInput(format!(
+            "too many outpoints({}), max allowed({MAX_UNLOCK_BATCH})",
+            request.outpoints.len()
+        )));
+    }
+
+    let mut results = Vec::with_capacity(request.outpoints.len());
+    for o in request.outpoints.iter() {
+        let status = match state.cache.as_ref() {
+            Some(cache) => match cache
+                .unlock_utxo(&o.tx_hash, o.vout, &request.request_id)
+                .await
+            {
+                Ok(status) => status,
+                Err(err) => {
+                    error!(
+                        "unable to release utxo lock: id={} error={err:#}",
+                        request.request_id
+                    );
+                    return Err(FBtcApiError::InternalError);
+                }
+            },
+            // there are no locks without cache
+            None => UnlockStatus::NotFound,
+        };
+
+        results.push(UnlockResult {
+            tx_hash: o.tx_hash.clone(),
+            vout: o.vout,
+            status,
+        });
+    }
+
+    Ok(Json(results))
+}
+
+/// Returns locks of the address utxos.
+/// The `request_id` of a lock is enough to unlock it, so the route requires the `lock` scope.
+/// Pagination is applied to the address utxos, not to the locks,
+/// so page can contain less records than the limit.
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/utxos/{address}/locks",
+    tag = "btc",
+    params(NetworkPath, UtxoRequest, PageParams),
+    responses((status = 200, body = ListResult<UtxoLock>))
+)]
+pub async fn list_utxo_locks(
+    state: Data<Context>,
+    params: Path<UtxoRequest>,
+    query: Query<PageParams>,
+) -> Result<Json<ListResult<UtxoLock>>, FBtcApiError> {
+    if let Err(err) = decode_address(&params.address, state.net) {
+        return Err(FBtcApiError::InvalidAddress(format!("{err}")));
+    }
+    let (limit, offset) = match query.limit_offset() {
+        Ok(v) => v,
+        Err(err) => {
+            return Err(FBtcApiError::BadInput(format!("{err}")));
+        }
+    };
+
+    let count = match state.db.count_utxos(&params.address).await {
+        Ok(c) => Some(c as u64),
+        Err(err) => {
+            error!("can't count utxos: error={:#?}", err);
+            None
+        }
+    };
+
     let rows_res = state
         .db
-        .select_utxos_with_amount_bounds(address, 10, lower_bound, upper_bound, older_than)
+        .select_utxo_with_pagination(
+            &params.address,
+            query.order,
+            AmountRange::default(),
+            None,
+            false,
+            false,
+            UtxoSortMode::Age,
+            None,
+            limit,
+            offset,
+        )
         .await;
     let rows = match rows_res {
         Ok(row) => row,
         Err(err) => {
             error!(
                 "failed to select btc utxos: address={} error={:#?}",
-                address, err
+                params.address, err
             );
             return Err(FBtcApiError::InternalError);
         }
     };
 
-    // filter & collect
-    let rows = match state
-        .filter_used_btc_utxos(&rows, true, Some(rid.into()))
-        .await
-    {
-        Ok(r) => r,
-        Err(err) => {
-            error!(
-                "failed to filter runes utxos: address={} error={:#?}",
-                address, err
-            );
-            return Err(FBtcApiError::InternalError);
-        }
-    };
+    let mut records = Vec::new();
+    if let Some(cache) = state.cache.as_ref() {
+        let outpoints: Vec<_> = rows.iter().map(|u| (&u.tx_hash, u.vout)).collect();
+        let locks = match cache.get_locks(&outpoints).await {
+            Ok(locks) => locks,
+            Err(err) => {
+                error!(
+                    "unable to read utxo locks: address={} error={err:#}",
+                    params.address
+                );
+                return Err(FBtcApiError::InternalError);
+            }
+        };
 
-    let result = min_utxos_to_reach_target(&rows, target_amount.into());
-    match result {
-        Ok(utxos) => {
-            lock_utxo(state, can_lock_utxo, &utxos, rid).await;
-            let resp = ListResult {
-                meta: Some(ListResponseMeta::new(10, 0, rows.len() as u64)),
-                records: utxos,
+        for (u, lock) in rows.iter().zip(locks) {
+            let Some((request_id, ttl)) = lock else {
+                continue;
             };
-
-            Ok(Some(resp))
-        }
-        Err(KnapsackError::NotEnoughBalance { .. }) => {
-            debug!("shortcut is unsuccessful, going in hard way");
-            Ok(None)
+            records.push(UtxoLock {
+                tx_hash: u.tx_hash.clone(),
+                vout: u.vout,
+                amount: u.amount,
+                request_id,
+                ttl,
+            });
         }
     }
-}
 
-async fn lock_utxo(state: &Context, can_lock_utxo: bool, utxos: &[BtcUtxo], rid: &str) {
-    let Some(cache) = state.cache.as_ref() else {
-        return;
-    };
-    if !can_lock_utxo {
-        return;
-    }
+    // records are the locked ones of the page, so the page is guessed by the db rows
+    Ok(Json(ListResult {
+        meta: Some(ListResponseMeta::from_optional_total(
+            limit,
+            offset,
+            count,
+            rows.len(),
+        )),
+        records,
+    }))
+}
 
-    for u in utxos.iter() {
-        let res = cache.lock_utxo(&u.tx_hash, u.vout, rid).await;
-        if let Err(err) = res {
-            error!("unable to write utxo lock: id={rid} error={err:#}");
-        }
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/fee-rate",
+    tag = "btc",
+    params(NetworkPath, FeeRateQuery),
+    responses((status = 200, body = FeeRate))
+)]
+pub async fn btc_fee_rate(
+    state: Data<Context>,
+    query: Query<FeeRateQuery>,
+) -> Result<Json<FeeRate>, FBtcApiError> {
+    if !state.is_ready(HealthGate::Full).await {
+        return Err(FBtcApiError::ServiceUnavailable);
     }
-}
 
-pub async fn btc_fee_rate(state: Data<Context>) -> Result<Json<FeeRate>, FBtcApiError> {
     match state.estimate_fee().await {
-        Ok(mut fee) => {
+        Ok(fee) => {
             use std::cmp::max;
+            let mut fee = if query.detailed { fee } else { fee.short() };
             let min_rate = state.cfg.min_fee_rate;
             fee.min = max(min_rate, fee.min);
             fee.normal = max(min_rate, fee.normal);
             fee.fast = max(min_rate, fee.fast);
+            if let Some(economy) = fee.economy.as_mut() {
+                *economy = max(min_rate, *economy);
+            }
+            if let Some(targets) = fee.targets.as_mut() {
+                targets.values_mut().for_each(|v| *v = max(min_rate, *v));
+            }
 
             Ok(Json(fee))
         }
@@ -436,14 +1268,21 @@ pub async fn btc_fee_rate(state: Data<Context>) -> Result<Json<FeeRate>, FBtcApi
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/tx/{txid}",
+    tag = "btc",
+    params(NetworkPath, ("txid" = String, Path)),
+    responses((status = 200, body = Response<RawTxInfo, RpcError>), (status = 410, description = "Queried heights are pruned by `db prune`"))
+)]
 pub async fn get_transaction(
     state: Data<Context>,
     txid: web::Path<String>,
-) -> Result<Json<GetTxResponse>, FBtcApiError> {
+) -> Result<CachedJson<GetTxResponse>, FBtcApiError> {
     use bitcoincore_rpc::jsonrpc::Error::Rpc as BtcRpcError;
     use bitcoincore_rpc::Error::JsonRpc as BtcJsonRpcError;
 
-    if !state.is_healthy().await {
+    if !state.is_ready(HealthGate::Full).await {
         return Err(FBtcApiError::ServiceUnavailable);
     }
 
@@ -459,17 +1298,36 @@ pub async fn get_transaction(
         // we got a response from bitcoind
         Ok(response) => response,
 
+        // pruned nodes don't have old txs, but the index may know them
+        Err(BtcJsonRpcError(BtcRpcError(ref rpc_error)))
+            if rpc_error.code == RPC_INVALID_ADDRESS_OR_KEY =>
+        {
+            match tx_info_from_index(&state, &txid).await? {
+                Some(info) => {
+                    let block = info.blockheight.map(|h| h as i64);
+                    let policy = state.block_cache_policy(block).await;
+                    let response = GetTxResponse {
+                        result: Some(info),
+                        error: None,
+                    };
+                    return Ok(CachedJson::new(response, policy));
+                }
+                None => return Err(FBtcApiError::NotFound),
+            }
+        }
+
         // we got jsonrpc error, we want to return it as an error "value"
         Err(BtcJsonRpcError(BtcRpcError(ref rpc_error))) => {
             error!("get_raw_transaction_info jsonrpc error: {:#?}", rpc_error);
-            return Ok(Json(GetTxResponse {
+            let response = GetTxResponse {
                 result: None,
                 error: Some(RpcError {
                     code: rpc_error.code,
                     message: rpc_error.message.clone(),
                     data: rpc_error.data.clone(),
                 }),
-            }));
+            };
+            return Ok(CachedJson::new(response, CachePolicy::NoStore));
         }
 
         // we got non-jsonrpc error. Treat them as internal errors.
@@ -502,7 +1360,10 @@ pub async fn get_transaction(
         (None, None)
     };
 
-    Ok(Json(GetTxResponse {
+    let policy = state
+        .block_cache_policy(blockheight.map(|h| h as i64))
+        .await;
+    let response = GetTxResponse {
         result: Some(RawTxInfo {
             in_active_chain: txinfo.in_active_chain,
             confirmations: txinfo.confirmations,
@@ -512,19 +1373,102 @@ pub async fn get_transaction(
             blockheight,
             txnumber,
             raw_tx: hex::encode(txinfo.hex),
+            source: TxSource::Node,
         }),
         error: None,
+    };
+    Ok(CachedJson::new(response, policy))
+}
+
+/// Number of confirmations of the block by the node's best block.
+fn confirmations(state: &Context, height: i64) -> Option<u64> {
+    match state.btc_client.get_block_count() {
+        Ok(best) => (best + 1).checked_sub(height as u64),
+        Err(err) => {
+            warn!("can't get best block: error={:#?}", err);
+            None
+        }
+    }
+}
+
+/// JSON-RPC error code for "No such mempool or blockchain transaction".
+pub(super) const RPC_INVALID_ADDRESS_OR_KEY: i32 = -5;
+
+/// Describes the tx by its indexed outputs, or inputs if outputs were pruned.
+/// Returns `None` if the tx is not indexed.
+async fn tx_info_from_index(
+    state: &Context,
+    txid: &bitcoin::Txid,
+) -> Result<Option<RawTxInfo>, FBtcApiError> {
+    let tx_hash = types::Hash::from(txid);
+    let outputs = match state.db.select_tx_outputs(&tx_hash).await {
+        Ok(outs) => outs,
+        Err(err) => {
+            error!("select of tx outs failed: tx={} err={:#}", txid, err);
+            return Err(FBtcApiError::InternalError);
+        }
+    };
+
+    let position = match outputs.first() {
+        Some(out) => Some((out.block, out.tx_id)),
+        None => match state.db.select_tx_inputs_ext(&tx_hash).await {
+            Ok(inputs) => inputs.first().map(|i| (i.block, i.tx_id)),
+            Err(err) => {
+                error!("select of tx inputs failed: tx={} err={:#}", txid, err);
+                return Err(FBtcApiError::InternalError);
+            }
+        },
+    };
+    let Some((height, tx_n)) = position else {
+        return Ok(None);
+    };
+    ensure_not_pruned(state, height).await?;
+
+    let block = match state
+        .db
+        .get_block_by_height(height, crate::indexer::BITCOIN_INDEX)
+        .await
+    {
+        Ok(block) => block,
+        Err(err) => {
+            error!("can't fetch block: height={} error={:#?}", height, err);
+            return Err(FBtcApiError::InternalError);
+        }
+    };
+    let confirmations = confirmations(state, height).map(|c| c as u32);
+
+    Ok(Some(RawTxInfo {
+        in_active_chain: None,
+        confirmations,
+        time: block.as_ref().map(|b| b.blocktime as usize),
+        blocktime: block.as_ref().map(|b| b.blocktime as usize),
+        blockhash: block
+            .as_ref()
+            .map(|b| bitcoin::BlockHash::from(&b.hash).to_string()),
+        blockheight: Some(height as usize),
+        txnumber: Some(tx_n as usize),
+        raw_tx: String::new(),
+        source: TxSource::Index,
     }))
 }
 
+#[utoipa::path(
+    post,
+    path = "/v1/{network}/tx",
+    tag = "btc",
+    params(NetworkPath, SendTxQuery),
+    request_body = SendTxRequest,
+    responses((status = 200, body = Response<TxHash, RpcError>))
+)]
 pub async fn send_raw_transaction(
     state: Data<Context>,
     req: web::Json<SendTxRequest>,
+    query: Query<SendTxQuery>,
 ) -> Result<Json<SendTxResponse>, FBtcApiError> {
     use bitcoincore_rpc::jsonrpc::Error::Rpc as BtcRpcError;
     use bitcoincore_rpc::Error::JsonRpc as BtcJsonRpcError;
 
-    if !state.is_healthy().await {
+    if !state.is_ready(HealthGate::Full).await {
         return Err(FBtcApiError::ServiceUnavailable);
     }
 
@@ -555,6 +1499,13 @@ pub async fn send_raw_transaction(
     };
 
     debug!("successfully sent tx={txid} to a mempool");
+    if query.sync_cache {
+        match bitcoin::consensus::encode::deserialize_hex::<bitcoin::Transaction>(&req.tx) {
+            Ok(tx) => state.mempool_index.add_tx(&tx).await,
+            Err(err) => warn!("can't decode sent tx={txid} to update mempool cache: error={err}"),
+        }
+    }
+
     Ok(Json(SendTxResponse {
         result: Some(TxHash {
             tx_hash: txid.to_string(),
@@ -563,13 +1514,132 @@ pub async fn send_raw_transaction(
     }))
 }
 
+/// JSON-RPC error code returned by nodes that don't know the method.
+const RPC_METHOD_NOT_FOUND: i32 = -32601;
+
+#[utoipa::path(
+    post,
+    path = "/v1/{network}/tx/test",
+    tag = "btc",
+    params(NetworkPath),
+    request_body = SendTxRequest,
+    responses((status = 200, body = Response<Vec<TxAcceptance>, RpcError>))
+)]
+pub async fn test_raw_transaction(
+    state: Data<Context>,
+    req: web::Json<SendTxRequest>,
+) -> Result<Json<TestTxResponse>, FBtcApiError> {
+    use bitcoincore_rpc::jsonrpc::Error::Rpc as BtcRpcError;
+    use bitcoincore_rpc::Error::JsonRpc as BtcJsonRpcError;
+
+    if !state.is_ready(HealthGate::Full).await {
+        return Err(FBtcApiError::ServiceUnavailable);
+    }
+
+    let results = match state.btc_client.test_mempool_accept(&[req.tx.as_str()]) {
+        Ok(results) => results,
+
+        Err(BtcJsonRpcError(BtcRpcError(ref rpc_error)))
+            if rpc_error.code == RPC_METHOD_NOT_FOUND =>
+        {
+            warn!(
+                "testmempoolaccept is not supported by the node: {:#?}",
+                rpc_error
+            );
+            return Err(FBtcApiError::BadInput(
+                "transaction validation is unavailable: btc node doesn't support testmempoolaccept"
+                    .into(),
+            ));
+        }
+
+        // we got some jsonrpc error
+        Err(BtcJsonRpcError(BtcRpcError(ref rpc_error))) => {
+            warn!("test_mempool_accept jsonrpc error: {:#?}", rpc_error);
+
+            return Ok(Json(TestTxResponse {
+                result: None,
+                error: Some(RpcError {
+                    code: rpc_error.code,
+                    message: rpc_error.message.clone(),
+                    data: rpc_error.data.clone(),
+                }),
+            }));
+        }
+
+        // we got non-jsonrpc error. Treat them as internal errors.
+        Err(e) => {
+            error!("test_mempool_accept internal error: {:#?}", e);
+            return Err(FBtcApiError::InternalError);
+        }
+    };
+
+    let result = results
+        .into_iter()
+        .map(|r| TxAcceptance {
+            tx_hash: r.txid.to_string(),
+            allowed: r.allowed,
+            reject_reason: r.reject_reason,
+            vsize: r.vsize,
+            fee: r.fees.map(|f| f.base.to_sat()),
+        })
+        .collect();
+
+    Ok(Json(TestTxResponse {
+        result: Some(result),
+        error: None,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/mempool/address/{address}",
+    tag = "btc",
+    params(NetworkPath, GetBalanceParams),
+    responses((status = 200, body = Vec<AddressMempoolTx>))
+)]
+pub async fn get_address_mempool_txs(
+    state: Data<Context>,
+    params: Path<GetBalanceParams>,
+) -> Result<Json<Vec<AddressMempoolTx>>, FBtcApiError> {
+    if !state.is_ready(HealthGate::Full).await {
+        return Err(FBtcApiError::ServiceUnavailable);
+    }
+
+    if let Err(err) = decode_address(&params.address, state.net) {
+        return Err(FBtcApiError::InvalidAddress(format!("{err}")));
+    }
+
+    let mut txs: Vec<_> = state
+        .mempool_index
+        .address_txs(&params.address)
+        .await
+        .into_iter()
+        .map(|(txid, tx)| AddressMempoolTx {
+            tx_hash: txid.into(),
+            incoming: tx.incoming,
+            outgoing: tx.outgoing,
+            first_seen: tx.first_seen,
+        })
+        .collect();
+    txs.sort_by_key(|t| std::cmp::Reverse(t.first_seen));
+
+    Ok(Json(txs))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/mempool/tx-list",
+    tag = "btc",
+    params(NetworkPath),
+    responses((status = 200, body = Response<Vec<String>, RpcError>))
+)]
 pub async fn get_txs_in_mempool(
     state: Data<Context>,
 ) -> Result<Json<GetMempoolTxsResponse>, FBtcApiError> {
     use bitcoincore_rpc::jsonrpc::Error::Rpc as BtcRpcError;
     use bitcoincore_rpc::Error::JsonRpc as BtcJsonRpcError;
 
-    if !state.is_healthy().await {
+    if !state.is_ready(HealthGate::Full).await {
         return Err(FBtcApiError::ServiceUnavailable);
     }
     let result = match state.btc_client.get_raw_mempool() {
@@ -602,10 +1672,17 @@ pub async fn get_txs_in_mempool(
     }))
 }
 
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/tx/{txid}/ins-outs",
+    tag = "btc",
+    params(NetworkPath, ("txid" = String, Path)),
+    responses((status = 200, body = TxInOuts), (status = 410, description = "Queried heights are pruned by `db prune`"))
+)]
 pub async fn get_tx_in_outs(
     state: Data<Context>,
     txid: web::Path<types::Hash>,
-) -> Result<Json<TxInOuts>, FBtcApiError> {
+) -> Result<CachedJson<TxInOuts>, FBtcApiError> {
     // TODO (?)
     // 3. calculate fee
     // 4. (? block info )
@@ -625,15 +1702,69 @@ pub async fn get_tx_in_outs(
         }
     };
 
-    Ok(Json(TxInOuts { inputs, outputs }))
+    let height = outputs
+        .first()
+        .map(|o| o.block)
+        .or(inputs.first().map(|i| i.block));
+    if let Some(height) = height {
+        ensure_not_pruned(&state, height).await?;
+    }
+    let block = match height {
+        Some(height) => match state
+            .db
+            .get_block_by_height(height, crate::indexer::BITCOIN_INDEX)
+            .await
+        {
+            Ok(block) => block,
+            Err(err) => {
+                error!("can't fetch block: height={} error={:#?}", height, err);
+                return Err(FBtcApiError::InternalError);
+            }
+        },
+        None => None,
+    };
+
+    let policy = state.block_cache_policy(height).await;
+    let response = TxInOuts {
+        height: height.map(|h| h as u64),
+        block_hash: block
+            .as_ref()
+            .map(|b| bitcoin::BlockHash::from(&b.hash).to_string()),
+        blocktime: block.as_ref().map(|b| b.blocktime as u64),
+        confirmations: height.and_then(|h| confirmations(&state, h)),
+        network_fee: network_fee(&inputs, &outputs),
+        inputs,
+        outputs,
+    };
+    Ok(CachedJson::new(response, policy))
+}
+
+/// Coinbase txs have no indexed inputs, so they have no fee.
+fn network_fee(inputs: &[InputFull], outputs: &[BtcOutput]) -> Option<u64> {
+    if inputs.is_empty() {
+        return None;
+    }
+
+    let input_sum: i64 = inputs.iter().map(|i| i.amount).sum();
+    let output_sum: i64 = outputs.iter().map(|o| o.amount).sum();
+    input_sum
+        .checked_sub(output_sum)
+        .and_then(|fee| u64::try_from(fee).ok())
 }
 
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/txs/address/{address}",
+    tag = "btc",
+    params(NetworkPath, UtxoRequest, ListTxQuery, PageParams),
+    responses((status = 200, body = ListResult<TxInfo>), (status = 410, description = "Queried heights are pruned by `db prune`"))
+)]
 pub async fn list_address_txs(
     state: Data<Context>,
     params: Path<UtxoRequest>,
     query: Query<ListTxQuery>,
 ) -> Result<Json<ListResult<TxInfo>>, FBtcApiError> {
-    if !state.is_healthy().await {
+    if !state.is_ready(HealthGate::DbOnly).await {
         return Err(FBtcApiError::ServiceUnavailable);
     }
 
@@ -647,100 +1778,459 @@ pub async fn list_address_txs(
         }
     };
 
-    let income_res = state
+    if query.mempool && !state.mempool_index.is_warmed() {
+        return Err(FBtcApiError::ServiceUnavailable);
+    }
+    ensure_not_pruned(&state, query.min_height.unwrap_or_default() as i64).await?;
+
+    let count = match state
         .db
-        .list_address_incoming_txs(&params.address, query.page.order, limit, offset)
-        .await;
-    let income_rows = match income_res {
-        Ok(row) => row,
+        .count_address_txs(&params.address, query.incoming_only, query.min_height)
+        .await
+    {
+        Ok(count) => count as u64,
         Err(err) => {
             error!(
-                "failed to select btc utxos: address={} error={:#?}",
+                "can't count address txs: address={} error={:#?}",
                 params.address, err
             );
             return Err(FBtcApiError::InternalError);
         }
     };
 
-    if query.incoming_only {
-        let records: Vec<TxInfo> = income_rows
-            .iter()
-            .map(|e| TxInfo {
-                tx_hash: e.tx_hash.clone(),
-                block: e.block,
-                spend: false,
-                income: true,
-            })
-            .collect();
-        let resp = ListResult {
-            meta: Some(ListResponseMeta {
-                page: query.page.page.unwrap_or_default(),
-                limit,
-                offset,
-                total_records: 0,
-                has_more: true,
-            }),
-            records,
+    let pending = if query.mempool {
+        pending_address_txs(
+            &state,
+            &params.address,
+            query.incoming_only,
+            query.page.order,
+        )
+        .await
+    } else {
+        Vec::new()
+    };
+
+    // mempool txs are the newest, so they are the head of the list in desc order
+    // and the tail in asc order
+    let pending_count = pending.len() as u64;
+    let (pending_range, confirmed_range) = match query.page.order {
+        OrderBy::Desc => split_page(pending_count, offset as u64, limit as u64),
+        OrderBy::Asc => {
+            let (confirmed, pending) = split_page(count, offset as u64, limit as u64);
+            (pending, confirmed)
+        }
+    };
+
+    let mut confirmed = Vec::new();
+    if !confirmed_range.is_empty() {
+        let res = state
+            .db
+            .list_address_txs(
+                &params.address,
+                query.incoming_only,
+                query.min_height,
+                query.page.order,
+                (confirmed_range.end - confirmed_range.start) as u32,
+                confirmed_range.start as u32,
+            )
+            .await;
+        confirmed = match res {
+            Ok(records) => records,
+            Err(err) => {
+                error!(
+                    "can't fetch address txs: address={} error={:#?}",
+                    params.address, err
+                );
+                return Err(FBtcApiError::InternalError);
+            }
         };
+    }
+
+    let pending_range = pending_range.start.min(pending_count) as usize
+        ..pending_range.end.min(pending_count) as usize;
+    let pending = pending[pending_range].iter().cloned();
+    let records = match query.page.order {
+        OrderBy::Desc => pending.chain(confirmed).collect(),
+        OrderBy::Asc => confirmed.into_iter().chain(pending).collect(),
+    };
+
+    Ok(Json(ListResult {
+        meta: Some(ListResponseMeta::from_counts(
+            limit,
+            offset,
+            count + pending_count,
+        )),
+        records,
+    }))
+}
 
-        return Ok(Json(resp));
+/// Mempool txs of the address ordered by the time they were noticed.
+async fn pending_address_txs(
+    state: &Context,
+    address: &str,
+    incoming_only: bool,
+    order: OrderBy,
+) -> Vec<TxInfo> {
+    let mut txs: Vec<_> = state
+        .mempool_index
+        .address_txs(address)
+        .await
+        .into_iter()
+        .filter(|(_, tx)| tx.incoming || !incoming_only)
+        .collect();
+    txs.sort_by(|(a_id, a), (b_id, b)| (a.first_seen, a_id).cmp(&(b.first_seen, b_id)));
+    if order == OrderBy::Desc {
+        txs.reverse();
     }
 
-    let spend_res = state
+    txs.into_iter()
+        .map(|(txid, tx)| TxInfo {
+            tx_hash: txid.into(),
+            block: -1,
+            income: tx.incoming,
+            spend: tx.outgoing,
+        })
+        .collect()
+}
+
+/// Splits the page window over two concatenated lists by the length of the first one.
+/// The range of the second list is relative to its start.
+fn split_page(first_len: u64, offset: u64, limit: u64) -> (Range<u64>, Range<u64>) {
+    let end = offset + limit;
+    let first = offset.min(first_len)..end.min(first_len);
+    let second = offset.saturating_sub(first_len)..end.saturating_sub(first_len);
+    (first, second)
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/blocks",
+    tag = "btc",
+    params(NetworkPath, ListBlocksQuery, PageParams),
+    responses((status = 200, body = ListResult<BlockInfo>))
+)]
+pub async fn list_blocks(
+    state: Data<Context>,
+    query: Query<ListBlocksQuery>,
+) -> Result<Json<ListResult<BlockInfo>>, FBtcApiError> {
+    let (limit, offset) = match query.page.limit_offset() {
+        Ok(v) => v,
+        Err(err) => {
+            return Err(FBtcApiError::BadInput(format!("{err}")));
+        }
+    };
+
+    let count = match state.db.count_blocks(query.indexer.clone()).await {
+        Ok(c) => Some(c as u64),
+        Err(err) => {
+            error!("can't count blocks: error={:#?}", err);
+            None
+        }
+    };
+
+    let res = state
         .db
-        .list_address_outgoing_txs(&params.address, query.page.order, limit, offset)
+        .list_blocks(query.page.order, limit, offset, query.indexer.clone())
         .await;
-    let spend_rows = match spend_res {
-        Ok(row) => row,
+
+    match res {
+        Ok(blocks) => Ok(Json(ListResult {
+            meta: Some(ListResponseMeta::from_optional_total(
+                limit,
+                offset,
+                count,
+                blocks.len(),
+            )),
+            records: blocks,
+        })),
         Err(err) => {
             error!(
-                "failed to select btc utxos: address={} error={:#?}",
-                params.address, err
+                "can't fetch blocks: indexer={:?} error={:#?}",
+                query.indexer, err
             );
+            Err(FBtcApiError::InternalError)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/blocks/{height}",
+    tag = "btc",
+    params(NetworkPath, ("height" = i64, Path)),
+    responses((status = 200, body = BlockDetails))
+)]
+pub async fn get_block(
+    state: Data<Context>,
+    height: Path<i64>,
+) -> Result<CachedJson<BlockDetails>, FBtcApiError> {
+    let height = height.into_inner();
+    let rows = match state.db.get_blocks_at_height(height).await {
+        Ok(rows) => rows,
+        Err(err) => {
+            error!("can't fetch blocks: height={} error={:#?}", height, err);
             return Err(FBtcApiError::InternalError);
         }
     };
 
-    let income_idx: BTreeSet<_> = income_rows.iter().map(|e| e.tx_hash.clone()).collect();
-    let out_idx: BTreeSet<_> = spend_rows.iter().map(|e| e.tx_hash.clone()).collect();
+    if rows.is_empty() {
+        return Err(FBtcApiError::NotFound);
+    }
 
-    let mut records: Vec<TxInfo> = spend_rows
-        .iter()
-        .map(|e| TxInfo {
-            tx_hash: e.tx_hash.clone(),
-            block: e.block,
-            spend: true,
-            income: income_idx.contains(&e.tx_hash),
+    let consistent = rows.iter().all(|b| b.hash == rows[0].hash);
+    let indexers = rows
+        .into_iter()
+        .map(|b| {
+            let block = IndexedBlock {
+                hash: b.hash,
+                blocktime: b.blocktime,
+            };
+            (b.indexer, block)
         })
         .collect();
 
-    let extra: Vec<_> = spend_rows
-        .iter()
-        .filter_map(|e| {
-            if out_idx.contains(&e.tx_hash) {
-                None
-            } else {
-                Some(TxInfo {
-                    tx_hash: e.tx_hash.clone(),
-                    block: e.block,
-                    spend: false,
-                    income: true,
-                })
+    // indexers may disagree until one of them handles the reorg
+    let policy = if consistent {
+        state.block_cache_policy(Some(height)).await
+    } else {
+        CachePolicy::NoStore
+    };
+    let response = BlockDetails {
+        height,
+        consistent,
+        indexers,
+    };
+    Ok(CachedJson::new(response, policy))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/blocks/{height}/stats",
+    tag = "btc",
+    params(NetworkPath, ("height" = i64, Path)),
+    responses((status = 200, body = BlockStats))
+)]
+pub async fn get_block_stats(
+    state: Data<Context>,
+    height: Path<i64>,
+) -> Result<CachedJson<BlockStats>, FBtcApiError> {
+    let height = height.into_inner();
+    match state.db.get_block_stats(height).await {
+        Ok(Some(stats)) => {
+            let policy = state.block_cache_policy(Some(height)).await;
+            Ok(CachedJson::new(stats, policy))
+        }
+        Ok(None) => Err(FBtcApiError::NotFound),
+        Err(err) => {
+            error!(
+                "can't fetch block stats: height={} error={:#?}",
+                height, err
+            );
+            Err(FBtcApiError::InternalError)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/blocks/stats",
+    tag = "btc",
+    params(NetworkPath, BlockStatsQuery),
+    responses((status = 200, body = ListResult<BlockStats>))
+)]
+pub async fn list_block_stats(
+    state: Data<Context>,
+    query: Query<BlockStatsQuery>,
+) -> Result<CachedJson<ListResult<BlockStats>>, FBtcApiError> {
+    let (from, to) = query
+        .range()
+        .map_err(|err| FBtcApiError::BadInput(format!("{err}")))?;
+
+    match state.db.list_block_stats(from, to).await {
+        Ok(records) => {
+            let policy = state.block_cache_policy(Some(to)).await;
+            let response = ListResult {
+                meta: None,
+                records,
+            };
+            Ok(CachedJson::new(response, policy))
+        }
+        Err(err) => {
+            error!(
+                "can't fetch block stats: from={} to={} error={:#?}",
+                from, to, err
+            );
+            Err(FBtcApiError::InternalError)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_stats_query() {
+        let query = Query::<BlockStatsQuery>::from_query("from=10&to=20").unwrap();
+        assert_eq!(query.range().unwrap(), (10, 20));
+
+        let query = Query::<BlockStatsQuery>::from_query("from=10&to=10").unwrap();
+        assert_eq!(query.range().unwrap(), (10, 10));
+
+        let query = Query::<BlockStatsQuery>::from_query("from=20&to=10").unwrap();
+        assert!(query.range().is_err());
+
+        let query = Query::<BlockStatsQuery>::from_query("from=0&to=1000").unwrap();
+        assert!(query.range().is_err());
+
+        assert!(Query::<BlockStatsQuery>::from_query("from=10").is_err());
+    }
+
+    #[test]
+    fn test_balance_history_query() {
+        let query = Query::<BalanceHistoryQuery>::from_query("").unwrap();
+        assert_eq!(query.granularity, HistoryGranularity::Block);
+        assert_eq!(query.range().unwrap(), (0, i64::MAX));
+
+        let query =
+            Query::<BalanceHistoryQuery>::from_query("from_block=10&to_block=20&granularity=daily")
+                .unwrap();
+        assert_eq!(query.granularity, HistoryGranularity::Daily);
+        assert_eq!(query.range().unwrap(), (10, 20));
+
+        let query = Query::<BalanceHistoryQuery>::from_query("from_block=20&to_block=10").unwrap();
+        assert!(query.range().is_err());
+        assert!(Query::<BalanceHistoryQuery>::from_query("granularity=monthly").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_balance_history_buckets() {
+        use futures::StreamExt;
+
+        let outputs: Vec<_> = [(1000, 500), (1100, 300), (1200, 200)]
+            .into_iter()
+            .map(|(block, amount)| BtcOutputsSum {
+                block,
+                amount,
+                ..Default::default()
+            })
+            .collect();
+        let inputs = vec![InputsSum {
+            block: 1150,
+            amount: 500,
+            ..Default::default()
+        }];
+        let history = |opening, from, granularity| {
+            let outputs = futures::stream::iter(outputs.clone().into_iter().map(Ok)).boxed();
+            let inputs = futures::stream::iter(inputs.clone().into_iter().map(Ok)).boxed();
+            balance_history(outputs, inputs, opening, from, granularity)
+        };
+
+        let points = history(0, 0, HistoryGranularity::Block).await.unwrap();
+        let balances: Vec<_> = points.iter().map(|p| (p.block, p.balance)).collect();
+        assert_eq!(
+            balances,
+            vec![(1000, 500), (1100, 800), (1150, 300), (1200, 500)]
+        );
+
+        // blocks 1000..1007 fall into the weekly bucket that starts at 0,
+        // it's clamped to the start of the range
+        let points = history(10_000, 1000, HistoryGranularity::Weekly)
+            .await
+            .unwrap();
+        let balances: Vec<_> = points
+            .iter()
+            .map(|p| (p.block, p.income, p.spent, p.balance))
+            .collect();
+        assert_eq!(
+            balances,
+            vec![(1000, 500, 0, 10_500), (1008, 500, 500, 10_500)]
+        );
+    }
+
+    #[test]
+    fn test_timeout_error() {
+        use actix_web::http::StatusCode;
+        use api_core::api_errors::ApiError;
+
+        let err = db_error(&sqlx::Error::RowNotFound);
+        assert!(matches!(err, FBtcApiError::InternalError));
+
+        let err = ApiError::from(&FBtcApiError::Timeout);
+        assert_eq!(err.http_code, StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!((err.code, err.status.as_str()), (504, "timeout"));
+        assert!(matches!(
+            FBtcApiError::try_from(&err).unwrap(),
+            FBtcApiError::Timeout
+        ));
+    }
+
+    #[test]
+    fn test_not_enough_balance_error() {
+        use api_core::api_errors::ApiError;
+
+        let err = collector_error(
+            CollectorError::NotEnoughBalance {
+                available: 50_000_000,
+                collectable: 1200,
+                target: 60_000,
+            },
+            "address",
+        );
+        let api_err = ApiError::from(&err);
+        assert_eq!(api_err.details["required"], "60000");
+        assert_eq!(api_err.details["available"], "50000000");
+        assert_eq!(api_err.details["collectable"], "1200");
+        assert!(matches!(
+            FBtcApiError::try_from(&api_err).unwrap(),
+            FBtcApiError::NotEnoughBalance {
+                required: 60_000,
+                available: 50_000_000,
+                collectable: 1200,
             }
-        })
-        .collect();
-    records.extend(extra);
+        ));
+    }
 
-    let resp = ListResult {
-        meta: Some(ListResponseMeta {
-            page: query.page.page.unwrap_or_default(),
-            limit,
-            offset,
-            total_records: 0,
-            has_more: true,
-        }),
-        records,
-    };
+    #[test]
+    fn test_utxo_query_accepts_dust_first() {
+        let query = Query::<UtxoQuery>::from_query("sorting=dust_first&limit=10").unwrap();
+        assert_eq!(query.sorting, UtxoSortMode::DustFirst);
+        assert_eq!(query.page.limit, Some(10));
 
-    Ok(Json(resp))
+        let query = Query::<UtxoQuery>::from_query("").unwrap();
+        assert_eq!(query.sorting, UtxoSortMode::Amount);
+
+        assert!(Query::<UtxoQuery>::from_query("sorting=dust").is_err());
+    }
+
+    #[test]
+    fn test_split_page() {
+        // 3 items in the first list
+        assert_eq!(split_page(3, 0, 2), (0..2, 0..0));
+        assert_eq!(split_page(3, 2, 2), (2..3, 0..1));
+        assert_eq!(split_page(3, 4, 2), (3..3, 1..3));
+        // empty first list
+        assert_eq!(split_page(0, 10, 5), (0..0, 10..15));
+    }
+
+    #[test]
+    fn test_network_fee() {
+        let input = |amount| InputFull {
+            amount,
+            ..Default::default()
+        };
+        let output = |amount| BtcOutput {
+            amount,
+            ..Default::default()
+        };
+
+        assert_eq!(network_fee(&[], &[output(50)]), None);
+        assert_eq!(
+            network_fee(&[input(100), input(50)], &[output(120), output(10)]),
+            Some(20)
+        );
+        assert_eq!(network_fee(&[input(100)], &[output(100)]), Some(0));
+        // some inputs are not indexed
+        assert_eq!(network_fee(&[input(100)], &[output(120)]), None);
+    }
 }
@@ -0,0 +1,162 @@
+use actix_web::web::{Data, Json, Path, Query, ReqData};
+use actix_web::HttpResponse;
+use orbtc_indexer_api::FBtcApiError;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use super::context::Context;
+use super::requests::decode_address;
+use super::swagger::NetworkPath;
+use crate::db::{AddressLabel, ApiKey};
+
+const MAX_LABEL_LEN: usize = 64;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CreateLabelRequest {
+    pub address: String,
+    /// Up to 64 chars, leading and trailing whitespaces are trimmed.
+    pub label: String,
+}
+
+/// Exactly one of the filters must be set.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListLabelsQuery {
+    /// Labels of the address.
+    pub address: Option<String>,
+    /// Addresses with the label.
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, IntoParams)]
+#[into_params(parameter_in = Path)]
+pub struct LabelPath {
+    pub address: String,
+    pub label: String,
+}
+
+fn validate_label(label: &str) -> Result<&str, FBtcApiError> {
+    let label = label.trim();
+    if label.is_empty() {
+        return Err(FBtcApiError::BadInput("label can't be empty".into()));
+    }
+    if label.chars().count() > MAX_LABEL_LEN {
+        return Err(FBtcApiError::BadInput(format!(
+            "label is too long, max allowed({MAX_LABEL_LEN}) chars"
+        )));
+    }
+    Ok(label)
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/labels",
+    tag = "labels",
+    params(NetworkPath, ListLabelsQuery),
+    responses((status = 200, body = Vec<AddressLabel>))
+)]
+pub async fn list_labels(
+    state: Data<Context>,
+    query: Query<ListLabelsQuery>,
+) -> Result<Json<Vec<AddressLabel>>, FBtcApiError> {
+    let result = match (&query.address, &query.label) {
+        (Some(address), None) => state.db.select_labels_by_address(address).await,
+        (None, Some(label)) => state.db.select_labels_by_label(label.trim()).await,
+        _ => {
+            return Err(FBtcApiError::BadInput(
+                "either address or label must be set".into(),
+            ))
+        }
+    };
+
+    match result {
+        Ok(rows) => Ok(Json(rows)),
+        Err(err) => {
+            error!("can't fetch labels: query={:?} error={:#?}", query, err);
+            Err(FBtcApiError::InternalError)
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/{network}/labels",
+    tag = "labels",
+    params(NetworkPath),
+    request_body = CreateLabelRequest,
+    responses((status = 200, body = AddressLabel))
+)]
+pub async fn create_label(
+    state: Data<Context>,
+    request: Json<CreateLabelRequest>,
+    api_key: ReqData<ApiKey>,
+) -> Result<Json<AddressLabel>, FBtcApiError> {
+    if let Err(err) = decode_address(&request.address, state.net) {
+        return Err(FBtcApiError::InvalidAddress(format!("{err}")));
+    }
+    let label = validate_label(&request.label)?;
+
+    match state
+        .db
+        .upsert_address_label(&request.address, label, &api_key.name)
+        .await
+    {
+        Ok(row) => {
+            info!(
+                "label was set: address={} label={} key={}",
+                row.address, row.label, row.created_by
+            );
+            Ok(Json(row))
+        }
+        Err(err) => {
+            error!(
+                "can't insert label: address={} error={:#?}",
+                request.address, err
+            );
+            Err(FBtcApiError::InternalError)
+        }
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/{network}/labels/{address}/{label}",
+    tag = "labels",
+    params(NetworkPath, LabelPath),
+    responses((status = 200, description = "Label is deleted"))
+)]
+pub async fn delete_label(
+    state: Data<Context>,
+    path: Path<LabelPath>,
+) -> Result<HttpResponse, FBtcApiError> {
+    let LabelPath { address, label } = path.into_inner();
+    match state.db.delete_address_label(&address, label.trim()).await {
+        Ok(true) => {}
+        Ok(false) => return Err(FBtcApiError::NotFound),
+        Err(err) => {
+            error!(
+                "can't delete label: address={address} label={label} error={:#?}",
+                err
+            );
+            return Err(FBtcApiError::InternalError);
+        }
+    }
+
+    info!("label was deleted: address={address} label={label}");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_label() {
+        assert_eq!(validate_label(" treasury ").unwrap(), "treasury");
+        assert!(validate_label("  ").is_err());
+        assert!(validate_label(&"a".repeat(MAX_LABEL_LEN)).is_ok());
+        assert!(validate_label(&"a".repeat(MAX_LABEL_LEN + 1)).is_err());
+        // chars, not bytes
+        assert!(validate_label(&"є".repeat(MAX_LABEL_LEN)).is_ok());
+    }
+}
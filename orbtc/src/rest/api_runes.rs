@@ -1,91 +1,29 @@
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::str::FromStr;
 
-use actix_web::http::StatusCode;
-use actix_web::web::{Data, Json, Path, Query};
-use actix_web::{HttpResponse, ResponseError};
-use api_core::api_errors::*;
-use api_core::pages::{ListResponseMeta, ListResult};
+use actix_web::web::{Data, Json, Path, Query, ReqData};
+use api_core::pages::{ListResponseMeta, ListResult, PageParams};
 use bigdecimal::{BigDecimal, ToPrimitive};
+use futures::stream::{BoxStream, TryStreamExt};
 use orbtc_indexer_api::{types, *};
+use ordinals::RuneId;
 use serde::{Deserialize, Serialize};
-
-use super::context::Context;
-use super::requests::decode_address;
-use crate::service::utxo_collector::{min_utxos_to_reach_target, KnapsackError};
-
-#[derive(Debug, thiserror::Error)]
-pub enum RuneApiError {
-    #[error("something went wrong")]
-    InternalError,
-    #[error("service is temporaly unavailable; check the /status response")]
-    ServiceUnavailable,
-    #[error("rune with this name not exist: {0}")]
-    NotFound(String),
-    #[error("rune name is invalid: {0}")]
-    InvalidRuneName(String),
-    #[error("address is invalid: {0}")]
-    InvalidAddress(String),
-    #[error("bad input: {0}")]
-    BadInput(String),
-    #[error("unauthorized")]
-    Unauthorized,
-    #[error("not enough balance: required={required}, available={available}")]
-    NotEnoughBalance { required: u128, available: u128 },
-}
-
-impl From<&RuneApiError> for ApiError {
-    fn from(error: &RuneApiError) -> ApiError {
-        use RuneApiError::*;
-        let mut details = HashMap::new();
-        let code = match error {
-            Unauthorized => ApiErrorCode::AccessDenied,
-            InternalError => ApiErrorCode::InternalError,
-            ServiceUnavailable => ApiErrorCode::ServiceUnavailable,
-            NotFound(_) => ApiErrorCode::NotFound,
-            InvalidRuneName(_) => ApiErrorCode::BadInput,
-            InvalidAddress(_) => ApiErrorCode::BadInput,
-            BadInput(_) => ApiErrorCode::BadInput,
-            NotEnoughBalance {
-                required,
-                available,
-            } => {
-                details.insert("required".into(), required.to_string());
-                details.insert("available".into(), available.to_string());
-                ApiErrorCode::NotEnoughBalance
-            }
-        };
-        ApiError {
-            code: code as u16,
-            status: code.to_string(),
-            http_code: error.status_code(),
-            message: error.to_string(),
-            details,
-        }
-    }
-}
-
-impl ResponseError for RuneApiError {
-    fn status_code(&self) -> actix_web::http::StatusCode {
-        use RuneApiError::*;
-        match self {
-            Unauthorized => StatusCode::UNAUTHORIZED,
-            InternalError => StatusCode::INTERNAL_SERVER_ERROR,
-            ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
-            NotFound(_) => StatusCode::NOT_FOUND,
-            InvalidRuneName(_) => StatusCode::BAD_REQUEST,
-            InvalidAddress(_) => StatusCode::BAD_REQUEST,
-            BadInput(_) => StatusCode::BAD_REQUEST,
-            NotEnoughBalance { .. } => StatusCode::BAD_REQUEST,
-        }
-    }
-
-    fn error_response(&self) -> HttpResponse {
-        ApiError::from(self).into()
-    }
-}
-
-#[derive(Debug, Serialize, Deserialize)]
+use tracing::error;
+use utoipa::IntoParams;
+
+use super::context::{Context, HealthGate, MAX_LOCK_ATTEMPTS};
+use super::http_cache::{CachePolicy, CachedJson};
+use super::requests::{decode_address, parse_cursor};
+use super::swagger::NetworkPath;
+use crate::db::{is_timeout, schema, scopes, ApiKey};
+use crate::indexer::{
+    allocate, find_commitment, CommitmentError, Issuance, MintChecker, RUNES_INDEX,
+};
+use crate::service::psbt::{self, PsbtError};
+use crate::service::utxo_collector::{CollectorError, FilterOpts};
+
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Path)]
 pub struct RuneAddressPath {
     pub address: String,
     pub rune: String,
@@ -112,11 +50,18 @@ pub async fn search_runes(
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/runes",
+    tag = "runes",
+    params(NetworkPath, ListRunesQuery, PageParams),
+    responses((status = 200, body = ListResult<Rune>))
+)]
 pub async fn list_runes(
     state: Data<Context>,
     params: Query<ListRunesQuery>,
 ) -> Result<Json<ListResult<Rune>>, RuneApiError> {
-    if !state.is_healthy().await {
+    if !state.is_ready(HealthGate::DbOnly).await {
         return Err(RuneApiError::ServiceUnavailable);
     }
 
@@ -127,47 +72,51 @@ pub async fn list_runes(
         }
     };
 
-    let mut name_filter = None;
-    if let Some(name) = params.name.clone() {
-        let name = name.to_ascii_uppercase(); // support search by lowercase
-        match ordinals::SpacedRune::from_str(&name) {
-            Ok(spr) => {
-                name_filter = Some(spr.rune.to_string());
-            }
-            Err(err) => {
-                return Err(RuneApiError::InvalidRuneName(format!("{err}")));
-            }
-        };
-    }
+    let filter = runes_list_filter(&params)?;
 
     let count_res = state
         .db
-        .count_runes(name_filter.clone(), params.featured)
+        .count_runes(
+            filter.name.clone(),
+            filter.featured,
+            filter.rune_ids.clone(),
+            filter.from_block,
+            filter.to_block,
+        )
         .await;
 
     let count = match count_res {
-        Ok(count) => count,
+        Ok(count) => Some(count as u64),
         Err(err) => {
             error!("can't count runes: error={:#?}", err);
-            0
+            None
         }
     };
 
     let res = state
         .db
         .list_runes(
+            params.sort.unwrap_or_default(),
             params.page.order,
             limit,
             offset,
-            name_filter,
-            params.featured,
+            filter.name,
+            filter.featured,
+            filter.rune_ids,
+            filter.from_block,
+            filter.to_block,
         )
         .await;
 
     match res {
         Ok(runes_rows) => {
             let resp = ListResult {
-                meta: Some(ListResponseMeta::new(limit, offset, count as u64)),
+                meta: Some(ListResponseMeta::from_optional_total(
+                    limit,
+                    offset,
+                    count,
+                    runes_rows.len(),
+                )),
                 records: runes_rows,
             };
 
@@ -180,40 +129,283 @@ pub async fn list_runes(
     }
 }
 
+/// Validated filters of the [`ListRunesQuery`].
+#[derive(Debug)]
+struct RunesListFilter {
+    name: Option<String>,
+    featured: Option<bool>,
+    rune_ids: Vec<String>,
+    from_block: Option<i64>,
+    to_block: Option<i64>,
+}
+
+fn runes_list_filter(params: &ListRunesQuery) -> Result<RunesListFilter, RuneApiError> {
+    let mut name = None;
+    if let Some(n) = params.name.as_ref() {
+        let n = n.to_ascii_uppercase(); // support search by lowercase
+        match ordinals::SpacedRune::from_str(&n) {
+            Ok(spr) => {
+                name = Some(spr.rune.to_string());
+            }
+            Err(err) => {
+                return Err(RuneApiError::InvalidRuneName(format!("{err}")));
+            }
+        };
+    }
+
+    if let Some(id) = params.rune_ids.iter().find(|id| !is_rune_id(id)) {
+        return Err(RuneApiError::BadInput(format!("invalid rune id: {id}")));
+    }
+    let (from_block, to_block) = (
+        params.from_block.map(|b| b as i64),
+        params.to_block.map(|b| b as i64),
+    );
+    if let (Some(from), Some(to)) = (from_block, to_block) {
+        if from > to {
+            return Err(RuneApiError::BadInput(format!(
+                "from_block({from}) must not be greater than to_block({to})"
+            )));
+        }
+    }
+
+    Ok(RunesListFilter {
+        name,
+        featured: params.featured,
+        rune_ids: params.rune_ids.clone(),
+        from_block,
+        to_block,
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/runes/stats",
+    tag = "runes",
+    params(NetworkPath, ListRunesQuery, PageParams),
+    responses((status = 200, body = ListResult<RuneStats>))
+)]
+pub async fn list_runes_stats(
+    state: Data<Context>,
+    params: Query<ListRunesQuery>,
+) -> Result<Json<ListResult<RuneStats>>, RuneApiError> {
+    if !state.is_ready(HealthGate::DbOnly).await {
+        return Err(RuneApiError::ServiceUnavailable);
+    }
+
+    let (limit, offset) = match params.page.limit_offset() {
+        Ok(v) => v,
+        Err(err) => {
+            return Err(RuneApiError::BadInput(format!("{err}")));
+        }
+    };
+    let filter = runes_list_filter(&params)?;
+    let sort = params.sort.unwrap_or_default();
+
+    let key = format!("{sort}:{}:{limit}:{offset}:{filter:?}", params.page.order);
+    if let Some(stats) = state.cached_runes_stats(&key).await {
+        return Ok(Json(stats));
+    }
+
+    let count = match state
+        .db
+        .count_runes(
+            filter.name.clone(),
+            filter.featured,
+            filter.rune_ids.clone(),
+            filter.from_block,
+            filter.to_block,
+        )
+        .await
+    {
+        Ok(count) => Some(count as u64),
+        Err(err) => {
+            error!("can't count runes: error={:#?}", err);
+            None
+        }
+    };
+
+    let records = match state
+        .db
+        .list_runes_stats(
+            sort,
+            params.page.order,
+            limit,
+            offset,
+            filter.name,
+            filter.featured,
+            filter.rune_ids,
+            filter.from_block,
+            filter.to_block,
+        )
+        .await
+    {
+        Ok(records) => records,
+        Err(err) => {
+            error!("can't fetch runes stats: error={:#?}", err);
+            return Err(RuneApiError::InternalError);
+        }
+    };
+
+    let resp = ListResult {
+        meta: Some(ListResponseMeta::from_optional_total(
+            limit,
+            offset,
+            count,
+            records.len(),
+        )),
+        records,
+    };
+    state.cache_runes_stats(key, resp.clone()).await;
+    Ok(Json(resp))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/runes/{rune}",
+    tag = "runes",
+    params(NetworkPath, ("rune" = String, Path, description = "Spaced rune name or rune id")),
+    responses((status = 200, body = Rune))
+)]
 pub async fn get_rune(
     state: Data<Context>,
     rune: Path<String>,
-) -> Result<Json<Rune>, RuneApiError> {
-    if !state.is_healthy().await {
+) -> Result<CachedJson<Rune>, RuneApiError> {
+    if !state.is_ready(HealthGate::DbOnly).await {
         return Err(RuneApiError::ServiceUnavailable);
     }
 
-    let name_filter = {
-        match ordinals::SpacedRune::from_str(&rune) {
-            Ok(spr) => spr.rune.to_string(),
-            Err(err) => {
-                return Err(RuneApiError::InvalidRuneName(format!("{err}")));
-            }
+    // etching is immutable, but mints and burns change the supply
+    let row = fetch_rune(&state, &rune).await?;
+    Ok(CachedJson::new(row, CachePolicy::Revalidate))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/runes/{rune}/details",
+    tag = "runes",
+    params(NetworkPath, ("rune" = String, Path, description = "Spaced rune name or rune id")),
+    responses((status = 200, body = RuneDetails))
+)]
+pub async fn get_rune_details(
+    state: Data<Context>,
+    rune: Path<String>,
+) -> Result<Json<RuneDetails>, RuneApiError> {
+    if !state.is_ready(HealthGate::DbOnly).await {
+        return Err(RuneApiError::ServiceUnavailable);
+    }
+
+    let row = fetch_rune(&state, &rune).await?;
+    let height = match state.db.get_last_indexed_block(RUNES_INDEX).await {
+        Ok(height) => height,
+        Err(err) => {
+            error!("can't fetch runes indexer height: error={:#?}", err);
+            return Err(RuneApiError::InternalError);
         }
     };
 
-    let res = state.db.get_rune(&name_filter).await;
+    Ok(Json(rune_details(row, height)))
+}
+
+fn rune_details(rune: Rune, indexed_height: u64) -> RuneDetails {
+    let checker = MintChecker {
+        block: rune.block as u64,
+        mints: rune.mints as u128,
+        premine: rune.premine.to_u128().unwrap_or_default(),
+        terms: schema::decode_terms(&rune.raw_data),
+    };
+
+    let terms = checker.terms.map(|terms| {
+        let cap = terms.cap.unwrap_or_default();
+        RuneMintTerms {
+            amount: BigDecimal::from(terms.amount.unwrap_or_default()),
+            cap: BigDecimal::from(cap),
+            remaining_mints: BigDecimal::from(cap.saturating_sub(checker.mints)),
+            start_height: checker.start(),
+            end_height: checker.end(),
+        }
+    });
+
+    RuneDetails {
+        mintable_now: checker.mintable(indexed_height + 1).is_ok(),
+        terms,
+        rune,
+    }
+}
+
+async fn fetch_rune(state: &Context, rune: &str) -> Result<Rune, RuneApiError> {
+    let res = if is_rune_id(rune) {
+        state.db.get_rune_by_rune_id(rune).await
+    } else {
+        let name_filter = {
+            match ordinals::SpacedRune::from_str(rune) {
+                Ok(spr) => spr.rune.to_string(),
+                Err(err) => {
+                    return Err(RuneApiError::InvalidRuneName(format!("{err}")));
+                }
+            }
+        };
+        state.db.get_rune(&name_filter).await
+    };
     match res {
-        Ok(Some(row)) => Ok(Json(row)),
+        Ok(Some(row)) => Ok(row),
         Ok(None) => Err(RuneApiError::NotFound(rune.to_owned())),
         Err(err) => {
-            error!("can't fetch rune by name: rune={rune} error={:#?}", err);
+            error!("can't fetch rune: rune={rune} error={:#?}", err);
             Err(RuneApiError::InternalError)
         }
     }
 }
 
+/// Resolves the `{rune}` path parameter to the canonical rune name,
+/// it can be either a spaced rune name or a rune id (`block:tx`).
+/// Returns `NotFound` for runes that aren't etched, so queries for a typo'd name
+/// don't silently return empty results.
+async fn resolve_rune_name(state: &Context, rune: &str) -> Result<String, RuneApiError> {
+    if !is_rune_id(rune) {
+        let name = match ordinals::SpacedRune::from_str(rune) {
+            Ok(spr) => spr.rune.to_string(),
+            Err(err) => return Err(RuneApiError::InvalidRuneName(format!("{err}"))),
+        };
+        return match state.rune_exists(&name).await {
+            Ok(true) => Ok(name),
+            Ok(false) => Err(RuneApiError::NotFound(rune.to_owned())),
+            Err(err) => {
+                error!("can't check the rune: rune={rune} error={:#?}", err);
+                Err(RuneApiError::InternalError)
+            }
+        };
+    }
+
+    match state.db.get_rune_by_rune_id(rune).await {
+        Ok(Some(row)) => Ok(row.name),
+        Ok(None) => Err(RuneApiError::NotFound(rune.to_owned())),
+        Err(err) => {
+            error!("can't fetch rune by id: rune_id={rune} error={:#?}", err);
+            Err(RuneApiError::InternalError)
+        }
+    }
+}
+
+fn is_rune_id(value: &str) -> bool {
+    let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    value
+        .split_once(':')
+        .is_some_and(|(block, tx)| is_number(block) && is_number(tx))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/runes/{rune}/balance",
+    tag = "runes",
+    params(NetworkPath, ("rune" = String, Path, description = "Spaced rune name or rune id"), RunesHoldersQuery, PageParams),
+    responses((status = 200, body = ListResult<RuneHolder>), (status = 504, description = "Query timeout"))
+)]
 pub async fn list_rune_holders(
     state: Data<Context>,
     rune: Path<String>,
     query: Query<RunesHoldersQuery>,
-) -> Result<Json<ListResult<RuneBalance>>, RuneApiError> {
-    if !state.is_healthy().await {
+) -> Result<Json<ListResult<RuneHolder>>, RuneApiError> {
+    if !state.is_ready(HealthGate::DbOnly).await {
         return Err(RuneApiError::ServiceUnavailable);
     }
 
@@ -223,190 +415,969 @@ pub async fn list_rune_holders(
             return Err(RuneApiError::BadInput(format!("{err}")));
         }
     };
-
-    let rune = {
-        match ordinals::SpacedRune::from_str(&rune) {
-            Ok(spr) => spr.rune.to_string(),
-            Err(err) => {
-                return Err(RuneApiError::InvalidRuneName(format!("{err}")));
-            }
+    let amount_threshold = match query.amount_threshold() {
+        Ok(v) => v,
+        Err(err) => {
+            return Err(RuneApiError::BadInput(format!("{err}")));
         }
     };
 
+    let rune = resolve_rune_name(&state, &rune).await?;
+
     let res = state
         .db
         .get_rune_holders(
             &rune,
             query.page.order,
-            query.amount_threshold,
+            amount_threshold.clone(),
             limit,
             offset,
         )
         .await;
 
-    let balances = match res {
-        Ok(balances) => balances,
+    let holders = match res {
+        Ok(holders) => holders,
         Err(err) => {
             error!("can't fetch rune holders: rune={rune} error={:#?}", err);
+            return Err(rune_db_error(&err));
+        }
+    };
+
+    let total = match state.db.count_rune_holders(&rune, amount_threshold).await {
+        Ok(count) => Some(count as u64),
+        Err(err) => {
+            error!("can't count rune holders: rune={rune} error={:#?}", err);
+            None
+        }
+    };
+
+    Ok(Json(ListResult {
+        meta: Some(ListResponseMeta::from_optional_total(
+            limit,
+            offset,
+            total,
+            holders.len(),
+        )),
+        records: holders,
+    }))
+}
+
+/// Lists transactions that allocated the rune to outputs, grouped by transaction.
+/// Transactions that only burn the rune (allocate it to OP_RETURN) are not indexed
+/// in `runes_outputs`, so they are not present in the list.
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/runes/{rune}/activity",
+    tag = "runes",
+    params(NetworkPath, ("rune" = String, Path, description = "Spaced rune name or rune id"), PageParams),
+    responses((status = 200, body = ListResult<RuneActivity>))
+)]
+pub async fn list_rune_activity(
+    state: Data<Context>,
+    rune: Path<String>,
+    query: Query<PageParams>,
+) -> Result<Json<ListResult<RuneActivity>>, RuneApiError> {
+    if !state.is_ready(HealthGate::DbOnly).await {
+        return Err(RuneApiError::ServiceUnavailable);
+    }
+
+    let (limit, offset) = match query.limit_offset() {
+        Ok(v) => v,
+        Err(err) => {
+            return Err(RuneApiError::BadInput(format!("{err}")));
+        }
+    };
+
+    let rune = resolve_rune_name(&state, &rune).await?;
+
+    let res = state
+        .db
+        .list_rune_activity(&rune, query.order, limit, offset)
+        .await;
+    let activity = match res {
+        Ok(activity) => activity,
+        Err(err) => {
+            error!("can't fetch rune activity: rune={rune} error={:#?}", err);
             return Err(RuneApiError::InternalError);
         }
     };
 
+    let total = match state.db.count_rune_activity(&rune).await {
+        Ok(count) => Some(count as u64),
+        Err(err) => {
+            error!("can't count rune activity: rune={rune} error={:#?}", err);
+            None
+        }
+    };
+
     Ok(Json(ListResult {
-        records: balances,
-        meta: Some(ListResponseMeta::new(limit, offset, 0)),
+        meta: Some(ListResponseMeta::from_optional_total(
+            limit,
+            offset,
+            total,
+            activity.len(),
+        )),
+        records: activity,
     }))
 }
 
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/runes/{rune}/events",
+    tag = "runes",
+    params(NetworkPath, ("rune" = String, Path, description = "Spaced rune name or rune id"), RuneEventsQuery, PageParams),
+    responses((status = 200, body = ListResult<RuneEvent>))
+)]
+pub async fn list_rune_events(
+    state: Data<Context>,
+    rune: Path<String>,
+    query: Query<RuneEventsQuery>,
+) -> Result<Json<ListResult<RuneEvent>>, RuneApiError> {
+    if !state.is_ready(HealthGate::DbOnly).await {
+        return Err(RuneApiError::ServiceUnavailable);
+    }
+
+    let (limit, offset) = match query.page.limit_offset() {
+        Ok(v) => v,
+        Err(err) => {
+            return Err(RuneApiError::BadInput(format!("{err}")));
+        }
+    };
+
+    let rune = resolve_rune_name(&state, &rune).await?;
+
+    let res = state
+        .db
+        .list_rune_events(&rune, query.event_type, query.page.order, limit, offset)
+        .await;
+    let events = match res {
+        Ok(events) => events,
+        Err(err) => {
+            error!("can't fetch rune events: rune={rune} error={:#?}", err);
+            return Err(RuneApiError::InternalError);
+        }
+    };
+
+    let total = match state.db.count_rune_events(&rune, query.event_type).await {
+        Ok(count) => Some(count as u64),
+        Err(err) => {
+            error!("can't count rune events: rune={rune} error={:#?}", err);
+            None
+        }
+    };
+
+    Ok(Json(ListResult {
+        meta: Some(ListResponseMeta::from_optional_total(
+            limit,
+            offset,
+            total,
+            events.len(),
+        )),
+        records: events,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/runes/{rune}/balance/{address}",
+    tag = "runes",
+    params(NetworkPath, RuneAddressPath),
+    responses((status = 200, body = RuneBalance))
+)]
 pub async fn get_rune_balance(
     state: Data<Context>,
     params: Path<RuneAddressPath>,
 ) -> Result<Json<RuneBalance>, RuneApiError> {
-    if !state.is_healthy().await {
+    if !state.is_ready(HealthGate::DbOnly).await {
         return Err(RuneApiError::ServiceUnavailable);
     }
 
     let address = params.address.clone();
-    let rune = {
-        match ordinals::SpacedRune::from_str(&params.rune) {
-            Ok(spr) => spr.rune.to_string(),
+    let rune = resolve_rune_name(&state, &params.rune).await?;
+
+    let res = state.db.get_rune_balance(&address, &rune).await;
+
+    let balance = match res {
+        Ok(balances) => balances,
+        Err(err) => {
+            error!(
+                "can't fetch rune balances: address={address} rune={rune} error={:#?}",
+                err
+            );
+            return Err(RuneApiError::InternalError);
+        }
+    };
+
+    Ok(Json(balance))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/runes/{rune}/balance-history/{address}",
+    tag = "runes",
+    params(NetworkPath, RuneAddressPath, BalanceHistoryQuery),
+    responses((status = 200, body = ListResult<RuneBalanceHistory>), (status = 504, description = "Query timeout"))
+)]
+pub async fn get_rune_balance_history(
+    state: Data<Context>,
+    params: Path<RuneAddressPath>,
+    query: Query<BalanceHistoryQuery>,
+) -> Result<Json<ListResult<RuneBalanceHistory>>, RuneApiError> {
+    if !state.is_ready(HealthGate::DbOnly).await {
+        return Err(RuneApiError::ServiceUnavailable);
+    }
+
+    let rune = resolve_rune_name(&state, &params.rune).await?;
+    let (from_block, to_block) = match query.range() {
+        Ok(v) => v,
+        Err(err) => {
+            return Err(RuneApiError::BadInput(format!("{err}")));
+        }
+    };
+    let (limit, offset) = match query.limit_offset() {
+        Ok(v) => v,
+        Err(err) => {
+            return Err(RuneApiError::BadInput(format!("{err}")));
+        }
+    };
+    let bucket_size = query.granularity.blocks();
+
+    let count = match state
+        .db
+        .count_rune_history_buckets(&params.address, &rune, from_block, to_block, bucket_size)
+        .await
+    {
+        Ok(count) => count as u64,
+        Err(err) => {
+            error!(
+                "can't count rune balance history: address={} rune={rune} error={:#?}",
+                params.address, err
+            );
+            return Err(rune_db_error(&err));
+        }
+    };
+    let buckets = match state
+        .db
+        .select_rune_history_buckets(
+            &params.address,
+            &rune,
+            from_block,
+            to_block,
+            bucket_size,
+            limit,
+            offset,
+        )
+        .await
+    {
+        Ok(b) => b,
+        Err(err) => {
+            error!(
+                "can't fetch rune balance history buckets: address={} rune={rune} error={:#?}",
+                params.address, err
+            );
+            return Err(rune_db_error(&err));
+        }
+    };
+
+    let meta = Some(ListResponseMeta::from_counts(limit, offset, count));
+    let (Some(&page_from), Some(&last)) = (buckets.first(), buckets.last()) else {
+        return Ok(Json(ListResult {
+            meta,
+            records: vec![],
+        }));
+    };
+    let page_to = (query.granularity.bucket(last) + bucket_size - 1).min(to_block);
+
+    // running balance of the page starts from everything below it
+    let opening_balance = if page_from > 0 {
+        match state
+            .db
+            .select_rune_opening_balance(&params.address, &rune, page_from)
+            .await
+        {
+            Ok(b) => b,
             Err(err) => {
-                return Err(RuneApiError::InvalidRuneName(format!("{err}")));
+                error!(
+                    "can't fetch opening rune balance: address={} rune={rune} error={:#?}",
+                    params.address, err
+                );
+                return Err(rune_db_error(&err));
             }
         }
+    } else {
+        (BigDecimal::from(0), 0)
     };
 
-    let res = state.db.get_rune_balance(&address, &rune).await;
+    let outputs = state
+        .db
+        .stream_tx_runes_outputs_sum(&params.address, &rune, page_from, page_to);
+    let inputs = state
+        .db
+        .stream_tx_runes_inputs_sum(&params.address, &rune, page_from, page_to);
+    let records = match rune_balance_history(
+        outputs,
+        inputs,
+        opening_balance,
+        page_from,
+        query.granularity,
+    )
+    .await
+    {
+        Ok(r) => r,
+        Err(err) => {
+            error!(
+                "can't fetch rune balance history: address={} rune={rune} error={:#?}",
+                params.address, err
+            );
+            return Err(rune_db_error(&err));
+        }
+    };
 
-    let balance = match res {
+    Ok(Json(ListResult { meta, records }))
+}
+
+/// Same as the btc balance history merge, `opening_balance` is `(rune, btc)` balance.
+async fn rune_balance_history(
+    mut outputs: BoxStream<'_, sqlx::Result<RuneOutputsSum>>,
+    mut inputs: BoxStream<'_, sqlx::Result<RuneInputsSum>>,
+    opening_balance: (BigDecimal, i64),
+    from_block: i64,
+    granularity: HistoryGranularity,
+) -> sqlx::Result<Vec<RuneBalanceHistory>> {
+    let mut result = Vec::new();
+    let mut current: Option<RuneBalanceHistory> = None;
+    let (mut rune_balance, mut btc_balance) = opening_balance;
+
+    let mut next_output = outputs.try_next().await?;
+    let mut next_input = inputs.try_next().await?;
+    loop {
+        // (block, rune amount, btc amount, is income)
+        let (block, amount, btc_amount, income) = match (next_output.take(), next_input.take()) {
+            (None, None) => break,
+            (Some(o), Some(i)) if i.block < o.block => {
+                next_output = Some(o);
+                next_input = inputs.try_next().await?;
+                (i.block, i.amount, i.btc_amount, false)
+            }
+            (Some(o), i) => {
+                next_input = i;
+                next_output = outputs.try_next().await?;
+                (o.block, o.amount, o.btc_amount, true)
+            }
+            (None, Some(i)) => {
+                next_input = inputs.try_next().await?;
+                (i.block, i.amount, i.btc_amount, false)
+            }
+        };
+
+        let bucket = granularity.bucket(block).max(from_block);
+        if current.as_ref().is_some_and(|p| p.block != bucket) {
+            result.extend(current.take());
+        }
+        let point = current.get_or_insert_with(|| RuneBalanceHistory {
+            block: bucket,
+            ..Default::default()
+        });
+        if income {
+            rune_balance += &amount;
+            btc_balance += btc_amount;
+            point.rune_income += amount;
+            point.btc_income += btc_amount;
+        } else {
+            rune_balance -= &amount;
+            btc_balance -= btc_amount;
+            point.rune_spent += amount;
+            point.btc_spent += btc_amount;
+        }
+        point.rune_balance = rune_balance.clone();
+        point.btc_balance = btc_balance;
+        point.out_count += 1;
+    }
+    result.extend(current);
+
+    Ok(result)
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/runes/balance/{address}",
+    tag = "runes",
+    params(NetworkPath, ("address" = String, Path)),
+    responses((status = 200, body = ListResult<RuneBalance>))
+)]
+pub async fn list_runes_balances(
+    state: Data<Context>,
+    address: Path<String>,
+) -> Result<Json<ListResult<RuneBalance>>, RuneApiError> {
+    if !state.is_ready(HealthGate::DbOnly).await {
+        return Err(RuneApiError::ServiceUnavailable);
+    }
+
+    let res = state.db.get_runes_balances(&address).await;
+
+    let balances = match res {
         Ok(balances) => balances,
         Err(err) => {
             error!(
-                "can't fetch rune balances: address={address} rune={rune} error={:#?}",
+                "can't fetch runes balances: address={address} error={:#?}",
+                err
+            );
+            return Err(RuneApiError::InternalError);
+        }
+    };
+
+    Ok(Json(ListResult {
+        records: balances,
+        meta: None,
+    }))
+}
+
+const MAX_BALANCES_BATCH: usize = 500;
+
+#[utoipa::path(
+    post,
+    path = "/v1/{network}/runes/decode",
+    tag = "runes",
+    params(NetworkPath),
+    request_body = DecodeRunestoneRequest,
+    responses((status = 200, body = DecodedRunestone))
+)]
+pub async fn decode_runestone(
+    state: Data<Context>,
+    req: Json<DecodeRunestoneRequest>,
+) -> Result<Json<DecodedRunestone>, RuneApiError> {
+    let tx = decode_raw_tx(&req.tx)?;
+    let mut decoded = decode_artifact(ordinals::Runestone::decipher(&tx));
+
+    let mut rune_ids: Vec<String> = decoded.edicts.iter().map(|e| e.rune_id.clone()).collect();
+    rune_ids.extend(decoded.mint.clone());
+    if rune_ids.is_empty() {
+        return Ok(Json(decoded));
+    }
+    rune_ids.sort();
+    rune_ids.dedup();
+
+    let names: HashMap<String, String> = match state.db.select_runes_by_rune_ids(&rune_ids).await {
+        Ok(rows) => rows
+            .into_iter()
+            .map(|row| (row.rune_id, row.display_name))
+            .collect(),
+        Err(err) => {
+            error!(
+                "can't fetch runes by ids: ids={rune_ids:?} error={:#?}",
+                err
+            );
+            return Err(RuneApiError::InternalError);
+        }
+    };
+
+    decoded.mint_rune = decoded.mint.as_ref().and_then(|id| names.get(id).cloned());
+    for edict in decoded.edicts.iter_mut() {
+        edict.rune = names.get(&edict.rune_id).cloned();
+    }
+
+    Ok(Json(decoded))
+}
+
+fn decode_raw_tx(tx: &str) -> Result<bitcoin::Transaction, RuneApiError> {
+    match hex::decode(tx.trim()) {
+        Ok(data) => match bitcoin::consensus::deserialize(&data) {
+            Ok(tx) => Ok(tx),
+            Err(err) => Err(RuneApiError::BadInput(format!("invalid tx: {err}"))),
+        },
+        Err(err) => Err(RuneApiError::BadInput(format!("invalid tx hex: {err}"))),
+    }
+}
+
+/// Runs the checks of the runes indexer against the etching of a pending tx.
+/// Nothing is written, the rune name isn't reserved by the check.
+#[utoipa::path(
+    post,
+    path = "/v1/{network}/runes/validate-etching",
+    tag = "runes",
+    params(NetworkPath),
+    request_body = ValidateEtchingRequest,
+    responses((status = 200, body = EtchingVerdict))
+)]
+pub async fn validate_etching(
+    state: Data<Context>,
+    req: Json<ValidateEtchingRequest>,
+) -> Result<Json<EtchingVerdict>, RuneApiError> {
+    if !state.is_ready(HealthGate::Full).await {
+        return Err(RuneApiError::ServiceUnavailable);
+    }
+    let tx = decode_raw_tx(&req.tx)?;
+
+    let indexed = match state.db.get_last_indexed_block(RUNES_INDEX).await {
+        Ok(height) => height,
+        Err(err) => {
+            error!("can't fetch runes indexer height: error={:#?}", err);
+            return Err(RuneApiError::InternalError);
+        }
+    };
+    let mut verdict = EtchingVerdict {
+        height: indexed + 1,
+        ..Default::default()
+    };
+
+    let etching = match etching_rune(ordinals::Runestone::decipher(&tx)) {
+        Ok(etching) => etching,
+        Err(reason) => {
+            verdict
+                .checks
+                .push(failed_check(EtchingCheckKind::Runestone, reason));
+            return Ok(Json(verdict));
+        }
+    };
+    verdict
+        .checks
+        .push(passed_check(EtchingCheckKind::Runestone, None));
+
+    let Some(rune) = etching else {
+        let reason = "the name is not set, a reserved name is assigned";
+        for check in [
+            EtchingCheckKind::MinimumName,
+            EtchingCheckKind::NameAvailable,
+            EtchingCheckKind::Commitment,
+        ] {
+            verdict
+                .checks
+                .push(passed_check(check, Some(reason.into())));
+        }
+        verdict.valid = true;
+        return Ok(Json(verdict));
+    };
+    verdict.rune = Some(rune.to_string());
+    let rune = rune.rune;
+
+    verdict
+        .checks
+        .push(minimum_name_check(state.net, rune, verdict.height));
+
+    let name_check = match state.db.get_rune(&rune.to_string()).await {
+        Ok(Some(row)) => failed_check(
+            EtchingCheckKind::NameAvailable,
+            format!("rune is already etched: rune_id={}", row.rune_id),
+        ),
+        Ok(None) => passed_check(EtchingCheckKind::NameAvailable, None),
+        Err(err) => {
+            error!("can't fetch rune: rune={rune} error={:#?}", err);
+            return Err(RuneApiError::InternalError);
+        }
+    };
+    verdict.checks.push(name_check);
+
+    let commitment = find_commitment(&mut state.btc_client.as_ref(), &tx, rune, verdict.height);
+    verdict.checks.push(commitment_check(commitment));
+
+    verdict.valid = verdict.checks.iter().all(|check| check.passed);
+    Ok(Json(verdict))
+}
+
+/// Rune of the etching, `None` if the name isn't set.
+/// Fails if the tx has no runestone with an etching.
+fn etching_rune(
+    artifact: Option<ordinals::Artifact>,
+) -> Result<Option<ordinals::SpacedRune>, String> {
+    match artifact {
+        None => Err("tx has no runestone".into()),
+        Some(ordinals::Artifact::Cenotaph(cenotaph)) => Err(match cenotaph.flaw {
+            Some(flaw) => format!("runestone is a cenotaph: {flaw}"),
+            None => "runestone is a cenotaph".into(),
+        }),
+        Some(ordinals::Artifact::Runestone(runestone)) => {
+            let Some(etching) = runestone.etching else {
+                return Err("runestone has no etching".into());
+            };
+            Ok(etching.rune.map(|rune| ordinals::SpacedRune {
+                rune,
+                spacers: etching.spacers.unwrap_or_default(),
+            }))
+        }
+    }
+}
+
+fn minimum_name_check(net: bitcoin::Network, rune: ordinals::Rune, height: u64) -> EtchingCheck {
+    let minimum = ordinals::Rune::minimum_at_height(net, ordinals::Height(height as u32));
+    if rune.is_reserved() {
+        failed_check(
+            EtchingCheckKind::MinimumName,
+            "rune name is reserved".into(),
+        )
+    } else if rune < minimum {
+        failed_check(
+            EtchingCheckKind::MinimumName,
+            format!("rune is less than the minimum {minimum} at the height {height}"),
+        )
+    } else {
+        passed_check(EtchingCheckKind::MinimumName, None)
+    }
+}
+
+fn commitment_check(
+    result: Result<bitcoin::Txid, Vec<(bitcoin::OutPoint, CommitmentError)>>,
+) -> EtchingCheck {
+    match result {
+        Ok(txid) => passed_check(
+            EtchingCheckKind::Commitment,
+            Some(format!("committed by tx {txid}")),
+        ),
+        Err(rejected) if rejected.is_empty() => failed_check(
+            EtchingCheckKind::Commitment,
+            "no input commits to the rune".into(),
+        ),
+        Err(rejected) => {
+            let reasons: Vec<_> = rejected
+                .iter()
+                .map(|(out_point, err)| format!("{out_point}: {err}"))
+                .collect();
+            failed_check(EtchingCheckKind::Commitment, reasons.join("; "))
+        }
+    }
+}
+
+fn passed_check(check: EtchingCheckKind, reason: Option<String>) -> EtchingCheck {
+    EtchingCheck {
+        check,
+        passed: true,
+        reason,
+    }
+}
+
+fn failed_check(check: EtchingCheckKind, reason: String) -> EtchingCheck {
+    EtchingCheck {
+        check,
+        passed: false,
+        reason: Some(reason),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/runes/trace/{txid}",
+    tag = "runes",
+    params(NetworkPath, ("txid" = String, Path)),
+    responses((status = 200, body = RuneAllocationTrace))
+)]
+pub async fn trace_rune_allocation(
+    state: Data<Context>,
+    txid: Path<String>,
+) -> Result<Json<RuneAllocationTrace>, RuneApiError> {
+    use bitcoincore_rpc::jsonrpc::Error::Rpc as BtcRpcError;
+    use bitcoincore_rpc::Error::JsonRpc as BtcJsonRpcError;
+    use bitcoincore_rpc::RpcApi;
+
+    use super::api_btc::RPC_INVALID_ADDRESS_OR_KEY;
+
+    if !state.is_ready(HealthGate::Full).await {
+        return Err(RuneApiError::ServiceUnavailable);
+    }
+    let txid = match bitcoin::Txid::from_str(&txid) {
+        Ok(txid) => txid,
+        Err(_) => return Err(RuneApiError::BadInput(format!("invalid txid={txid}"))),
+    };
+
+    let info = match state.btc_client.get_raw_transaction_info(&txid, None) {
+        Ok(info) => info,
+        Err(BtcJsonRpcError(BtcRpcError(ref rpc_error)))
+            if rpc_error.code == RPC_INVALID_ADDRESS_OR_KEY =>
+        {
+            return Err(RuneApiError::BadInput(format!("tx {txid} not found")));
+        }
+        Err(err) => {
+            error!(
+                "get_raw_transaction_info failed: tx={txid} error={:#?}",
                 err
             );
             return Err(RuneApiError::InternalError);
         }
     };
-
-    Ok(Json(balance))
-}
-
-pub async fn get_rune_balance_history(
-    state: Data<Context>,
-    params: Path<RuneAddressPath>,
-) -> Result<Json<Vec<RuneBalanceHistory>>, RuneApiError> {
-    if !state.is_healthy().await {
-        return Err(RuneApiError::ServiceUnavailable);
-    }
-
-    let rune = {
-        match ordinals::SpacedRune::from_str(&params.rune) {
-            Ok(spr) => spr.rune.to_string(),
-            Err(err) => {
-                return Err(RuneApiError::InvalidRuneName(format!("{err}")));
-            }
+    let tx = match info.transaction() {
+        Ok(tx) => tx,
+        Err(err) => {
+            error!("can't decode tx: tx={txid} error={err}");
+            return Err(RuneApiError::InternalError);
         }
     };
 
-    let outputs = match state
-        .db
-        .select_tx_runes_outputs_sum(&params.address, &rune)
-        .await
-    {
-        Ok(o) => o,
+    // the trace is built from the indexed parent outputs and events of the tx
+    let Some(blockhash) = info.blockhash else {
+        return Err(RuneApiError::BadInput(format!(
+            "tx {txid} is not confirmed"
+        )));
+    };
+    let height = match state.btc_client.get_block_header_info(&blockhash) {
+        Ok(header) => header.height as u64,
         Err(err) => {
             error!(
-                "can't fetch rune outputs: address={} rune={rune} error={:#?}",
-                params.address, err
+                "get_block_header_info failed: block={blockhash} error={:#?}",
+                err
             );
             return Err(RuneApiError::InternalError);
         }
     };
-
-    let mut points: BTreeMap<i64, RuneBalanceHistory> = BTreeMap::new();
-    for o in outputs {
-        let entry = points.entry(o.block).or_default();
-        entry.block = o.block;
-        entry.rune_income += o.amount;
-        entry.btc_income += o.btc_amount;
-        entry.out_count += 1;
+    let indexed = match state.db.get_last_indexed_block(RUNES_INDEX).await {
+        Ok(height) => height,
+        Err(err) => {
+            error!("can't fetch runes indexer height: error={:#?}", err);
+            return Err(RuneApiError::InternalError);
+        }
+    };
+    if height > indexed {
+        return Err(RuneApiError::BadInput(format!(
+            "tx {txid} is not indexed yet"
+        )));
     }
 
-    let inputs = match state
-        .db
-        .select_tx_runes_inputs_sum(&params.address, &rune)
-        .await
-    {
-        Ok(o) => o,
+    let tx_hash = types::Hash::from(&txid);
+    let inputs = match state.db.select_tx_runes_inputs_ext(&tx_hash).await {
+        Ok(rows) => rows,
         Err(err) => {
-            error!(
-                "can't fetch rune inputs: address={} rune={rune} error={:#?}",
-                params.address, err
-            );
+            error!("select of tx rune inputs failed: tx={txid} error={:#}", err);
+            return Err(RuneApiError::InternalError);
+        }
+    };
+    let events = match state.db.select_tx_rune_events(&tx_hash).await {
+        Ok(rows) => rows,
+        Err(err) => {
+            error!("select of tx rune events failed: tx={txid} error={:#}", err);
             return Err(RuneApiError::InternalError);
         }
     };
 
-    for i in inputs {
-        let entry = points.entry(i.block).or_default();
-        entry.block = i.block;
-        entry.rune_spent += i.amount;
-        entry.btc_spent += i.btc_amount;
-        entry.out_count += 1;
+    // every traced rune comes from the inputs, the mint or the etching
+    let mut names: HashMap<RuneId, String> = HashMap::new();
+    let mut parse_id = |rune_id: &str, rune: &str| match RuneId::from_str(rune_id) {
+        Ok(id) => {
+            names.insert(id, rune.to_string());
+            Ok(id)
+        }
+        Err(err) => {
+            error!("invalid indexed rune id: tx={txid} rune_id={rune_id} error={err}");
+            Err(RuneApiError::InternalError)
+        }
+    };
+    let mut parents: BTreeMap<RuneId, u128> = BTreeMap::new();
+    for input in &inputs {
+        let id = parse_id(&input.rune_id, &input.rune)?;
+        *parents.entry(id).or_default() += input.amount.to_u128().unwrap_or_default();
+    }
+    let mut issuance = Issuance::default();
+    for event in &events {
+        let amount = event.amount.to_u128().unwrap_or_default();
+        match event.event_type {
+            RuneEventType::Mint => {
+                issuance.mint = Some((parse_id(&event.rune_id, &event.rune)?, amount))
+            }
+            RuneEventType::Etch => {
+                issuance.etching = Some((parse_id(&event.rune_id, &event.rune)?, amount))
+            }
+            RuneEventType::Burn => {}
+        }
     }
 
-    let mut result = points.values().cloned().collect::<Vec<_>>();
-    result.sort_by(|a, b| a.block.cmp(&b.block));
+    let artifact = ordinals::Runestone::decipher(&tx);
+    let trace = allocate(&tx, parents, artifact.as_ref(), issuance);
 
-    let mut total_btc_balance = 0;
-    let mut total_runes_balance = BigDecimal::from(0);
-    for p in result.iter_mut() {
-        total_btc_balance += p.btc_income;
-        total_btc_balance -= p.btc_spent;
-        p.btc_balance = total_btc_balance;
+    let amount = |id: &RuneId, amount: u128| TracedRuneAmount {
+        rune_id: id.to_string(),
+        rune: names.get(id).cloned(),
+        amount: BigDecimal::from(amount),
+    };
+    let amounts = |balances: &BTreeMap<RuneId, u128>| -> Vec<TracedRuneAmount> {
+        balances.iter().map(|(id, v)| amount(id, *v)).collect()
+    };
+    let edicts = trace
+        .edicts
+        .iter()
+        .map(|step| TracedEdict {
+            rune_id: step.edict.id.to_string(),
+            amount: BigDecimal::from(step.edict.amount),
+            output: step.edict.output,
+            allocations: step
+                .allocations
+                .iter()
+                .filter_map(|(vout, v)| {
+                    Some(TracedAllocation {
+                        vout: *vout as u32,
+                        rune: amount(&step.id?, *v),
+                    })
+                })
+                .collect(),
+        })
+        .collect();
+    let outputs = trace
+        .allocated
+        .iter()
+        .enumerate()
+        .flat_map(|(vout, balances)| {
+            balances.iter().map(move |(id, v)| TracedAllocation {
+                vout: vout as u32,
+                rune: amount(id, *v),
+            })
+        })
+        .collect();
+
+    Ok(Json(RuneAllocationTrace {
+        txid: tx_hash,
+        kind: decode_artifact(artifact).kind,
+        mint: issuance.mint.map(|(id, v)| amount(&id, v)),
+        premine: issuance.etching.map(|(id, v)| amount(&id, v)),
+        unallocated: amounts(&trace.unallocated),
+        edicts,
+        pointer: trace.pointer,
+        default_output: trace.default_output.map(|vout| vout as u32),
+        remainder: amounts(&trace.remainder),
+        outputs,
+        burned: amounts(&trace.burned),
+    }))
+}
 
-        total_runes_balance += &p.rune_income;
-        total_runes_balance -= &p.rune_spent;
-        p.rune_balance = total_runes_balance.clone();
+/// Converts the runestone into the api response, rune names are not resolved.
+fn decode_artifact(artifact: Option<ordinals::Artifact>) -> DecodedRunestone {
+    match artifact {
+        None => DecodedRunestone::default(),
+        Some(ordinals::Artifact::Cenotaph(cenotaph)) => DecodedRunestone {
+            kind: ArtifactKind::Cenotaph,
+            etching: cenotaph.etching.map(|rune| DecodedEtching {
+                rune: Some(rune.to_string()),
+                ..Default::default()
+            }),
+            mint: cenotaph.mint.map(|id| id.to_string()),
+            flaw: cenotaph.flaw.map(|flaw| flaw.to_string()),
+            ..Default::default()
+        },
+        Some(ordinals::Artifact::Runestone(runestone)) => DecodedRunestone {
+            kind: ArtifactKind::Runestone,
+            etching: runestone.etching.map(|etching| DecodedEtching {
+                rune: etching.rune.map(|rune| {
+                    ordinals::SpacedRune {
+                        rune,
+                        spacers: etching.spacers.unwrap_or_default(),
+                    }
+                    .to_string()
+                }),
+                divisibility: etching.divisibility,
+                symbol: etching.symbol,
+                premine: BigDecimal::from(etching.premine.unwrap_or_default()),
+                turbo: etching.turbo,
+                terms: etching.terms.map(|terms| DecodedTerms {
+                    amount: BigDecimal::from(terms.amount.unwrap_or_default()),
+                    cap: BigDecimal::from(terms.cap.unwrap_or_default()),
+                    height_start: terms.height.0,
+                    height_end: terms.height.1,
+                    offset_start: terms.offset.0,
+                    offset_end: terms.offset.1,
+                }),
+            }),
+            mint: runestone.mint.map(|id| id.to_string()),
+            pointer: runestone.pointer,
+            edicts: runestone
+                .edicts
+                .iter()
+                .map(|edict| DecodedEdict {
+                    rune_id: edict.id.to_string(),
+                    rune: None,
+                    amount: BigDecimal::from(edict.amount),
+                    output: edict.output,
+                })
+                .collect(),
+            ..Default::default()
+        },
     }
-
-    Ok(Json(result))
 }
 
-pub async fn list_runes_balances(
+#[utoipa::path(
+    post,
+    path = "/v1/{network}/runes/balances",
+    tag = "runes",
+    params(NetworkPath),
+    request_body = RunesBalancesRequest,
+    responses((status = 200, body = RunesBalancesResponse))
+)]
+pub async fn list_runes_balances_multi(
     state: Data<Context>,
-    address: Path<String>,
-) -> Result<Json<ListResult<RuneBalance>>, RuneApiError> {
-    if !state.is_healthy().await {
+    req: Json<RunesBalancesRequest>,
+) -> Result<Json<RunesBalancesResponse>, RuneApiError> {
+    if !state.is_ready(HealthGate::DbOnly).await {
         return Err(RuneApiError::ServiceUnavailable);
     }
 
-    let res = state.db.get_runes_balances(&address).await;
+    if req.addresses.len() > MAX_BALANCES_BATCH {
+        return Err(RuneApiError::BadInput(format!(
+            "too many addresses({}), max allowed({MAX_BALANCES_BATCH})",
+            req.addresses.len()
+        )));
+    }
 
-    let balances = match res {
-        Ok(balances) => balances,
+    let runes = match &req.runes {
+        Some(runes) => {
+            let mut names = Vec::new();
+            for rune in runes.iter() {
+                match ordinals::SpacedRune::from_str(rune) {
+                    Ok(spr) => names.push(spr.rune.to_string()),
+                    Err(err) => {
+                        return Err(RuneApiError::InvalidRuneName(format!("{err}")));
+                    }
+                }
+            }
+            Some(names)
+        }
+        None => None,
+    };
+
+    let mut balances: BTreeMap<String, Vec<RuneBalance>> = BTreeMap::new();
+    let mut errors = Vec::new();
+    for address in req.addresses.iter() {
+        match decode_address(address, state.net) {
+            Ok(_) => {
+                balances.insert(address.clone(), Vec::new());
+            }
+            Err(err) => errors.push(AddressError {
+                address: address.clone(),
+                error: format!("{err}"),
+            }),
+        }
+    }
+
+    let addresses: Vec<String> = balances.keys().cloned().collect();
+    let res = state
+        .db
+        .get_runes_balances_multi(&addresses, runes.as_deref())
+        .await;
+    let rows = match res {
+        Ok(rows) => rows,
         Err(err) => {
             error!(
-                "can't fetch runes balances: address={address} error={:#?}",
+                "can't fetch runes balances: addresses={} error={:#?}",
+                addresses.len(),
                 err
             );
             return Err(RuneApiError::InternalError);
         }
     };
 
-    Ok(Json(ListResult {
-        records: balances,
-        meta: None,
-    }))
+    for row in rows {
+        balances.entry(row.address.clone()).or_default().push(row);
+    }
+
+    Ok(Json(RunesBalancesResponse { balances, errors }))
 }
 
+#[utoipa::path(
+    post,
+    path = "/v1/{network}/runes/balance/{address}",
+    tag = "runes",
+    params(NetworkPath, ("address" = String, Path)),
+    request_body = RunesFilter,
+    responses((status = 200, body = ListResult<RuneBalance>))
+)]
 pub async fn list_filtered_runes_balances(
     state: Data<Context>,
     address: Path<String>,
     req: Json<RunesFilter>,
 ) -> Result<Json<ListResult<RuneBalance>>, RuneApiError> {
-    if !state.is_healthy().await {
+    if !state.is_ready(HealthGate::DbOnly).await {
         return Err(RuneApiError::ServiceUnavailable);
     }
 
@@ -434,24 +1405,24 @@ pub async fn list_filtered_runes_balances(
     }))
 }
 
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/runes/{rune}/utxos/{address}",
+    tag = "runes",
+    params(NetworkPath, RuneAddressPath, RunesUtxoQuery, PageParams),
+    responses((status = 200, body = ListResult<RuneUtxo>))
+)]
 pub async fn list_rune_utxos(
     state: Data<Context>,
     params: Path<RuneAddressPath>,
     query: Query<RunesUtxoQuery>,
 ) -> Result<Json<ListResult<RuneUtxo>>, RuneApiError> {
-    if !state.is_healthy().await {
+    if !state.is_ready(HealthGate::DbOnly).await {
         return Err(RuneApiError::ServiceUnavailable);
     }
 
     let address = params.address.clone();
-    let rune = {
-        match ordinals::SpacedRune::from_str(&params.rune) {
-            Ok(spr) => spr.rune.to_string(),
-            Err(err) => {
-                return Err(RuneApiError::InvalidRuneName(format!("{err}")));
-            }
-        }
-    };
+    let rune = resolve_rune_name(&state, &params.rune).await?;
 
     let (limit, offset) = match query.page.limit_offset() {
         Ok(v) => v,
@@ -459,13 +1430,28 @@ pub async fn list_rune_utxos(
             return Err(RuneApiError::BadInput(format!("{err}")));
         }
     };
+    let after_id = match parse_cursor(&query.page, &query.cursor) {
+        Ok(v) => v,
+        Err(err) => {
+            return Err(RuneApiError::BadInput(format!("{err}")));
+        }
+    };
+    let amount = match query.amount_range() {
+        Ok(v) => v,
+        Err(err) => {
+            return Err(RuneApiError::BadInput(format!("{err}")));
+        }
+    };
 
-    let count_res = state.db.count_runes_utxo(&rune, &address).await;
+    let count_res = state
+        .db
+        .count_runes_utxo(&rune, &address, amount.clone())
+        .await;
     let count = match count_res {
-        Ok(c) => c,
+        Ok(c) => Some(c as u64),
         Err(err) => {
             error!("can't count rune utxos: rune={rune} error={:#?}", err);
-            0
+            None
         }
     };
 
@@ -475,8 +1461,9 @@ pub async fn list_rune_utxos(
             &rune,
             &address,
             query.page.order,
-            query.amount_threshold,
+            amount,
             query.sorting,
+            after_id,
             limit,
             offset,
         )
@@ -492,7 +1479,15 @@ pub async fn list_rune_utxos(
             return Err(RuneApiError::InternalError);
         }
     };
-    let rows = match state.filter_used_runes_utxos(&rows, None).await {
+    let page_len = rows.len();
+    let next_cursor = match rows.last() {
+        Some(last) if page_len as u32 == limit => Some(last.id.to_string()),
+        _ => None,
+    };
+    let mut rows = match state
+        .filter_used_runes_utxos(&rows, None, query.include_inscribed)
+        .await
+    {
         Ok(r) => r,
         Err(err) => {
             error!(
@@ -502,41 +1497,53 @@ pub async fn list_rune_utxos(
             return Err(RuneApiError::InternalError);
         }
     };
+    let tip = state.chain_tip().await;
+    rows.iter_mut().for_each(|u| u.set_confirmations(tip));
 
+    let meta = if after_id.is_some() {
+        ListResponseMeta {
+            total_is_exact: count.is_some(),
+            ..ListResponseMeta::with_cursor(limit, count.unwrap_or_default(), next_cursor)
+        }
+    } else {
+        // the page is guessed by the db rows, the used ones are filtered out after
+        ListResponseMeta {
+            next_cursor,
+            ..ListResponseMeta::from_optional_total(limit, offset, count, page_len)
+        }
+    };
     let resp = ListResult {
-        meta: Some(ListResponseMeta::new(limit, offset, count as u64)),
+        meta: Some(meta),
         records: rows,
     };
 
     Ok(Json(resp))
 }
 
+#[utoipa::path(
+    post,
+    path = "/v1/{network}/runes/{rune}/utxos/{address}",
+    tag = "runes",
+    params(NetworkPath, RuneAddressPath),
+    request_body = CollectRunesUtxo,
+    responses((status = 200, body = ListResult<RuneUtxo>))
+)]
 pub async fn list_rune_utxos_with_lock(
     state: Data<Context>,
     params: Path<RuneAddressPath>,
     request: Json<CollectRunesUtxo>,
-    api_key: super::auth_middleware::XApiKey,
+    api_key: ReqData<ApiKey>,
 ) -> Result<Json<ListResult<RuneUtxo>>, RuneApiError> {
-    if !state.is_healthy().await {
+    if !state.is_ready(HealthGate::Full).await {
         return Err(RuneApiError::ServiceUnavailable);
     }
-    let Some(apk) = state.get_api_key(&api_key.0) else {
-        return Err(RuneApiError::Unauthorized);
-    };
     if let Err(err) = decode_address(&params.address, state.net) {
         return Err(RuneApiError::InvalidAddress(format!("{err}")));
     }
 
     let target_amount = request.amount.clone();
     let address = params.address.clone();
-    let rune = {
-        match ordinals::SpacedRune::from_str(&params.rune) {
-            Ok(spr) => spr.rune.to_string(),
-            Err(err) => {
-                return Err(RuneApiError::InvalidRuneName(format!("{err}")));
-            }
-        }
-    };
+    let rune = resolve_rune_name(&state, &params.rune).await?;
 
     if target_amount <= BigDecimal::from(0) || !target_amount.is_integer() {
         return Err(RuneApiError::BadInput(
@@ -544,186 +1551,275 @@ pub async fn list_rune_utxos_with_lock(
         ));
     }
 
-    let balance = match state.db.get_rune_balance(&address, &rune).await {
-        Ok(b) => b,
-        Err(err) => {
-            error!(
-                "can't get rune balance: rune={} address={} err={err:#}",
-                rune, address
-            );
-            return Err(RuneApiError::InternalError);
-        }
+    let opts = FilterOpts {
+        request_id: Some(request.request_id.clone()),
+        skip_inscriptions: true,
+        ..Default::default()
     };
-
-    if balance.balance < target_amount {
-        return Err(RuneApiError::NotEnoughBalance {
-            required: target_amount.to_u128().unwrap_or_default(),
-            available: balance.balance.to_u128().unwrap(),
-        });
-    }
-
-    let collected = coolect_utxo_shortcut(
-        &state,
-        &rune,
-        &address,
-        apk.can_lock_utxo,
-        target_amount.clone(),
-        &request.request_id,
-    )
-    .await?;
-    if let Some(resp) = collected {
-        return Ok(Json(resp));
-    }
-
-    let mut collected_utxos = Vec::new();
-    let limit = 200;
-    let mut offset = 0;
-
-    loop {
-        let rows_res = state
-            .db
-            .select_rune_utxo_with_pagination(
-                &rune,
+    let can_lock = api_key.has_scope(scopes::LOCK);
+    let mut attempt = 0;
+    let mut collected = loop {
+        let res = state
+            .collector
+            .collect_spendable_rune_utxo(
                 &address,
-                OrderBy::Desc,
-                None,
-                UtxoSortMode::Amount,
-                limit,
-                offset,
+                &rune,
+                target_amount.to_u128().unwrap_or_default(),
+                &opts,
             )
             .await;
-
-        let rows = match rows_res {
-            Ok(row) => row,
-            Err(err) => {
-                error!(
-                    "failed to select runes utxos: rune={} address={} error={:#?}",
-                    rune, address, err
-                );
-                return Err(RuneApiError::InternalError);
-            }
-        };
-        if rows.is_empty() {
-            return Err(RuneApiError::NotEnoughBalance {
-                required: target_amount.to_u128().unwrap_or_default(),
-                available: collected_utxos
-                    .iter()
-                    .map(|e: &RuneUtxo| e.amount.to_u128().unwrap_or_default())
-                    .sum::<u128>(),
-            });
-        }
-        match state
-            .filter_used_runes_utxos(&rows, Some(request.request_id.clone()))
-            .await
-        {
-            Ok(r) => collected_utxos.extend(r),
-            Err(err) => {
-                error!(
-                    "failed to filter runes utxos: address={} error={:#?}",
-                    params.address, err
-                );
-                return Err(RuneApiError::InternalError);
-            }
+        let collected = match res {
+            Ok(c) => c,
+            Err(err) => return Err(rune_collector_error(err, &rune, &address)),
         };
 
-        let result = min_utxos_to_reach_target(
-            &collected_utxos,
-            request.amount.to_u128().unwrap_or_default(),
-        );
-        match result {
-            Ok(utxos) => {
-                lock_utxo(&state, apk.can_lock_utxo, &utxos, &request.request_id).await;
-                let resp = ListResult {
-                    meta: Some(ListResponseMeta::new(limit, offset, utxos.len() as u64)),
-                    records: utxos,
-                };
-
-                return Ok(Json(resp));
-            }
-            Err(KnapsackError::NotEnoughBalance { .. }) => {
-                // try to select more utxos
-                offset += limit;
-                continue;
-            }
+        attempt += 1;
+        if lock_utxo(&state, can_lock, &collected.utxos, &request.request_id).await {
+            break collected;
         }
-    }
-}
-
-async fn coolect_utxo_shortcut(
-    state: &Context,
-    rune: &str,
-    address: &str,
-    can_lock_utxo: bool,
-    target_amount: BigDecimal,
-    rid: &str,
-) -> Result<Option<ListResult<RuneUtxo>>, RuneApiError> {
-    let lower_bound = &target_amount / BigDecimal::from(10);
-    let upper_bound = &target_amount * BigDecimal::from(4);
-    // shortcut
-    let rows_res = state
-        .db
-        .select_rune_utxos_with_amount_bounds(rune, address, 10, &lower_bound, &upper_bound)
-        .await;
-    let rows = match rows_res {
-        Ok(row) => row,
-        Err(err) => {
+        if attempt >= MAX_LOCK_ATTEMPTS {
             error!(
-                "failed to select runes utxos: rune={} address={} error={:#?}",
-                rune, address, err
+                "unable to lock collected utxos, they are taken by concurrent requests: address={} attempts={}",
+                address, attempt
             );
             return Err(RuneApiError::InternalError);
         }
+        // utxos taken by others are skipped by the filter on the next run
+        debug!("collected utxos are locked by another request, retry: address={address} attempt={attempt}");
     };
-    let target_amount = target_amount
-        .to_u128()
-        .expect("rune amount is out of bounds");
-
-    // filter & collect
-    let rows = match state.filter_used_runes_utxos(&rows, Some(rid.into())).await {
-        Ok(r) => r,
-        Err(err) => {
-            error!(
-                "failed to filter runes utxos: address={} error={:#?}",
-                address, err
-            );
-            return Err(RuneApiError::InternalError);
-        }
+    let tip = state.chain_tip().await;
+    collected
+        .utxos
+        .iter_mut()
+        .for_each(|u| u.set_confirmations(tip));
+
+    // the meta describes the returned set, not the pages scanned by the collector
+    let count = collected.utxos.len();
+    let resp = ListResult {
+        meta: Some(ListResponseMeta::from_counts(count as u32, 0, count as u64)),
+        records: collected.utxos,
     };
 
-    let result = min_utxos_to_reach_target(&rows, target_amount);
-    match result {
-        Ok(utxos) => {
-            lock_utxo(state, can_lock_utxo, &utxos, rid).await;
-            let resp = ListResult {
-                meta: Some(ListResponseMeta::new(10, 0, rows.len() as u64)),
-                records: utxos,
-            };
+    Ok(Json(resp))
+}
+
+/// Heavy queries cancelled by the db timeout are reported with 504.
+fn rune_db_error(err: &sqlx::Error) -> RuneApiError {
+    if is_timeout(err) {
+        RuneApiError::Timeout
+    } else {
+        RuneApiError::InternalError
+    }
+}
 
-            Ok(Some(resp))
+fn rune_collector_error(err: CollectorError, rune: &str, address: &str) -> RuneApiError {
+    match err {
+        CollectorError::NotEnoughBalance {
+            available,
+            collectable,
+            target,
+        } => RuneApiError::NotEnoughBalance {
+            required: target,
+            available,
+            collectable,
+        },
+        CollectorError::BadInput(msg) => RuneApiError::BadInput(msg),
+        err @ (CollectorError::ZeroTarget | CollectorError::MaxUtxosExceeded { .. }) => {
+            RuneApiError::BadInput(err.to_string())
         }
-        Err(KnapsackError::NotEnoughBalance { .. }) => {
-            debug!("shortcut is unsuccessful, going in hard way");
-            Ok(None)
+        err => {
+            error!(
+                "failed to collect runes utxos: rune={} address={} error={:#}",
+                rune, address, err
+            );
+            RuneApiError::InternalError
         }
     }
 }
 
-async fn lock_utxo(state: &Context, can_lock_utxo: bool, utxos: &[RuneUtxo], rid: &str) {
-    let Some(cache) = state.cache.as_ref() else {
-        return;
-    };
+/// Returns `false` if some of the utxos are already locked by another request.
+async fn lock_utxo(state: &Context, can_lock_utxo: bool, utxos: &[RuneUtxo], rid: &str) -> bool {
     if !can_lock_utxo {
-        return;
+        return true;
+    }
+
+    let outpoints: Vec<_> = utxos.iter().map(|u| (&u.tx_hash, u.vout)).collect();
+    state.lock_outpoints(&outpoints, rid).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/{network}/runes/{rune}/psbt/build",
+    tag = "runes",
+    params(NetworkPath, ("rune" = String, Path, description = "Spaced rune name or rune id")),
+    request_body = BuildRunePsbtRequest,
+    responses((status = 200, body = BuildRunePsbtResponse))
+)]
+pub async fn build_rune_psbt(
+    state: Data<Context>,
+    rune: Path<String>,
+    request: Json<BuildRunePsbtRequest>,
+) -> Result<Json<BuildRunePsbtResponse>, RuneApiError> {
+    if !state.is_ready(HealthGate::Full).await {
+        return Err(RuneApiError::ServiceUnavailable);
     }
 
-    for u in utxos.iter() {
-        let res = cache.lock_utxo(&u.tx_hash, u.vout, rid).await;
-        if let Err(err) = res {
-            error!("unable to write utxo lock: id={rid} error={err:#}");
+    let from_address = match decode_address(&request.from_address, state.net) {
+        Ok(address) => address,
+        Err(err) => return Err(RuneApiError::InvalidAddress(format!("{err}"))),
+    };
+    let change_script = from_address.script_pubkey();
+    // inputs get only `witness_utxo`, legacy ones require the whole parent tx
+    if !change_script.is_witness_program() && !change_script.is_p2sh() {
+        return Err(RuneApiError::BadInput(
+            "from_address must be a segwit address".into(),
+        ));
+    }
+    let recipient = match decode_address(&request.to_address, state.net) {
+        Ok(address) => address.script_pubkey(),
+        Err(err) => {
+            return Err(RuneApiError::InvalidAddress(format!(
+                "{}: {err}",
+                request.to_address
+            )))
         }
+    };
+    let amount = match request.amount.to_u128() {
+        Some(amount) if amount > 0 && request.amount.is_integer() => amount,
+        _ => {
+            return Err(RuneApiError::BadInput(
+                "amount must be positive integer value".into(),
+            ))
+        }
+    };
+    if !request.fee_rate.is_finite() || request.fee_rate <= 0.0 {
+        return Err(RuneApiError::BadInput(
+            "fee_rate must be positive number".into(),
+        ));
     }
+    let rune = resolve_rune_name(&state, &rune).await?;
+    let address = &request.from_address;
+
+    let older_than = state.coinbase_maturity_cutoff().await;
+
+    let rune_opts = FilterOpts {
+        request_id: Some(request.request_id.clone()),
+        skip_inscriptions: true,
+        ..Default::default()
+    };
+    // rune utxos are never spent as pure fee inputs
+    let fee_opts = FilterOpts {
+        request_id: Some(request.request_id.clone()),
+        skip_inscriptions: true,
+        skip_runes: true,
+        skip_premature: older_than,
+    };
+    let mut attempt = 0;
+    let (mut rune_inputs, mut btc_inputs, built) = loop {
+        let res = state
+            .collector
+            .collect_spendable_rune_utxo(address, &rune, amount, &rune_opts)
+            .await;
+        let rune_inputs = match res {
+            Ok(c) => c.utxos,
+            Err(err) => return Err(rune_collector_error(err, &rune, address)),
+        };
+        let Some(rune_id) = rune_inputs
+            .first()
+            .and_then(|u| ordinals::RuneId::from_str(&u.rune_id).ok())
+        else {
+            error!("collected rune utxos have no valid rune id: rune={rune} address={address}");
+            return Err(RuneApiError::InternalError);
+        };
+        let transfer = psbt::RuneTransfer {
+            rune_id,
+            amount,
+            recipient: recipient.clone(),
+        };
+        let rune_btc_amount: u64 = rune_inputs.iter().map(|u| u.btc_amount as u64).sum();
+
+        let mut btc_inputs = Vec::new();
+        let built = loop {
+            let res = psbt::build_rune_transfer_psbt(
+                &rune_inputs,
+                &btc_inputs,
+                &transfer,
+                &change_script,
+                request.fee_rate,
+            );
+            match res {
+                Ok(built) => break built,
+                // btc of the rune utxos can't pay the fee, collect more.
+                // `required` grows with every collected input, so it ends
+                // either with the psbt or with not enough balance.
+                Err(PsbtError::NotEnoughBalance { required, .. }) => {
+                    let target = required - rune_btc_amount;
+                    let res = state
+                        .collector
+                        .collect_spendable_btc_utxo(address, target, &fee_opts)
+                        .await;
+                    btc_inputs = match res {
+                        Ok(c) => c.utxos,
+                        Err(CollectorError::NotEnoughBalance {
+                            available,
+                            collectable,
+                            target,
+                        }) => {
+                            return Err(RuneApiError::BadInput(format!(
+                                "not enough btc to pay the fee: required={target}, available={available}, collectable={collectable}"
+                            )));
+                        }
+                        Err(err) => return Err(rune_collector_error(err, &rune, address)),
+                    };
+                }
+                Err(PsbtError::BadInput(msg)) => return Err(RuneApiError::BadInput(msg)),
+            }
+        };
+
+        attempt += 1;
+        let outpoints: Vec<_> = rune_inputs
+            .iter()
+            .map(|u| (&u.tx_hash, u.vout))
+            .chain(btc_inputs.iter().map(|u| (&u.tx_hash, u.vout)))
+            .collect();
+        if state.lock_outpoints(&outpoints, &request.request_id).await {
+            break (rune_inputs, btc_inputs, built);
+        }
+        if attempt >= MAX_LOCK_ATTEMPTS {
+            error!(
+                "unable to lock psbt inputs, they are taken by concurrent requests: address={} attempts={}",
+                address, attempt
+            );
+            return Err(RuneApiError::InternalError);
+        }
+        // utxos taken by others are skipped by the filter on the next run
+        debug!(
+            "psbt inputs are locked by another request, retry: address={address} attempt={attempt}"
+        );
+    };
+    let tip = state.chain_tip().await;
+    rune_inputs
+        .iter_mut()
+        .for_each(|u| u.set_confirmations(tip));
+    btc_inputs.iter_mut().for_each(|u| u.set_confirmations(tip));
+
+    Ok(Json(BuildRunePsbtResponse {
+        psbt: built.psbt.to_string(),
+        rune_inputs,
+        btc_inputs,
+        vsize: built.vsize,
+        fee: built.fee,
+        change: built.change,
+    }))
 }
 
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/tx/{txid}/ins-outs/runes",
+    tag = "runes",
+    params(NetworkPath, ("txid" = String, Path)),
+    responses((status = 200, body = RuneTxInOuts))
+)]
 pub async fn get_tx_runes_utxos(
     state: Data<Context>,
     txid: Path<types::Hash>,
@@ -750,3 +1846,202 @@ pub async fn get_tx_runes_utxos(
 
     Ok(Json(RuneTxInOuts { inputs, outputs }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list_runes_query_filters() {
+        let query = Query::<ListRunesQuery>::from_query("").unwrap();
+        assert!(query.rune_ids.is_empty());
+        assert_eq!((query.from_block, query.to_block), (None, None));
+
+        let query = Query::<ListRunesQuery>::from_query(
+            "rune_ids=840000:1,%20840000:2,&from_block=840000&limit=10",
+        )
+        .unwrap();
+        assert_eq!(query.rune_ids, ["840000:1", "840000:2"]);
+        assert_eq!((query.from_block, query.to_block), (Some(840000), None));
+        assert_eq!(query.page.limit, Some(10));
+    }
+
+    #[test]
+    fn test_etching_rune() {
+        use ordinals::{Etching, Runestone};
+
+        assert_eq!(etching_rune(None), Err("tx has no runestone".into()));
+        let runestone = |etching| {
+            Some(ordinals::Artifact::Runestone(Runestone {
+                etching,
+                ..Default::default()
+            }))
+        };
+        assert_eq!(
+            etching_rune(runestone(None)),
+            Err("runestone has no etching".into())
+        );
+        assert_eq!(etching_rune(runestone(Some(Etching::default()))), Ok(None));
+
+        let rune: ordinals::Rune = "AAAAAAAAAAAAA".parse().unwrap();
+        let etching = Etching {
+            rune: Some(rune),
+            spacers: Some(1),
+            ..Default::default()
+        };
+        let spaced = etching_rune(runestone(Some(etching))).unwrap().unwrap();
+        assert_eq!(spaced.to_string(), "A•AAAAAAAAAAAA");
+    }
+
+    #[test]
+    fn test_minimum_name_check() {
+        let net = bitcoin::Network::Bitcoin;
+        let long: ordinals::Rune = "AAAAAAAAAAAAAAAAAAAAAAAAAA".parse().unwrap();
+        assert!(minimum_name_check(net, long, 840_000).passed);
+
+        // 12 letter names are locked at the activation height
+        let short: ordinals::Rune = "AAAAAAAAAAAA".parse().unwrap();
+        let check = minimum_name_check(net, short, 840_000);
+        assert!(!check.passed);
+        assert!(check.reason.unwrap().contains("less than the minimum"));
+
+        let reserved = ordinals::Rune::reserved(840_000, 1);
+        let check = minimum_name_check(net, reserved, 840_000);
+        assert_eq!(check.reason.as_deref(), Some("rune name is reserved"));
+    }
+
+    #[test]
+    fn test_commitment_check() {
+        use bitcoin::hashes::Hash;
+
+        let txid = bitcoin::Txid::all_zeros();
+        assert!(commitment_check(Ok(txid)).passed);
+
+        let check = commitment_check(Err(vec![]));
+        assert!(!check.passed);
+        assert_eq!(
+            check.reason.as_deref(),
+            Some("no input commits to the rune")
+        );
+
+        let out_point = bitcoin::OutPoint::new(txid, 1);
+        let check = commitment_check(Err(vec![
+            (out_point, CommitmentError::NotTaproot),
+            (out_point, CommitmentError::Immature(2)),
+        ]));
+        assert_eq!(
+            check.reason.unwrap(),
+            format!(
+                "{out_point}: spent output is not taproot; \
+                 {out_point}: commitment tx has 2 confirmations, 6 required"
+            )
+        );
+    }
+
+    #[test]
+    fn test_not_enough_balance_error() {
+        use api_core::api_errors::{ApiError, ApiErrorCode};
+
+        let err = rune_collector_error(
+            CollectorError::NotEnoughBalance {
+                available: 1000,
+                collectable: 300,
+                target: 500,
+            },
+            "AAAA",
+            "address",
+        );
+        let api_err = ApiError::from(&err);
+        assert_eq!(api_err.code, ApiErrorCode::NotEnoughBalance as u16);
+        assert_eq!(api_err.details["required"], "500");
+        assert_eq!(api_err.details["available"], "1000");
+        assert_eq!(api_err.details["collectable"], "300");
+    }
+
+    #[test]
+    fn test_decode_artifact() {
+        let runestone = ordinals::Runestone {
+            edicts: vec![ordinals::Edict {
+                id: ordinals::RuneId {
+                    block: 840_000,
+                    tx: 1,
+                },
+                amount: 500,
+                output: 1,
+            }],
+            etching: Some(ordinals::Etching {
+                rune: Some(ordinals::Rune::from_str("UNCOMMONGOODS").unwrap()),
+                spacers: Some(0b1000_0000),
+                divisibility: Some(2),
+                premine: Some(1000),
+                ..Default::default()
+            }),
+            pointer: Some(0),
+            ..Default::default()
+        };
+        let tx = bitcoin::Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![],
+            output: vec![bitcoin::TxOut {
+                value: bitcoin::Amount::ZERO,
+                script_pubkey: runestone.encipher(),
+            }],
+        };
+
+        let decoded = decode_artifact(ordinals::Runestone::decipher(&tx));
+        assert_eq!(decoded.kind, ArtifactKind::Runestone);
+        assert_eq!(decoded.pointer, Some(0));
+        let etching = decoded.etching.unwrap();
+        assert_eq!(etching.rune.as_deref(), Some("UNCOMMON•GOODS"));
+        assert_eq!(etching.premine, BigDecimal::from(1000));
+        assert_eq!(decoded.edicts.len(), 1);
+        assert_eq!(decoded.edicts[0].rune_id, "840000:1");
+        assert_eq!(decoded.edicts[0].amount, BigDecimal::from(500));
+
+        let tx = bitcoin::Transaction {
+            output: vec![],
+            ..tx
+        };
+        let decoded = decode_artifact(ordinals::Runestone::decipher(&tx));
+        assert_eq!(decoded.kind, ArtifactKind::None);
+    }
+
+    #[test]
+    fn test_rune_details() {
+        let runestone = ordinals::Runestone {
+            etching: Some(ordinals::Etching {
+                terms: Some(ordinals::Terms {
+                    amount: Some(100),
+                    cap: Some(10),
+                    height: (Some(850_010), None),
+                    offset: (None, Some(100)),
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let rune = Rune {
+            block: 850_000,
+            mints: 4,
+            raw_data: serde_json::to_vec(&runestone).unwrap(),
+            ..Default::default()
+        };
+
+        let details = rune_details(rune.clone(), 850_005);
+        let terms = details.terms.unwrap();
+        assert_eq!(terms.amount, BigDecimal::from(100));
+        assert_eq!(terms.remaining_mints, BigDecimal::from(6));
+        assert_eq!(terms.start_height, Some(850_010));
+        assert_eq!(terms.end_height, Some(850_100));
+        assert!(!details.mintable_now);
+
+        assert!(rune_details(rune.clone(), 850_009).mintable_now);
+        assert!(!rune_details(rune, 850_099).mintable_now);
+
+        // cenotaph
+        let details = rune_details(Rune::default(), 850_009);
+        assert!(details.terms.is_none());
+        assert!(!details.mintable_now);
+    }
+}
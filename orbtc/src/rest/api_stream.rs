@@ -0,0 +1,183 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use actix_web::http::header::{CACHE_CONTROL, CONTENT_ENCODING, CONTENT_TYPE};
+use actix_web::web::{Bytes, Data, Query};
+use actix_web::{HttpRequest, HttpResponse};
+use orbtc_indexer_api::{BlockInfo, FBtcApiError};
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use super::context::Context;
+use super::swagger::NetworkPath;
+use crate::db::Repo;
+use crate::indexer::BITCOIN_INDEX;
+
+/// The API may run without the indexer in the same process,
+/// so new blocks are discovered by polling the db.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Proxies tend to drop connections that are idle for a minute or so.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+const MAX_BLOCKS_PER_POLL: i64 = 100;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StreamBlocksQuery {
+    /// `btc_utxo_index` by default.
+    pub indexer: Option<String>,
+}
+
+/// Emits an SSE event for every block committed by the indexer.
+/// Event id is the block height, reconnecting clients with `Last-Event-ID`
+/// get all blocks after it, otherwise the stream starts from the next block.
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/stream/blocks",
+    tag = "btc",
+    params(NetworkPath, StreamBlocksQuery, ("Last-Event-ID" = Option<u64>, Header)),
+    responses((status = 200, description = "SSE stream, data of every event is a block", content_type = "text/event-stream", body = BlockInfo))
+)]
+pub async fn stream_blocks(
+    state: Data<Context>,
+    req: HttpRequest,
+    query: Query<StreamBlocksQuery>,
+) -> Result<HttpResponse, FBtcApiError> {
+    let indexer = query
+        .indexer
+        .clone()
+        .unwrap_or_else(|| BITCOIN_INDEX.to_owned());
+
+    let last_event_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|h| h.to_str().ok())
+        .map(|h| h.trim().parse::<i64>());
+    let last_height = match last_event_id {
+        Some(Ok(height)) => height,
+        Some(Err(_)) => {
+            return Err(FBtcApiError::BadInput(
+                "Last-Event-ID must be a block height".into(),
+            ))
+        }
+        None => match state.db.get_last_indexed_block(&indexer).await {
+            Ok(height) => height as i64,
+            Err(err) => {
+                error!("can't fetch last indexed block: error={:#?}", err);
+                return Err(FBtcApiError::InternalError);
+            }
+        },
+    };
+
+    let stream = BlockStream {
+        db: state.db.clone(),
+        indexer,
+        last_height,
+        queue: VecDeque::new(),
+        polled_at: None,
+        sent_at: Instant::now(),
+    };
+
+    Ok(HttpResponse::Ok()
+        .insert_header((CONTENT_TYPE, "text/event-stream"))
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        // compression buffers the events, keep the stream as is
+        .insert_header((CONTENT_ENCODING, "identity"))
+        .streaming(futures::stream::unfold(stream, |mut stream| async move {
+            let event = stream.next_event().await;
+            Some((Ok::<_, actix_web::Error>(event), stream))
+        })))
+}
+
+struct BlockStream {
+    db: Arc<Repo>,
+    indexer: String,
+    /// Height of the last sent block.
+    last_height: i64,
+    queue: VecDeque<BlockInfo>,
+    polled_at: Option<Instant>,
+    sent_at: Instant,
+}
+
+impl BlockStream {
+    async fn next_event(&mut self) -> Bytes {
+        loop {
+            if let Some(block) = self.queue.pop_front() {
+                self.last_height = block.height;
+                self.sent_at = Instant::now();
+                return Bytes::from(block_event(&block));
+            }
+
+            if self.sent_at.elapsed() >= KEEP_ALIVE_INTERVAL {
+                self.sent_at = Instant::now();
+                return Bytes::from_static(b": keep-alive\n\n");
+            }
+
+            if let Some(polled_at) = self.polled_at {
+                let next_poll = (polled_at + POLL_INTERVAL)
+                    .min(self.sent_at + KEEP_ALIVE_INTERVAL)
+                    .saturating_duration_since(Instant::now());
+                tokio::time::sleep(next_poll).await;
+                if polled_at.elapsed() < POLL_INTERVAL {
+                    continue;
+                }
+            }
+
+            self.poll().await;
+        }
+    }
+
+    async fn poll(&mut self) {
+        self.polled_at = Some(Instant::now());
+
+        // after reorg indexer continues from the fork root,
+        // blocks of the new branch must be sent again
+        match self.db.get_last_indexed_block(&self.indexer).await {
+            Ok(height) if (height as i64) < self.last_height => {
+                self.last_height = height as i64;
+            }
+            Ok(_) => {}
+            Err(err) => {
+                error!("can't fetch last indexed block: error={:#?}", err);
+                return;
+            }
+        }
+
+        match self
+            .db
+            .list_blocks_after(&self.indexer, self.last_height, MAX_BLOCKS_PER_POLL)
+            .await
+        {
+            Ok(blocks) => self.queue.extend(blocks),
+            Err(err) => {
+                error!(
+                    "can't fetch new blocks: indexer={} error={:#?}",
+                    self.indexer, err
+                );
+            }
+        }
+    }
+}
+
+fn block_event(block: &BlockInfo) -> String {
+    let data = serde_json::to_string(block).unwrap_or_default();
+    format!("id: {}\nevent: block\ndata: {data}\n\n", block.height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_event() {
+        let block = BlockInfo {
+            height: 840000,
+            blocktime: 1713571767,
+            indexer: BITCOIN_INDEX.into(),
+            ..Default::default()
+        };
+        let event = block_event(&block);
+        assert!(event.starts_with("id: 840000\nevent: block\ndata: {\"height\":840000,"));
+        assert!(event.ends_with("\"indexer\":\"btc_utxo_index\"}\n\n"));
+    }
+}
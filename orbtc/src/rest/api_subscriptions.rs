@@ -0,0 +1,218 @@
+use std::net::IpAddr;
+
+use actix_web::web::{Data, Json, Path, ReqData};
+use actix_web::HttpResponse;
+use orbtc_indexer_api::FBtcApiError;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::context::Context;
+use super::requests::decode_address;
+use super::swagger::NetworkPath;
+use crate::db::{scopes, ApiKey, Subscription};
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CreateSubscriptionRequest {
+    pub address: String,
+    /// Receives a POST for every new output of the address.
+    pub url: String,
+}
+
+/// Admin keys manage subscriptions of all keys.
+fn owner_filter(api_key: &ApiKey) -> Option<&str> {
+    if api_key.has_scope(scopes::ADMIN) {
+        None
+    } else {
+        Some(api_key.name.as_str())
+    }
+}
+
+/// Webhooks are sent from inside the deployment, so the url must be a public http(s) host.
+/// Redirects aren't followed on delivery, see [`crate::service::webhooks`].
+async fn check_webhook_url(url: &str) -> Result<(), FBtcApiError> {
+    let url = match reqwest::Url::parse(url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => url,
+        _ => {
+            return Err(FBtcApiError::BadInput(
+                "url must be a valid http(s) url".into(),
+            ))
+        }
+    };
+    let not_public = || FBtcApiError::BadInput("url must point to a public host".into());
+
+    let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+        return Err(not_public());
+    };
+    // ip hosts are taken as is, domains are resolved
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addrs: Vec<IpAddr> = match tokio::net::lookup_host((host, port)).await {
+        Ok(addrs) => addrs.map(|a| a.ip()).collect(),
+        Err(err) => {
+            return Err(FBtcApiError::BadInput(format!(
+                "can't resolve url host: {err}"
+            )))
+        }
+    };
+    if addrs.is_empty() || !addrs.into_iter().all(is_public_ip) {
+        return Err(not_public());
+    }
+
+    Ok(())
+}
+
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                // shared address space, 100.64.0.0/10
+                || (ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ip(ip.into()),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/{network}/subscriptions",
+    tag = "subscriptions",
+    params(NetworkPath),
+    responses((status = 200, body = Vec<Subscription>))
+)]
+pub async fn list_subscriptions(
+    state: Data<Context>,
+    api_key: ReqData<ApiKey>,
+) -> Result<Json<Vec<Subscription>>, FBtcApiError> {
+    match state.db.select_subscriptions(owner_filter(&api_key)).await {
+        Ok(rows) => Ok(Json(rows)),
+        Err(err) => {
+            error!("can't fetch subscriptions: error={:#?}", err);
+            Err(FBtcApiError::InternalError)
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/{network}/subscriptions",
+    tag = "subscriptions",
+    params(NetworkPath),
+    request_body = CreateSubscriptionRequest,
+    responses(
+        (status = 200, body = Subscription),
+        (status = 400, description = "The url isn't a public http(s) url or the address is already subscribed to it")
+    )
+)]
+pub async fn create_subscription(
+    state: Data<Context>,
+    request: Json<CreateSubscriptionRequest>,
+    api_key: ReqData<ApiKey>,
+) -> Result<Json<Subscription>, FBtcApiError> {
+    if let Err(err) = decode_address(&request.address, state.net) {
+        return Err(FBtcApiError::InvalidAddress(format!("{err}")));
+    }
+    check_webhook_url(&request.url).await?;
+
+    let result = state
+        .db
+        .insert_subscription(&request.address, &request.url, &api_key.name)
+        .await;
+    match result {
+        Ok(Some(row)) => {
+            info!(
+                "subscription was created: id={} address={} key={}",
+                row.id, row.address, row.api_key_name
+            );
+            Ok(Json(row))
+        }
+        Ok(None) => Err(FBtcApiError::BadInput(format!(
+            "address={} is already subscribed to this url",
+            request.address
+        ))),
+        Err(err) => {
+            error!(
+                "can't insert subscription: address={} error={:#?}",
+                request.address, err
+            );
+            Err(FBtcApiError::InternalError)
+        }
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/{network}/subscriptions/{id}",
+    tag = "subscriptions",
+    params(NetworkPath, ("id" = i64, Path)),
+    responses((status = 200, description = "Subscription is deleted"))
+)]
+pub async fn delete_subscription(
+    state: Data<Context>,
+    id: Path<i64>,
+    api_key: ReqData<ApiKey>,
+) -> Result<HttpResponse, FBtcApiError> {
+    let id = id.into_inner();
+    match state
+        .db
+        .delete_subscription(id, owner_filter(&api_key))
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => return Err(FBtcApiError::NotFound),
+        Err(err) => {
+            error!("can't delete subscription: id={id} error={:#?}", err);
+            return Err(FBtcApiError::InternalError);
+        }
+    }
+
+    info!("subscription was deleted: id={id}");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_check_webhook_url() {
+        let public = [
+            "https://8.8.8.8/hook",
+            "http://1.1.1.1:8080/hook",
+            "https://[2606:4700:4700::1111]/hook",
+        ];
+        for url in public {
+            assert!(check_webhook_url(url).await.is_ok(), "{url}");
+        }
+
+        let rejected = [
+            "ftp://8.8.8.8/hook",
+            "not a url",
+            "http://localhost:8000/hook",
+            "http://127.0.0.1/hook",
+            "http://10.0.0.5/hook",
+            "http://172.16.3.4/hook",
+            "http://192.168.1.1/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://100.64.0.1/hook",
+            "http://0.0.0.0/hook",
+            "http://[::1]/hook",
+            "http://[fd00::1]/hook",
+            "http://[fe80::1]/hook",
+            "http://[::ffff:127.0.0.1]/hook",
+        ];
+        for url in rejected {
+            assert!(check_webhook_url(url).await.is_err(), "{url}");
+        }
+    }
+}
@@ -1,13 +1,16 @@
 use actix::fut::{ready, Ready};
 use actix_web::body::MessageBody;
 use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
 use actix_web::middleware::Next;
 use actix_web::web::Data;
-use actix_web::{Error, FromRequest};
+use actix_web::{Error, FromRequest, HttpMessage};
 use serde::{Deserialize, Serialize};
 
-const AUTH_HEADER: &str = "x-api-key";
+pub const AUTH_HEADER: &str = "x-api-key";
+use super::api::{net_as_str, NETWORK_ROUTES};
 use super::context::Context;
+use crate::db::scopes;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct XApiKey(pub String);
@@ -59,13 +62,169 @@ pub async fn ensure_api_key(
         .expect("Context should be present")
         .clone();
 
-    let Some(api_key) = state.get_api_key(token) else {
+    let Some(api_key) = state.get_api_key(token).await else {
         return Err(api_core::api_errors::access_denied().into());
     };
-    if api_key.blocked {
+    // keys that expired after the last refresh are still cached
+    if api_key.blocked || api_key.is_expired() || !api_key.allows_network(net_as_str(state.net)) {
         return Err(api_core::api_errors::forbidden().into());
     }
 
+    let route = scope_route(&req).and_then(|pattern| required_scope(req.method(), &pattern));
+    let scope = match route {
+        Some(scope) => scope,
+        // unknown paths end with 404 for any readable key
+        None if req.method() == Method::GET => scopes::READ,
+        // mutating routes without the declared scope fail closed
+        None => return Err(api_core::api_errors::forbidden().into()),
+    };
+    if !api_key.has_scope(scope) {
+        return Err(api_core::api_errors::forbidden().into());
+    }
+
+    tracing::Span::current().record("api_key", api_key.name.as_str());
+    // handlers can take the key with `ReqData<ApiKey>`
+    req.extensions_mut().insert(api_key);
+
     // invoke the wrapped middleware or service
     next.call(req).await
 }
+
+/// Pattern of the matched route without the prefix of the scope that wraps the middleware,
+/// e.g. `/utxos/{address}` for `/v1/mainnet/utxos/bc1q...`.
+fn scope_route(req: &ServiceRequest) -> Option<String> {
+    let prefix_len = req.path().len() - req.match_info().unprocessed().len();
+    let pattern = req.match_pattern()?;
+    pattern.get(prefix_len..).map(String::from)
+}
+
+/// Scope that the key must have to call the route, as declared in [`NETWORK_ROUTES`].
+/// `pattern` is relative to the network scope, `None` if the route has no scope.
+fn required_scope(method: &Method, pattern: &str) -> Option<&'static str> {
+    let (_, methods) = NETWORK_ROUTES.iter().find(|(path, _)| *path == pattern)?;
+    methods
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(method.as_str()))?
+        .1
+}
+
+/// Allows only not blocked keys with the admin flag.
+pub async fn ensure_admin_key(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let (request, _) = req.parts();
+    let Some(token) = request
+        .headers()
+        .get(AUTH_HEADER)
+        .and_then(|h| h.to_str().ok())
+    else {
+        return Err(api_core::api_errors::access_denied().into());
+    };
+
+    let state = req
+        .app_data::<Data<Context>>()
+        .expect("Context should be present")
+        .clone();
+
+    let Some(api_key) = state.get_api_key(token).await else {
+        return Err(api_core::api_errors::access_denied().into());
+    };
+    if api_key.blocked || api_key.is_expired() || !api_key.has_scope(scopes::ADMIN) {
+        return Err(api_core::api_errors::forbidden().into());
+    }
+
+    tracing::Span::current().record("api_key", api_key.name.as_str());
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_required_scope() {
+        let cases = [
+            (Method::GET, "/utxos/{address}", scopes::READ),
+            (Method::POST, "/utxos/{address}", scopes::LOCK),
+            (Method::GET, "/utxos/{address}/locks", scopes::LOCK),
+            (Method::POST, "/utxos/unlock", scopes::LOCK),
+            (Method::DELETE, "/utxos/unlock", scopes::LOCK),
+            (Method::POST, "/runes/{rune}/utxos/{address}", scopes::LOCK),
+            (Method::POST, "/tx", scopes::BROADCAST),
+            (Method::POST, "/tx/test", scopes::BROADCAST),
+            (Method::POST, "/runes/validate-etching", scopes::BROADCAST),
+            (Method::POST, "/psbt/build", scopes::LOCK),
+            (Method::POST, "/runes/{rune}/psbt/build", scopes::LOCK),
+            (Method::GET, "/subscriptions", scopes::SUBSCRIPTIONS),
+            (Method::POST, "/subscriptions", scopes::SUBSCRIPTIONS),
+            (Method::DELETE, "/subscriptions/{id}", scopes::SUBSCRIPTIONS),
+            (Method::POST, "/balances", scopes::READ),
+            (Method::POST, "/runes/balance/{address}", scopes::READ),
+            (Method::GET, "/runes/stats", scopes::READ),
+            (Method::GET, "/labels", scopes::LABELS),
+            (Method::POST, "/labels", scopes::LABELS),
+            (Method::DELETE, "/labels/{address}/{label}", scopes::LABELS),
+        ];
+        for (method, pattern, scope) in cases {
+            assert_eq!(
+                required_scope(&method, pattern),
+                Some(scope),
+                "{method} {pattern}"
+            );
+        }
+        assert_eq!(required_scope(&Method::PUT, "/tx"), None);
+        assert_eq!(required_scope(&Method::POST, "/unknown"), None);
+    }
+
+    #[actix_web::test]
+    async fn test_scope_route() {
+        use actix_web::middleware::from_fn;
+        use actix_web::web::{get, resource, scope};
+        use actix_web::{test, App, HttpResponse};
+
+        async fn echo_route(
+            req: ServiceRequest,
+            _: Next<impl MessageBody>,
+        ) -> Result<ServiceResponse, Error> {
+            let route = scope_route(&req).unwrap_or_default();
+            Ok(req.into_response(HttpResponse::Ok().body(route)))
+        }
+
+        let app = test::init_service(
+            App::new().service(
+                scope("/v1").service(
+                    scope("/mainnet")
+                        .wrap(from_fn(echo_route))
+                        .service(resource("/blocks/stats").route(get().to(HttpResponse::Ok)))
+                        .service(resource("/blocks/{height}").route(get().to(HttpResponse::Ok))),
+                ),
+            ),
+        )
+        .await;
+
+        let cases = [
+            ("/v1/mainnet/blocks/stats", "/blocks/stats"),
+            ("/v1/mainnet/blocks/100", "/blocks/{height}"),
+            ("/v1/mainnet/unknown", ""),
+        ];
+        for (uri, route) in cases {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let body = test::call_and_read_body(&app, req).await;
+            assert_eq!(body, route.as_bytes(), "{uri}");
+        }
+    }
+
+    #[test]
+    fn test_network_routes_declare_scope() {
+        for (path, methods) in NETWORK_ROUTES {
+            for (method, scope) in methods.iter() {
+                assert!(
+                    scope.is_some(),
+                    "{} {path} has no scope",
+                    method.to_uppercase()
+                );
+            }
+        }
+    }
+}
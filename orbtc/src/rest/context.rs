@@ -1,21 +1,68 @@
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::sync::Arc;
 
+use api_core::pages::ListResult;
+use async_trait::async_trait;
 use bitcoincore_rpc::json::EstimateMode;
-use bitcoincore_rpc::{Auth, RpcApi};
+use bitcoincore_rpc::RpcApi;
 use instant::{Duration, Instant};
-use orbtc_indexer_api::{BtcUtxo, RuneUtxo, StatusResponse};
+use orbtc_indexer_api::{BtcUtxo, RuneStats, RuneUtxo, StatusResponse};
 use tokio::sync::RwLock;
 use tokio_util::sync::CancellationToken;
 
+use super::http_cache::CachePolicy;
 use super::mempool_cache::MempoolCacheManager;
-use super::requests::FeeRate;
-use crate::config::Config;
+use super::rate_limit::RateLimiter;
+use super::requests::{FeeRate, FEE_TARGETS};
+use crate::cache::LockStatus;
+use crate::config::{Config, HealthConfig};
 use crate::db::{open_postgres_db, Repo};
 use crate::mempool_api::MempoolClient;
 use crate::rest::metrics;
+use crate::service::utxo_collector::{FilterOpts, UtxoCollector, UtxoCollectorService, UtxoFilter};
 use crate::{cache, db};
 
+/// How many times a collect request re-runs the selection
+/// when picked utxos are concurrently locked by another request.
+pub const MAX_LOCK_ATTEMPTS: usize = 3;
+
+/// Etched runes can only disappear on a reorg, so positive lookups are cached for a while.
+const KNOWN_RUNE_TTL: Duration = Duration::from_secs(300);
+const MAX_KNOWN_RUNES: usize = 10_000;
+
+/// Runes stats aggregate holders of all runes, so the pages are cached for a while.
+const RUNES_STATS_TTL: Duration = Duration::from_secs(30);
+const MAX_RUNES_STATS_PAGES: usize = 1_000;
+
+/// History endpoints check the pruned height on every request, it changes only on `db prune`.
+const PRUNED_HEIGHT_TTL: Duration = Duration::from_secs(30);
+
+/// Dependencies that must be healthy to serve the endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthGate {
+    /// The node, the db and the indexers, for endpoints that ask the node
+    /// or rely on the mempool (fees, broadcast, utxo locks and psbts).
+    Full,
+    /// Only the db, read-only queries of the indexed data stay up during node hiccups.
+    DbOnly,
+    /// The endpoint is always served.
+    None,
+}
+
+impl HealthGate {
+    pub fn allows(self, status: &StatusResponse) -> bool {
+        match self {
+            HealthGate::Full => status.healthy,
+            HealthGate::DbOnly => status.db,
+            HealthGate::None => true,
+        }
+    }
+}
+
+/// Pages of the runes stats by the request filters, with the fetch time.
+pub type RunesStatsCache = HashMap<String, (ListResult<RuneStats>, Instant)>;
+pub type PrunedHeightCache = Option<(Option<i64>, Instant)>;
+
 #[derive(Clone)]
 pub struct Context {
     pub net: bitcoin::Network,
@@ -29,7 +76,15 @@ pub struct Context {
     pub mempool_index: Arc<MempoolCacheManager>,
     pub cached_fee: Arc<RwLock<Option<(FeeRate, Instant)>>>,
 
-    pub api_keys: HashMap<String, db::ApiKey>,
+    /// Api keys by the key value, refreshed periodically by `refresh_api_keys`.
+    pub api_keys: Arc<RwLock<HashMap<String, db::ApiKey>>>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub collector: Arc<dyn UtxoCollector>,
+    /// Names of the runes that were found in the db, with the lookup time.
+    pub known_runes: Arc<RwLock<HashMap<String, Instant>>>,
+    pub runes_stats: Arc<RwLock<RunesStatsCache>>,
+    /// Height pruned by `db prune` with the lookup time.
+    pub pruned_height: Arc<RwLock<PrunedHeightCache>>,
 }
 
 impl Context {
@@ -38,47 +93,109 @@ impl Context {
         let db = Arc::new(repo);
 
         let net = cfg.btc.get_network();
-        let auth = Auth::UserPass(cfg.btc.rpc_user.clone(), cfg.btc.rpc_password.clone());
-        let btc = bitcoincore_rpc::Client::new(&cfg.btc.address, auth)?;
+        let btc = cfg.btc.rpc_client()?;
 
         let btc_client = Arc::new(btc);
-        let mi = MempoolCacheManager::new(&cfg.btc)?;
-        let metrics_collector = MetricsCollector::new(db.clone(), btc_client.clone());
+        let mempool_index = Arc::new(MempoolCacheManager::new(&cfg.btc, db.clone())?);
+        let metrics_collector =
+            MetricsCollector::new(db.clone(), btc_client.clone(), cfg.health.clone());
         let cache_repo = if cfg.cache.enable {
             Some(cache::Repo::new(&cfg.cache.redis, cfg.cache.lock_ttl).await?)
         } else {
             None
         };
+        let cache = Arc::new(cache_repo);
 
-        // Right now new api key can be added only manually.
-        // So, we load all keys once at start.
-        let api_keys = db
-            .select_api_keys()
-            .await?
-            .iter()
-            .map(|e| (e.key.clone(), e.clone()))
-            .collect();
+        let utxo_filter = SpendableUtxoFilter {
+            db: db.clone(),
+            cache: cache.clone(),
+            mempool_index: mempool_index.clone(),
+        };
+        let collector =
+            UtxoCollectorService::new(db.clone(), cfg.collector.clone(), Arc::new(utxo_filter));
+
+        let api_keys = Arc::new(RwLock::new(load_api_keys(&db).await?));
+        let rate_limiter = Arc::new(RateLimiter::new(cfg.api.rate_limit.clone(), cache.clone()));
 
         Ok(Self {
             db,
             btc_client,
             net,
             cfg,
-            cache: Arc::new(cache_repo),
+            cache,
             cached_fee: Arc::new(RwLock::new(None)),
             metrics_collector: Arc::new(metrics_collector),
-            mempool_index: Arc::new(mi),
+            mempool_index,
             api_keys,
+            rate_limiter,
+            collector: Arc::new(collector),
+            known_runes: Arc::new(RwLock::new(HashMap::new())),
+            runes_stats: Arc::new(RwLock::new(HashMap::new())),
+            pruned_height: Arc::new(RwLock::new(None)),
         })
     }
 
+    /// Checks that the rune with the given name is etched.
+    pub async fn rune_exists(&self, name: &str) -> anyhow::Result<bool> {
+        if let Some(instant) = self.known_runes.read().await.get(name) {
+            if Instant::now().duration_since(*instant) < KNOWN_RUNE_TTL {
+                return Ok(true);
+            }
+        }
+
+        if self.db.get_rune(name).await?.is_none() {
+            return Ok(false);
+        }
+
+        let mut known_runes = self.known_runes.write().await;
+        if known_runes.len() >= MAX_KNOWN_RUNES {
+            let now = Instant::now();
+            known_runes.retain(|_, instant| now.duration_since(*instant) < KNOWN_RUNE_TTL);
+        }
+        known_runes.insert(name.to_owned(), Instant::now());
+        Ok(true)
+    }
+
+    /// Page of the runes stats fetched less than [`RUNES_STATS_TTL`] ago.
+    pub async fn cached_runes_stats(&self, key: &str) -> Option<ListResult<RuneStats>> {
+        let runes_stats = self.runes_stats.read().await;
+        let (stats, instant) = runes_stats.get(key)?;
+        if Instant::now().duration_since(*instant) < RUNES_STATS_TTL {
+            return Some(stats.clone());
+        }
+        None
+    }
+
+    pub async fn cache_runes_stats(&self, key: String, stats: ListResult<RuneStats>) {
+        let mut runes_stats = self.runes_stats.write().await;
+        if runes_stats.len() >= MAX_RUNES_STATS_PAGES {
+            let now = Instant::now();
+            runes_stats.retain(|_, (_, instant)| now.duration_since(*instant) < RUNES_STATS_TTL);
+        }
+        runes_stats.insert(key, (stats, Instant::now()));
+    }
+
+    /// Height pruned by `db prune` fetched less than [`PRUNED_HEIGHT_TTL`] ago,
+    /// `None` if the db was never pruned.
+    pub async fn pruned_height(&self) -> anyhow::Result<Option<i64>> {
+        if let Some((height, instant)) = *self.pruned_height.read().await {
+            if Instant::now().duration_since(instant) < PRUNED_HEIGHT_TTL {
+                return Ok(height);
+            }
+        }
+
+        let height = self.db.get_pruned_height().await?;
+        *self.pruned_height.write().await = Some((height, Instant::now()));
+        Ok(height)
+    }
+
     pub async fn estimate_fee(&self) -> anyhow::Result<FeeRate> {
         const CACHE_TTL: Duration = Duration::from_secs(10);
 
         // Read from cache.
-        if let Some((cached, instant)) = *self.cached_fee.read().await {
-            if Instant::now().duration_since(instant) < CACHE_TTL {
-                return Ok(cached);
+        if let Some((cached, instant)) = self.cached_fee.read().await.as_ref() {
+            if Instant::now().duration_since(*instant) < CACHE_TTL {
+                return Ok(cached.clone());
             }
         }
 
@@ -98,6 +215,7 @@ impl Context {
                                 fast: 3,
                                 normal: 2,
                                 min: 1,
+                                ..Default::default()
                             }
                         } else {
                             return Err(err);
@@ -112,7 +230,7 @@ impl Context {
         };
 
         // Successful response, update the cache.
-        *self.cached_fee.write().await = Some((fee, Instant::now()));
+        *self.cached_fee.write().await = Some((fee.clone(), Instant::now()));
 
         Ok(fee)
     }
@@ -121,9 +239,9 @@ impl Context {
         const CACHE_TTL: Duration = Duration::from_secs(10);
 
         // Read from cache.
-        if let Some((cached, instant)) = *self.cached_fee.read().await {
-            if Instant::now().duration_since(instant) < CACHE_TTL {
-                return Ok(cached);
+        if let Some((cached, instant)) = self.cached_fee.read().await.as_ref() {
+            if Instant::now().duration_since(*instant) < CACHE_TTL {
+                return Ok(cached.clone());
             }
         }
 
@@ -132,14 +250,37 @@ impl Context {
         let normal_fee = get_fee_local(&self.btc_client, 3, EstimateMode::Conservative).await?;
         let min_fee = get_fee_local(&self.btc_client, 6, EstimateMode::Economical).await?;
 
+        // Extended estimation is best effort, node may have not enough data for long targets.
+        let economy_fee = get_fee_local(&self.btc_client, 144, EstimateMode::Economical)
+            .await
+            .ok();
+        let mut targets = BTreeMap::new();
+        for target in FEE_TARGETS {
+            let fee = match target {
+                1 => fastest_fee,
+                3 => normal_fee,
+                _ => {
+                    match get_fee_local(&self.btc_client, target, EstimateMode::Conservative).await
+                    {
+                        Ok(fee) => fee,
+                        Err(_) => continue,
+                    }
+                }
+            };
+            targets.insert(target, fee);
+        }
+
         let fee = FeeRate {
             fast: fastest_fee,
             normal: normal_fee,
             min: min_fee,
+            economy: economy_fee,
+            targets: Some(targets),
+            histogram: None,
         };
 
         // Successful response, update the cache.
-        *self.cached_fee.write().await = Some((fee, Instant::now()));
+        *self.cached_fee.write().await = Some((fee.clone(), Instant::now()));
 
         Ok(fee)
     }
@@ -164,41 +305,235 @@ impl Context {
         check_no_runes: bool,
         request_id: Option<String>,
     ) -> anyhow::Result<Vec<BtcUtxo>> {
-        let mut rows = self.mempool_index.filter_used_utxos(utxos).await;
+        let opts = FilterOpts {
+            request_id,
+            skip_inscriptions: true,
+            skip_runes: check_no_runes,
+            skip_premature: None,
+        };
+        self.utxo_filter().filter_btc_utxos(utxos, &opts).await
+    }
 
-        let utxo_ids: Vec<_> = utxos.iter().map(|u| u.id).collect();
-        let has_inscriptions: BTreeSet<_> = self
-            .db
-            .select_outputs_extras(&utxo_ids)
-            .await?
+    pub async fn filter_used_runes_utxos(
+        &self,
+        utxos: &[RuneUtxo],
+        request_id: Option<String>,
+        include_inscribed: bool,
+    ) -> anyhow::Result<Vec<RuneUtxo>> {
+        let opts = FilterOpts {
+            request_id,
+            skip_inscriptions: !include_inscribed,
+            ..Default::default()
+        };
+        self.utxo_filter().filter_rune_utxos(utxos, &opts).await
+    }
+
+    fn utxo_filter(&self) -> SpendableUtxoFilter {
+        SpendableUtxoFilter {
+            db: self.db.clone(),
+            cache: self.cache.clone(),
+            mempool_index: self.mempool_index.clone(),
+        }
+    }
+
+    /// Checks the dependencies the endpoint requires against the cached status.
+    pub async fn is_ready(&self, gate: HealthGate) -> bool {
+        if gate == HealthGate::None {
+            return true;
+        }
+        gate.allows(&self.metrics_collector.service_status().await)
+    }
+
+    /// Current chain tip from the cached status, falls back to the indexed height
+    /// when the node is unavailable.
+    pub async fn chain_tip(&self) -> u64 {
+        let status = self.metrics_collector.service_status().await;
+        status.btc_height.max(status.btc_indexer_height)
+    }
+
+    /// Exclusive height bound of mature coinbase outputs for the `skip_premature` filter,
+    /// see [`MetricsCollector::coinbase_maturity_cutoff`].
+    pub async fn coinbase_maturity_cutoff(&self) -> Option<u64> {
+        self.metrics_collector.coinbase_maturity_cutoff().await
+    }
+
+    /// Data of the block is immutable once it has more than `http_cache.final_confirmations`.
+    /// Unconfirmed data (`None` block) is never cached.
+    pub async fn block_cache_policy(&self, block: Option<i64>) -> CachePolicy {
+        let cfg = &self.cfg.http_cache;
+        let is_final = match block {
+            Some(block) => {
+                let confirmations = orbtc_indexer_api::confirmations(block, self.chain_tip().await);
+                confirmations > cfg.final_confirmations as i64
+            }
+            None => false,
+        };
+        CachePolicy::immutable_if(is_final, cfg)
+    }
+
+    /// Locks all outpoints for `rid`. If some outpoint is already held by another request,
+    /// locks taken by this call are released and `false` is returned.
+    /// Cache errors are only logged and don't fail the request.
+    pub async fn lock_outpoints(
+        &self,
+        outpoints: &[(&orbtc_indexer_api::types::Hash, i32)],
+        rid: &str,
+    ) -> bool {
+        let Some(cache) = self.cache.as_ref() else {
+            return true;
+        };
+        // one owner for the whole batch, so anonymous locks can be released below
+        let rid = &cache::lock_owner_id(rid);
+
+        // locks held by the same request before this call are kept on a conflict
+        let mut created = Vec::with_capacity(outpoints.len());
+        let mut conflict = false;
+        for (tx_hash, vout) in outpoints.iter() {
+            match cache.lock_utxo(tx_hash, *vout, rid).await {
+                Ok(LockStatus::Created) => created.push((*tx_hash, *vout)),
+                Ok(LockStatus::Renewed) => {}
+                Ok(LockStatus::OwnedByOther) => {
+                    debug!("utxo is locked by another request: utxo={tx_hash}:{vout} id={rid}");
+                    conflict = true;
+                    break;
+                }
+                Err(err) => error!("unable to write utxo lock: id={rid} error={err:#}"),
+            }
+        }
+        if !conflict {
+            return true;
+        }
+
+        for (tx_hash, vout) in created {
+            if let Err(err) = cache.unlock_utxo(tx_hash, vout, rid).await {
+                error!("unable to release utxo lock: id={rid} error={err:#}");
+            }
+        }
+        false
+    }
+
+    /// Sum of the `utxos` locked by any request, 0 if the cache is disabled.
+    pub async fn locked_amount(&self, utxos: &[db::ShortUtxo]) -> anyhow::Result<i64> {
+        let outpoints: Vec<_> = utxos.iter().map(|u| (&u.tx_hash, u.vout)).collect();
+        let locked = self
+            .utxo_filter()
+            .locked_outpoints(&outpoints, &None)
+            .await?;
+        Ok(utxos
             .iter()
-            .filter_map(|e| if e.has_inscriptions { Some(e.id) } else { None })
-            .collect();
+            .filter(|u| locked.contains(&(u.tx_hash.clone(), u.vout)))
+            .map(|u| u.amount)
+            .sum())
+    }
+
+    pub async fn get_api_key(&self, api_key: &str) -> Option<db::ApiKey> {
+        self.api_keys.read().await.get(api_key).cloned()
+    }
+
+    /// Replaces cached api keys with the rows from the db.
+    pub async fn reload_api_keys(&self) -> anyhow::Result<()> {
+        let keys = load_api_keys(&self.db).await?;
+        *self.api_keys.write().await = keys;
+        Ok(())
+    }
+}
+
+/// Expired keys are not loaded, so they are dropped by the next refresh.
+async fn load_api_keys(db: &Repo) -> anyhow::Result<HashMap<String, db::ApiKey>> {
+    let keys = db
+        .select_api_keys(false)
+        .await?
+        .into_iter()
+        .map(|e| (e.key.clone(), e))
+        .collect();
+    Ok(keys)
+}
+
+/// Picks up api keys changed by the CLI or by other API replicas.
+pub async fn refresh_api_keys(context: Context, cancel: CancellationToken) {
+    use tokio::time::sleep;
+
+    let interval = Duration::from_secs(context.cfg.api_keys_refresh_secs.max(1));
+    loop {
+        tokio::select! {
+            _ = sleep(interval) => {}
+
+            _ = cancel.cancelled() => {
+                log::info!("refresh api keys task cancelled");
+                break;
+            }
+        };
 
-        let runes_outs = if check_no_runes {
+        if let Err(err) = context.reload_api_keys().await {
+            error!("can't refresh api keys: error={:#}", err);
+        }
+    }
+}
+
+/// Drops utxos that are used in the mempool, locked by other requests
+/// or carry inscriptions/runes.
+#[derive(Clone)]
+pub struct SpendableUtxoFilter {
+    db: Arc<Repo>,
+    cache: Arc<Option<cache::Repo>>,
+    mempool_index: Arc<MempoolCacheManager>,
+}
+
+impl SpendableUtxoFilter {
+    /// Returns outpoints locked by other requests, all of them are checked in one cache request.
+    async fn locked_outpoints(
+        &self,
+        outpoints: &[(&orbtc_indexer_api::Hash, i32)],
+        request_id: &Option<String>,
+    ) -> anyhow::Result<BTreeSet<(orbtc_indexer_api::Hash, i32)>> {
+        match self.cache.as_ref() {
+            Some(repo) => repo.check_locked_batch(outpoints, request_id).await,
+            None => Ok(BTreeSet::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl UtxoFilter for SpendableUtxoFilter {
+    async fn filter_btc_utxos(
+        &self,
+        utxos: &[BtcUtxo],
+        opts: &FilterOpts,
+    ) -> anyhow::Result<Vec<BtcUtxo>> {
+        let rows = self.mempool_index.filter_used_utxos(utxos).await;
+
+        let has_inscriptions: BTreeSet<_> = if opts.skip_inscriptions {
+            let utxo_ids: Vec<_> = utxos.iter().map(|u| u.id).collect();
+            self.db
+                .select_outputs_extras(&utxo_ids)
+                .await?
+                .iter()
+                .filter_map(|e| if e.has_inscriptions { Some(e.id) } else { None })
+                .collect()
+        } else {
+            BTreeSet::new()
+        };
+
+        let runes_outs: BTreeSet<_> = if opts.skip_runes {
             let txs: Vec<_> = utxos.iter().map(|u| &u.tx_hash).collect();
-            let runes_outs = self.db.select_runes_utxo_for_txs(&txs).await?;
-            let runes_outs: BTreeSet<_> = runes_outs
+            self.db
+                .select_runes_utxo_for_txs(&txs)
+                .await?
                 .iter()
                 .map(|o| (o.tx_hash.clone(), o.vout))
-                .collect();
-            runes_outs
+                .collect()
         } else {
             BTreeSet::new()
         };
 
+        let outpoints: Vec<_> = rows.iter().map(|r| (&r.tx_hash, r.vout)).collect();
+        let locked = self.locked_outpoints(&outpoints, &opts.request_id).await?;
+
         let mut filtered = Vec::new();
         for r in rows {
-            // TODO: make cache request optional
-            let locked = if let Some(repo) = self.cache.as_ref() {
-                repo.check_is_locked(&r.tx_hash, r.vout, &request_id)
-                    .await?
-            } else {
-                false
-            };
-
-            if locked
-                || runes_outs.contains(&(r.tx_hash.clone(), r.vout))
+            let outpoint = (r.tx_hash.clone(), r.vout);
+            if locked.contains(&outpoint)
+                || runes_outs.contains(&outpoint)
                 || has_inscriptions.contains(&r.id)
             {
                 continue;
@@ -206,16 +541,16 @@ impl Context {
 
             filtered.push(r)
         }
-        rows = filtered;
 
-        Ok(rows)
+        Ok(filtered)
     }
 
-    pub async fn filter_used_runes_utxos(
+    async fn filter_rune_utxos(
         &self,
         utxos: &[RuneUtxo],
-        request_id: Option<String>,
+        opts: &FilterOpts,
     ) -> anyhow::Result<Vec<RuneUtxo>> {
+        // fetched even if inscribed utxos are allowed, to mark them in the response
         let utxo_ids: Vec<_> = utxos.iter().map(|u| u.id).collect();
         let has_inscriptions: BTreeSet<_> = self
             .db
@@ -224,34 +559,24 @@ impl Context {
             .iter()
             .filter_map(|e| if e.has_inscriptions { Some(e.id) } else { None })
             .collect();
-        let mut rows = self.mempool_index.filter_used_runes_utxos(utxos).await;
-        let mut filtered = Vec::new();
-        for r in rows {
-            // TODO: make cache request optional
-            let locked = if let Some(repo) = self.cache.as_ref() {
-                repo.check_is_locked(&r.tx_hash, r.vout, &request_id)
-                    .await?
-            } else {
-                false
-            };
 
-            if locked || has_inscriptions.contains(&r.id) {
+        let rows = self.mempool_index.filter_used_runes_utxos(utxos).await;
+        let outpoints: Vec<_> = rows.iter().map(|r| (&r.tx_hash, r.vout)).collect();
+        let locked = self.locked_outpoints(&outpoints, &opts.request_id).await?;
+
+        let mut filtered = Vec::new();
+        for mut r in rows {
+            r.has_inscriptions = has_inscriptions.contains(&r.id);
+            if locked.contains(&(r.tx_hash.clone(), r.vout))
+                || (opts.skip_inscriptions && r.has_inscriptions)
+            {
                 continue;
             }
 
             filtered.push(r)
         }
-        rows = filtered;
-
-        Ok(rows)
-    }
 
-    pub async fn is_healthy(&self) -> bool {
-        self.metrics_collector.service_status().await.healthy
-    }
-
-    pub fn get_api_key(&self, api_key: &str) -> Option<db::ApiKey> {
-        self.api_keys.get(api_key).cloned()
+        Ok(filtered)
     }
 }
 
@@ -279,32 +604,46 @@ async fn get_fee_local(
     Ok(fee_sat_per_vbyte)
 }
 
+/// Number of blocks a coinbase output needs to be spendable.
+const COINBASE_MATURITY: u64 = 100;
+
+/// Coinbase outputs of blocks up to `tip - 99` can be spent in the block `tip + 1`,
+/// returns the exclusive bound of them.
+fn coinbase_maturity_cutoff(tip: u64) -> u64 {
+    (tip + 2).saturating_sub(COINBASE_MATURITY)
+}
+
 pub struct MetricsCollector {
     db: Arc<Repo>,
     btc_client: Arc<bitcoincore_rpc::Client>,
+    health: HealthConfig,
     status: Arc<RwLock<Option<(StatusResponse, Instant)>>>,
 }
 
 impl MetricsCollector {
-    pub fn new(db: Arc<Repo>, btc_client: Arc<bitcoincore_rpc::Client>) -> Self {
+    pub fn new(
+        db: Arc<Repo>,
+        btc_client: Arc<bitcoincore_rpc::Client>,
+        health: HealthConfig,
+    ) -> Self {
         Self {
             db,
             btc_client,
+            health,
             status: Arc::new(RwLock::new(None)),
         }
     }
 
-    pub async fn service_status(&self) -> StatusResponse {
-        #[cfg(test)]
-        const CACHE_TTL: Duration = Duration::from_millis(200);
-        #[cfg(not(test))]
-        const CACHE_TTL: Duration = Duration::from_secs(10);
+    /// Returns number of in-use and idle connections of the db pool.
+    pub fn db_pool_usage(&self) -> (u64, u64) {
+        let size = self.db.pool.size() as u64;
+        let idle = self.db.pool.num_idle() as u64;
+        (size.saturating_sub(idle), idle)
+    }
 
-        // Read from cache.
-        if let Some((cached, instant)) = *self.status.read().await {
-            if Instant::now().duration_since(instant) < CACHE_TTL {
-                return cached;
-            }
+    pub async fn service_status(&self) -> StatusResponse {
+        if let Some(cached) = self.fresh_status().await {
+            return cached;
         }
 
         let status = self.aggregate_status().await;
@@ -312,6 +651,35 @@ impl MetricsCollector {
         status
     }
 
+    /// Cached status if it's not older than `status_cache_ttl_secs`.
+    async fn fresh_status(&self) -> Option<StatusResponse> {
+        #[cfg(test)]
+        let cache_ttl = Duration::from_millis(200);
+        #[cfg(not(test))]
+        let cache_ttl = Duration::from_secs(self.health.status_cache_ttl_secs);
+
+        let (cached, instant) = (*self.status.read().await)?;
+        (Instant::now().duration_since(instant) < cache_ttl).then_some(cached)
+    }
+
+    /// Coinbase outputs of blocks below the returned height are mature,
+    /// so they can be spent by the next block after the indexed tip.
+    /// The tip is the indexed height of the cached status, the node is asked
+    /// only when the cache is stale. `None` if the node is unavailable.
+    pub async fn coinbase_maturity_cutoff(&self) -> Option<u64> {
+        let tip = match self.fresh_status().await {
+            Some(status) => status.btc_indexer_height,
+            None => match self.btc_client.get_block_count() {
+                Ok(block) => block,
+                Err(err) => {
+                    warn!("can't get BTC block count: error={:#?}", err);
+                    return None;
+                }
+            },
+        };
+        Some(coinbase_maturity_cutoff(tip))
+    }
+
     pub async fn aggregate_status(&self) -> StatusResponse {
         use crate::indexer::BITCOIN_INDEX;
         let (btc_node, btc_height) = match self.btc_client.get_block_count() {
@@ -323,38 +691,84 @@ impl MetricsCollector {
         };
 
         let mut db = true;
-        let btc = match self.db.get_last_indexed_block(BITCOIN_INDEX).await {
-            Ok(block) => block,
+        let btc = match self.db.get_last_indexed_block_info(BITCOIN_INDEX).await {
+            Ok(block) => block.unwrap_or_default(),
             Err(err) => {
                 db = false;
                 error!("failed to get bitcoin indexer status: error={:#?}", err);
-                0
+                Default::default()
             }
         };
 
-        let mut runes = 0;
         use crate::indexer::RUNES_INDEX;
-        match self.db.get_last_indexed_block(RUNES_INDEX).await {
-            Ok(block) => runes = block,
+        let runes = match self.db.get_last_indexed_block_info(RUNES_INDEX).await {
+            Ok(block) => block.unwrap_or_default(),
             Err(err) => {
                 db = false;
-                error!("failed to get bitcoin indexer status: error={:#?}", err);
+                error!("failed to get runes indexer status: error={:#?}", err);
+                Default::default()
+            }
+        };
+        let (btc_updated_at, runes_updated_at) = (btc.updated_at, runes.updated_at);
+        let (btc, runes) = (btc.height as u64, runes.height as u64);
+
+        let mut inscriptions = None;
+        if self.health.expect_inscriptions_indexer {
+            use crate::indexer::INSCRIPTIONS_CACHE_INDEX;
+            match self
+                .db
+                .get_last_indexed_block(INSCRIPTIONS_CACHE_INDEX)
+                .await
+            {
+                Ok(block) => inscriptions = Some(block),
+                Err(err) => {
+                    db = false;
+                    error!(
+                        "failed to get inscriptions indexer status: error={:#?}",
+                        err
+                    );
+                }
             }
         }
 
-        let btc_indexer_ok = btc_height.max(btc) - btc <= 3;
-        let runes_indexer_ok = btc_height.max(runes) - runes <= 3;
-        let healthy = db && btc_node && btc_indexer_ok && runes_indexer_ok;
+        let max_lag = self.health.max_indexer_lag;
+        let lag = |block: u64| btc_height.max(block) - block;
+        let btc_indexer_ok = lag(btc) <= max_lag;
+        let runes_indexer_ok = lag(runes) <= max_lag;
+        let inscriptions_indexer_ok = match (self.health.expect_inscriptions_indexer, inscriptions)
+        {
+            (false, _) => None,
+            (true, Some(block)) => Some(lag(block) <= max_lag),
+            (true, None) => Some(false),
+        };
+        let worst_lag = [Some(btc), Some(runes), inscriptions]
+            .into_iter()
+            .flatten()
+            .map(lag)
+            .max()
+            .unwrap_or_default();
+        let degraded = worst_lag > max_lag / 2;
+        let seconds_since_last_block =
+            seconds_since_last_block(&[btc_updated_at, runes_updated_at], db::unix_now());
+        let max_stale = self.health.max_seconds_since_last_block;
+        let stalled = max_stale > 0 && seconds_since_last_block.is_some_and(|s| s > max_stale);
+        let healthy = db
+            && btc_node
+            && btc_indexer_ok
+            && runes_indexer_ok
+            && inscriptions_indexer_ok.unwrap_or(true)
+            && !stalled;
 
         if !healthy {
             error!(
-                "Indexer API is unhealthy: db={} btc={} height={} btc_indexer={} runes_indexer={}",
-                db, btc_node, btc_height, btc, runes,
+                "Indexer API is unhealthy: db={} btc={} height={} btc_indexer={} runes_indexer={} inscriptions_indexer={:?} seconds_since_last_block={:?}",
+                db, btc_node, btc_height, btc, runes, inscriptions, seconds_since_last_block,
             );
         }
 
         StatusResponse {
             healthy,
+            degraded,
             db,
             btc_node,
             btc_height,
@@ -362,10 +776,25 @@ impl MetricsCollector {
             btc_indexer_height: btc,
             runes_indexer: runes_indexer_ok,
             runes_indexer_height: runes,
+            inscriptions_indexer: inscriptions_indexer_ok,
+            inscriptions_indexer_height: inscriptions,
+            btc_indexer_updated_at: btc_updated_at,
+            runes_indexer_updated_at: runes_updated_at,
+            seconds_since_last_block,
         }
     }
 }
 
+/// Seconds since the least recently updated of the indexers,
+/// `None` if none of them has the update time.
+fn seconds_since_last_block(updated_at: &[Option<i64>], now: i64) -> Option<u64> {
+    updated_at
+        .iter()
+        .flatten()
+        .min()
+        .map(|&t| now.saturating_sub(t).max(0) as u64)
+}
+
 pub async fn update_metrics(cache: Arc<MetricsCollector>, cancel: CancellationToken) {
     use std::time::Duration;
 
@@ -375,6 +804,8 @@ pub async fn update_metrics(cache: Arc<MetricsCollector>, cancel: CancellationTo
         info!("Update metrics status");
         let status = cache.service_status().await;
         metrics::update(status);
+        let (in_use, idle) = cache.db_pool_usage();
+        metrics::update_db_pool(in_use, idle);
 
         tokio::select! {
             _ = sleep(Duration::from_secs(10)) => {
@@ -388,3 +819,160 @@ pub async fn update_metrics(cache: Arc<MetricsCollector>, cancel: CancellationTo
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use sqlx::postgres::PgPoolOptions;
+
+    use super::*;
+
+    /// The node is never available, so a height returned without the cache comes from nowhere.
+    fn offline_collector(db: Repo) -> MetricsCollector {
+        let btc_client =
+            bitcoincore_rpc::Client::new("http://127.0.0.1:1", bitcoincore_rpc::Auth::None)
+                .unwrap();
+        MetricsCollector::new(Arc::new(db), Arc::new(btc_client), HealthConfig::default())
+    }
+
+    fn lazy_repo() -> Repo {
+        let pool = PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/orbtc")
+            .unwrap();
+        Repo {
+            pool,
+            query_timeout: None,
+        }
+    }
+
+    #[test]
+    fn test_coinbase_maturity_cutoff() {
+        // coinbase of the block 901 can be spent by the block 1001
+        assert_eq!(coinbase_maturity_cutoff(1000), 902);
+        assert_eq!(coinbase_maturity_cutoff(98), 0);
+        assert_eq!(coinbase_maturity_cutoff(0), 0);
+    }
+
+    #[tokio::test]
+    async fn test_coinbase_maturity_cutoff_uses_cached_status() {
+        let collector = offline_collector(lazy_repo());
+        let status = StatusResponse {
+            btc_height: 1010,
+            btc_indexer_height: 1000,
+            ..Default::default()
+        };
+        *collector.status.write().await = Some((status, Instant::now()));
+        // judged by the indexed height, the node is not asked
+        assert_eq!(collector.coinbase_maturity_cutoff().await, Some(902));
+
+        // stale cache falls back to the node
+        *collector.status.write().await = Some((status, Instant::now() - Duration::from_secs(1)));
+        assert_eq!(collector.coinbase_maturity_cutoff().await, None);
+    }
+
+    #[test]
+    fn test_seconds_since_last_block() {
+        assert_eq!(seconds_since_last_block(&[None, None], 1000), None);
+        // the stalled indexer is the one that matters
+        assert_eq!(
+            seconds_since_last_block(&[Some(990), Some(400)], 1000),
+            Some(600)
+        );
+        assert_eq!(seconds_since_last_block(&[None, Some(990)], 1000), Some(10));
+        // clock skew between the db and the api
+        assert_eq!(seconds_since_last_block(&[Some(1010)], 1000), Some(0));
+    }
+
+    #[test]
+    fn test_health_gate() {
+        let node_down = StatusResponse {
+            db: true,
+            btc_node: false,
+            ..Default::default()
+        };
+        assert!(!HealthGate::Full.allows(&node_down));
+        assert!(HealthGate::DbOnly.allows(&node_down));
+        assert!(HealthGate::None.allows(&node_down));
+
+        let db_down = StatusResponse::default();
+        assert!(!HealthGate::DbOnly.allows(&db_down));
+        assert!(HealthGate::None.allows(&db_down));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires postgres, set DATABASE_URL"]
+    async fn test_health_gate_with_dead_node() {
+        let dsn = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let db = open_postgres_db(&crate::config::DBConfig {
+            dsn,
+            automigrate: false,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        db.reset_schema().await.unwrap();
+
+        let collector = offline_collector(db);
+        let status = collector.service_status().await;
+        assert!(status.db);
+        assert!(!status.btc_node);
+        assert!(!status.healthy);
+        // pure db endpoints are served, node dependent ones are not
+        assert!(HealthGate::DbOnly.allows(&status));
+        assert!(!HealthGate::Full.allows(&status));
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires postgres, set DATABASE_URL"]
+    async fn test_blocked_api_key_is_rejected_after_refresh() {
+        use actix_web::http::StatusCode;
+        use actix_web::{test, App};
+        use api_core::server::APIProvider;
+
+        use crate::config::{BTCConfig, DBConfig};
+        use crate::rest::api::Service;
+
+        let dsn = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let cfg = Config {
+            btc: BTCConfig {
+                network: Some("regtest".into()),
+                address: "http://127.0.0.1:1".into(),
+                ..Default::default()
+            },
+            db: DBConfig {
+                dsn,
+                automigrate: false,
+                seed_test_api_key: true,
+                ..Default::default()
+            },
+            api_keys_refresh_secs: 1,
+            ..Default::default()
+        };
+        let db = open_postgres_db(&cfg.db).await.unwrap();
+        db.reset_schema().await.unwrap();
+        // inserts the `TEST_API_KEY` seed key
+        db::apply_migrations(&cfg.db).await.unwrap();
+
+        let context = Context::new(cfg).await.unwrap();
+        let cancel = CancellationToken::new();
+        tokio::spawn(refresh_api_keys(context.clone(), cancel.clone()));
+        let app = test::init_service(App::new().service(Service { context }.service())).await;
+
+        let request = || {
+            test::TestRequest::get()
+                .uri("/v1/regtest/blocks")
+                .insert_header(("x-api-key", "TEST_API_KEY"))
+                .to_request()
+        };
+        // btc node is not available, so the key passes auth but the service is unhealthy
+        let resp = test::call_service(&app, request()).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        // the same as `orbtc api-key block`
+        db.block_api_key("test-client").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+
+        let resp = test::call_service(&app, request()).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        cancel.cancel();
+    }
+}
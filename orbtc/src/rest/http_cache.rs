@@ -0,0 +1,163 @@
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{ContentType, HeaderValue, CACHE_CONTROL, ETAG, IF_NONE_MATCH};
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpRequest, HttpResponse, Responder};
+use orbtc_indexer_api::Hash;
+use serde::Serialize;
+
+use crate::config::HttpCacheConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CachePolicy {
+    /// Mutable data, e.g. utxos and balances.
+    NoStore,
+    /// Data can change, clients must revalidate it with the ETag.
+    Revalidate,
+    /// Data is final, e.g. deeply confirmed, it's cached for `max_age` seconds.
+    Immutable { max_age: u64 },
+}
+
+impl CachePolicy {
+    /// `Immutable` if the data is `is_final`, `NoStore` otherwise.
+    pub fn immutable_if(is_final: bool, cfg: &HttpCacheConfig) -> Self {
+        if is_final {
+            Self::Immutable {
+                max_age: cfg.max_age_secs,
+            }
+        } else {
+            Self::NoStore
+        }
+    }
+
+    fn cache_control(&self) -> String {
+        match self {
+            Self::NoStore => "no-store".into(),
+            Self::Revalidate => "no-cache".into(),
+            Self::Immutable { max_age } => format!("public, max-age={max_age}, immutable"),
+        }
+    }
+}
+
+/// Json response with the cache headers of the `policy`.
+/// Cacheable responses get an ETag of the body, `If-None-Match`
+/// with the same ETag is answered with 304.
+pub struct CachedJson<T> {
+    pub value: T,
+    pub policy: CachePolicy,
+}
+
+impl<T> CachedJson<T> {
+    pub fn new(value: T, policy: CachePolicy) -> Self {
+        Self { value, policy }
+    }
+}
+
+impl<T: Serialize> Responder for CachedJson<T> {
+    type Body = BoxBody;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse<Self::Body> {
+        let body = match serde_json::to_vec(&self.value) {
+            Ok(body) => body,
+            Err(err) => {
+                error!("can't serialize response: error={err}");
+                return HttpResponse::InternalServerError().finish();
+            }
+        };
+
+        let cache_control = (CACHE_CONTROL, self.policy.cache_control());
+        if self.policy == CachePolicy::NoStore {
+            return HttpResponse::Ok()
+                .content_type(ContentType::json())
+                .insert_header(cache_control)
+                .body(body);
+        }
+
+        let etag = format!("\"{}\"", Hash::sha2(&body));
+        let if_none_match = req
+            .headers()
+            .get(IF_NONE_MATCH)
+            .and_then(|v| v.to_str().ok());
+        if if_none_match.is_some_and(|v| etag_matches(v, &etag)) {
+            return HttpResponse::NotModified()
+                .insert_header(cache_control)
+                .insert_header((ETAG, etag))
+                .finish();
+        }
+
+        HttpResponse::Ok()
+            .content_type(ContentType::json())
+            .insert_header(cache_control)
+            .insert_header((ETAG, etag))
+            .body(body)
+    }
+}
+
+/// `If-None-Match` is a list of ETags or `*`, weak ETags match as well.
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match.split(',').any(|tag| {
+        let tag = tag.trim();
+        tag == "*" || tag.trim_start_matches("W/") == etag
+    })
+}
+
+/// Responses are not cached unless the handler has set its own `Cache-Control`,
+/// so CDNs never serve stale utxos or balances.
+pub async fn no_store_by_default(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let mut res = next.call(req).await?;
+    if !res.headers().contains_key(CACHE_CONTROL) {
+        res.headers_mut()
+            .insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    #[test]
+    fn test_etag_matches() {
+        let etag = "\"abc\"";
+        assert!(etag_matches("\"abc\"", etag));
+        assert!(etag_matches("W/\"abc\"", etag));
+        assert!(etag_matches("\"xyz\", \"abc\"", etag));
+        assert!(etag_matches("*", etag));
+        assert!(!etag_matches("\"xyz\"", etag));
+        assert!(!etag_matches("abc", etag));
+    }
+
+    #[test]
+    fn test_cached_json() {
+        let policy = CachePolicy::Immutable { max_age: 60 };
+        let req = TestRequest::default().to_http_request();
+        let res = CachedJson::new(vec![1, 2], policy).respond_to(&req);
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get(CACHE_CONTROL).unwrap(),
+            "public, max-age=60, immutable"
+        );
+        let etag = res.headers().get(ETAG).unwrap().clone();
+
+        let req = TestRequest::default()
+            .insert_header((IF_NONE_MATCH, etag.clone()))
+            .to_http_request();
+        let res = CachedJson::new(vec![1, 2], policy).respond_to(&req);
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(res.headers().get(ETAG).unwrap(), &etag);
+
+        let res = CachedJson::new(vec![1, 3], policy).respond_to(&req);
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = CachedJson::new(vec![1, 2], CachePolicy::NoStore).respond_to(&req);
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(CACHE_CONTROL).unwrap(), "no-store");
+        assert!(res.headers().get(ETAG).is_none());
+    }
+}
@@ -20,11 +20,20 @@ pub fn update(status: StatusResponse) {
     STATE.update(status);
 }
 
+pub fn update_db_pool(in_use: u64, idle: u64) {
+    STATE.db_pool_in_use.set(in_use);
+    STATE.db_pool_idle.set(idle);
+}
+
 struct State {
     registry: Registry,
     last_block_btc: GenericGauge<AtomicU64>,
     last_indexed_block_btc: GenericGauge<AtomicU64>,
     last_indexed_block_runes: GenericGauge<AtomicU64>,
+    last_indexed_block_inscriptions: GenericGauge<AtomicU64>,
+    seconds_since_last_block: GenericGauge<AtomicU64>,
+    db_pool_in_use: GenericGauge<AtomicU64>,
+    db_pool_idle: GenericGauge<AtomicU64>,
 }
 
 impl State {
@@ -39,15 +48,34 @@ impl State {
             "last_block_runes_indexer",
             "Last indexed block by runes indexer",
         )?;
+        let last_indexed_block_inscriptions = GenericGauge::new(
+            "last_block_inscriptions_indexer",
+            "Last indexed block by inscriptions indexer",
+        )?;
+        let seconds_since_last_block = GenericGauge::new(
+            "seconds_since_last_indexed_block",
+            "Seconds since the least recently updated indexer has indexed a block",
+        )?;
+        let db_pool_in_use =
+            GenericGauge::new("db_pool_in_use", "DB connections that are in use by API")?;
+        let db_pool_idle = GenericGauge::new("db_pool_idle", "Idle DB connections of API")?;
 
         shared_registry.register(Box::new(last_block.clone()))?;
         shared_registry.register(Box::new(last_indexed_block_btc.clone()))?;
         shared_registry.register(Box::new(last_indexed_block_runes.clone()))?;
+        shared_registry.register(Box::new(last_indexed_block_inscriptions.clone()))?;
+        shared_registry.register(Box::new(seconds_since_last_block.clone()))?;
+        shared_registry.register(Box::new(db_pool_in_use.clone()))?;
+        shared_registry.register(Box::new(db_pool_idle.clone()))?;
         Ok(Self {
             registry: shared_registry,
             last_block_btc: last_block,
             last_indexed_block_btc,
             last_indexed_block_runes,
+            last_indexed_block_inscriptions,
+            seconds_since_last_block,
+            db_pool_in_use,
+            db_pool_idle,
         })
     }
 
@@ -56,5 +84,11 @@ impl State {
         self.last_indexed_block_btc.set(status.btc_indexer_height);
         self.last_indexed_block_runes
             .set(status.runes_indexer_height);
+        if let Some(height) = status.inscriptions_indexer_height {
+            self.last_indexed_block_inscriptions.set(height);
+        }
+        if let Some(seconds) = status.seconds_since_last_block {
+            self.seconds_since_last_block.set(seconds);
+        }
     }
 }
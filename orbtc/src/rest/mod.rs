@@ -1,9 +1,15 @@
 pub mod api;
+pub mod api_admin;
 pub mod api_btc;
+pub mod api_labels;
 pub mod api_runes;
+pub mod api_stream;
+pub mod api_subscriptions;
 pub mod auth_middleware;
 pub mod context;
+pub mod http_cache;
 pub mod mempool_cache;
 pub mod metrics;
+pub mod rate_limit;
 pub mod requests;
 pub mod swagger;
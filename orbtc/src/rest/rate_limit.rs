@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderValue, RETRY_AFTER, X_FORWARDED_FOR};
+use actix_web::middleware::Next;
+use actix_web::web::Data;
+use actix_web::{Error, HttpResponse};
+use api_core::server::RateLimitConfig;
+
+use super::auth_middleware::AUTH_HEADER;
+use super::context::Context;
+use crate::cache;
+
+/// Local buckets are dropped once they are refilled,
+/// this bounds memory used by clients identified by ip.
+const MAX_LOCAL_BUCKETS: usize = 100_000;
+
+/// Token bucket rate limiter.
+/// Buckets are kept in redis when cache is enabled, so all replicas share the limits,
+/// otherwise (or when redis is unavailable) in-process.
+pub struct RateLimiter {
+    cfg: RateLimitConfig,
+    cache: Arc<Option<cache::Repo>>,
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limit {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+impl RateLimiter {
+    pub fn new(cfg: RateLimitConfig, cache: Arc<Option<cache::Repo>>) -> Self {
+        Self {
+            cfg,
+            cache,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.cfg.enable
+    }
+
+    /// Returns limit for the client, `None` means the client is not limited.
+    pub fn limit(&self, key_override: Option<i32>) -> Option<Limit> {
+        let per_minute = match key_override {
+            Some(value) if value <= 0 => return None,
+            Some(value) => value as u32,
+            None => self.cfg.requests_per_minute.max(1),
+        };
+
+        Some(Limit {
+            burst: self.cfg.burst.max(1),
+            per_minute,
+        })
+    }
+
+    /// Ip of the client, `forwarded_for` is used only if the peer is a trusted proxy.
+    /// Proxies append the address they got the request from, so the rightmost address
+    /// that isn't a trusted proxy is the client, the ones to the left of it can be forged.
+    pub fn client_ip(&self, peer: Option<IpAddr>, forwarded_for: &str) -> Option<IpAddr> {
+        let trusted = &self.cfg.trusted_proxies;
+        let mut client = peer?;
+        if !trusted.contains(&client) {
+            return Some(client);
+        }
+
+        for hop in forwarded_for.rsplit(',') {
+            let Ok(ip) = hop.trim().parse::<IpAddr>() else {
+                break;
+            };
+            client = ip;
+            if !trusted.contains(&ip) {
+                break;
+            }
+        }
+        Some(client)
+    }
+
+    /// Returns `None` if the request is allowed, otherwise time to wait for the next token.
+    pub async fn check(&self, key: &str, limit: Limit) -> Option<Duration> {
+        if let Some(cache) = self.cache.as_ref() {
+            match cache
+                .take_rate_limit_token(key, limit.burst, limit.per_minute)
+                .await
+            {
+                Ok(wait) => return wait,
+                Err(err) => {
+                    warn!("can't check rate limit in cache, fallback to local: error={err}");
+                }
+            }
+        }
+
+        self.check_local(key, limit, Instant::now())
+    }
+
+    fn check_local(&self, key: &str, limit: Limit, now: Instant) -> Option<Duration> {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() >= MAX_LOCAL_BUCKETS && !buckets.contains_key(key) {
+            buckets.retain(|_, bucket| !bucket.is_full(now, limit));
+        }
+
+        buckets
+            .entry(key.to_owned())
+            .or_insert_with(|| TokenBucket::new(limit, now))
+            .take(now, limit)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: Limit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst as f64,
+            updated_at: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant, limit: Limit) {
+        let rate = limit.per_minute as f64 / 60.0;
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(limit.burst as f64);
+        self.updated_at = now;
+    }
+
+    fn is_full(&self, now: Instant, limit: Limit) -> bool {
+        let mut bucket = *self;
+        bucket.refill(now, limit);
+        bucket.tokens >= limit.burst as f64
+    }
+
+    fn take(&mut self, now: Instant, limit: Limit) -> Option<Duration> {
+        self.refill(now, limit);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return None;
+        }
+
+        let rate = limit.per_minute as f64 / 60.0;
+        Some(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+    }
+}
+
+/// Limits requests by `x-api-key` of a known key, or by client ip otherwise.
+/// Must wrap the auth middleware, so rejected requests are counted too.
+pub async fn rate_limit(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let state = req
+        .app_data::<Data<Context>>()
+        .expect("Context should be present")
+        .clone();
+    if !state.rate_limiter.enabled() {
+        return next.call(req).await.map(|r| r.map_into_left_body());
+    }
+
+    let token = req
+        .headers()
+        .get(AUTH_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .map(|t| t.to_owned());
+    let api_key = match token {
+        Some(token) => state.get_api_key(&token).await,
+        None => None,
+    };
+
+    // Unknown keys are limited by ip,
+    // otherwise a client can bypass the limit by sending random keys.
+    let (key, limit) = match api_key {
+        Some(api_key) => (
+            format!("key:{}", api_key.name),
+            state.rate_limiter.limit(api_key.rate_limit_per_minute),
+        ),
+        None => {
+            let forwarded_for = req
+                .headers()
+                .get_all(X_FORWARDED_FOR)
+                .filter_map(|h| h.to_str().ok())
+                .collect::<Vec<_>>()
+                .join(",");
+            let ip = state
+                .rate_limiter
+                .client_ip(req.peer_addr().map(|a| a.ip()), &forwarded_for)
+                .map_or("unknown".to_owned(), |ip| ip.to_string());
+            (format!("ip:{ip}"), state.rate_limiter.limit(None))
+        }
+    };
+
+    let Some(limit) = limit else {
+        return next.call(req).await.map(|r| r.map_into_left_body());
+    };
+
+    let Some(wait) = state.rate_limiter.check(&key, limit).await else {
+        return next.call(req).await.map(|r| r.map_into_left_body());
+    };
+
+    let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+    let mut response: HttpResponse = api_core::api_errors::too_many_requests(retry_after).into();
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+
+    Ok(req.into_response(response).map_into_right_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let limiter = RateLimiter::new(
+            RateLimitConfig {
+                enable: true,
+                requests_per_minute: 60,
+                burst: 2,
+                ..Default::default()
+            },
+            Arc::new(None),
+        );
+        let limit = limiter.limit(None).unwrap();
+        assert_eq!(
+            limit,
+            Limit {
+                burst: 2,
+                per_minute: 60
+            }
+        );
+        assert_eq!(limiter.limit(Some(0)), None);
+        assert_eq!(limiter.limit(Some(10)).unwrap().per_minute, 10);
+
+        let now = Instant::now();
+        assert!(limiter.check_local("a", limit, now).is_none());
+        assert!(limiter.check_local("a", limit, now).is_none());
+        let wait = limiter.check_local("a", limit, now).unwrap();
+        assert_eq!(wait, Duration::from_secs(1));
+
+        // other keys have their own buckets
+        assert!(limiter.check_local("b", limit, now).is_none());
+
+        // one token per second
+        let later = now + Duration::from_millis(1500);
+        assert!(limiter.check_local("a", limit, later).is_none());
+        let wait = limiter.check_local("a", limit, later).unwrap();
+        assert_eq!(wait, Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let limiter = RateLimiter::new(
+            RateLimitConfig {
+                trusted_proxies: vec![ip("10.0.0.1"), ip("10.0.0.2")],
+                ..Default::default()
+            },
+            Arc::new(None),
+        );
+        let client_ip = |peer: &str, forwarded_for: &str| {
+            limiter
+                .client_ip(Some(ip(peer)), forwarded_for)
+                .unwrap()
+                .to_string()
+        };
+
+        // the header of an untrusted peer is ignored
+        assert_eq!(client_ip("1.1.1.1", "2.2.2.2"), "1.1.1.1");
+        assert_eq!(client_ip("10.0.0.1", ""), "10.0.0.1");
+        assert_eq!(client_ip("10.0.0.1", "2.2.2.2"), "2.2.2.2");
+        // addresses sent by the client are skipped
+        assert_eq!(client_ip("10.0.0.1", "3.3.3.3, 2.2.2.2"), "2.2.2.2");
+        assert_eq!(
+            client_ip("10.0.0.1", "3.3.3.3, 2.2.2.2, 10.0.0.2"),
+            "2.2.2.2"
+        );
+        assert_eq!(client_ip("10.0.0.1", "garbage, 2.2.2.2"), "2.2.2.2");
+        assert_eq!(client_ip("10.0.0.1", "10.0.0.2, garbage"), "10.0.0.1");
+        assert_eq!(limiter.client_ip(None, "2.2.2.2"), None);
+    }
+}
@@ -1,16 +1,67 @@
+use std::collections::BTreeMap;
 use std::str::FromStr;
 
+use api_core::pages::PageParams;
 use bitcoin::address::NetworkChecked;
 use bitcoin::{Address, Network};
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
 pub fn decode_address(address: &str, net: Network) -> anyhow::Result<Address<NetworkChecked>> {
     Ok(Address::from_str(address)?.require_network(net)?)
 }
 
-#[derive(Copy, Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+/// Parses the keyset pagination cursor, it's an id of the last utxo from the previous page.
+pub fn parse_cursor(page: &PageParams, cursor: &Option<String>) -> anyhow::Result<Option<i64>> {
+    let Some(cursor) = cursor else {
+        return Ok(None);
+    };
+
+    if page.offset.is_some() || page.page.is_some() {
+        anyhow::bail!("cursor can't be used together with offset or page");
+    }
+
+    match cursor.parse::<i64>() {
+        Ok(id) => Ok(Some(id)),
+        Err(_) => anyhow::bail!("invalid cursor: {cursor}"),
+    }
+}
+
+/// Confirmation targets (in blocks) of the detailed fee estimation.
+pub const FEE_TARGETS: [u16; 5] = [1, 3, 6, 12, 24];
+
+/// Fee rates in sat/vB.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
 pub struct FeeRate {
     pub fast: u64,
     pub normal: u64,
     pub min: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub economy: Option<u64>,
+    /// Fee rate to get confirmed within the given number of blocks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub targets: Option<BTreeMap<u16, u64>>,
+    /// Mempool fee histogram: pairs of fee rate and vsize of txs paying that rate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Vec<Vec<f64>>>)]
+    pub histogram: Option<Vec<(f64, u64)>>,
+}
+
+impl FeeRate {
+    /// Drops fields that are returned only for `detailed=true`.
+    pub fn short(self) -> Self {
+        Self {
+            fast: self.fast,
+            normal: self.normal,
+            min: self.min,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FeeRateQuery {
+    #[serde(default)]
+    pub detailed: bool,
 }
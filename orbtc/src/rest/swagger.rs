@@ -1,6 +1,12 @@
 use actix_web::{HttpResponse, Responder};
+use api_core::api_errors::ErrorResponse;
 use mime_guess::from_path;
 use rust_embed::RustEmbed;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
+use utoipa::{IntoParams, Modify, OpenApi};
+
+use super::auth_middleware::AUTH_HEADER;
+use super::{api, api_admin, api_btc, api_labels, api_runes, api_stream, api_subscriptions};
 
 #[derive(RustEmbed)]
 #[folder = "../docs/swagger/"]
@@ -19,6 +25,168 @@ pub async fn ui() -> impl Responder {
     handle_embedded_file("index.html")
 }
 
+/// DEPRECATED: static spec, it isn't updated anymore. Use `openapi_json`.
 pub async fn spec() -> impl Responder {
     handle_embedded_file("swagger.yaml")
 }
+
+pub async fn openapi_json() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+/// The API is served under the name of the network it's configured for.
+#[derive(IntoParams)]
+#[into_params(parameter_in = Path)]
+#[allow(dead_code)]
+pub struct NetworkPath {
+    /// `mainnet`, `testnet4`, `signet` or `regtest`.
+    network: String,
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "OrBTC Indexer API",
+        description = "Errors are returned as `ErrorResponse` with the matching http status."
+    ),
+    paths(
+        api::healthcheck,
+        api::version,
+        api::service_status,
+        api_admin::list_api_keys,
+        api_admin::create_api_key,
+        api_admin::delete_api_key,
+        api_admin::update_api_key,
+        api_btc::list_blocks,
+        api_btc::get_block,
+        api_btc::get_block_stats,
+        api_btc::list_block_stats,
+        api_stream::stream_blocks,
+        api_btc::unlock_utxos,
+        api_btc::list_utxos,
+        api_btc::list_utxos_with_lock,
+        api_btc::get_utxo_stats,
+        api_btc::get_consolidation_plan,
+        api_btc::list_utxo_locks,
+        api_btc::list_address_outputs,
+        api_btc::get_balance,
+        api_btc::get_balances,
+        api_btc::get_address_summary,
+        api_btc::get_balance_history,
+        api_btc::btc_fee_rate,
+        api_runes::list_runes,
+        api_runes::list_runes_stats,
+        api_runes::list_runes_balances_multi,
+        api_runes::decode_runestone,
+        api_runes::validate_etching,
+        api_runes::trace_rune_allocation,
+        api_runes::get_rune,
+        api_runes::get_rune_details,
+        api_runes::list_rune_utxos,
+        api_runes::list_rune_utxos_with_lock,
+        api_runes::build_rune_psbt,
+        api_runes::list_rune_holders,
+        api_runes::list_rune_activity,
+        api_runes::list_rune_events,
+        api_runes::get_rune_balance,
+        api_runes::get_rune_balance_history,
+        api_runes::list_runes_balances,
+        api_runes::list_filtered_runes_balances,
+        api_btc::list_address_txs,
+        api_btc::send_raw_transaction,
+        api_btc::build_btc_psbt,
+        api_btc::test_raw_transaction,
+        api_btc::get_transaction,
+        api_btc::get_tx_in_outs,
+        api_runes::get_tx_runes_utxos,
+        api_subscriptions::list_subscriptions,
+        api_subscriptions::create_subscription,
+        api_subscriptions::delete_subscription,
+        api_labels::list_labels,
+        api_labels::create_label,
+        api_labels::delete_label,
+        api_btc::get_txs_in_mempool,
+        api_btc::get_address_mempool_txs,
+    ),
+    components(schemas(ErrorResponse)),
+    modifiers(&ApiKeyAuth, &RouteAliases),
+    security(("api_key" = [])),
+    tags(
+        (name = "system"),
+        (name = "admin", description = "Requires an admin api key"),
+        (name = "btc"),
+        (name = "runes"),
+        (name = "subscriptions"),
+        (name = "labels", description = "Requires the `labels` scope"),
+    )
+)]
+pub struct ApiDoc;
+
+struct ApiKeyAuth;
+
+impl Modify for ApiKeyAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(AUTH_HEADER))),
+        );
+    }
+}
+
+/// Routes that are served by the handler of another route.
+struct RouteAliases;
+
+impl Modify for RouteAliases {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        const ALIASES: [(&str, &str, &str); 1] = [(
+            "/v1/{network}/runes",
+            "/v1/{network}/runes/search",
+            "search_runes",
+        )];
+
+        let paths = &mut openapi.paths.paths;
+        for (path, alias, operation_id) in ALIASES {
+            let Some(mut item) = paths.get(path).cloned() else {
+                continue;
+            };
+            if let Some(op) = item.get.as_mut() {
+                op.operation_id = Some(operation_id.into());
+            }
+            paths.insert(alias.into(), item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rest::api::{ADMIN_ROUTES, NETWORK_ROUTES, SYSTEM_ROUTES};
+
+    #[test]
+    fn test_registered_routes_are_documented() {
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let paths = &spec["paths"];
+
+        let scopes = [
+            ("/v1", SYSTEM_ROUTES),
+            ("/v1/admin", ADMIN_ROUTES),
+            ("/v1/{network}", NETWORK_ROUTES),
+        ];
+        for (prefix, routes) in scopes {
+            for (path, methods) in routes {
+                let path = format!("{prefix}{path}");
+                if path.starts_with("/v1/swagger") {
+                    continue;
+                }
+                for (method, _) in methods.iter() {
+                    assert!(
+                        paths[path.as_str()][method].is_object(),
+                        "{} {path} is missing in the openapi spec",
+                        method.to_uppercase()
+                    );
+                }
+            }
+        }
+    }
+}
@@ -1 +1,4 @@
+pub mod psbt;
+pub mod tx_size;
 pub mod utxo_collector;
+pub mod webhooks;
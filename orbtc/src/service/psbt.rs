@@ -0,0 +1,404 @@
+use bigdecimal::ToPrimitive;
+use bitcoin::absolute::LockTime;
+use bitcoin::psbt::Psbt;
+use bitcoin::transaction::Version;
+use bitcoin::{Amount, Script, ScriptBuf, Transaction, TxIn, TxOut};
+use orbtc_indexer_api::{BtcUtxo, RuneUtxo};
+use ordinals::{Edict, RuneId, Runestone};
+
+use super::tx_size::{estimate_vsize, fee_for_vsize};
+
+#[derive(Debug, thiserror::Error)]
+pub enum PsbtError {
+    #[error("Not enough balance. Available: {available}, Required: {required}")]
+    NotEnoughBalance { available: u64, required: u64 },
+
+    #[error("Bad input: {0}")]
+    BadInput(String),
+}
+
+/// Value of the output that receives runes.
+pub const RUNE_OUTPUT_VALUE: u64 = 546;
+
+/// Unsigned psbt with the fee and the change paid by it.
+#[derive(Debug, Clone)]
+pub struct BuiltPsbt {
+    pub psbt: Psbt,
+    pub vsize: u64,
+    pub fee: u64,
+    /// Value of the change output, 0 if the tx has no change.
+    pub change: u64,
+}
+
+/// Fee of a tx that spends one `input_script` into `outputs` and a change to the `input_script`.
+/// It's used as the first guess of the amount to collect.
+pub fn min_fee(input_script: &Script, outputs: &[TxOut], fee_rate: f64) -> Result<u64, PsbtError> {
+    let mut output_scripts: Vec<_> = outputs
+        .iter()
+        .map(|o| o.script_pubkey.as_script())
+        .collect();
+    output_scripts.push(input_script);
+    let vsize = estimate_vsize(&[input_script], &output_scripts).ok_or_else(unsupported_input)?;
+    Ok(fee_for_vsize(vsize, fee_rate))
+}
+
+/// Builds an unsigned psbt that spends `inputs` into `outputs`,
+/// the rest goes to `change_script` unless it's dust, then it's left to the fee.
+/// Inputs get `witness_utxo` from the indexed `pk_script` and amount.
+/// `PsbtError::NotEnoughBalance` has the amount that must be collected to pay the fee.
+pub fn build_psbt(
+    inputs: &[BtcUtxo],
+    outputs: Vec<TxOut>,
+    change_script: &Script,
+    fee_rate: f64,
+) -> Result<BuiltPsbt, PsbtError> {
+    let parents = inputs.iter().map(|u| u.into_tx_parent()).collect();
+    assemble(parents, outputs, change_script, fee_rate, false)
+}
+
+/// Rune transfer from the sender to the `recipient`.
+#[derive(Debug, Clone)]
+pub struct RuneTransfer {
+    pub rune_id: RuneId,
+    pub amount: u128,
+    pub recipient: ScriptBuf,
+}
+
+/// Builds an unsigned psbt that transfers runes of `rune_inputs`,
+/// `fee_inputs` pay the rest of the fee.
+/// Outputs are the recipient with `RUNE_OUTPUT_VALUE` sats, the runestone
+/// with an edict of the `amount` to the recipient, and the change to `change_script`.
+/// The change is never dropped, the runestone pointer sends it the rest of the runes
+/// (including other runes of the inputs).
+/// `PsbtError::NotEnoughBalance` has the btc amount of all inputs that is required to pay the fee.
+pub fn build_rune_transfer_psbt(
+    rune_inputs: &[RuneUtxo],
+    fee_inputs: &[BtcUtxo],
+    transfer: &RuneTransfer,
+    change_script: &Script,
+    fee_rate: f64,
+) -> Result<BuiltPsbt, PsbtError> {
+    let rune_id = transfer.rune_id.to_string();
+    if let Some(utxo) = rune_inputs.iter().find(|u| u.rune_id != rune_id) {
+        return Err(PsbtError::BadInput(format!(
+            "utxo {}:{} doesn't hold the rune {rune_id}",
+            utxo.tx_hash, utxo.vout
+        )));
+    }
+    let available: u128 = rune_inputs
+        .iter()
+        .map(|u| u.amount.to_u128().unwrap_or_default())
+        .sum();
+    if transfer.amount == 0 || available < transfer.amount {
+        return Err(PsbtError::BadInput(format!(
+            "rune inputs hold {available} of the rune, {} is required",
+            transfer.amount
+        )));
+    }
+
+    const RECIPIENT_OUTPUT: u32 = 0;
+    // appended by `assemble` after the recipient and the runestone
+    const CHANGE_OUTPUT: u32 = 2;
+    let runestone = Runestone {
+        edicts: vec![Edict {
+            id: transfer.rune_id,
+            amount: transfer.amount,
+            output: RECIPIENT_OUTPUT,
+        }],
+        pointer: Some(CHANGE_OUTPUT),
+        ..Default::default()
+    };
+    let outputs = vec![
+        TxOut {
+            value: Amount::from_sat(RUNE_OUTPUT_VALUE),
+            script_pubkey: transfer.recipient.clone(),
+        },
+        TxOut {
+            value: Amount::ZERO,
+            script_pubkey: runestone.encipher(),
+        },
+    ];
+
+    let parents = rune_inputs
+        .iter()
+        .map(|u| u.into_tx_parent())
+        .chain(fee_inputs.iter().map(|u| u.into_tx_parent()))
+        .collect();
+    assemble(parents, outputs, change_script, fee_rate, true)
+}
+
+/// Spends `parents` into `outputs` and the change.
+/// If `keep_change` is set, the change output is added even if it has to be paid by the inputs.
+fn assemble(
+    parents: Vec<(TxIn, TxOut)>,
+    outputs: Vec<TxOut>,
+    change_script: &Script,
+    fee_rate: f64,
+    keep_change: bool,
+) -> Result<BuiltPsbt, PsbtError> {
+    for output in &outputs {
+        let dust = output.script_pubkey.minimal_non_dust();
+        if output.value < dust {
+            return Err(PsbtError::BadInput(format!(
+                "output amount {} is below the dust limit {}",
+                output.value.to_sat(),
+                dust.to_sat()
+            )));
+        }
+    }
+
+    let input_amount: u64 = parents.iter().map(|(_, out)| out.value.to_sat()).sum();
+    let output_amount: u64 = outputs.iter().map(|o| o.value.to_sat()).sum();
+    let input_scripts: Vec<_> = parents
+        .iter()
+        .map(|(_, out)| out.script_pubkey.as_script())
+        .collect();
+    let mut output_scripts: Vec<_> = outputs
+        .iter()
+        .map(|o| o.script_pubkey.as_script())
+        .collect();
+
+    let vsize_no_change =
+        estimate_vsize(&input_scripts, &output_scripts).ok_or_else(unsupported_input)?;
+    let fee_no_change = fee_for_vsize(vsize_no_change, fee_rate);
+    output_scripts.push(change_script);
+    let vsize = estimate_vsize(&input_scripts, &output_scripts).ok_or_else(unsupported_input)?;
+    let fee = fee_for_vsize(vsize, fee_rate);
+
+    let dust = change_script.minimal_non_dust().to_sat();
+    let (vsize, fee, change) = if input_amount >= output_amount + fee + dust {
+        (vsize, fee, input_amount - output_amount - fee)
+    } else if !keep_change && input_amount >= output_amount + fee_no_change {
+        // change would be dust, it's left to the miners
+        (vsize_no_change, input_amount - output_amount, 0)
+    } else {
+        let required = if keep_change {
+            output_amount + fee + dust
+        } else {
+            output_amount + fee_no_change
+        };
+        return Err(PsbtError::NotEnoughBalance {
+            available: input_amount,
+            required,
+        });
+    };
+
+    let mut tx_outputs = outputs;
+    if change > 0 {
+        tx_outputs.push(TxOut {
+            value: Amount::from_sat(change),
+            script_pubkey: ScriptBuf::from(change_script),
+        });
+    }
+    let (tx_inputs, parents): (Vec<_>, Vec<_>) = parents.into_iter().unzip();
+    let tx = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: tx_inputs,
+        output: tx_outputs,
+    };
+
+    let mut psbt = Psbt::from_unsigned_tx(tx)
+        .map_err(|err| PsbtError::BadInput(format!("can't build psbt: {err}")))?;
+    for (input, parent) in psbt.inputs.iter_mut().zip(parents) {
+        input.witness_utxo = Some(parent);
+    }
+
+    Ok(BuiltPsbt {
+        psbt,
+        vsize,
+        fee,
+        change,
+    })
+}
+
+fn unsupported_input() -> PsbtError {
+    PsbtError::BadInput(
+        "can't estimate the size of the inputs, address type is not supported".into(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use orbtc_indexer_api::Hash;
+
+    use super::*;
+
+    const P2WPKH: &str = "0014751e76e8199196d454941c45d1b3a323f1433bd6";
+    const P2TR: &str = "5120a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c";
+
+    fn utxos(amounts: &[i64]) -> Vec<BtcUtxo> {
+        amounts
+            .iter()
+            .enumerate()
+            .map(|(i, amount)| BtcUtxo {
+                id: i as i64,
+                tx_hash: Hash::sha2(i.to_string()),
+                pk_script: hex::decode(P2WPKH).unwrap(),
+                amount: *amount,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn output(amount: u64) -> TxOut {
+        TxOut {
+            value: Amount::from_sat(amount),
+            script_pubkey: ScriptBuf::from_hex(P2TR).unwrap(),
+        }
+    }
+
+    fn change_script() -> ScriptBuf {
+        ScriptBuf::from_hex(P2WPKH).unwrap()
+    }
+
+    #[test]
+    fn test_build_psbt_with_change() {
+        let inputs = utxos(&[10_000, 5000]);
+        let built = build_psbt(&inputs, vec![output(12_000)], &change_script(), 1.0).unwrap();
+
+        // 10.5 overhead + 2 * 68 inputs + 43 + 31 outputs
+        assert_eq!(built.vsize, 221);
+        assert_eq!(built.fee, 221);
+        assert_eq!(built.change, 15_000 - 12_000 - 221);
+
+        let tx = &built.psbt.unsigned_tx;
+        assert_eq!(tx.input.len(), 2);
+        assert_eq!(tx.input[0].previous_output, inputs[0].out_point());
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(tx.output[1].value.to_sat(), built.change);
+        assert_eq!(tx.output[1].script_pubkey, change_script());
+
+        let witness_utxo = built.psbt.inputs[1].witness_utxo.as_ref().unwrap();
+        assert_eq!(witness_utxo.value.to_sat(), 5000);
+        assert_eq!(witness_utxo.script_pubkey, change_script());
+    }
+
+    #[test]
+    fn test_build_psbt_dust_change() {
+        let inputs = utxos(&[10_000]);
+        // change would be 10_000 - 9700 - 153 = 147 sats
+        let built = build_psbt(&inputs, vec![output(9700)], &change_script(), 1.0).unwrap();
+        assert_eq!(built.change, 0);
+        assert_eq!(built.vsize, 122);
+        assert_eq!(built.fee, 300);
+        assert_eq!(built.psbt.unsigned_tx.output.len(), 1);
+    }
+
+    #[test]
+    fn test_build_psbt_not_enough() {
+        let inputs = utxos(&[10_000]);
+        let err = build_psbt(&inputs, vec![output(9950)], &change_script(), 1.0).unwrap_err();
+        assert!(matches!(
+            err,
+            PsbtError::NotEnoughBalance {
+                available: 10_000,
+                required: 10_072
+            }
+        ));
+
+        let err = build_psbt(&inputs, vec![output(100)], &change_script(), 1.0).unwrap_err();
+        assert!(matches!(err, PsbtError::BadInput(_)));
+    }
+
+    fn rune_utxos(amounts: &[(u128, i64)]) -> Vec<RuneUtxo> {
+        amounts
+            .iter()
+            .enumerate()
+            .map(|(i, (amount, btc_amount))| RuneUtxo {
+                id: i as i64,
+                tx_hash: Hash::sha2(format!("rune-{i}")),
+                rune_id: "840000:1".into(),
+                pk_script: hex::decode(P2WPKH).unwrap(),
+                amount: (*amount).into(),
+                btc_amount: *btc_amount,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn transfer(amount: u128) -> RuneTransfer {
+        RuneTransfer {
+            rune_id: RuneId {
+                block: 840_000,
+                tx: 1,
+            },
+            amount,
+            recipient: ScriptBuf::from_hex(P2TR).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_build_rune_transfer_psbt() {
+        let rune_inputs = rune_utxos(&[(700, 546), (500, 546)]);
+        let fee_inputs = utxos(&[10_000]);
+        let built = build_rune_transfer_psbt(
+            &rune_inputs,
+            &fee_inputs,
+            &transfer(1000),
+            &change_script(),
+            2.0,
+        )
+        .unwrap();
+
+        let tx = &built.psbt.unsigned_tx;
+        assert_eq!(tx.input.len(), 3);
+        assert_eq!(tx.input[0].previous_output, rune_inputs[0].out_point());
+        assert_eq!(tx.input[2].previous_output, fee_inputs[0].out_point());
+        assert_eq!(
+            built.psbt.inputs[1].witness_utxo.as_ref().unwrap().value,
+            Amount::from_sat(546)
+        );
+
+        assert_eq!(tx.output.len(), 3);
+        assert_eq!(tx.output[0].value.to_sat(), RUNE_OUTPUT_VALUE);
+        assert_eq!(tx.output[0].script_pubkey, transfer(1000).recipient);
+        assert_eq!(tx.output[2].script_pubkey, change_script());
+        assert_eq!(tx.output[2].value.to_sat(), built.change);
+        assert_eq!(
+            built.change,
+            546 * 2 + 10_000 - RUNE_OUTPUT_VALUE - built.fee
+        );
+
+        let Some(ordinals::Artifact::Runestone(runestone)) = Runestone::decipher(tx) else {
+            panic!("tx must have a runestone");
+        };
+        assert_eq!(
+            runestone.edicts,
+            vec![Edict {
+                id: transfer(1000).rune_id,
+                amount: 1000,
+                output: 0,
+            }]
+        );
+        assert_eq!(runestone.pointer, Some(2));
+    }
+
+    #[test]
+    fn test_build_rune_transfer_psbt_needs_fee_inputs() {
+        let rune_inputs = rune_utxos(&[(1000, 546)]);
+        let err =
+            build_rune_transfer_psbt(&rune_inputs, &[], &transfer(1000), &change_script(), 1.0)
+                .unwrap_err();
+        let PsbtError::NotEnoughBalance {
+            available,
+            required,
+        } = err
+        else {
+            panic!("unexpected error: {err}");
+        };
+        assert_eq!(available, 546);
+        // the change is kept even without runes left
+        assert!(required > RUNE_OUTPUT_VALUE + 294);
+
+        let err = build_rune_transfer_psbt(
+            &rune_inputs,
+            &utxos(&[10_000]),
+            &transfer(1001),
+            &change_script(),
+            1.0,
+        )
+        .unwrap_err();
+        assert!(matches!(err, PsbtError::BadInput(_)));
+    }
+}
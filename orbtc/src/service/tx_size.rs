@@ -0,0 +1,109 @@
+use bitcoin::Script;
+
+/// version + locktime.
+const TX_BASE_WEIGHT: u64 = 8 * 4;
+/// Segwit marker and flag, they are not scaled.
+const SEGWIT_HEADER_WEIGHT: u64 = 2;
+/// outpoint + script_sig length + sequence.
+const INPUT_BASE_WEIGHT: u64 = (36 + 1 + 4) * 4;
+/// Witness of a p2wpkh input: items count, signature and pubkey.
+const P2WPKH_WITNESS_WEIGHT: u64 = 1 + 73 + 34;
+/// Witness of a p2tr key path input: items count and schnorr signature.
+const P2TR_WITNESS_WEIGHT: u64 = 1 + 65;
+/// script_sig of a p2pkh input: signature and pubkey.
+const P2PKH_SCRIPT_SIG_WEIGHT: u64 = (73 + 34) * 4;
+/// script_sig of a p2sh-p2wpkh input: push of the redeem script.
+const P2SH_P2WPKH_SCRIPT_SIG_WEIGHT: u64 = 23 * 4;
+
+/// Estimated virtual size of a tx that spends outputs with `inputs` scripts
+/// into outputs with `outputs` scripts.
+/// Returns `None` if the size of an input can't be estimated,
+/// only p2wpkh, p2tr (key path), p2pkh and p2sh-p2wpkh inputs are supported.
+pub fn estimate_vsize(inputs: &[&Script], outputs: &[&Script]) -> Option<u64> {
+    let mut weight =
+        TX_BASE_WEIGHT + (varint_len(inputs.len() as u64) + varint_len(outputs.len() as u64)) * 4;
+
+    let mut has_witness = false;
+    for script in inputs {
+        let (input_weight, witness) = input_weight(script)?;
+        weight += input_weight;
+        has_witness |= witness;
+    }
+    if has_witness {
+        weight += SEGWIT_HEADER_WEIGHT;
+    }
+
+    for script in outputs {
+        let len = script.len() as u64;
+        weight += (8 + varint_len(len) + len) * 4;
+    }
+
+    Some(weight.div_ceil(4))
+}
+
+/// Fee of `vsize` at `fee_rate` sat/vB, rounded up.
+pub fn fee_for_vsize(vsize: u64, fee_rate: f64) -> u64 {
+    (vsize as f64 * fee_rate).ceil() as u64
+}
+
+/// Weight of the input that spends `script` and whether it has a witness.
+/// ECDSA signatures are counted with the max size, schnorr ones with the default sighash.
+fn input_weight(script: &Script) -> Option<(u64, bool)> {
+    if script.is_p2wpkh() {
+        Some((INPUT_BASE_WEIGHT + P2WPKH_WITNESS_WEIGHT, true))
+    } else if script.is_p2tr() {
+        Some((INPUT_BASE_WEIGHT + P2TR_WITNESS_WEIGHT, true))
+    } else if script.is_p2pkh() {
+        Some((INPUT_BASE_WEIGHT + P2PKH_SCRIPT_SIG_WEIGHT, false))
+    } else if script.is_p2sh() {
+        // assumes the common nested p2wpkh
+        Some((
+            INPUT_BASE_WEIGHT + P2SH_P2WPKH_SCRIPT_SIG_WEIGHT + P2WPKH_WITNESS_WEIGHT,
+            true,
+        ))
+    } else {
+        None
+    }
+}
+
+fn varint_len(n: u64) -> u64 {
+    match n {
+        0..=0xfc => 1,
+        0xfd..=0xffff => 3,
+        0x10000..=0xffff_ffff => 5,
+        _ => 9,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::ScriptBuf;
+
+    use super::*;
+
+    fn script(hex: &str) -> ScriptBuf {
+        ScriptBuf::from_hex(hex).unwrap()
+    }
+
+    #[test]
+    fn test_estimate_vsize() {
+        let p2wpkh = script("0014751e76e8199196d454941c45d1b3a323f1433bd6");
+        let p2tr = script("5120a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c");
+        let p2pkh = script("76a914751e76e8199196d454941c45d1b3a323f1433bd688ac");
+        let p2wsh = script("00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262");
+
+        // 10.5 overhead + 68 input + 2 * 31 outputs
+        assert_eq!(estimate_vsize(&[&p2wpkh], &[&p2wpkh, &p2wpkh]), Some(141));
+        // 10.5 overhead + 2 * 57.5 inputs + 43 output
+        assert_eq!(estimate_vsize(&[&p2tr, &p2tr], &[&p2tr]), Some(169));
+        // 10 overhead + 148 input + 34 output, no segwit header
+        assert_eq!(estimate_vsize(&[&p2pkh], &[&p2pkh]), Some(192));
+        assert_eq!(estimate_vsize(&[&p2wsh], &[&p2wpkh]), None);
+    }
+
+    #[test]
+    fn test_fee_for_vsize() {
+        assert_eq!(fee_for_vsize(141, 1.0), 141);
+        assert_eq!(fee_for_vsize(169, 1.5), 254);
+    }
+}
@@ -0,0 +1,205 @@
+use bitcoin::Script;
+use orbtc_indexer_api::{BtcUtxo, ConsolidationPlan, UtxoRef};
+
+use super::CollectorError;
+use crate::service::tx_size::{estimate_vsize, fee_for_vsize};
+
+/// Checks the consolidation parameters before any utxos are fetched.
+pub fn check_consolidation_params(target_count: u32, fee_rate: f64) -> Result<(), CollectorError> {
+    if target_count == 0 {
+        return Err(CollectorError::BadInput(
+            "target_count must be positive".into(),
+        ));
+    }
+    if !fee_rate.is_finite() || fee_rate <= 0.0 {
+        return Err(CollectorError::BadInput(
+            "fee_rate must be positive number".into(),
+        ));
+    }
+    Ok(())
+}
+
+/// Number of utxos that must be merged into one output,
+/// so an address with `utxo_count` utxos has at most `target_count` of them.
+pub fn consolidation_inputs_needed(utxo_count: u64, target_count: u32) -> u64 {
+    if utxo_count <= target_count as u64 {
+        return 0;
+    }
+    utxo_count - target_count as u64 + 1
+}
+
+/// Plans a tx that spends the smallest `candidates` into one output to the same address,
+/// so the address is left with at most `target_count` utxos.
+/// `utxo_count` is the number of all utxos of the address, including the ones
+/// that can't be spent (runes, inscriptions, locked), `candidates` are the spendable ones.
+/// The plan is empty if the address already has at most `target_count` utxos.
+/// INVARIANT: candidates MUST be sorted in ascending order by amount.
+pub fn plan_consolidation(
+    candidates: &[BtcUtxo],
+    utxo_count: u64,
+    target_count: u32,
+    fee_rate: f64,
+    max_utxos: u32,
+) -> Result<ConsolidationPlan, CollectorError> {
+    check_consolidation_params(target_count, fee_rate)?;
+
+    let mut plan = ConsolidationPlan {
+        utxo_count,
+        resulting_count: utxo_count,
+        fee_rate,
+        ..Default::default()
+    };
+    let needed = consolidation_inputs_needed(utxo_count, target_count);
+    if needed == 0 {
+        return Ok(plan);
+    }
+    if needed > max_utxos as u64 {
+        return Err(CollectorError::MaxUtxosExceeded {
+            needed: needed as u32,
+            max: max_utxos,
+        });
+    }
+    if needed > candidates.len() as u64 {
+        return Err(CollectorError::BadInput(format!(
+            "{needed} utxos must be consolidated to reach the target_count, but only {} are spendable",
+            candidates.len()
+        )));
+    }
+
+    let inputs = &candidates[..needed as usize];
+    // the output goes back to the address of the inputs
+    let output_script = Script::from_bytes(&inputs[0].pk_script);
+    let input_scripts: Vec<_> = inputs
+        .iter()
+        .map(|u| Script::from_bytes(&u.pk_script))
+        .collect();
+    let Some(vsize) = estimate_vsize(&input_scripts, &[output_script]) else {
+        return Err(CollectorError::BadInput(
+            "can't estimate the size of the inputs, address type is not supported".into(),
+        ));
+    };
+    let fee = fee_for_vsize(vsize, fee_rate);
+    let input_amount: u64 = inputs.iter().map(|u| u.amount as u64).sum();
+    let dust = output_script.minimal_non_dust().to_sat();
+    if input_amount < fee + dust {
+        return Err(CollectorError::BadInput(format!(
+            "fee {fee} of the consolidation leaves less than dust of the {input_amount} inputs"
+        )));
+    }
+
+    plan.resulting_count = utxo_count - needed + 1;
+    plan.inputs = inputs
+        .iter()
+        .map(|u| UtxoRef {
+            tx_hash: u.tx_hash.clone(),
+            vout: u.vout,
+        })
+        .collect();
+    plan.input_amount = input_amount;
+    plan.vsize = vsize;
+    plan.fee = fee;
+    plan.output_value = input_amount - fee;
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use orbtc_indexer_api::Hash;
+
+    use super::*;
+
+    const P2WPKH: &str = "0014751e76e8199196d454941c45d1b3a323f1433bd6";
+    const P2TR: &str = "5120a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c";
+    const P2WSH: &str = "00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262";
+
+    fn utxos(script: &str, amounts: &[i64]) -> Vec<BtcUtxo> {
+        amounts
+            .iter()
+            .enumerate()
+            .map(|(i, amount)| BtcUtxo {
+                id: i as i64,
+                tx_hash: Hash::sha2(i.to_string()),
+                pk_script: hex::decode(script).unwrap(),
+                amount: *amount,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_consolidation_inputs_needed() {
+        assert_eq!(consolidation_inputs_needed(3, 5), 0);
+        assert_eq!(consolidation_inputs_needed(5, 5), 0);
+        assert_eq!(consolidation_inputs_needed(6, 5), 2);
+        assert_eq!(consolidation_inputs_needed(10, 1), 10);
+    }
+
+    #[test]
+    fn test_plan_consolidation_p2wpkh() {
+        let candidates = utxos(P2WPKH, &[1000, 2000, 3000, 50_000]);
+        let plan = plan_consolidation(&candidates, 6, 4, 2.0, 100).unwrap();
+
+        assert_eq!(plan.utxo_count, 6);
+        assert_eq!(plan.resulting_count, 4);
+        let inputs: Vec<_> = plan.inputs.iter().map(|i| i.tx_hash.clone()).collect();
+        let smallest: Vec<_> = candidates[..3].iter().map(|u| u.tx_hash.clone()).collect();
+        assert_eq!(inputs, smallest);
+        assert_eq!(plan.input_amount, 6000);
+        // 10.5 overhead + 3 * 68 inputs + 31 output
+        assert_eq!(plan.vsize, 246);
+        assert_eq!(plan.fee, 492);
+        assert_eq!(plan.output_value, 6000 - 492);
+    }
+
+    #[test]
+    fn test_plan_consolidation_p2tr() {
+        let candidates = utxos(P2TR, &[1000, 2000]);
+        let plan = plan_consolidation(&candidates, 2, 1, 1.5, 100).unwrap();
+
+        assert_eq!(plan.resulting_count, 1);
+        assert_eq!(plan.inputs.len(), 2);
+        // 10.5 overhead + 2 * 57.5 inputs + 43 output
+        assert_eq!(plan.vsize, 169);
+        assert_eq!(plan.fee, 254);
+        assert_eq!(plan.output_value, 3000 - 254);
+    }
+
+    #[test]
+    fn test_plan_consolidation_under_target() {
+        let candidates = utxos(P2WPKH, &[1000, 2000]);
+        let plan = plan_consolidation(&candidates, 2, 2, 1.0, 100).unwrap();
+        assert_eq!(plan.resulting_count, 2);
+        assert!(plan.inputs.is_empty());
+        assert_eq!((plan.vsize, plan.fee, plan.output_value), (0, 0, 0));
+    }
+
+    #[test]
+    fn test_plan_consolidation_errors() {
+        let candidates = utxos(P2WPKH, &[1000, 2000, 3000]);
+
+        let err = plan_consolidation(&candidates, 3, 0, 1.0, 100).unwrap_err();
+        assert!(matches!(err, CollectorError::BadInput(_)));
+        let err = plan_consolidation(&candidates, 3, 1, 0.0, 100).unwrap_err();
+        assert!(matches!(err, CollectorError::BadInput(_)));
+        let err = plan_consolidation(&candidates, 3, 1, f64::NAN, 100).unwrap_err();
+        assert!(matches!(err, CollectorError::BadInput(_)));
+
+        // runes and inscriptions are counted, but can't be spent
+        let err = plan_consolidation(&candidates, 5, 1, 1.0, 100).unwrap_err();
+        assert!(matches!(err, CollectorError::BadInput(_)));
+
+        let err = plan_consolidation(&candidates, 3, 1, 1.0, 2).unwrap_err();
+        assert!(matches!(
+            err,
+            CollectorError::MaxUtxosExceeded { needed: 3, max: 2 }
+        ));
+
+        // the fee eats the whole amount
+        let err = plan_consolidation(&candidates, 3, 1, 100.0, 100).unwrap_err();
+        assert!(matches!(err, CollectorError::BadInput(_)));
+
+        let candidates = utxos(P2WSH, &[1000, 2000]);
+        let err = plan_consolidation(&candidates, 2, 1, 1.0, 100).unwrap_err();
+        assert!(matches!(err, CollectorError::BadInput(_)));
+    }
+}
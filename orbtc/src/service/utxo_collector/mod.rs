@@ -2,17 +2,27 @@ use std::sync::Arc;
 
 pub use algo::{min_utxos_to_reach_target, KnapsackError};
 use async_trait::async_trait;
-use bigdecimal::ToPrimitive;
-use orbtc_indexer_api::{BtcUtxo, OrderBy, RuneUtxo, UtxoSortMode};
+use bigdecimal::{BigDecimal, ToPrimitive};
+pub use consolidation::plan_consolidation;
+use consolidation::{check_consolidation_params, consolidation_inputs_needed};
+use orbtc_indexer_api::{AmountRange, BtcUtxo, ConsolidationPlan, OrderBy, RuneUtxo, UtxoSortMode};
 
+use crate::config::CollectorConfig;
 use crate::db::Repo;
 
 mod algo;
+mod consolidation;
 
 #[derive(Debug, thiserror::Error)]
 pub enum CollectorError {
-    #[error("Not enough balance. Available: {available}, Required: {target}")]
-    NotEnoughBalance { available: u128, target: u128 },
+    /// `available` is the confirmed balance of the address,
+    /// `collectable` is the part of it left after the utxos filtering.
+    #[error("Not enough balance. Available: {available}, Collectable: {collectable}, Required: {target}")]
+    NotEnoughBalance {
+        available: u128,
+        collectable: u128,
+        target: u128,
+    },
 
     #[error("Top {max} biggest UTXOs are not enough to collect {target} amount (collected={collected}). Total UTXOs={total_utxo}")]
     NeedMoreUtxos {
@@ -22,11 +32,54 @@ pub enum CollectorError {
         target: u128,
     },
 
+    #[error("Target requires {needed} UTXOs, but only {max} are allowed")]
+    MaxUtxosExceeded { needed: u32, max: u32 },
+
+    #[error("Target amount is zero")]
+    ZeroTarget,
+
     #[error("Bad input: {0}")]
     BadInput(String),
 
     #[error("DB error: {0}")]
     DbError(#[from] sqlx::Error),
+
+    #[error("Filter error: {0}")]
+    FilterError(anyhow::Error),
+}
+
+/// Describes which UTXOs must be dropped from the candidates.
+#[derive(Debug, Clone, Default)]
+pub struct FilterOpts {
+    /// UTXOs locked with this request id are treated as not locked.
+    pub request_id: Option<String>,
+    pub skip_inscriptions: bool,
+    pub skip_runes: bool,
+    /// Skip coinbase UTXOs mined at or after this height.
+    pub skip_premature: Option<u64>,
+}
+
+/// Hook to drop UTXOs that can't be spent right now:
+/// used by transactions in the mempool, locked by other requests, etc.
+#[async_trait]
+pub trait UtxoFilter: Send + Sync {
+    async fn filter_btc_utxos(
+        &self,
+        utxos: &[BtcUtxo],
+        opts: &FilterOpts,
+    ) -> anyhow::Result<Vec<BtcUtxo>>;
+
+    async fn filter_rune_utxos(
+        &self,
+        utxos: &[RuneUtxo],
+        opts: &FilterOpts,
+    ) -> anyhow::Result<Vec<RuneUtxo>>;
+}
+
+/// Result of the collection with filters.
+#[derive(Debug, Clone)]
+pub struct CollectedUtxos<U> {
+    pub utxos: Vec<U>,
 }
 
 impl algo::Utxo for BtcUtxo {
@@ -63,18 +116,98 @@ pub trait UtxoCollector: Send + Sync {
         target: u128,
         max_utxos: u32,
     ) -> Result<Vec<RuneUtxo>, CollectorError>;
+
+    /// collect BTC UTXOs for a given address, skipping UTXOs rejected by the filter.
+    /// There is no limit of the UTXOs count, candidates are scanned page by page
+    /// until the target is reached.
+    async fn collect_spendable_btc_utxo(
+        &self,
+        address: &str,
+        target: u64,
+        opts: &FilterOpts,
+    ) -> Result<CollectedUtxos<BtcUtxo>, CollectorError>;
+
+    /// collect RUNE UTXOs for a given address and rune, skipping UTXOs rejected by the filter.
+    /// There is no limit of the UTXOs count, candidates are scanned page by page
+    /// until the target is reached.
+    async fn collect_spendable_rune_utxo(
+        &self,
+        address: &str,
+        rune: &str,
+        target: u128,
+        opts: &FilterOpts,
+    ) -> Result<CollectedUtxos<RuneUtxo>, CollectorError>;
+
+    /// plan consolidation of the smallest BTC UTXOs of a given address,
+    /// so it has at most `target_count` UTXOs. UTXOs rejected by the filter are not spent.
+    /// Nothing is locked, the plan is only advisory.
+    async fn plan_btc_consolidation(
+        &self,
+        address: &str,
+        target_count: u32,
+        fee_rate: f64,
+        opts: &FilterOpts,
+    ) -> Result<ConsolidationPlan, CollectorError>;
 }
 
+/// Size of the candidates page for the collection with filters.
+const CANDIDATES_PAGE: u32 = 200;
+/// Max number of the candidates for the shortcut collection.
+const SHORTCUT_LIMIT: u32 = 10;
+
 /// This service is responsible for collecting UTXOs for a given address/rune.
 ///
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct UtxoCollectorService {
     db: Arc<Repo>,
+    cfg: CollectorConfig,
+    filter: Arc<dyn UtxoFilter>,
 }
 
 impl UtxoCollectorService {
-    pub fn new(db: Arc<Repo>) -> Self {
-        Self { db }
+    pub fn new(db: Arc<Repo>, cfg: CollectorConfig, filter: Arc<dyn UtxoFilter>) -> Self {
+        Self { db, cfg, filter }
+    }
+
+    fn dust_threshold(&self) -> Option<u64> {
+        if self.cfg.dust_threshold > 0 {
+            Some(self.cfg.dust_threshold)
+        } else {
+            None
+        }
+    }
+}
+
+/// Converts failed knapsack into collector error.
+/// The balance is checked before the collection,
+/// so if all `max_utxos` candidates were used, the cap is the limiting factor, not the balance.
+fn knapsack_error(
+    err: KnapsackError,
+    candidates: usize,
+    max_utxos: u32,
+    total_utxo: i64,
+    balance: u128,
+) -> CollectorError {
+    let (collected, target) = match err {
+        KnapsackError::NotEnoughBalance { available, target } => (available, target),
+        KnapsackError::ZeroTarget => return CollectorError::ZeroTarget,
+        KnapsackError::MaxUtxosExceeded { needed, max } => {
+            return CollectorError::MaxUtxosExceeded { needed, max }
+        }
+    };
+    if candidates as u32 >= max_utxos {
+        CollectorError::NeedMoreUtxos {
+            total_utxo: total_utxo as u32,
+            max: max_utxos,
+            collected,
+            target,
+        }
+    } else {
+        CollectorError::NotEnoughBalance {
+            available: balance,
+            collectable: collected,
+            target,
+        }
     }
 }
 
@@ -87,13 +220,10 @@ impl UtxoCollector for UtxoCollectorService {
         mut max_utxos: u32,
     ) -> Result<Vec<BtcUtxo>, CollectorError> {
         if target == 0 {
-            return Err(CollectorError::BadInput(
-                "Target amount is zero".to_string(),
-            ));
+            return Err(CollectorError::ZeroTarget);
         }
 
-        // 1k is a reasonable upper limit for the number of UTXOs to consider
-        max_utxos = max_utxos.clamp(1, 1000);
+        max_utxos = max_utxos.clamp(1, self.cfg.max_utxos.max(1));
 
         // shortcut: if user's balance is already "not enough", return early
         let balance = self
@@ -104,6 +234,7 @@ impl UtxoCollector for UtxoCollectorService {
         if (balance.balance as u64) < target {
             return Err(CollectorError::NotEnoughBalance {
                 available: balance.balance as u128,
+                collectable: balance.balance as u128,
                 target: target.into(),
             });
         }
@@ -119,24 +250,43 @@ impl UtxoCollector for UtxoCollectorService {
         }
 
         // at this point we know that the user has enough balance,
-        let candidates = self
-            .db
-            .select_utxo_with_pagination(
-                address,
-                OrderBy::Desc,
-                Some(800),
-                None, // TODO(Bohdan): must skip immature UTXOs as they are not spendable
-                UtxoSortMode::Amount,
-                max_utxos,
-                0,
-            )
-            .await
-            .map_err(CollectorError::DbError)?;
+        let mut amount_threshold = self.dust_threshold();
+        loop {
+            let candidates = self
+                .db
+                .select_utxo_with_pagination(
+                    address,
+                    OrderBy::Desc,
+                    AmountRange::above(amount_threshold),
+                    None, // TODO(Bohdan): must skip immature UTXOs as they are not spendable
+                    true,
+                    false,
+                    UtxoSortMode::Amount,
+                    None,
+                    max_utxos,
+                    0,
+                )
+                .await
+                .map_err(CollectorError::DbError)?;
 
-        match min_utxos_to_reach_target(&candidates, target.into()) {
-            Ok(utxos) => Ok(utxos),
-            Err(KnapsackError::NotEnoughBalance { available, target }) => {
-                Err(CollectorError::NotEnoughBalance { available, target })
+            match min_utxos_to_reach_target(&candidates, target.into(), Some(max_utxos)) {
+                Ok(utxos) => return Ok(utxos),
+                // dust can be enough to reach the target, so retry once without the threshold
+                Err(_) if amount_threshold.is_some() => amount_threshold = None,
+                Err(err) => {
+                    let total_utxo = self
+                        .db
+                        .count_utxos(address)
+                        .await
+                        .map_err(CollectorError::DbError)?;
+                    return Err(knapsack_error(
+                        err,
+                        candidates.len(),
+                        max_utxos,
+                        total_utxo,
+                        balance.balance as u128,
+                    ));
+                }
             }
         }
     }
@@ -149,13 +299,10 @@ impl UtxoCollector for UtxoCollectorService {
         mut max_utxos: u32,
     ) -> Result<Vec<RuneUtxo>, CollectorError> {
         if target == 0 {
-            return Err(CollectorError::BadInput(
-                "Target amount is zero".to_string(),
-            ));
+            return Err(CollectorError::ZeroTarget);
         }
 
-        // 1k is a reasonable upper limit for the number of UTXOs to consider
-        max_utxos = max_utxos.clamp(1, 1000);
+        max_utxos = max_utxos.clamp(1, self.cfg.max_utxos.max(1));
 
         // shortcut: if user's balance is already "not enough", return early
         let balance = self
@@ -170,6 +317,7 @@ impl UtxoCollector for UtxoCollectorService {
         if balance < target {
             return Err(CollectorError::NotEnoughBalance {
                 available: balance,
+                collectable: balance,
                 target,
             });
         }
@@ -185,25 +333,452 @@ impl UtxoCollector for UtxoCollectorService {
         }
 
         // at this point we know that the user has enough balance,
-        let candidates = self
+        let mut amount_threshold = self.dust_threshold();
+        loop {
+            let candidates = self
+                .db
+                .select_rune_utxo_with_pagination(
+                    rune,
+                    address,
+                    OrderBy::Desc,
+                    AmountRange::above(amount_threshold.map(BigDecimal::from)),
+                    UtxoSortMode::Amount,
+                    None,
+                    max_utxos,
+                    0,
+                )
+                .await
+                .map_err(CollectorError::DbError)?;
+
+            match min_utxos_to_reach_target(&candidates, target, Some(max_utxos)) {
+                Ok(utxos) => return Ok(utxos),
+                // dust can be enough to reach the target, so retry once without the threshold
+                Err(_) if amount_threshold.is_some() => amount_threshold = None,
+                Err(err) => {
+                    let total_utxo = self
+                        .db
+                        .count_runes_utxo(rune, address, AmountRange::default())
+                        .await
+                        .map_err(CollectorError::DbError)?;
+                    return Err(knapsack_error(
+                        err,
+                        candidates.len(),
+                        max_utxos,
+                        total_utxo,
+                        balance,
+                    ));
+                }
+            }
+        }
+    }
+
+    async fn collect_spendable_btc_utxo(
+        &self,
+        address: &str,
+        target: u64,
+        opts: &FilterOpts,
+    ) -> Result<CollectedUtxos<BtcUtxo>, CollectorError> {
+        if target == 0 {
+            return Err(CollectorError::ZeroTarget);
+        }
+
+        let balance = self.db.get_balance(address).await?;
+        if (balance.balance as u64) < target {
+            return Err(CollectorError::NotEnoughBalance {
+                available: balance.balance as u128,
+                collectable: balance.balance as u128,
+                target: target.into(),
+            });
+        }
+
+        // shortcut: try to pick few utxos with amount close to the target
+        let rows = self
             .db
-            .select_rune_utxo_with_pagination(
-                rune,
+            .select_utxos_with_amount_bounds(
                 address,
-                OrderBy::Desc,
-                Some(800),
-                orbtc_indexer_api::UtxoSortMode::Amount,
-                max_utxos,
-                0,
+                SHORTCUT_LIMIT,
+                target / 10,
+                target * 4,
+                opts.skip_premature.unwrap_or_default(),
             )
+            .await?;
+        let rows = self
+            .filter
+            .filter_btc_utxos(&rows, opts)
             .await
-            .map_err(CollectorError::DbError)?;
+            .map_err(CollectorError::FilterError)?;
+        if let Ok(utxos) = min_utxos_to_reach_target(&rows, target.into(), None) {
+            return Ok(CollectedUtxos { utxos });
+        }
+        debug!("shortcut is unsuccessful, going in hard way");
+
+        let mut candidates = Vec::new();
+        let mut offset = 0;
+        loop {
+            let rows = self
+                .db
+                .select_utxo_with_pagination(
+                    address,
+                    OrderBy::Desc,
+                    AmountRange::default(),
+                    opts.skip_premature,
+                    opts.skip_inscriptions,
+                    false,
+                    UtxoSortMode::Amount,
+                    None,
+                    CANDIDATES_PAGE,
+                    offset,
+                )
+                .await?;
+            if rows.is_empty() {
+                return Err(CollectorError::NotEnoughBalance {
+                    available: balance.balance as u128,
+                    collectable: candidates.iter().map(|u: &BtcUtxo| u.amount as u128).sum(),
+                    target: target.into(),
+                });
+            }
+
+            let rows = self
+                .filter
+                .filter_btc_utxos(&rows, opts)
+                .await
+                .map_err(CollectorError::FilterError)?;
+            candidates.extend(rows);
+
+            if let Ok(utxos) = min_utxos_to_reach_target(&candidates, target.into(), None) {
+                return Ok(CollectedUtxos { utxos });
+            }
+            offset += CANDIDATES_PAGE;
+        }
+    }
+
+    async fn collect_spendable_rune_utxo(
+        &self,
+        address: &str,
+        rune: &str,
+        target: u128,
+        opts: &FilterOpts,
+    ) -> Result<CollectedUtxos<RuneUtxo>, CollectorError> {
+        if target == 0 {
+            return Err(CollectorError::ZeroTarget);
+        }
+
+        let balance = self
+            .db
+            .get_rune_balance(address, rune)
+            .await?
+            .balance
+            .to_u128()
+            .expect("balance must fit u128");
+        if balance < target {
+            return Err(CollectorError::NotEnoughBalance {
+                available: balance,
+                collectable: balance,
+                target,
+            });
+        }
+
+        // shortcut: try to pick few utxos with amount close to the target
+        let target_amount = BigDecimal::from(target);
+        let rows = self
+            .db
+            .select_rune_utxos_with_amount_bounds(
+                address,
+                rune,
+                SHORTCUT_LIMIT,
+                &(&target_amount / BigDecimal::from(10)),
+                &(&target_amount * BigDecimal::from(4)),
+            )
+            .await?;
+        let rows = self
+            .filter
+            .filter_rune_utxos(&rows, opts)
+            .await
+            .map_err(CollectorError::FilterError)?;
+        if let Ok(utxos) = min_utxos_to_reach_target(&rows, target, None) {
+            return Ok(CollectedUtxos { utxos });
+        }
+        debug!("shortcut is unsuccessful, going in hard way");
+
+        let mut candidates = Vec::new();
+        let mut offset = 0;
+        loop {
+            let rows = self
+                .db
+                .select_rune_utxo_with_pagination(
+                    rune,
+                    address,
+                    OrderBy::Desc,
+                    AmountRange::default(),
+                    UtxoSortMode::Amount,
+                    None,
+                    CANDIDATES_PAGE,
+                    offset,
+                )
+                .await?;
+            if rows.is_empty() {
+                return Err(CollectorError::NotEnoughBalance {
+                    available: balance,
+                    collectable: candidates
+                        .iter()
+                        .map(|u: &RuneUtxo| u.amount.to_u128().unwrap_or_default())
+                        .sum(),
+                    target,
+                });
+            }
+
+            let rows = self
+                .filter
+                .filter_rune_utxos(&rows, opts)
+                .await
+                .map_err(CollectorError::FilterError)?;
+            candidates.extend(rows);
+
+            if let Ok(utxos) = min_utxos_to_reach_target(&candidates, target, None) {
+                return Ok(CollectedUtxos { utxos });
+            }
+            offset += CANDIDATES_PAGE;
+        }
+    }
+
+    async fn plan_btc_consolidation(
+        &self,
+        address: &str,
+        target_count: u32,
+        fee_rate: f64,
+        opts: &FilterOpts,
+    ) -> Result<ConsolidationPlan, CollectorError> {
+        check_consolidation_params(target_count, fee_rate)?;
+
+        let max_utxos = self.cfg.max_utxos.max(1);
+        let utxo_count = self.db.count_utxos(address).await? as u64;
+        let needed = consolidation_inputs_needed(utxo_count, target_count);
+
+        // the smallest ones go first, pages are scanned until there are enough spendable utxos
+        let mut candidates = Vec::new();
+        let mut offset = 0;
+        while needed > 0 && needed <= max_utxos as u64 && (candidates.len() as u64) < needed {
+            let rows = self
+                .db
+                .select_utxo_with_pagination(
+                    address,
+                    OrderBy::Asc,
+                    AmountRange::default(),
+                    opts.skip_premature,
+                    opts.skip_inscriptions,
+                    opts.skip_runes,
+                    UtxoSortMode::Amount,
+                    None,
+                    CANDIDATES_PAGE,
+                    offset,
+                )
+                .await?;
+            if rows.is_empty() {
+                break;
+            }
+
+            let rows = self
+                .filter
+                .filter_btc_utxos(&rows, opts)
+                .await
+                .map_err(CollectorError::FilterError)?;
+            candidates.extend(rows);
+            offset += CANDIDATES_PAGE;
+        }
+
+        plan_consolidation(&candidates, utxo_count, target_count, fee_rate, max_utxos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::config::DBConfig;
+
+    const ADDRESS: &str = "bcrt1q6rz28mcfaxtmd6v789l9rrlrusdprr9pz3cppk";
 
-        match min_utxos_to_reach_target(&candidates, target) {
-            Ok(utxos) => Ok(utxos),
-            Err(KnapsackError::NotEnoughBalance { available, target }) => {
-                Err(CollectorError::NotEnoughBalance { available, target })
+    /// Emulates redis locks: tx_id -> request_id.
+    struct LocksFilter {
+        locks: BTreeMap<i32, String>,
+    }
+
+    impl LocksFilter {
+        fn is_available(&self, tx_id: i32, opts: &FilterOpts) -> bool {
+            match self.locks.get(&tx_id) {
+                Some(owner) => opts.request_id.as_ref() == Some(owner),
+                None => true,
             }
         }
     }
+
+    #[async_trait]
+    impl UtxoFilter for LocksFilter {
+        async fn filter_btc_utxos(
+            &self,
+            utxos: &[BtcUtxo],
+            opts: &FilterOpts,
+        ) -> anyhow::Result<Vec<BtcUtxo>> {
+            Ok(utxos
+                .iter()
+                .filter(|u| self.is_available(u.tx_id, opts))
+                .cloned()
+                .collect())
+        }
+
+        async fn filter_rune_utxos(
+            &self,
+            utxos: &[RuneUtxo],
+            opts: &FilterOpts,
+        ) -> anyhow::Result<Vec<RuneUtxo>> {
+            Ok(utxos
+                .iter()
+                .filter(|u| self.is_available(u.tx_id, opts))
+                .cloned()
+                .collect())
+        }
+    }
+
+    /// Creates a clean database with one output per amount, `tx_id` is the index of the amount.
+    async fn setup_collector(amounts: &[i64], locks: &[(i32, &str)]) -> UtxoCollectorService {
+        let dsn = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let db = crate::db::open_postgres_db(&DBConfig {
+            dsn,
+            automigrate: false,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        db.reset_schema().await.unwrap();
+
+        db.exec_raw(&format!(
+            "INSERT INTO addresses (address, address_type, pk_script) VALUES ('{ADDRESS}', 'p2wpkh', '\\x00');"
+        ))
+        .await
+        .unwrap();
+        for (i, amount) in amounts.iter().enumerate() {
+            let tx_hash = orbtc_indexer_api::Hash::sha2(i.to_string());
+            db.exec_raw(&format!(
+                "INSERT INTO outputs (block, tx_id, tx_hash, vout, address, amount, coinbase) \
+                 VALUES (1, {i}, '\\x{tx_hash}', 0, '{ADDRESS}', {amount}, false);"
+            ))
+            .await
+            .unwrap();
+        }
+
+        let filter = LocksFilter {
+            locks: locks
+                .iter()
+                .map(|(tx_id, owner)| (*tx_id, owner.to_string()))
+                .collect(),
+        };
+        UtxoCollectorService::new(Arc::new(db), CollectorConfig::default(), Arc::new(filter))
+    }
+
+    /// Adds the rune `AAAA` with one output of the `ADDRESS` per amount.
+    async fn add_rune_utxos(collector: &UtxoCollectorService, amounts: &[u128]) {
+        collector
+            .db
+            .insert_rune(&orbtc_indexer_api::Rune {
+                block: 1,
+                rune_id: "1:0".into(),
+                name: "AAAA".into(),
+                display_name: "AAAA".into(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        for (i, amount) in amounts.iter().enumerate() {
+            let tx_hash = orbtc_indexer_api::Hash::sha2(format!("rune-{i}"));
+            collector
+                .db
+                .exec_raw(&format!(
+                    "INSERT INTO runes_outputs \
+                        (block, tx_id, tx_hash, vout, rune, rune_id, address, amount, btc_amount) \
+                     VALUES (1, {i}, '\\x{tx_hash}', 0, 'AAAA', '1:0', '{ADDRESS}', {amount}, 546);"
+                ))
+                .await
+                .unwrap();
+        }
+    }
+
+    fn amounts(utxos: &[BtcUtxo]) -> Vec<i64> {
+        utxos.iter().map(|u| u.amount).collect()
+    }
+
+    #[tokio::test]
+    #[ignore = "requires postgres, set DATABASE_URL"]
+    async fn test_collect_spendable_btc_utxo_skips_locked() {
+        let collector = setup_collector(&[5000, 4000, 3000, 1000], &[(0, "other")]).await;
+
+        let opts = FilterOpts::default();
+        let res = collector
+            .collect_spendable_btc_utxo(ADDRESS, 4500, &opts)
+            .await
+            .unwrap();
+        assert_eq!(amounts(&res.utxos), vec![4000, 1000]);
+
+        // the owner of the lock can reuse its utxo
+        let opts = FilterOpts {
+            request_id: Some("other".to_string()),
+            ..Default::default()
+        };
+        let res = collector
+            .collect_spendable_btc_utxo(ADDRESS, 4500, &opts)
+            .await
+            .unwrap();
+        assert_eq!(amounts(&res.utxos), vec![5000]);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires postgres, set DATABASE_URL"]
+    async fn test_collect_spendable_btc_utxo_not_enough_when_locked() {
+        let collector = setup_collector(&[1000, 1000], &[(1, "other")]).await;
+
+        let err = collector
+            .collect_spendable_btc_utxo(ADDRESS, 1500, &FilterOpts::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            CollectorError::NotEnoughBalance {
+                available: 2000,
+                collectable: 1000,
+                target: 1500
+            }
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires postgres, set DATABASE_URL"]
+    async fn test_collect_rune_utxo_above_i64() {
+        let collector = setup_collector(&[], &[]).await;
+        let half = 1u128 << 69;
+        add_rune_utxos(&collector, &[1, half, half + 5]).await;
+
+        // no single utxo reaches 2^70, it must not wrap into a small target
+        let target = 1u128 << 70;
+        let single = collector
+            .db
+            .get_address_rune_utxo_ge_amount(ADDRESS, "AAAA", target)
+            .await
+            .unwrap();
+        assert!(single.is_none());
+        let single = collector
+            .db
+            .get_address_rune_utxo_ge_amount(ADDRESS, "AAAA", half + 1)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(single.amount, BigDecimal::from(half + 5));
+
+        let utxos = collector
+            .collect_rune_utxo(ADDRESS, "AAAA", target, 10)
+            .await
+            .unwrap();
+        let total: u128 = utxos.iter().map(|u| u.amount.to_u128().unwrap()).sum();
+        assert_eq!(utxos.len(), 2);
+        assert!(total >= target);
+    }
 }
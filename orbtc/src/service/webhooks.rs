@@ -0,0 +1,160 @@
+//! Delivery of address subscription webhooks.
+//!
+//! The BitcoinUtxo indexer enqueues a delivery for every new output of a subscribed address
+//! into `webhook_deliveries` together with the block data. API service picks them up here,
+//! so the indexer never waits for receivers.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::join_all;
+use orbtc_indexer_api::types::Hash;
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+
+use crate::db::{Repo, WebhookDelivery};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const BATCH_SIZE: i64 = 100;
+/// Claimed deliveries are retried by any replica after this time,
+/// e.g. when the one that claimed them was stopped.
+const LEASE_SECS: i64 = 60;
+const MAX_ATTEMPTS: i32 = 10;
+const BASE_RETRY_DELAY_SECS: i64 = 10;
+const MAX_RETRY_DELAY_SECS: i64 = 3600;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputEvent {
+    /// Same for all attempts, receivers can use it to skip duplicates.
+    pub delivery_id: i64,
+    pub subscription_id: i64,
+    pub address: String,
+    pub block: i64,
+    pub tx_hash: Hash,
+    pub vout: i32,
+    pub amount: i64,
+}
+
+impl From<&WebhookDelivery> for OutputEvent {
+    fn from(d: &WebhookDelivery) -> Self {
+        Self {
+            delivery_id: d.id,
+            subscription_id: d.subscription_id,
+            address: d.address.clone(),
+            block: d.block,
+            tx_hash: d.tx_hash.clone(),
+            vout: d.vout,
+            amount: d.amount,
+        }
+    }
+}
+
+/// Delay before the next attempt, `None` when the delivery should be given up.
+/// `attempts` includes the failed one.
+pub fn retry_delay_secs(attempts: i32) -> Option<i64> {
+    if attempts >= MAX_ATTEMPTS {
+        return None;
+    }
+
+    let exp = attempts.clamp(1, 16) as u32 - 1;
+    Some((BASE_RETRY_DELAY_SECS << exp).min(MAX_RETRY_DELAY_SECS))
+}
+
+pub async fn delivery_routine(db: Arc<Repo>, cancel: CancellationToken) {
+    use tokio::time::sleep;
+
+    // subscription urls are checked to be public on creation, a redirect could lead anywhere
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .build();
+    let client = match client {
+        Ok(client) => client,
+        Err(err) => {
+            error!("can't create webhooks client: error={err}");
+            return;
+        }
+    };
+
+    loop {
+        let deliveries = match db.claim_webhook_deliveries(BATCH_SIZE, LEASE_SECS).await {
+            Ok(deliveries) => deliveries,
+            Err(err) => {
+                error!("can't fetch webhook deliveries: error={:#?}", err);
+                Vec::new()
+            }
+        };
+
+        if deliveries.is_empty() {
+            tokio::select! {
+                _ = sleep(POLL_INTERVAL) => {}
+
+                _ = cancel.cancelled() => {
+                    log::info!("webhooks delivery task cancelled");
+                    break;
+                }
+            };
+            continue;
+        }
+
+        join_all(deliveries.iter().map(|d| deliver(&db, &client, d))).await;
+        if cancel.is_cancelled() {
+            break;
+        }
+    }
+}
+
+async fn deliver(db: &Repo, client: &reqwest::Client, delivery: &WebhookDelivery) {
+    let result = client
+        .post(&delivery.url)
+        .json(&OutputEvent::from(delivery))
+        .send()
+        .await
+        .and_then(|resp| resp.error_for_status())
+        .map_err(|err| err.to_string())
+        .and_then(|resp| match resp.status().is_redirection() {
+            true => Err(format!(
+                "redirect is not followed: status={}",
+                resp.status()
+            )),
+            false => Ok(()),
+        });
+
+    let result = match result {
+        Ok(()) => db.mark_webhook_delivered(delivery.id).await,
+        Err(err) => {
+            let attempts = delivery.attempts + 1;
+            let retry_in = retry_delay_secs(attempts);
+            if retry_in.is_none() {
+                warn!(
+                    "webhook delivery failed, giving up: id={} url={} attempts={attempts} error={err}",
+                    delivery.id, delivery.url
+                );
+            }
+            db.mark_webhook_attempt_failed(delivery.id, &err, retry_in)
+                .await
+        }
+    };
+
+    if let Err(err) = result {
+        error!(
+            "can't update webhook delivery: id={} error={:#?}",
+            delivery.id, err
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay_secs(1), Some(10));
+        assert_eq!(retry_delay_secs(2), Some(20));
+        assert_eq!(retry_delay_secs(4), Some(80));
+        assert_eq!(retry_delay_secs(9), Some(2560));
+        assert_eq!(retry_delay_secs(MAX_ATTEMPTS), None);
+    }
+}
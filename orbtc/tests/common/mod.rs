@@ -0,0 +1,201 @@
+//! Fixtures of the end-to-end tests: Postgres and regtest bitcoind are started in docker,
+//! the bitcoin utxo and runes indexers run against them in the background.
+//! Redis isn't started, so the API runs without the cache and utxo locks.
+
+use std::time::{Duration, Instant};
+
+use bitcoin::{Address, Amount, Txid};
+use bitcoincore_rpc::{Client, RpcApi};
+use orbtc::config::{BTCConfig, CacheConfig, Config, DBConfig, HealthConfig};
+use orbtc::db::{self, scopes, ApiKey, Repo};
+use orbtc::indexer::{self, BlockIndexerRt, IndexerType, IndexingOpts};
+use testcontainers::core::{IntoContainerPort, WaitFor};
+use testcontainers::runners::AsyncRunner;
+use testcontainers::{ContainerAsync, GenericImage, ImageExt};
+use testcontainers_modules::postgres::Postgres;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+pub const API_KEY: &str = "E2E_API_KEY";
+const RPC_PORT: u16 = 18443;
+const WALLET: &str = "e2e";
+/// Coinbase outputs are spendable after 100 confirmations.
+const COINBASE_MATURITY: u64 = 100;
+const INDEXING_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub struct TestEnv {
+    pub cfg: Config,
+    pub db: Repo,
+    /// Client of the node wallet that funds test addresses.
+    pub wallet: Client,
+    miner: Address,
+    cancel: CancellationToken,
+    _tasker: TaskTracker,
+    _postgres: ContainerAsync<Postgres>,
+    _bitcoind: ContainerAsync<GenericImage>,
+}
+
+impl TestEnv {
+    /// Starts the containers, funds the node wallet and runs the indexers until the tip.
+    pub async fn start() -> anyhow::Result<Self> {
+        indexer::set_indexer_wait_interval(Duration::from_millis(100));
+
+        let postgres = Postgres::default().with_tag("17-alpine").start().await?;
+        let dsn = format!(
+            "postgres://postgres:postgres@{}:{}/postgres",
+            postgres.get_host().await?,
+            postgres.get_host_port_ipv4(5432).await?
+        );
+
+        let bitcoind = GenericImage::new("bitcoin/bitcoin", "28-alpine")
+            .with_exposed_port(RPC_PORT.tcp())
+            .with_wait_for(WaitFor::message_on_stdout("init message: Done loading"))
+            .with_cmd([
+                "-regtest",
+                "-server",
+                "-txindex",
+                "-rpcuser=dev",
+                "-rpcpassword=dev",
+                "-rpcbind=0.0.0.0",
+                "-rpcallowip=0.0.0.0/0",
+                "-fallbackfee=0.0002",
+            ])
+            .start()
+            .await?;
+        let btc = BTCConfig {
+            network: Some("regtest".into()),
+            address: format!(
+                "http://{}:{}",
+                bitcoind.get_host().await?,
+                bitcoind.get_host_port_ipv4(RPC_PORT).await?
+            ),
+            rpc_user: "dev".into(),
+            rpc_password: "dev".into(),
+            ..Default::default()
+        };
+
+        let cfg = Config {
+            btc: btc.clone(),
+            db: DBConfig {
+                dsn,
+                automigrate: false,
+                ..Default::default()
+            },
+            cache: CacheConfig {
+                enable: false,
+                ..Default::default()
+            },
+            health: HealthConfig {
+                status_cache_ttl_secs: 0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        // a no-op if another test has set the logger
+        let _ = orbtc::cmd::init_log(&cfg.log, false);
+
+        btc.rpc_client()?
+            .create_wallet(WALLET, None, None, None, None)?;
+        let wallet = BTCConfig {
+            wallet: Some(WALLET.into()),
+            ..btc
+        }
+        .rpc_client()?;
+        let miner = wallet.get_new_address(None, None)?.assume_checked();
+        wallet.generate_to_address(COINBASE_MATURITY + 1, &miner)?;
+
+        db::apply_migrations(&cfg.db).await?;
+        let db = db::open_postgres_db(&cfg.db).await?;
+        // api keys are loaded by the context on start, so the key goes first
+        db.insert_api_key(ApiKey {
+            key: API_KEY.into(),
+            scopes: scopes::ALL.iter().map(|s| s.to_string()).collect(),
+            ..ApiKey::new("e2e-client")
+        })
+        .await?;
+
+        let cancel = CancellationToken::new();
+        let tasker = TaskTracker::new();
+        for opts in [
+            IndexingOpts {
+                indexer_type: IndexerType::BitcoinUtxo,
+                retry_on_fail: true,
+                ..Default::default()
+            },
+            IndexingOpts {
+                indexer_type: IndexerType::Runes,
+                retry_on_fail: true,
+                skip_inputs: true,
+                disable_commitment_validation: true,
+                ..Default::default()
+            },
+        ] {
+            BlockIndexerRt::new(&cfg.db, &cfg.btc, opts)?.start(&tasker, cancel.clone())?;
+        }
+
+        let env = Self {
+            cfg,
+            db,
+            wallet,
+            miner,
+            cancel,
+            _tasker: tasker,
+            _postgres: postgres,
+            _bitcoind: bitcoind,
+        };
+        env.wait_for_tip().await?;
+        Ok(env)
+    }
+
+    pub fn new_address(&self) -> anyhow::Result<Address> {
+        Ok(self.wallet.get_new_address(None, None)?.assume_checked())
+    }
+
+    /// Mines `count` blocks and returns the new tip height.
+    pub fn mine_blocks(&self, count: u64) -> anyhow::Result<u64> {
+        self.wallet.generate_to_address(count, &self.miner)?;
+        Ok(self.wallet.get_block_count()?)
+    }
+
+    /// Sends `amount` to the address and confirms it with a block.
+    pub fn fund_address(&self, address: &Address, amount: Amount) -> anyhow::Result<Txid> {
+        let txid = self
+            .wallet
+            .send_to_address(address, amount, None, None, None, None, None, None)?;
+        self.mine_blocks(1)?;
+        Ok(txid)
+    }
+
+    /// Waits until the indexer, e.g. [`indexer::BITCOIN_INDEX`], reaches the height.
+    pub async fn wait_for_height(&self, indexer: &str, height: u64) -> anyhow::Result<()> {
+        let started = Instant::now();
+        loop {
+            let last = self.db.get_last_indexed_block(indexer).await?;
+            if last >= height {
+                return Ok(());
+            }
+            if self.cancel.is_cancelled() {
+                anyhow::bail!("indexers are stopped: indexer={indexer} last={last}");
+            }
+            if started.elapsed() > INDEXING_TIMEOUT {
+                anyhow::bail!("indexing timed out: indexer={indexer} last={last} height={height}");
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Waits until all indexers reach the node tip.
+    pub async fn wait_for_tip(&self) -> anyhow::Result<u64> {
+        let tip = self.wallet.get_block_count()?;
+        for indexer in [indexer::BITCOIN_INDEX, indexer::RUNES_INDEX] {
+            self.wait_for_height(indexer, tip).await?;
+        }
+        Ok(tip)
+    }
+}
+
+impl Drop for TestEnv {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
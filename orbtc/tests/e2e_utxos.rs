@@ -0,0 +1,64 @@
+#![cfg(feature = "integration")]
+
+mod common;
+
+use actix_web::{test, App};
+use api_core::pages::ListResult;
+use api_core::server::APIProvider;
+use bitcoin::Amount;
+use common::{TestEnv, API_KEY};
+use orbtc::rest::api::Service;
+use orbtc::rest::auth_middleware::AUTH_HEADER;
+use orbtc_indexer_api::{BtcUtxo, CollectUtxo, Hash};
+
+#[actix_web::test]
+async fn test_list_utxos() {
+    let env = TestEnv::start().await.unwrap();
+    let address = env.new_address().unwrap();
+    let txid = env
+        .fund_address(&address, Amount::from_sat(50_000))
+        .unwrap();
+    env.wait_for_tip().await.unwrap();
+
+    let service = Service::new(env.cfg.clone()).await.unwrap();
+    let app = test::init_service(App::new().service(service.service())).await;
+    let uri = format!("/v1/regtest/utxos/{address}");
+
+    let req = test::TestRequest::get()
+        .uri(&uri)
+        .insert_header((AUTH_HEADER, API_KEY))
+        .to_request();
+    let resp: ListResult<BtcUtxo> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(resp.records.len(), 1);
+    let utxo = &resp.records[0];
+    assert_eq!(utxo.tx_hash, Hash::from(txid));
+    assert_eq!(utxo.amount, 50_000);
+
+    // without redis nothing is locked, so every request gets the same utxo
+    for request_id in ["req-1", "req-2"] {
+        let req = test::TestRequest::post()
+            .uri(&uri)
+            .insert_header((AUTH_HEADER, API_KEY))
+            .set_json(CollectUtxo {
+                amount: 20_000,
+                request_id: request_id.into(),
+            })
+            .to_request();
+        let resp: ListResult<BtcUtxo> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp.records.len(), 1, "request_id={request_id}");
+        assert_eq!(resp.records[0].tx_hash, utxo.tx_hash);
+        assert_eq!(resp.records[0].vout, utxo.vout);
+    }
+
+    // the amount isn't covered by the address utxos
+    let req = test::TestRequest::post()
+        .uri(&uri)
+        .insert_header((AUTH_HEADER, API_KEY))
+        .set_json(CollectUtxo {
+            amount: 100_000,
+            request_id: "req-3".into(),
+        })
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_client_error(), "status={}", resp.status());
+}